@@ -0,0 +1,98 @@
+// Renders /openapi.json without any third-party code: one section per tag, each operation with
+// its parameters, request body and responses, and the component schemas at the end.
+(function () {
+  "use strict";
+
+  const el = (tag, attrs, ...children) => {
+    const node = document.createElement(tag);
+    Object.entries(attrs || {}).forEach(([key, value]) => node.setAttribute(key, value));
+    children.flat().forEach((child) => node.append(child instanceof Node ? child : String(child)));
+    return node;
+  };
+
+  const refName = (ref) => ref.split("/").pop();
+
+  // A compact, JSON-like outline of a schema; references link to the schema section.
+  function outline(schema, depth) {
+    if (!schema) return "";
+    if (schema.$ref) return el("a", { href: "#schema-" + refName(schema.$ref) }, refName(schema.$ref));
+    const pad = "  ".repeat(depth);
+    if (schema.allOf || schema.oneOf || schema.anyOf) {
+      const [kind, parts] = schema.allOf ? ["allOf", schema.allOf] : schema.oneOf ? ["oneOf", schema.oneOf] : ["anyOf", schema.anyOf];
+      return el("span", {}, kind + " [", parts.map((part, i) => [i ? ", " : "", outline(part, depth)]), "]");
+    }
+    if (schema.type === "array") return el("span", {}, "[", outline(schema.items, depth), "]");
+    if (schema.type === "object" || schema.properties) {
+      const required = new Set(schema.required || []);
+      const fields = Object.entries(schema.properties || {}).map(([name, field]) => [
+        "\n" + pad + "  " + name + (required.has(name) ? "" : "?") + ": ",
+        outline(field, depth + 1),
+        field.description ? "  // " + field.description.split("\n")[0] : "",
+      ]);
+      const extra = schema.additionalProperties ? ["\n" + pad + "  [key]: ", outline(schema.additionalProperties, depth + 1)] : [];
+      return el("span", {}, "{", fields, extra, "\n" + pad + "}");
+    }
+    const type = Array.isArray(schema.type) ? schema.type.join(" | ") : schema.type || "any";
+    const values = schema.enum ? " (" + schema.enum.map((value) => JSON.stringify(value)).join(", ") + ")" : "";
+    return type + (schema.format ? " <" + schema.format + ">" : "") + values + (schema.nullable ? " | null" : "");
+  }
+
+  function operation(path, method, op) {
+    const section = el("div", { class: "op", id: op.operationId || method + path });
+    section.append(el("div", {}, el("span", { class: "method " + method }, method), " ", el("code", {}, path)));
+    if (op.summary) section.append(el("p", {}, el("strong", {}, op.summary)));
+    if (op.description) section.append(el("p", {}, op.description));
+
+    if (op.parameters && op.parameters.length) {
+      const rows = op.parameters.map((param) =>
+        el("tr", {}, el("td", {}, el("code", {}, param.name)), el("td", {}, param.in), el("td", {}, param.required ? "required" : "optional"), el("td", {}, outline(param.schema, 0)), el("td", {}, param.description || ""))
+      );
+      section.append(el("h4", {}, "Parameters"), el("table", {}, rows));
+    }
+    const body = op.requestBody && op.requestBody.content && op.requestBody.content["application/json"];
+    if (body) section.append(el("h4", {}, "Request body"), el("pre", {}, outline(body.schema, 0)));
+
+    const responses = Object.entries(op.responses || {}).map(([status, response]) => {
+      const content = response.content ? Object.values(response.content)[0] : null;
+      return el("tr", {}, el("td", {}, el("code", {}, status)), el("td", {}, response.description || ""), el("td", {}, content ? el("pre", {}, outline(content.schema, 0)) : ""));
+    });
+    section.append(el("h4", {}, "Responses"), el("table", {}, responses));
+    if (op.security) section.append(el("p", {}, "Requires ", op.security.map((item) => Object.keys(item).join(", ")).join(" or "), "."));
+    return section;
+  }
+
+  function render(spec) {
+    const main = document.getElementById("api");
+    const nav = document.getElementById("nav");
+    main.replaceChildren(el("h1", {}, spec.info.title + " " + spec.info.version));
+    if (spec.info.description) main.append(el("p", {}, spec.info.description));
+    nav.replaceChildren(el("strong", {}, spec.info.title));
+
+    const byTag = new Map();
+    Object.entries(spec.paths || {}).forEach(([path, item]) =>
+      Object.entries(item).forEach(([method, op]) => {
+        const tag = (op.tags && op.tags[0]) || "other";
+        if (!byTag.has(tag)) byTag.set(tag, []);
+        byTag.get(tag).push(operation(path, method, op));
+      })
+    );
+    byTag.forEach((ops, tag) => {
+      main.append(el("h2", { id: "tag-" + tag }, tag), ops);
+      nav.append(el("a", { href: "#tag-" + tag }, tag));
+    });
+
+    const schemas = (spec.components && spec.components.schemas) || {};
+    main.append(el("h2", { id: "schemas" }, "Schemas"));
+    nav.append(el("a", { href: "#schemas" }, "Schemas"));
+    Object.entries(schemas).forEach(([name, schema]) =>
+      main.append(el("h3", { id: "schema-" + name }, name), schema.description ? el("p", {}, schema.description) : "", el("pre", {}, outline(schema, 0)))
+    );
+  }
+
+  fetch("/openapi.json")
+    .then((response) => response.json())
+    .then(render)
+    .catch((err) => {
+      document.getElementById("api").textContent = "Cannot load /openapi.json: " + err;
+    });
+})();
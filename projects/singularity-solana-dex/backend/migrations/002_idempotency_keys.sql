@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status_code INT NOT NULL,
+    body TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS candles (
+    symbol TEXT NOT NULL,
+    resolution TEXT NOT NULL,
+    open_time BIGINT NOT NULL,
+    open NUMERIC(38, 18) NOT NULL,
+    high NUMERIC(38, 18) NOT NULL,
+    low NUMERIC(38, 18) NOT NULL,
+    close NUMERIC(38, 18) NOT NULL,
+    volume NUMERIC(38, 18) NOT NULL,
+    PRIMARY KEY (symbol, resolution, open_time)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS pending_liquidations (
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    market TEXT NOT NULL,
+    exit_price NUMERIC(38, 18) NOT NULL,
+    signature TEXT NOT NULL,
+    last_valid_block_height BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (account_id, market)
+);
@@ -0,0 +1,23 @@
+-- Every transaction sent for a pending liquidation, since an earlier attempt can still land
+-- after a retry was sent.
+CREATE TABLE IF NOT EXISTS pending_liquidation_attempts (
+    signature TEXT PRIMARY KEY,
+    account_id UUID NOT NULL,
+    market TEXT NOT NULL,
+    last_valid_block_height BIGINT NOT NULL,
+    FOREIGN KEY (account_id, market) REFERENCES pending_liquidations (account_id, market) ON DELETE CASCADE
+);
+
+-- Rows written before this kept only their latest attempt.
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'pending_liquidations' AND column_name = 'signature'
+    ) THEN
+        INSERT INTO pending_liquidation_attempts (signature, account_id, market, last_valid_block_height)
+        SELECT signature, account_id, market, last_valid_block_height FROM pending_liquidations
+        ON CONFLICT (signature) DO NOTHING;
+        ALTER TABLE pending_liquidations DROP COLUMN signature, DROP COLUMN last_valid_block_height;
+    END IF;
+END $$;
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS program_pause (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    paused BOOLEAN NOT NULL
+);
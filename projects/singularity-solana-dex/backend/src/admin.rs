@@ -0,0 +1,722 @@
+//! Admin market management: list, create, re-parameterize, change the status of and settle
+//! markets.
+//!
+//! Every change goes through the [`Registry`](crate::registry::Registry), so it is validated,
+//! refused if unsafe for open positions, applied to the risk engine and oracle, and written
+//! back to the registry file. Markets with an `oracle` entry are mirrored on-chain through the
+//! [`MarketChain`] before the backend commits: a failed transaction leaves nothing changed.
+
+use crate::config::OracleMarketConfig;
+use crate::errors::{AppError, RiskError};
+use crate::models::{MarketConfig, MarketStatus};
+use crate::registry::{MarketEntry, MarketRegistry, RegistryGuard};
+use crate::settlement::{settle_positions, SettlementReport};
+use crate::state::AppState;
+use crate::validation::ValidatedJson;
+use axum::extract::{Path, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Json;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+/// Stands in for a market account the chain has yet to create, so the rest of the entry can
+/// be checked before anything is sent.
+const PENDING_ACCOUNT: &str = "pending";
+
+/// Where admin changes to markets and the program config are mirrored on-chain.
+#[async_trait::async_trait]
+pub trait MarketChain: Send + Sync {
+    /// Sends `InitializeMarket`, which creates the market's PDA on first use. A
+    /// `market_account` other than that PDA is refused. Returns the market account and the
+    /// transaction signature.
+    async fn initialize_market(
+        &self,
+        market: &MarketConfig,
+        market_id: u16,
+        market_account: Option<&str>,
+    ) -> Result<(String, String), String>;
+
+    /// Sends `UpdateMarketParams` and returns the transaction signature.
+    async fn update_market_params(
+        &self,
+        market: &MarketConfig,
+        market_id: u16,
+        market_account: &str,
+    ) -> Result<String, String>;
+
+    /// Sends `SettleMarket` at the market's `settlement_price`.
+    async fn settle_market(&self, market: &MarketConfig, market_id: u16, market_account: &str) -> Result<String, String>;
+
+    /// Sends `SettlePosition` for one position of a settled market.
+    async fn settle_position(
+        &self,
+        market_id: u16,
+        market_account: &str,
+        account_state: &str,
+        position_account: &str,
+    ) -> Result<String, String>;
+
+    /// Sends `MigrateMarket`, moving a market account created at a keypair address to the
+    /// market's PDA; one created before markets had an admin gets the backend's keypair as its
+    /// admin. Returns the PDA and the transaction signature.
+    async fn migrate_market(&self, market_id: u16, market_account: &str) -> Result<(String, String), String>;
+
+    /// Sends `SetOracleAuthority`, handing the market's price updates to
+    /// `MARKET_ORACLE_AUTHORITY`.
+    async fn set_oracle_authority(&self, market_id: u16, market_account: &str) -> Result<String, String>;
+
+    /// The program config account; `None` until `InitializeConfig` has been sent.
+    async fn program_config(&self) -> Result<Option<ProgramConfig>, String>;
+
+    /// Sends `InitializeConfig`, making the backend's keypair the program admin.
+    async fn initialize_config(&self, guardian: &str) -> Result<String, String>;
+
+    async fn set_pause(&self, paused: bool) -> Result<String, String>;
+
+    async fn set_guardian(&self, guardian: &str) -> Result<String, String>;
+
+    async fn propose_admin(&self, new_admin: &str) -> Result<String, String>;
+
+    /// Sends `AcceptAdmin` signed by the backend's keypair, which must be the proposed admin.
+    async fn accept_admin(&self) -> Result<String, String>;
+}
+
+/// The program-wide settings kept on-chain.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ProgramConfig {
+    pub admin: String,
+    /// Waiting to accept the admin role.
+    pub pending_admin: Option<String>,
+    pub guardian: String,
+    pub paused: bool,
+}
+
+/// `ADMIN_API_KEY` and `GUARDIAN_API_KEY`, expected as `Authorization: Bearer <key>`. Admin
+/// routes are disabled without the admin key; the guardian key can only pause the program.
+#[derive(Clone)]
+pub struct AdminKeys {
+    admin: Option<Arc<str>>,
+    guardian: Option<Arc<str>>,
+}
+
+/// Which key authorized a request; set as a request extension by [`require_guardian`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Guardian,
+}
+
+impl AdminKeys {
+    pub fn from_env() -> Self {
+        let key = |name: &str| std::env::var(name).ok().filter(|key| !key.is_empty()).map(Arc::from);
+        Self {
+            admin: key("ADMIN_API_KEY"),
+            guardian: key("GUARDIAN_API_KEY"),
+        }
+    }
+
+    fn role(&self, request: &Request) -> Result<Role, AppError> {
+        if self.admin.is_none() && self.guardian.is_none() {
+            return Err(AppError::Unauthorized("admin API is disabled".to_string()));
+        }
+        let supplied = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        let matches = |key: &Option<Arc<str>>| {
+            key.as_ref()
+                .is_some_and(|key| constant_time_eq(supplied.as_bytes(), key.as_bytes()))
+        };
+        if matches(&self.admin) {
+            Ok(Role::Admin)
+        } else if matches(&self.guardian) {
+            Ok(Role::Guardian)
+        } else {
+            Err(AppError::Unauthorized("missing or wrong admin key".to_string()))
+        }
+    }
+}
+
+pub async fn require_admin(State(keys): State<AdminKeys>, request: Request, next: Next) -> Result<Response, AppError> {
+    match keys.role(&request)? {
+        Role::Admin => Ok(next.run(request).await),
+        Role::Guardian => Err(AppError::Unauthorized("the guardian key can only pause".to_string())),
+    }
+}
+
+/// Admits both the admin and the guardian key.
+pub async fn require_guardian(
+    State(keys): State<AdminKeys>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let role = keys.role(&request)?;
+    request.extensions_mut().insert(role);
+    Ok(next.run(request).await)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminMarket {
+    #[serde(flatten)]
+    pub market: MarketConfig,
+    /// On-chain market id, for markets with an oracle entry.
+    pub market_id: Option<u16>,
+    pub market_account: Option<String>,
+}
+
+impl From<&MarketEntry> for AdminMarket {
+    fn from(entry: &MarketEntry) -> Self {
+        Self {
+            market: entry.market.clone(),
+            market_id: entry.oracle.as_ref().map(|oracle| oracle.market_id),
+            market_account: entry.oracle.as_ref().map(|oracle| oracle.market_account.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminMarketResponse {
+    pub market: AdminMarket,
+    /// Signature of the on-chain transaction, when one was sent.
+    pub signature: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateMarketRequest {
+    #[serde(flatten)]
+    pub market: MarketConfig,
+    /// Oracle entry as in the market registry. Leave `market_account` empty to have the
+    /// backend create the market's PDA on-chain.
+    #[schema(value_type = Option<Object>)]
+    pub oracle: Option<OracleMarketConfig>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MarketStatusRequest {
+    /// Any status but `settled`, which goes through the settle endpoint.
+    pub status: MarketStatus,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SettleMarketRequest {
+    pub settlement_price: Decimal,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SettleMarketResponse {
+    pub market: AdminMarket,
+    /// `SettleMarket` transaction, when one was sent.
+    pub signature: Option<String>,
+    pub settlement: SettlementReport,
+}
+
+/// Fields left out keep their current value.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateMarketRequest {
+    pub max_leverage_bps: Option<u32>,
+    pub initial_margin_bps: Option<u32>,
+    pub maintenance_margin_bps: Option<u32>,
+    pub max_open_interest: Option<Decimal>,
+    pub tick_size: Option<Decimal>,
+    pub lot_size: Option<Decimal>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/markets",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses((status = 200, body = Vec<AdminMarket>), (status = 401, body = ErrorResponse))
+)]
+pub async fn list_markets(State(state): State<Arc<AppState>>) -> Json<Vec<AdminMarket>> {
+    let registry = state.registry.lock().await;
+    let mut markets: Vec<AdminMarket> = registry.current().markets.iter().map(AdminMarket::from).collect();
+    markets.sort_by(|a, b| a.market.symbol.cmp(&b.market.symbol));
+    Json(markets)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body = CreateMarketRequest,
+    responses(
+        (status = 200, body = AdminMarketResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Invalid market; `details.problems` lists every problem"),
+        (status = 502, body = ErrorResponse, description = "The on-chain transaction failed; nothing was changed")
+    )
+)]
+pub async fn create_market(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<CreateMarketRequest>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    let mut market = payload.market;
+    market.symbol = market.symbol.to_uppercase();
+    let symbol = market.symbol.clone();
+    let oracle = payload.oracle.map(|oracle| OracleMarketConfig {
+        symbol: symbol.clone(),
+        ..oracle
+    });
+
+    let mut registry = state.registry.lock().await;
+    if registry.current().entry(&symbol).is_some() {
+        return Err(AppError::MarketExists(symbol));
+    }
+    let mut next = registry.current().clone();
+    next.markets.push(MarketEntry { market, oracle });
+
+    let creates_account = state.market_chain.is_some()
+        && next
+            .entry(&symbol)
+            .and_then(|entry| entry.oracle.as_ref())
+            .is_some_and(|oracle| oracle.market_account.is_empty());
+    let mut checked = next.clone();
+    if creates_account {
+        if let Some(oracle) = checked.entry_mut(&symbol).and_then(|entry| entry.oracle.as_mut()) {
+            oracle.market_account = PENDING_ACCOUNT.to_string();
+        }
+    }
+    registry.check(&state, &checked).await?;
+
+    let mut signature = None;
+    if let (Some(chain), Some(entry)) = (&state.market_chain, next.entry_mut(&symbol)) {
+        if let Some(oracle) = entry.oracle.as_mut() {
+            let existing = (!oracle.market_account.is_empty()).then_some(oracle.market_account.as_str());
+            let (account, sig) = chain
+                .initialize_market(&entry.market, oracle.market_id, existing)
+                .await
+                .map_err(AppError::Chain)?;
+            oracle.market_account = account;
+            signature = Some(sig);
+        }
+    }
+
+    commit(&state, &mut registry, next, &symbol, signature).await.map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    request_body = UpdateMarketRequest,
+    responses(
+        (status = 200, body = AdminMarketResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse, description = "The change would make open positions liquidatable"),
+        (status = 422, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "The on-chain transaction failed; nothing was changed")
+    )
+)]
+pub async fn update_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+    ValidatedJson(payload): ValidatedJson<UpdateMarketRequest>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    edit_market(&state, &symbol, |market| {
+        if let Some(value) = payload.max_leverage_bps {
+            market.max_leverage_bps = value;
+        }
+        if let Some(value) = payload.initial_margin_bps {
+            market.initial_margin_bps = value;
+        }
+        if let Some(value) = payload.maintenance_margin_bps {
+            market.maintenance_margin_bps = value;
+        }
+        if let Some(value) = payload.max_open_interest {
+            market.max_open_interest = value;
+        }
+        if let Some(value) = payload.tick_size {
+            market.tick_size = value;
+        }
+        if let Some(value) = payload.lot_size {
+            market.lot_size = value;
+        }
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}/pause",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    responses(
+        (status = 200, body = AdminMarketResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+pub async fn pause_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    edit_market(&state, &symbol, |market| market.status = MarketStatus::Paused).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}/resume",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    responses(
+        (status = 200, body = AdminMarketResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+pub async fn resume_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    edit_market(&state, &symbol, |market| market.status = MarketStatus::Active).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}/status",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    request_body = MarketStatusRequest,
+    responses(
+        (status = 200, body = AdminMarketResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse, description = "The market is settled"),
+        (status = 422, body = ErrorResponse),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+pub async fn set_market_status(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+    ValidatedJson(payload): ValidatedJson<MarketStatusRequest>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    edit_market(&state, &symbol, |market| market.status = payload.status).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}/settle",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    request_body = SettleMarketRequest,
+    responses(
+        (status = 200, body = SettleMarketResponse, description = "The market is settled; `settlement.failed` lists positions left open, which a repeated call retries"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse, description = "Already settled at another price"),
+        (status = 422, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "`SettleMarket` failed; nothing was changed")
+    )
+)]
+pub async fn settle_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+    ValidatedJson(payload): ValidatedJson<SettleMarketRequest>,
+) -> Result<Json<SettleMarketResponse>, AppError> {
+    let symbol = symbol.to_uppercase();
+    let mut registry = state.registry.lock().await;
+    let mut next = registry.current().clone();
+    let entry = next
+        .entry_mut(&symbol)
+        .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?;
+
+    // Settling again at the same price only retries the positions still open.
+    let mut signature = None;
+    if entry.market.status == MarketStatus::Settled {
+        if entry.market.settlement_price != Some(payload.settlement_price) {
+            return Err(RiskError::MarketSettled(symbol).into());
+        }
+    } else {
+        entry.market.status = MarketStatus::Settled;
+        entry.market.settlement_price = Some(payload.settlement_price);
+        registry.check(&state, &next).await?;
+        if let (Some(chain), Some(entry)) = (&state.market_chain, next.entry(&symbol)) {
+            if let Some(oracle) = &entry.oracle {
+                let sig = chain
+                    .settle_market(&entry.market, oracle.market_id, &oracle.market_account)
+                    .await
+                    .map_err(AppError::Chain)?;
+                signature = Some(sig);
+            }
+        }
+        commit(&state, &mut registry, next, &symbol, signature.clone()).await?;
+    }
+
+    let entry = registry
+        .current()
+        .entry(&symbol)
+        .cloned()
+        .ok_or_else(|| AppError::Internal(format!("{} missing after commit", symbol)))?;
+    drop(registry);
+    let settlement = settle_positions(&state, &entry).await?;
+    info!(market = %symbol, settled = settlement.settled.len(), failed = settlement.failed.len(), "market settled");
+    Ok(Json(SettleMarketResponse {
+        market: AdminMarket::from(&entry),
+        signature,
+        settlement,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}/migrate",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    responses(
+        (status = 200, body = AdminMarketResponse, description = "The market now lives at its PDA, recorded as its `market_account`; a legacy one has the backend's keypair as its admin"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse, description = "Unknown market, or one without an `oracle` entry"),
+        (status = 501, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "`MigrateMarket` failed, e.g. the market already is at its PDA or the keypair is neither its admin, for a legacy market its oracle authority, nor the program admin")
+    )
+)]
+pub async fn migrate_market(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    let symbol = symbol.to_uppercase();
+    let chain = state.market_chain.as_ref().ok_or(AppError::ChainNotConfigured)?;
+    let mut registry = state.registry.lock().await;
+    let mut next = registry.current().clone();
+    let oracle = next
+        .entry_mut(&symbol)
+        .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?
+        .oracle
+        .as_mut()
+        .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?;
+    let (account, signature) = chain
+        .migrate_market(oracle.market_id, &oracle.market_account)
+        .await
+        .map_err(AppError::Chain)?;
+    info!(market = %symbol, %account, %signature, "market account migrated");
+    oracle.market_account = account;
+    commit(&state, &mut registry, next, &symbol, Some(signature)).await.map(Json)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/markets/{symbol}/oracle-authority",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("symbol" = String, Path, description = "Market symbol")),
+    responses(
+        (status = 200, body = AdminMarketResponse, description = "`MARKET_ORACLE_AUTHORITY` now signs the market's price updates"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse, description = "Unknown market, or one without an `oracle` entry"),
+        (status = 501, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "`SetOracleAuthority` failed, e.g. the backend's keypair isn't the market's admin")
+    )
+)]
+pub async fn set_oracle_authority(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    let symbol = symbol.to_uppercase();
+    let chain = state.market_chain.as_ref().ok_or(AppError::ChainNotConfigured)?;
+    let registry = state.registry.lock().await;
+    let entry = registry
+        .current()
+        .entry(&symbol)
+        .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?;
+    let oracle = entry
+        .oracle
+        .as_ref()
+        .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?;
+    let signature = chain
+        .set_oracle_authority(oracle.market_id, &oracle.market_account)
+        .await
+        .map_err(AppError::Chain)?;
+    info!(market = %symbol, %signature, "oracle authority set");
+    Ok(Json(AdminMarketResponse {
+        market: AdminMarket::from(entry),
+        signature: Some(signature),
+    }))
+}
+
+/// Applies `edit` to the market's registry entry, sending `UpdateMarketParams` first when a
+/// parameter the program keeps changed.
+async fn edit_market(
+    state: &AppState,
+    symbol: &str,
+    edit: impl FnOnce(&mut MarketConfig),
+) -> Result<Json<AdminMarketResponse>, AppError> {
+    let symbol = symbol.to_uppercase();
+    let mut registry = state.registry.lock().await;
+    let mut next = registry.current().clone();
+    let entry = next
+        .entry_mut(&symbol)
+        .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?;
+    if entry.market.status == MarketStatus::Settled {
+        return Err(RiskError::MarketSettled(symbol).into());
+    }
+    let before = entry.market.clone();
+    edit(&mut entry.market);
+    let onchain_changed = before.max_leverage_bps != entry.market.max_leverage_bps
+        || before.initial_margin_bps != entry.market.initial_margin_bps
+        || before.maintenance_margin_bps != entry.market.maintenance_margin_bps
+        || before.status != entry.market.status;
+    registry.check(state, &next).await?;
+
+    let mut signature = None;
+    if let (Some(chain), Some(entry), true) = (&state.market_chain, next.entry(&symbol), onchain_changed) {
+        if let Some(oracle) = &entry.oracle {
+            let sig = chain
+                .update_market_params(&entry.market, oracle.market_id, &oracle.market_account)
+                .await
+                .map_err(AppError::Chain)?;
+            signature = Some(sig);
+        }
+    }
+
+    commit(state, &mut registry, next, &symbol, signature).await.map(Json)
+}
+
+async fn commit(
+    state: &AppState,
+    registry: &mut RegistryGuard<'_>,
+    next: MarketRegistry,
+    symbol: &str,
+    signature: Option<String>,
+) -> Result<AdminMarketResponse, AppError> {
+    let diff = match registry.commit(state, next, true).await {
+        Ok(diff) => diff,
+        Err(err) => {
+            // Positions moved between the check and the commit; the chain is already ahead.
+            if let Some(signature) = &signature {
+                error!(market = %symbol, %signature, error = %err, "market changed on-chain but not in the backend");
+            }
+            return Err(err.into());
+        }
+    };
+    info!(market = %symbol, %diff, signature = signature.as_deref().unwrap_or("-"), "market registry changed by admin");
+    let entry = registry
+        .current()
+        .entry(symbol)
+        .ok_or_else(|| AppError::Internal(format!("{} missing after commit", symbol)))?;
+    Ok(AdminMarketResponse {
+        market: AdminMarket::from(entry),
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{admin_request, app, send, state};
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    async fn post(uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = admin_request(Method::POST, uri, body);
+        // The router reads the admin key as it is built, so build it after the request.
+        let (status, _, body) = send(&app(&state()), request).await;
+        (status, body)
+    }
+
+    fn fields(body: &Value) -> Vec<&str> {
+        body["details"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["field"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_are_validation_errors() {
+        let (status, body) = post("/admin/markets/BTC/status", json!({ "status": "closed" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "VALIDATION_FAILED");
+        assert_eq!(fields(&body), ["body"]);
+
+        let (status, body) = post("/admin/markets/BTC/settle", json!({})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "VALIDATION_FAILED");
+    }
+
+    #[tokio::test]
+    async fn settled_status_points_at_the_settle_endpoint() {
+        let (status, body) = post("/admin/markets/BTC/status", json!({ "status": "settled" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&body), ["status"]);
+    }
+
+    #[tokio::test]
+    async fn settlement_price_must_be_positive_and_on_tick() {
+        let (status, body) = post("/admin/markets/BTC/settle", json!({ "settlement_price": "-1" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&body), ["settlement_price"]);
+
+        let (status, body) = post("/admin/markets/BTC/settle", json!({ "settlement_price": "67000.000001" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&body), ["settlement_price"]);
+    }
+
+    #[tokio::test]
+    async fn update_rejects_every_bad_field_at_once() {
+        let (status, body) = post(
+            "/admin/markets/BTC",
+            json!({ "max_leverage_bps": 0, "tick_size": "0", "lot_size": "-0.1" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&body), ["max_leverage_bps", "tick_size", "lot_size"]);
+    }
+
+    #[tokio::test]
+    async fn program_keys_must_look_like_pubkeys() {
+        let (status, body) = post("/admin/program/guardian", json!({ "guardian": "not-a-key" })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&body), ["guardian"]);
+
+        let (status, body) = post("/admin/program/propose-admin", json!({ "new_admin": "0".repeat(44) })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&body), ["new_admin"]);
+
+        // A well-formed key gets past validation to the missing on-chain admin.
+        let (status, _) = post(
+            "/admin/program/guardian",
+            json!({ "guardian": "11111111111111111111111111111111" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[tokio::test]
+    async fn migrating_needs_the_chain() {
+        let (status, body) = post("/admin/markets/BTC/migrate", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(body["code"], "CHAIN_NOT_CONFIGURED");
+    }
+
+    #[tokio::test]
+    async fn handing_over_the_oracle_needs_the_chain() {
+        let (status, body) = post("/admin/markets/BTC/oracle-authority", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(body["code"], "CHAIN_NOT_CONFIGURED");
+    }
+}
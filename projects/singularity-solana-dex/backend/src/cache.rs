@@ -0,0 +1,320 @@
+use crate::errors::UpstreamError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Slots beyond this are pruned of entries nobody is waiting on and that are too old to serve.
+const MAX_ENTRIES: usize = 1024;
+
+/// Upstream response cache keyed by request. Concurrent misses for the same key share one
+/// upstream call, and when a refresh fails the last good value is served for up to `max_stale`.
+pub struct TtlCache<V> {
+    ttl: Duration,
+    max_stale: Duration,
+    slots: Mutex<HashMap<String, Arc<Slot<V>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    stale_served: AtomicU64,
+    errors: AtomicU64,
+}
+
+struct Slot<V> {
+    state: Mutex<SlotState<V>>,
+    /// Held for the duration of an upstream fetch.
+    flight: tokio::sync::Mutex<()>,
+}
+
+struct SlotState<V> {
+    value: Option<(V, Instant)>,
+    /// The last failed refresh, remembered for one TTL so waiters don't retry it in turn.
+    failure: Option<(UpstreamError, Instant)>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub ttl_ms: u64,
+    pub entries: usize,
+    /// Served from a fresh entry.
+    pub hits: u64,
+    /// Went upstream.
+    pub misses: u64,
+    /// Waited for another request's upstream call and shared its result.
+    pub coalesced: u64,
+    /// Served an expired entry because the upstream call failed.
+    pub stale_served: u64,
+    /// Upstream failed with nothing cached to fall back on.
+    pub errors: u64,
+}
+
+enum Lookup<V> {
+    Fresh(V),
+    /// The last refresh failed within the TTL and an older value is still servable.
+    Stale(V, UpstreamError),
+    Failed(UpstreamError),
+    Expired,
+}
+
+impl<V: Clone> Slot<V> {
+    fn lookup(&self, ttl: Duration, max_stale: Duration) -> Lookup<V> {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((value, fetched_at)) = &state.value {
+            if fetched_at.elapsed() < ttl {
+                return Lookup::Fresh(value.clone());
+            }
+        }
+        if let Some((err, failed_at)) = &state.failure {
+            if failed_at.elapsed() < ttl {
+                return match &state.value {
+                    Some((value, fetched_at)) if fetched_at.elapsed() < max_stale => {
+                        Lookup::Stale(value.clone(), err.clone())
+                    }
+                    _ => Lookup::Failed(err.clone()),
+                };
+            }
+        }
+        Lookup::Expired
+    }
+
+    fn stale(&self, max_stale: Duration) -> Option<V> {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state
+            .value
+            .as_ref()
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < max_stale)
+            .map(|(value, _)| value.clone())
+    }
+
+    fn expired_for(&self, age: Duration) -> bool {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let last_touched = match (&state.value, &state.failure) {
+            (Some((_, fetched_at)), _) => *fetched_at,
+            (None, Some((_, failed_at))) => *failed_at,
+            (None, None) => return false,
+        };
+        last_touched.elapsed() >= age
+    }
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration, max_stale: Duration) -> Self {
+        Self {
+            ttl,
+            max_stale: max_stale.max(ttl),
+            slots: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            stale_served: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<V, UpstreamError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, UpstreamError>>,
+    {
+        self.lookup_or_fetch(key, fetch, true).await
+    }
+
+    /// Like [`Self::get_or_fetch`], but fails rather than serve a value older than the TTL,
+    /// for callers that act on the value rather than display it.
+    pub async fn get_fresh_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<V, UpstreamError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, UpstreamError>>,
+    {
+        self.lookup_or_fetch(key, fetch, false).await
+    }
+
+    async fn lookup_or_fetch<F, Fut>(&self, key: &str, fetch: F, serve_stale: bool) -> Result<V, UpstreamError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, UpstreamError>>,
+    {
+        let slot = self.slot(key);
+        let mut waited = false;
+        let _flight = loop {
+            match slot.lookup(self.ttl, self.max_stale) {
+                Lookup::Fresh(value) => {
+                    let counter = if waited { &self.coalesced } else { &self.hits };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Lookup::Stale(value, _) if serve_stale => {
+                    self.stale_served.fetch_add(1, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Lookup::Stale(_, err) | Lookup::Failed(err) => {
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                    return Err(err);
+                }
+                Lookup::Expired if waited => break slot.flight.lock().await,
+                // Whoever holds the flight may refresh the entry for us; look again once it's free.
+                Lookup::Expired => match slot.flight.try_lock() {
+                    Ok(guard) => break guard,
+                    Err(_) => {
+                        drop(slot.flight.lock().await);
+                        waited = true;
+                    }
+                },
+            }
+        };
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        match fetch().await {
+            Ok(value) => {
+                let mut state = slot.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                state.value = Some((value.clone(), Instant::now()));
+                state.failure = None;
+                Ok(value)
+            }
+            Err(err) => {
+                slot.state
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .failure = Some((err.clone(), Instant::now()));
+                match slot.stale(self.max_stale).filter(|_| serve_stale) {
+                    Some(value) => {
+                        warn!(key, error = %err, "upstream refresh failed, serving cached value");
+                        self.stale_served.fetch_add(1, Ordering::Relaxed);
+                        Ok(value)
+                    }
+                    None => {
+                        self.errors.fetch_add(1, Ordering::Relaxed);
+                        Err(err)
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            ttl_ms: self.ttl.as_millis() as u64,
+            entries: self.slots.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            stale_served: self.stale_served.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    fn slot(&self, key: &str) -> Arc<Slot<V>> {
+        let mut slots = self.slots.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(slot) = slots.get(key) {
+            return slot.clone();
+        }
+        if slots.len() >= MAX_ENTRIES {
+            let max_stale = self.max_stale;
+            slots.retain(|_, slot| Arc::strong_count(slot) > 1 || !slot.expired_for(max_stale));
+        }
+        let slot = Arc::new(Slot {
+            state: Mutex::new(SlotState {
+                value: None,
+                failure: None,
+            }),
+            flight: tokio::sync::Mutex::new(()),
+        });
+        slots.insert(key.to_string(), slot.clone());
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::join_all;
+    use std::sync::atomic::AtomicUsize;
+
+    const TTL: Duration = Duration::from_millis(50);
+
+    fn down() -> UpstreamError {
+        UpstreamError::Unavailable {
+            service: "test".to_string(),
+            reason: "down".to_string(),
+        }
+    }
+
+    /// Counts its calls and answers with `result`.
+    async fn fetch(calls: &AtomicUsize, result: Result<u32, UpstreamError>) -> Result<u32, UpstreamError> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        result
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_fetch() {
+        let cache = TtlCache::new(TTL, Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        let results = join_all((0..8).map(|_| cache.get_or_fetch("BTC", || fetch(&calls, Ok(1))))).await;
+        assert!(results.into_iter().all(|result| result.unwrap() == 1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.misses, stats.coalesced, stats.hits), (1, 7, 0));
+    }
+
+    #[tokio::test]
+    async fn entries_expire_after_the_ttl() {
+        let cache = TtlCache::new(TTL, Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Ok(1))).await.unwrap(), 1);
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Ok(2))).await.unwrap(), 1);
+        tokio::time::sleep(TTL * 2).await;
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Ok(2))).await.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn the_last_value_is_served_while_refreshes_fail() {
+        let cache = TtlCache::new(TTL, TTL * 4);
+        let calls = AtomicUsize::new(0);
+        cache.get_or_fetch("BTC", || fetch(&calls, Ok(1))).await.unwrap();
+        tokio::time::sleep(TTL * 2).await;
+
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Err(down()))).await.unwrap(), 1);
+        // Within the failure's TTL the stale value is served without another call.
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Err(down()))).await.unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.stats().stale_served, 2);
+        // Callers that need a current value get the failure instead.
+        assert!(cache.get_fresh_or_fetch("BTC", || fetch(&calls, Ok(3))).await.is_err());
+
+        // Past `max_stale` the value is gone.
+        tokio::time::sleep(TTL * 3).await;
+        assert!(cache.get_or_fetch("BTC", || fetch(&calls, Err(down()))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failures_are_remembered_for_one_ttl() {
+        let cache = TtlCache::new(TTL, Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        assert!(cache.get_or_fetch("BTC", || fetch(&calls, Err(down()))).await.is_err());
+        assert!(cache.get_or_fetch("BTC", || fetch(&calls, Ok(1))).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(TTL * 2).await;
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Ok(1))).await.unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.stats().errors, 2);
+    }
+
+    #[tokio::test]
+    async fn fresh_lookups_refuse_a_stale_fallback() {
+        let cache = TtlCache::new(TTL, Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        cache.get_fresh_or_fetch("BTC", || fetch(&calls, Ok(1))).await.unwrap();
+        assert_eq!(cache.get_fresh_or_fetch("BTC", || fetch(&calls, Ok(2))).await.unwrap(), 1);
+        tokio::time::sleep(TTL * 2).await;
+        assert!(cache.get_fresh_or_fetch("BTC", || fetch(&calls, Err(down()))).await.is_err());
+        assert_eq!(cache.get_or_fetch("BTC", || fetch(&calls, Ok(2))).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,574 @@
+//! OHLCV candles built from trades and published price updates, persisted through the
+//! [`Store`](crate::db::Store) and served in the TradingView UDF `history` format. Trades come
+//! from the price feed's trade stream when its first provider streams (`binance_ws`), so
+//! every trade is counted once; otherwise the latest trades of each market are polled.
+//!
+//! The aggregator only holds what changed since the last flush: each flush folds those
+//! partial candles into the stored ones (see [`Candle::merge`]), so restarts and late trades
+//! never overwrite a candle with a partial one.
+
+use crate::errors::{AppError, RiskError};
+use crate::events::Event;
+use crate::price_feed::{SymbolTrade, Trade};
+use crate::state::AppState;
+use axum::extract::{Query, State};
+use axum::Json;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+/// Trades requested per market on every poll.
+const TRADE_BATCH: usize = 100;
+/// Largest number of bars one `/candles` request may span.
+const MAX_BARS: i64 = 5_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+    OneDay,
+}
+
+impl Resolution {
+    pub const ALL: [Resolution; 4] = [
+        Resolution::OneMinute,
+        Resolution::FiveMinutes,
+        Resolution::OneHour,
+        Resolution::OneDay,
+    ];
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            Resolution::OneMinute => 60,
+            Resolution::FiveMinutes => 300,
+            Resolution::OneHour => 3_600,
+            Resolution::OneDay => 86_400,
+        }
+    }
+
+    /// UDF resolution string, also the stored form.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Resolution::OneMinute => "1",
+            Resolution::FiveMinutes => "5",
+            Resolution::OneHour => "60",
+            Resolution::OneDay => "1D",
+        }
+    }
+
+    /// Accepts the UDF strings (`1`, `5`, `60`, `1D`/`D`) and `1m`, `5m`, `1h`, `1d`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_uppercase().as_str() {
+            "1" | "1M" => Some(Resolution::OneMinute),
+            "5" | "5M" => Some(Resolution::FiveMinutes),
+            "60" | "1H" => Some(Resolution::OneHour),
+            "D" | "1D" | "1440" => Some(Resolution::OneDay),
+            _ => None,
+        }
+    }
+
+    /// Start of the bar containing `time` (unix seconds).
+    fn bucket(self, time: i64) -> i64 {
+        time - time.rem_euclid(self.seconds())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Candle {
+    pub symbol: String,
+    pub resolution: Resolution,
+    /// Unix seconds, aligned to the resolution.
+    pub open_time: i64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+}
+
+impl Candle {
+    fn new(symbol: &str, resolution: Resolution, open_time: i64, price: Decimal, volume: Decimal) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            resolution,
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume,
+        }
+    }
+
+    /// Folds in ticks that came after this candle's: the open is kept, the range widened,
+    /// the close replaced and volumes added.
+    pub fn merge(&mut self, later: &Candle) {
+        self.high = self.high.max(later.high);
+        self.low = self.low.min(later.low);
+        self.close = later.close;
+        self.volume += later.volume;
+    }
+}
+
+/// Symbol, resolution and open time.
+type CandleKey = (String, Resolution, i64);
+
+#[derive(Default)]
+struct Pending {
+    candles: HashMap<CandleKey, Candle>,
+    /// Newest trade time seen per symbol and how many trades carried it, so overlapping
+    /// polls don't count a trade twice.
+    trade_marks: HashMap<String, (u64, usize)>,
+}
+
+pub struct CandleAggregator {
+    pending: Mutex<Pending>,
+    /// Trades older than this were counted by a previous run, if at all.
+    started_at_ms: u64,
+}
+
+impl CandleAggregator {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(Pending::default()),
+            started_at_ms: unix_millis(),
+        }
+    }
+
+    /// Adds a tick to every resolution; price updates without a trade carry zero volume.
+    pub fn record(&self, symbol: &str, price: Decimal, volume: Decimal, time_ms: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        record_tick(&mut pending, symbol, price, volume, time_ms);
+    }
+
+    /// Adds the trades (oldest first) not seen by an earlier call.
+    pub fn record_trades(&self, symbol: &str, trades: &[Trade]) {
+        let Some(last) = trades.last() else {
+            return;
+        };
+        let mut pending = self.pending.lock().unwrap();
+        let (mark, seen) = pending
+            .trade_marks
+            .get(symbol)
+            .copied()
+            .unwrap_or((self.started_at_ms, 0));
+        let mut at_mark = 0;
+        for trade in trades {
+            if trade.time < mark {
+                continue;
+            }
+            if trade.time == mark {
+                at_mark += 1;
+                if at_mark <= seen {
+                    continue;
+                }
+            }
+            record_tick(&mut pending, symbol, trade.price, trade.qty, trade.time);
+        }
+        let mark = if last.time == mark {
+            (mark, at_mark.max(seen))
+        } else if last.time > mark {
+            (last.time, trades.iter().filter(|trade| trade.time == last.time).count())
+        } else {
+            (mark, seen)
+        };
+        pending.trade_marks.insert(symbol.to_string(), mark);
+    }
+
+    /// Unflushed candles for one series opening in `from..to`.
+    pub fn pending(&self, symbol: &str, resolution: Resolution, from: i64, to: i64) -> Vec<Candle> {
+        let pending = self.pending.lock().unwrap();
+        pending
+            .candles
+            .values()
+            .filter(|candle| {
+                candle.symbol == symbol
+                    && candle.resolution == resolution
+                    && candle.open_time >= from
+                    && candle.open_time < to
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn take(&self) -> Vec<Candle> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.candles.drain().map(|(_, candle)| candle).collect()
+    }
+
+    /// Puts back candles a failed flush took, ahead of anything recorded since.
+    fn restore(&self, candles: Vec<Candle>) {
+        let mut pending = self.pending.lock().unwrap();
+        for mut candle in candles {
+            let key = (candle.symbol.clone(), candle.resolution, candle.open_time);
+            if let Some(later) = pending.candles.get(&key) {
+                candle.merge(later);
+            }
+            pending.candles.insert(key, candle);
+        }
+    }
+}
+
+fn record_tick(pending: &mut Pending, symbol: &str, price: Decimal, volume: Decimal, time_ms: u64) {
+    let time = (time_ms / 1_000) as i64;
+    for resolution in Resolution::ALL {
+        let open_time = resolution.bucket(time);
+        let tick = Candle::new(symbol, resolution, open_time, price, volume);
+        pending
+            .candles
+            .entry((symbol.to_string(), resolution, open_time))
+            .and_modify(|candle| candle.merge(&tick))
+            .or_insert(tick);
+    }
+}
+
+/// How often trades are polled and candles written out.
+pub struct CandleConfig {
+    /// `None` disables trade polling; candles then come from price updates alone. Unused when
+    /// the price feed streams trades.
+    pub trade_poll: Option<Duration>,
+    pub flush: Duration,
+}
+
+impl CandleConfig {
+    /// Reads `CANDLE_TRADE_POLL_MS` (default 10000, `0` disables trade polling) and
+    /// `CANDLE_FLUSH_MS` (5000).
+    pub fn from_env() -> Self {
+        let millis = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|val| val.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        let trade_poll = millis("CANDLE_TRADE_POLL_MS", 10_000);
+        Self {
+            trade_poll: (trade_poll > 0).then(|| Duration::from_millis(trade_poll)),
+            flush: Duration::from_millis(millis("CANDLE_FLUSH_MS", 5_000).max(1)),
+        }
+    }
+}
+
+/// Feeds `state.candles` from price events on the bus and from the feed's trade stream, or
+/// when it has none and polling is enabled, from polled trades of every listed market, and
+/// flushes it to the store periodically.
+pub fn spawn(state: Arc<AppState>, config: CandleConfig) {
+    let events_state = state.clone();
+    tokio::spawn(async move {
+        let mut events = events_state.events.subscribe_market_data();
+        loop {
+            match events.recv().await {
+                Ok(envelope) => {
+                    if let Event::Price { symbol, price } = &envelope.data {
+                        // A market delisted since the event was published gets no more candles.
+                        if events_state.risk.market(symbol).is_some() {
+                            events_state.candles.record(symbol, *price, Decimal::ZERO, unix_millis());
+                        }
+                    }
+                }
+                // Missing a few ticks only narrows a candle's range slightly.
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    if let Some(trades) = state.prices.subscribe_trades() {
+        tokio::spawn(record_streamed_trades(state.clone(), trades));
+    } else if let Some(period) = config.trade_poll {
+        let trades_state = state.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                // Re-read every poll so markets added by a registry reload get candles too.
+                let symbols: Vec<String> = trades_state
+                    .risk
+                    .markets()
+                    .into_iter()
+                    .map(|market| market.symbol)
+                    .collect();
+                for symbol in &symbols {
+                    // Provider failures are already logged by the price feed.
+                    if let Ok(trades) = trades_state.prices.fetch_trades(symbol, TRADE_BATCH).await {
+                        trades_state.candles.record_trades(symbol, &trades);
+                    }
+                }
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = interval(config.flush);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let candles = state.candles.take();
+            if candles.is_empty() {
+                continue;
+            }
+            if let Err(err) = state.store.merge_candles(&candles).await {
+                warn!(error = %err, count = candles.len(), "failed to persist candles");
+                state.candles.restore(candles);
+            }
+        }
+    });
+}
+
+async fn record_streamed_trades(state: Arc<AppState>, mut trades: broadcast::Receiver<SymbolTrade>) {
+    loop {
+        match trades.recv().await {
+            Ok(SymbolTrade { symbol, trade }) => {
+                if state.risk.market(&symbol).is_some() {
+                    state.candles.record(&symbol, trade.price, trade.qty, trade.time);
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "candle aggregation fell behind the trade stream; volume undercounted");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct CandlesQuery {
+    symbol: String,
+    /// `1`, `5`, `60` or `1D` (also `1m`, `5m`, `1h`, `1d`).
+    resolution: String,
+    /// Unix seconds, inclusive.
+    from: i64,
+    /// Unix seconds, exclusive.
+    to: i64,
+}
+
+/// TradingView UDF `history` response: parallel arrays of bar open time (unix seconds),
+/// open, high, low, close and volume. `s` is `no_data` when the range has no bars, with
+/// `nextTime` pointing at the latest earlier bar if there is one.
+#[derive(Serialize, ToSchema)]
+pub struct CandlesResponse {
+    s: &'static str,
+    t: Vec<i64>,
+    o: Vec<f64>,
+    h: Vec<f64>,
+    l: Vec<f64>,
+    c: Vec<f64>,
+    v: Vec<f64>,
+    #[serde(rename = "nextTime", skip_serializing_if = "Option::is_none")]
+    next_time: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/candles",
+    tag = "markets",
+    params(CandlesQuery),
+    responses(
+        (status = 200, description = "Bars in TradingView UDF history format", body = CandlesResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 422, body = ErrorResponse)
+    )
+)]
+/// OHLCV bars for one market, aggregated from trades and price updates.
+pub async fn get_candles(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<CandlesQuery>,
+) -> Result<Json<CandlesResponse>, AppError> {
+    let symbol = params.symbol.trim().to_uppercase();
+    if state.risk.market(&symbol).is_none() {
+        return Err(RiskError::MarketNotFound(symbol).into());
+    }
+    let resolution = Resolution::parse(&params.resolution).ok_or_else(|| AppError::InvalidParameter {
+        name: "resolution",
+        reason: "expected one of 1, 5, 60, 1D".to_string(),
+    })?;
+    if params.from >= params.to {
+        return Err(AppError::InvalidParameter {
+            name: "from",
+            reason: "must be before `to`".to_string(),
+        });
+    }
+    if (params.to - params.from) / resolution.seconds() > MAX_BARS {
+        return Err(AppError::InvalidParameter {
+            name: "to",
+            reason: format!("range spans more than {} bars", MAX_BARS),
+        });
+    }
+
+    let from = resolution.bucket(params.from);
+    let mut bars: BTreeMap<i64, Candle> = state
+        .store
+        .load_candles(&symbol, resolution, from, params.to)
+        .await?
+        .into_iter()
+        .map(|candle| (candle.open_time, candle))
+        .collect();
+    for candle in state.candles.pending(&symbol, resolution, from, params.to) {
+        bars.entry(candle.open_time)
+            .and_modify(|stored| stored.merge(&candle))
+            .or_insert(candle);
+    }
+
+    let mut response = CandlesResponse {
+        s: "ok",
+        t: Vec::with_capacity(bars.len()),
+        o: Vec::with_capacity(bars.len()),
+        h: Vec::with_capacity(bars.len()),
+        l: Vec::with_capacity(bars.len()),
+        c: Vec::with_capacity(bars.len()),
+        v: Vec::with_capacity(bars.len()),
+        next_time: None,
+    };
+    if bars.is_empty() {
+        response.s = "no_data";
+        response.next_time = state.store.last_candle_before(&symbol, resolution, from).await?;
+        return Ok(Json(response));
+    }
+    let float = |value: Decimal| value.to_f64().unwrap_or_default();
+    for candle in bars.into_values() {
+        response.t.push(candle.open_time);
+        response.o.push(float(candle.open));
+        response.h.push(float(candle.high));
+        response.l.push(float(candle.low));
+        response.c.push(float(candle.close));
+        response.v.push(float(candle.volume));
+    }
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::state;
+
+    fn aggregator() -> CandleAggregator {
+        CandleAggregator {
+            pending: Mutex::new(Pending::default()),
+            started_at_ms: 0,
+        }
+    }
+
+    fn trade(price: i64, qty: i64, time_ms: u64) -> Trade {
+        Trade {
+            price: Decimal::from(price),
+            qty: Decimal::from(qty),
+            time: time_ms,
+            side: "buy".to_string(),
+        }
+    }
+
+    /// `(open time, open, high, low, close, volume)` of every pending candle in the series.
+    fn bars(candles: &CandleAggregator, resolution: Resolution) -> Vec<(i64, i64, i64, i64, i64, i64)> {
+        let mut bars: Vec<_> = candles
+            .pending("BTC", resolution, 0, i64::MAX)
+            .into_iter()
+            .map(|candle| {
+                let int = |value: Decimal| value.to_i64().unwrap();
+                (
+                    candle.open_time,
+                    int(candle.open),
+                    int(candle.high),
+                    int(candle.low),
+                    int(candle.close),
+                    int(candle.volume),
+                )
+            })
+            .collect();
+        bars.sort();
+        bars
+    }
+
+    #[test]
+    fn ticks_roll_over_into_the_next_bucket() {
+        let candles = aggregator();
+        candles.record("BTC", Decimal::from(100), Decimal::from(1), 59_999);
+        candles.record("BTC", Decimal::from(105), Decimal::from(2), 30_000);
+        candles.record("BTC", Decimal::from(98), Decimal::from(3), 60_000);
+        candles.record("BTC", Decimal::from(99), Decimal::ZERO, 299_999);
+        candles.record("BTC", Decimal::from(101), Decimal::from(1), 300_000);
+
+        // Ticks are folded in arrival order, so the 30s tick closes the first minute.
+        assert_eq!(
+            bars(&candles, Resolution::OneMinute),
+            [(0, 100, 105, 100, 105, 3), (60, 98, 98, 98, 98, 3), (240, 99, 99, 99, 99, 0), (300, 101, 101, 101, 101, 1)]
+        );
+        assert_eq!(
+            bars(&candles, Resolution::FiveMinutes),
+            [(0, 100, 105, 98, 99, 6), (300, 101, 101, 101, 101, 1)]
+        );
+        assert_eq!(bars(&candles, Resolution::OneHour), [(0, 100, 105, 98, 101, 7)]);
+    }
+
+    #[test]
+    fn day_bars_roll_over_at_utc_midnight() {
+        let candles = aggregator();
+        candles.record("BTC", Decimal::from(100), Decimal::from(1), 86_399_999);
+        candles.record("BTC", Decimal::from(110), Decimal::from(1), 86_400_000);
+        assert_eq!(
+            bars(&candles, Resolution::OneDay),
+            [(0, 100, 100, 100, 100, 1), (86_400, 110, 110, 110, 110, 1)]
+        );
+        assert_eq!(bars(&candles, Resolution::OneHour)[1].0, 86_400);
+    }
+
+    #[test]
+    fn overlapping_polls_count_each_trade_once() {
+        let candles = aggregator();
+        candles.record_trades("BTC", &[trade(100, 1, 1_000), trade(101, 1, 2_000), trade(102, 1, 2_000)]);
+        // The next poll repeats the two trades at 2s and adds a third one there.
+        candles.record_trades(
+            "BTC",
+            &[trade(101, 1, 2_000), trade(102, 1, 2_000), trade(103, 1, 2_000), trade(104, 1, 61_000)],
+        );
+        assert_eq!(
+            bars(&candles, Resolution::OneMinute),
+            [(0, 100, 103, 100, 103, 4), (60, 104, 104, 104, 104, 1)]
+        );
+    }
+
+    #[test]
+    fn failed_flush_is_merged_ahead_of_newer_ticks() {
+        let candles = aggregator();
+        candles.record("BTC", Decimal::from(100), Decimal::from(1), 1_000);
+        let taken = candles.take();
+        candles.record("BTC", Decimal::from(90), Decimal::from(2), 2_000);
+        candles.restore(taken);
+        assert_eq!(bars(&candles, Resolution::OneMinute), [(0, 100, 100, 90, 90, 3)]);
+    }
+
+    #[tokio::test]
+    async fn streamed_trades_of_listed_markets_become_candles() {
+        let state = state();
+        let (sender, receiver) = broadcast::channel(16);
+        for (symbol, price) in [("BTC", 67_000), ("NOPE", 1), ("BTC", 67_010)] {
+            sender
+                .send(SymbolTrade {
+                    symbol: symbol.to_string(),
+                    trade: trade(price, 2, 120_000),
+                })
+                .unwrap();
+        }
+        drop(sender);
+        record_streamed_trades(state.clone(), receiver).await;
+
+        let btc = state.candles.pending("BTC", Resolution::OneMinute, 0, i64::MAX);
+        assert_eq!(btc.len(), 1);
+        assert_eq!((btc[0].open_time, btc[0].close, btc[0].volume), (120, Decimal::from(67_010), Decimal::from(4)));
+        assert!(state.candles.pending("NOPE", Resolution::OneMinute, 0, i64::MAX).is_empty());
+    }
+}
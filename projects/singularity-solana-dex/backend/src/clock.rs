@@ -0,0 +1,11 @@
+//! Wall-clock time, in the unix seconds the store and the oracle's publish times use.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the unix epoch; 0 if the system clock is set before it.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
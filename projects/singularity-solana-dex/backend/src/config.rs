@@ -1,17 +1,167 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct OracleMarketConfig {
+    /// Omitted in the market registry, which fills it from the entry.
+    #[serde(default, skip_serializing)]
     pub symbol: String,
     pub market_id: u16,
     pub oracle_pubkey: String,
+    /// Switchboard aggregator account, read by the `switchboard` source.
+    pub switchboard_pubkey: Option<String>,
     pub market_account: String,
     pub static_price: Option<String>,
+    /// Sources to try in order until one passes the guards. Unset uses `ORACLE_SOURCES`.
+    pub sources: Option<Vec<OracleSource>>,
+    #[serde(default)]
+    pub guards: OracleGuards,
+    #[serde(default)]
+    pub twap: TwapConfig,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// Time-weighted average of the market's accepted prices, and whether liquidations use it.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct TwapConfig {
+    /// Averaging window; unset uses `ORACLE_TWAP_WINDOW_SECS`.
+    pub window_secs: Option<i64>,
+    #[serde(default)]
+    pub liquidate_on: LiquidationTrigger,
+}
+
+/// Which price must show a position below maintenance margin before it is liquidated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidationTrigger {
+    /// The latest accepted price.
+    #[default]
+    Spot,
+    /// The TWAP, so a short wick can't trigger a liquidation.
+    Twap,
+    /// Both at once.
+    Both,
+}
+
+impl LiquidationTrigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LiquidationTrigger::Spot => "spot",
+            LiquidationTrigger::Twap => "twap",
+            LiquidationTrigger::Both => "both",
+        }
+    }
+
+    pub fn triggers(self, on_spot: bool, on_twap: bool) -> bool {
+        match self {
+            LiquidationTrigger::Spot => on_spot,
+            LiquidationTrigger::Twap => on_twap,
+            LiquidationTrigger::Both => on_spot && on_twap,
+        }
+    }
+}
+
+/// Where a market's price can come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OracleSource {
+    /// Pyth price account at `oracle_pubkey`.
+    Pyth,
+    /// Switchboard aggregator at `switchboard_pubkey`.
+    Switchboard,
+    /// Off-chain median index from the price providers.
+    Index,
+    /// The market's `static_price`.
+    Static,
+}
+
+impl OracleSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OracleSource::Pyth => "pyth",
+            OracleSource::Switchboard => "switchboard",
+            OracleSource::Index => "index",
+            OracleSource::Static => "static",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "pyth" => Some(OracleSource::Pyth),
+            "switchboard" => Some(OracleSource::Switchboard),
+            "index" => Some(OracleSource::Index),
+            "static" => Some(OracleSource::Static),
+            _ => None,
+        }
+    }
+
+    /// Sources that are read from on-chain accounts over RPC.
+    pub fn needs_rpc(self) -> bool {
+        matches!(self, OracleSource::Pyth | OracleSource::Switchboard)
+    }
+}
+
+/// Checks every oracle reading must pass before it is used. Unset limits fall back to
+/// `PYTH_MAX_AGE_SECS` and `PYTH_MAX_CONF_BPS`; without `max_jump_bps` jumps are not checked.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct OracleGuards {
+    /// Oldest publish time accepted; for the index, the time of its oldest quote. A static
+    /// price has no age and always passes.
+    pub max_age_secs: Option<i64>,
+    /// Widest confidence interval accepted, relative to the price.
+    pub max_conf_bps: Option<u32>,
+    /// Largest move from the last accepted price.
+    pub max_jump_bps: Option<u32>,
+    /// Consecutive readings that must agree on a new level before a jump is accepted.
+    #[serde(default = "default_jump_confirmations")]
+    pub jump_confirmations: u32,
+    #[serde(default)]
+    pub on_violation: ViolationAction,
+}
+
+impl Default for OracleGuards {
+    fn default() -> Self {
+        Self {
+            max_age_secs: None,
+            max_conf_bps: None,
+            max_jump_bps: None,
+            jump_confirmations: default_jump_confirmations(),
+            on_violation: ViolationAction::default(),
+        }
+    }
+}
+
+fn default_jump_confirmations() -> u32 {
+    3
+}
+
+/// What happens to a market while its oracle reading fails a guard. The reading itself is
+/// never used; the last accepted price stays in effect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationAction {
+    /// Nothing else; liquidations continue at the last accepted price until it is older than
+    /// `max_age_secs`, and halt after that.
+    SkipUpdate,
+    /// No liquidations in the market.
+    #[default]
+    HaltLiquidations,
+    /// No liquidations, and no positions opened or levered up in the market.
+    FreezeMarket,
+}
+
+impl ViolationAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ViolationAction::SkipUpdate => "skip_update",
+            ViolationAction::HaltLiquidations => "halt_liquidations",
+            ViolationAction::FreezeMarket => "freeze_market",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct OracleConfig {
     pub markets: Vec<OracleMarketConfig>,
 }
@@ -24,3 +174,11 @@ impl OracleConfig {
         Ok(config)
     }
 }
+
+/// `name` parsed as a `T`, or `default` when it is unset or doesn't parse.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,8 +1,10 @@
+use crate::candles::{Candle, Resolution};
 use crate::errors::AppError;
-use crate::models::{Account, Position, Side};
+use crate::models::{Account, IdempotentResponse, LiquidationAttempt, PendingLiquidation, Position, Side};
 use rust_decimal::Decimal;
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
 use uuid::Uuid;
 
 #[async_trait::async_trait]
@@ -16,6 +18,38 @@ pub trait Store: Send + Sync {
     ) -> Result<(), AppError>;
     async fn upsert_position(&self, account_id: Uuid, position: &Position) -> Result<(), AppError>;
     async fn delete_position(&self, account_id: Uuid, market: &str) -> Result<(), AppError>;
+    async fn load_idempotent_response(&self, key: &str) -> Result<Option<IdempotentResponse>, AppError>;
+    async fn save_idempotent_response(&self, response: &IdempotentResponse) -> Result<(), AppError>;
+    /// Drops responses stored before `cutoff` (unix seconds), returning how many.
+    async fn delete_idempotent_responses_before(&self, cutoff: i64) -> Result<u64, AppError>;
+    /// Folds partial candles into the stored ones, see [`Candle::merge`].
+    async fn merge_candles(&self, candles: &[Candle]) -> Result<(), AppError>;
+    /// Candles of one series opening in `from..to` (unix seconds), oldest first.
+    async fn load_candles(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Candle>, AppError>;
+    /// Open time of the latest candle of the series opening before `before`.
+    async fn last_candle_before(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        before: i64,
+    ) -> Result<Option<i64>, AppError>;
+    /// Records the liquidation and its attempts; attempts recorded earlier are kept.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    async fn save_pending_liquidation(&self, pending: &PendingLiquidation) -> Result<(), AppError>;
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    async fn delete_pending_liquidation(&self, account_id: Uuid, market: &str) -> Result<(), AppError>;
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    async fn load_pending_liquidations(&self) -> Result<Vec<PendingLiquidation>, AppError>;
+    /// The backend's own pause, kept while there is no on-chain config to hold it.
+    async fn save_paused(&self, paused: bool) -> Result<(), AppError>;
+    /// `false` until a pause was saved.
+    async fn load_paused(&self) -> Result<bool, AppError>;
 }
 
 pub struct PostgresStore {
@@ -31,6 +65,7 @@ impl PostgresStore {
         Ok(Self { pool })
     }
 
+    #[allow(dead_code)]
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
@@ -155,13 +190,244 @@ impl Store for PostgresStore {
             .await?;
         Ok(())
     }
+
+    async fn load_idempotent_response(&self, key: &str) -> Result<Option<IdempotentResponse>, AppError> {
+        let row: Option<IdempotencyRow> = sqlx::query_as(
+            "SELECT key, method, path, request_hash, status_code, body, created_at FROM idempotency_keys WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| IdempotentResponse {
+            key: row.key,
+            method: row.method,
+            path: row.path,
+            request_hash: row.request_hash,
+            status_code: row.status_code as u16,
+            body: row.body,
+            created_at: row.created_at,
+        }))
+    }
+
+    async fn save_idempotent_response(&self, response: &IdempotentResponse) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO idempotency_keys (key, method, path, request_hash, status_code, body, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (key)
+             DO UPDATE SET method = EXCLUDED.method, path = EXCLUDED.path,
+                request_hash = EXCLUDED.request_hash, status_code = EXCLUDED.status_code, body = EXCLUDED.body,
+                created_at = EXCLUDED.created_at",
+        )
+        .bind(&response.key)
+        .bind(&response.method)
+        .bind(&response.path)
+        .bind(&response.request_hash)
+        .bind(response.status_code as i32)
+        .bind(&response.body)
+        .bind(response.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_idempotent_responses_before(&self, cutoff: i64) -> Result<u64, AppError> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn merge_candles(&self, candles: &[Candle]) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        for candle in candles {
+            sqlx::query(
+                "INSERT INTO candles (symbol, resolution, open_time, open, high, low, close, volume)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (symbol, resolution, open_time)
+                 DO UPDATE SET high = GREATEST(candles.high, EXCLUDED.high),
+                    low = LEAST(candles.low, EXCLUDED.low), close = EXCLUDED.close,
+                    volume = candles.volume + EXCLUDED.volume",
+            )
+            .bind(&candle.symbol)
+            .bind(candle.resolution.as_str())
+            .bind(candle.open_time)
+            .bind(candle.open)
+            .bind(candle.high)
+            .bind(candle.low)
+            .bind(candle.close)
+            .bind(candle.volume)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_candles(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Candle>, AppError> {
+        let rows: Vec<CandleRow> = sqlx::query_as(
+            "SELECT open_time, open, high, low, close, volume FROM candles
+             WHERE symbol = $1 AND resolution = $2 AND open_time >= $3 AND open_time < $4
+             ORDER BY open_time ASC",
+        )
+        .bind(symbol)
+        .bind(resolution.as_str())
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Candle {
+                symbol: symbol.to_string(),
+                resolution,
+                open_time: row.open_time,
+                open: row.open,
+                high: row.high,
+                low: row.low,
+                close: row.close,
+                volume: row.volume,
+            })
+            .collect())
+    }
+
+    async fn last_candle_before(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        before: i64,
+    ) -> Result<Option<i64>, AppError> {
+        let open_time: Option<i64> = sqlx::query_scalar(
+            "SELECT MAX(open_time) FROM candles WHERE symbol = $1 AND resolution = $2 AND open_time < $3",
+        )
+        .bind(symbol)
+        .bind(resolution.as_str())
+        .bind(before)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(open_time)
+    }
+
+    async fn save_pending_liquidation(&self, pending: &PendingLiquidation) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO pending_liquidations (account_id, market, exit_price, created_at)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (account_id, market) DO UPDATE SET exit_price = EXCLUDED.exit_price",
+        )
+        .bind(pending.account_id)
+        .bind(&pending.market)
+        .bind(pending.exit_price)
+        .bind(pending.created_at)
+        .execute(&mut *tx)
+        .await?;
+        for attempt in &pending.attempts {
+            sqlx::query(
+                "INSERT INTO pending_liquidation_attempts (signature, account_id, market, last_valid_block_height)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (signature) DO NOTHING",
+            )
+            .bind(&attempt.signature)
+            .bind(pending.account_id)
+            .bind(&pending.market)
+            .bind(attempt.last_valid_block_height as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_pending_liquidation(&self, account_id: Uuid, market: &str) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM pending_liquidations WHERE account_id = $1 AND market = $2")
+            .bind(account_id)
+            .bind(market)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn load_pending_liquidations(&self) -> Result<Vec<PendingLiquidation>, AppError> {
+        let rows: Vec<PendingLiquidationRow> = sqlx::query_as(
+            "SELECT account_id, market, exit_price, created_at FROM pending_liquidations ORDER BY created_at ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        // Later attempts carry newer blockhashes, so they expire later.
+        let attempts: Vec<LiquidationAttemptRow> = sqlx::query_as(
+            "SELECT signature, account_id, market, last_valid_block_height FROM pending_liquidation_attempts
+             ORDER BY last_valid_block_height ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut pending: Vec<PendingLiquidation> = rows
+            .into_iter()
+            .map(|row| PendingLiquidation {
+                account_id: row.account_id,
+                market: row.market,
+                exit_price: row.exit_price,
+                attempts: Vec::new(),
+                created_at: row.created_at,
+            })
+            .collect();
+        for row in attempts {
+            if let Some(entry) = pending
+                .iter_mut()
+                .find(|entry| entry.account_id == row.account_id && entry.market == row.market)
+            {
+                entry.attempts.push(LiquidationAttempt {
+                    signature: row.signature,
+                    last_valid_block_height: row.last_valid_block_height as u64,
+                });
+            }
+        }
+        Ok(pending)
+    }
+
+    async fn save_paused(&self, paused: bool) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO program_pause (id, paused) VALUES (TRUE, $1)
+             ON CONFLICT (id) DO UPDATE SET paused = EXCLUDED.paused",
+        )
+        .bind(paused)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_paused(&self) -> Result<bool, AppError> {
+        let paused: Option<bool> = sqlx::query_scalar("SELECT paused FROM program_pause")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(paused.unwrap_or(false))
+    }
 }
 
-pub struct MemoryStore;
+pub struct MemoryStore {
+    idempotency: Mutex<HashMap<String, IdempotentResponse>>,
+    candles: Mutex<HashMap<(String, Resolution), BTreeMap<i64, Candle>>>,
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pending_liquidations: Mutex<HashMap<(Uuid, String), PendingLiquidation>>,
+    paused: Mutex<bool>,
+}
 
 impl MemoryStore {
     pub fn new() -> Self {
-        Self
+        Self {
+            idempotency: Mutex::new(HashMap::new()),
+            candles: Mutex::new(HashMap::new()),
+            pending_liquidations: Mutex::new(HashMap::new()),
+            paused: Mutex::new(false),
+        }
     }
 }
 
@@ -190,6 +456,95 @@ impl Store for MemoryStore {
     async fn delete_position(&self, _account_id: Uuid, _market: &str) -> Result<(), AppError> {
         Ok(())
     }
+
+    async fn load_idempotent_response(&self, key: &str) -> Result<Option<IdempotentResponse>, AppError> {
+        Ok(self.idempotency.lock().unwrap().get(key).cloned())
+    }
+
+    async fn save_idempotent_response(&self, response: &IdempotentResponse) -> Result<(), AppError> {
+        self.idempotency
+            .lock()
+            .unwrap()
+            .insert(response.key.clone(), response.clone());
+        Ok(())
+    }
+
+    async fn delete_idempotent_responses_before(&self, cutoff: i64) -> Result<u64, AppError> {
+        let mut stored = self.idempotency.lock().unwrap();
+        let before = stored.len();
+        stored.retain(|_, response| response.created_at >= cutoff);
+        Ok((before - stored.len()) as u64)
+    }
+
+    async fn merge_candles(&self, candles: &[Candle]) -> Result<(), AppError> {
+        let mut stored = self.candles.lock().unwrap();
+        for candle in candles {
+            stored
+                .entry((candle.symbol.clone(), candle.resolution))
+                .or_default()
+                .entry(candle.open_time)
+                .and_modify(|existing| existing.merge(candle))
+                .or_insert_with(|| candle.clone());
+        }
+        Ok(())
+    }
+
+    async fn load_candles(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Candle>, AppError> {
+        let stored = self.candles.lock().unwrap();
+        Ok(stored
+            .get(&(symbol.to_string(), resolution))
+            .map(|series| series.range(from..to).map(|(_, candle)| candle.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    async fn last_candle_before(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        before: i64,
+    ) -> Result<Option<i64>, AppError> {
+        let stored = self.candles.lock().unwrap();
+        Ok(stored
+            .get(&(symbol.to_string(), resolution))
+            .and_then(|series| series.range(..before).next_back().map(|(open_time, _)| *open_time)))
+    }
+
+    async fn save_pending_liquidation(&self, pending: &PendingLiquidation) -> Result<(), AppError> {
+        self.pending_liquidations
+            .lock()
+            .unwrap()
+            .insert((pending.account_id, pending.market.clone()), pending.clone());
+        Ok(())
+    }
+
+    async fn delete_pending_liquidation(&self, account_id: Uuid, market: &str) -> Result<(), AppError> {
+        self.pending_liquidations
+            .lock()
+            .unwrap()
+            .remove(&(account_id, market.to_string()));
+        Ok(())
+    }
+
+    async fn load_pending_liquidations(&self) -> Result<Vec<PendingLiquidation>, AppError> {
+        let mut pending: Vec<PendingLiquidation> = self.pending_liquidations.lock().unwrap().values().cloned().collect();
+        pending.sort_by_key(|entry| entry.created_at);
+        Ok(pending)
+    }
+
+    async fn save_paused(&self, paused: bool) -> Result<(), AppError> {
+        *self.paused.lock().unwrap() = paused;
+        Ok(())
+    }
+
+    async fn load_paused(&self) -> Result<bool, AppError> {
+        Ok(*self.paused.lock().unwrap())
+    }
 }
 
 #[derive(sqlx::FromRow)]
@@ -202,6 +557,7 @@ struct AccountRow {
 
 #[derive(sqlx::FromRow)]
 struct PositionRow {
+    #[allow(dead_code)]
     id: Uuid,
     account_id: Uuid,
     market: String,
@@ -211,3 +567,41 @@ struct PositionRow {
     leverage_bps: i32,
     position_account: Option<String>,
 }
+
+#[derive(sqlx::FromRow)]
+struct IdempotencyRow {
+    key: String,
+    method: String,
+    path: String,
+    request_hash: String,
+    status_code: i32,
+    body: String,
+    created_at: i64,
+}
+
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
+#[derive(sqlx::FromRow)]
+struct PendingLiquidationRow {
+    account_id: Uuid,
+    market: String,
+    exit_price: Decimal,
+    created_at: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct LiquidationAttemptRow {
+    signature: String,
+    account_id: Uuid,
+    market: String,
+    last_valid_block_height: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct CandleRow {
+    open_time: i64,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    volume: Decimal,
+}
@@ -1,51 +1,323 @@
+use crate::registry::RegistryError;
+use crate::validation::FieldError;
 use axum::{http::StatusCode, response::IntoResponse, Json};
+use rust_decimal::Decimal;
 use serde::Serialize;
+use serde_json::{json, Value};
 use thiserror::Error;
+use tracing::error;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
 #[derive(Debug, Error)]
 pub enum RiskError {
-    #[error("market not found")]
-    MarketNotFound,
-    #[error("position not found")]
-    PositionNotFound,
+    #[error("market not found: {0}")]
+    MarketNotFound(String),
+    #[error("position not found: {0}")]
+    PositionNotFound(String),
+    #[error("position already open in {0}")]
+    PositionExists(String),
     #[error("invalid quantity")]
     InvalidQuantity,
-    #[error("invalid leverage")]
-    InvalidLeverage,
-    #[error("insufficient collateral")]
-    InsufficientCollateral,
+    #[error("invalid leverage {requested_bps} bps, max {max_bps} bps")]
+    InvalidLeverage { requested_bps: u32, max_bps: u32 },
+    #[error("insufficient collateral: required {required}, available {available}")]
+    InsufficientCollateral { required: Decimal, available: Decimal },
     #[error("withdraw would violate margin requirements")]
     MarginViolation,
     #[error("missing mark price for {0}")]
     MissingMarkPrice(String),
+    #[error("market {0} is frozen while its oracle price is unreliable")]
+    MarketFrozen(String),
+    #[error("market {0} is paused")]
+    MarketPaused(String),
+    #[error("market {0} is reduce-only")]
+    MarketReduceOnly(String),
+    #[error("market {0} is settled")]
+    MarketSettled(String),
+    #[error("trading is paused: opens, leverage increases and withdrawals are disabled")]
+    ProgramPaused,
+    #[error("market {market} is post-only: entry price {entry_price} would cross the mark {mark_price}")]
+    PostOnlyWouldCross {
+        market: String,
+        entry_price: Decimal,
+        mark_price: Decimal,
+    },
+    #[error("a liquidation of the {0} position is awaiting confirmation")]
+    LiquidationPending(String),
+    #[error("fill price {price} for {market} is more than {max_deviation_bps} bps from the index {index}")]
+    FillPriceOutOfRange {
+        market: String,
+        price: Decimal,
+        index: Decimal,
+        max_deviation_bps: u32,
+    },
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum UpstreamError {
+    #[error("{0} timed out")]
+    Timeout(String),
+    #[error("{service} is unreachable: {reason}")]
+    Unavailable { service: String, reason: String },
+    #[error("{service} returned status {status}")]
+    Status { service: String, status: u16 },
+    #[error("{service} returned an invalid response: {reason}")]
+    InvalidResponse { service: String, reason: String },
+}
+
+impl UpstreamError {
+    pub fn from_reqwest(service: &str, err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            UpstreamError::Timeout(service.to_string())
+        } else if err.is_decode() {
+            UpstreamError::InvalidResponse {
+                service: service.to_string(),
+                reason: err.to_string(),
+            }
+        } else if let Some(status) = err.status() {
+            UpstreamError::Status {
+                service: service.to_string(),
+                status: status.as_u16(),
+            }
+        } else {
+            UpstreamError::Unavailable {
+                service: service.to_string(),
+                reason: err.to_string(),
+            }
+        }
+    }
+
+    pub fn invalid(service: &str, reason: impl Into<String>) -> Self {
+        UpstreamError::InvalidResponse {
+            service: service.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    fn service(&self) -> &str {
+        match self {
+            UpstreamError::Timeout(service) => service,
+            UpstreamError::Unavailable { service, .. }
+            | UpstreamError::Status { service, .. }
+            | UpstreamError::InvalidResponse { service, .. } => service,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum AppError {
-    #[error("risk error: {0}")]
+    #[error("{0}")]
     Risk(#[from] RiskError),
     #[error("database error: {0}")]
     Database(#[from] sqlx::Error),
-    #[error("not found")]
-    NotFound,
-    #[error("upstream error")]
-    Upstream,
+    #[error("account {0} not found")]
+    AccountNotFound(Uuid),
+    #[error("request validation failed")]
+    Validation(Vec<FieldError>),
+    #[error("invalid parameter {name}: {reason}")]
+    InvalidParameter { name: &'static str, reason: String },
+    #[error("upstream error: {0}")]
+    Upstream(#[from] UpstreamError),
+    #[error("internal error: {0}")]
+    Internal(String),
+    #[error("invalid idempotency key")]
+    InvalidIdempotencyKey,
+    #[error("idempotency key was used for a different request")]
+    IdempotencyKeyReused,
+    #[error("a request with this idempotency key is already in progress")]
+    IdempotencyInProgress,
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("market {0} already exists")]
+    MarketExists(String),
+    #[error("{0}")]
+    Registry(#[from] RegistryError),
+    #[error("on-chain transaction failed: {0}")]
+    Chain(String),
+    #[error("no on-chain admin keypair is configured")]
+    ChainNotConfigured,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Stable machine-readable code, e.g. `INSUFFICIENT_COLLATERAL`.
+    pub code: &'static str,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl RiskError {
+    fn code(&self) -> &'static str {
+        match self {
+            RiskError::MarketNotFound(_) => "MARKET_NOT_FOUND",
+            RiskError::PositionNotFound(_) => "POSITION_NOT_FOUND",
+            RiskError::PositionExists(_) => "POSITION_EXISTS",
+            RiskError::InvalidQuantity => "INVALID_QUANTITY",
+            RiskError::InvalidLeverage { .. } => "INVALID_LEVERAGE",
+            RiskError::InsufficientCollateral { .. } => "INSUFFICIENT_COLLATERAL",
+            RiskError::MarginViolation => "MARGIN_VIOLATION",
+            RiskError::MissingMarkPrice(_) => "MISSING_MARK_PRICE",
+            RiskError::MarketFrozen(_) => "MARKET_FROZEN",
+            RiskError::MarketPaused(_) => "MARKET_PAUSED",
+            RiskError::MarketReduceOnly(_) => "MARKET_REDUCE_ONLY",
+            RiskError::MarketSettled(_) => "MARKET_SETTLED",
+            RiskError::PostOnlyWouldCross { .. } => "MARKET_POST_ONLY",
+            RiskError::ProgramPaused => "PROTOCOL_PAUSED",
+            RiskError::LiquidationPending(_) => "LIQUIDATION_PENDING",
+            RiskError::FillPriceOutOfRange { .. } => "FILL_PRICE_OUT_OF_RANGE",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            RiskError::MarketNotFound(_) | RiskError::PositionNotFound(_) => StatusCode::NOT_FOUND,
+            RiskError::PositionExists(_)
+            | RiskError::MarginViolation
+            | RiskError::MarketFrozen(_)
+            | RiskError::MarketPaused(_)
+            | RiskError::MarketReduceOnly(_)
+            | RiskError::MarketSettled(_)
+            | RiskError::PostOnlyWouldCross { .. }
+            | RiskError::LiquidationPending(_) => StatusCode::CONFLICT,
+            RiskError::InvalidQuantity
+            | RiskError::InvalidLeverage { .. }
+            | RiskError::InsufficientCollateral { .. }
+            | RiskError::MissingMarkPrice(_)
+            | RiskError::FillPriceOutOfRange { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            RiskError::ProgramPaused => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            RiskError::MarketNotFound(market)
+            | RiskError::PositionNotFound(market)
+            | RiskError::PositionExists(market)
+            | RiskError::MissingMarkPrice(market)
+            | RiskError::MarketFrozen(market)
+            | RiskError::MarketPaused(market)
+            | RiskError::MarketReduceOnly(market)
+            | RiskError::MarketSettled(market)
+            | RiskError::LiquidationPending(market) => Some(json!({ "market": market })),
+            RiskError::PostOnlyWouldCross { market, entry_price, mark_price } => Some(json!({
+                "market": market,
+                "entry_price": entry_price,
+                "mark_price": mark_price,
+            })),
+            RiskError::FillPriceOutOfRange {
+                market,
+                price,
+                index,
+                max_deviation_bps,
+            } => Some(json!({
+                "market": market,
+                "price": price,
+                "index": index,
+                "max_deviation_bps": max_deviation_bps,
+            })),
+            RiskError::InvalidLeverage { requested_bps, max_bps } => Some(json!({
+                "requested_bps": requested_bps,
+                "max_bps": max_bps,
+            })),
+            RiskError::InsufficientCollateral { required, available } => Some(json!({
+                "required": required,
+                "available": available,
+            })),
+            RiskError::InvalidQuantity | RiskError::MarginViolation | RiskError::ProgramPaused => None,
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Risk(err) => err.code(),
+            AppError::Database(_) => "DATABASE_ERROR",
+            AppError::AccountNotFound(_) => "ACCOUNT_NOT_FOUND",
+            AppError::Validation(_) => "VALIDATION_FAILED",
+            AppError::InvalidParameter { .. } => "INVALID_PARAMETER",
+            AppError::Upstream(UpstreamError::Timeout(_)) => "UPSTREAM_TIMEOUT",
+            AppError::Upstream(UpstreamError::Unavailable { .. }) => "UPSTREAM_UNAVAILABLE",
+            AppError::Upstream(UpstreamError::Status { .. }) => "UPSTREAM_STATUS",
+            AppError::Upstream(UpstreamError::InvalidResponse { .. }) => "UPSTREAM_INVALID_RESPONSE",
+            AppError::Internal(_) => "INTERNAL_ERROR",
+            AppError::InvalidIdempotencyKey => "INVALID_IDEMPOTENCY_KEY",
+            AppError::IdempotencyKeyReused => "IDEMPOTENCY_KEY_REUSED",
+            AppError::IdempotencyInProgress => "IDEMPOTENCY_IN_PROGRESS",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::MarketExists(_) => "MARKET_EXISTS",
+            AppError::Registry(RegistryError::Invalid(_)) => "INVALID_MARKET_CONFIG",
+            AppError::Registry(RegistryError::Unsafe(_)) => "MARKET_CHANGE_REJECTED",
+            AppError::Registry(_) => "INTERNAL_ERROR",
+            AppError::Chain(_) => "CHAIN_ERROR",
+            AppError::ChainNotConfigured => "CHAIN_NOT_CONFIGURED",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Risk(err) => err.status(),
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::AccountNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) | AppError::InvalidParameter { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Upstream(UpstreamError::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            AppError::InvalidIdempotencyKey => StatusCode::BAD_REQUEST,
+            AppError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::IdempotencyInProgress => StatusCode::CONFLICT,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::MarketExists(_) | AppError::Registry(RegistryError::Unsafe(_)) => StatusCode::CONFLICT,
+            AppError::Registry(RegistryError::Invalid(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Registry(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Chain(_) => StatusCode::BAD_GATEWAY,
+            AppError::ChainNotConfigured => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            AppError::Risk(err) => err.details(),
+            AppError::AccountNotFound(id) => Some(json!({ "account_id": id })),
+            AppError::Validation(fields) => Some(json!({ "fields": fields })),
+            AppError::InvalidParameter { name, .. } => Some(json!({ "parameter": name })),
+            AppError::MarketExists(market) => Some(json!({ "market": market })),
+            AppError::Registry(RegistryError::Invalid(problems) | RegistryError::Unsafe(problems)) => {
+                Some(json!({ "problems": problems }))
+            }
+            AppError::Upstream(err) => Some(match err {
+                UpstreamError::Status { service, status } => json!({ "service": service, "status": status }),
+                _ => json!({ "service": err.service() }),
+            }),
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let (status, message) = match &self {
-            AppError::Risk(_) => (StatusCode::BAD_REQUEST, self.to_string()),
-            AppError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
-            AppError::NotFound => (StatusCode::NOT_FOUND, self.to_string()),
-            AppError::Upstream => (StatusCode::BAD_GATEWAY, self.to_string()),
+        let status = self.status();
+        if status.is_server_error() {
+            error!(error = %self, code = self.code(), "request failed");
+        }
+        // Database and internal causes stay in the logs, correlated through the request id.
+        let message = match &self {
+            AppError::Database(_) | AppError::Internal(_) | AppError::Registry(RegistryError::Io(_) | RegistryError::Parse(_)) => {
+                "internal server error".to_string()
+            }
+            _ => self.to_string(),
         };
 
-        (status, Json(ErrorResponse { error: message })).into_response()
+        let body = ErrorResponse {
+            code: self.code(),
+            error: message,
+            details: self.details(),
+            request_id: crate::request_id::current(),
+        };
+        (status, Json(body)).into_response()
     }
 }
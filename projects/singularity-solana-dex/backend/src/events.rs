@@ -0,0 +1,221 @@
+use crate::models::{Account, Position};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const DEFAULT_CAPACITY: usize = 1024;
+/// Replayable events kept for `since`; market data is never kept, see [`Event::is_market_data`].
+const HISTORY_LEN: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Account {
+        account: Account,
+    },
+    Position {
+        account_id: Uuid,
+        market: String,
+        position: Option<Position>,
+    },
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    Liquidation {
+        account_id: Uuid,
+        market: String,
+        exit_price: Decimal,
+        pnl: Decimal,
+    },
+    /// A position in a delisted market was closed at its settlement price.
+    Settlement {
+        account_id: Uuid,
+        market: String,
+        settlement_price: Decimal,
+        pnl: Decimal,
+    },
+    Price {
+        symbol: String,
+        price: Decimal,
+    },
+    /// Indicative funding rate: the mark's premium over the index, paid by longs to shorts
+    /// while positive and by shorts to longs while negative.
+    Funding {
+        market: String,
+        rate_bps: Decimal,
+        mark: Decimal,
+        index: Decimal,
+    },
+    /// The program was paused or unpaused by an admin or the guardian.
+    ProgramPause {
+        paused: bool,
+    },
+    /// An oracle reading failed a market's guards; `action` is what was done about it.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    OracleAlert {
+        market: String,
+        action: String,
+        reason: String,
+    },
+}
+
+impl Event {
+    /// Channel name clients subscribe to, e.g. `account:<id>`, `prices:BTC` or `funding:BTC`.
+    pub fn channel(&self) -> String {
+        match self {
+            Event::Account { account } => format!("account:{}", account.id),
+            Event::Position { account_id, .. } => format!("positions:{}", account_id),
+            Event::Liquidation { .. } => "liquidations".to_string(),
+            Event::Settlement { .. } => "settlements".to_string(),
+            Event::Price { symbol, .. } => format!("prices:{}", symbol),
+            Event::Funding { market, .. } => format!("funding:{}", market),
+            Event::ProgramPause { .. } | Event::OracleAlert { .. } => "alerts".to_string(),
+        }
+    }
+
+    /// Prices and funding rates, republished every market events tick. They go out on their
+    /// own stream and aren't replayed, so they can't push account events out of the history
+    /// or make a slow client lag behind them; the next tick supersedes a missed one.
+    pub fn is_market_data(&self) -> bool {
+        matches!(self, Event::Price { .. } | Event::Funding { .. })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Envelope {
+    pub seq: u64,
+    pub channel: String,
+    pub data: Event,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Envelope>>,
+    market_data: broadcast::Sender<Arc<Envelope>>,
+    seq: AtomicU64,
+    history: Mutex<History>,
+}
+
+#[derive(Default)]
+struct History {
+    events: VecDeque<Arc<Envelope>>,
+    /// Seq of the newest event that fell out of `events`; a cursor before it missed one.
+    evicted: u64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(DEFAULT_CAPACITY);
+        let (market_data, _) = broadcast::channel(DEFAULT_CAPACITY);
+        Self {
+            sender,
+            market_data,
+            seq: AtomicU64::new(0),
+            history: Mutex::new(History {
+                events: VecDeque::with_capacity(HISTORY_LEN),
+                evicted: 0,
+            }),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let channel = event.channel();
+        let mut history = self.history.lock().unwrap();
+        let envelope = Arc::new(Envelope {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed) + 1,
+            channel,
+            data: event,
+        });
+        // No subscribers is not an error; the event is simply dropped.
+        if envelope.data.is_market_data() {
+            let _ = self.market_data.send(envelope);
+            return;
+        }
+        if history.events.len() == HISTORY_LEN {
+            if let Some(evicted) = history.events.pop_front() {
+                history.evicted = evicted.seq;
+            }
+        }
+        history.events.push_back(envelope.clone());
+        let _ = self.sender.send(envelope);
+    }
+
+    /// Replayable events published after `seq`, or `None` if some of them already fell out
+    /// of history.
+    pub fn replay_since(&self, seq: u64) -> Option<Vec<Arc<Envelope>>> {
+        let history = self.history.lock().unwrap();
+        // A cursor ahead of us comes from a previous server instance.
+        if seq > self.last_seq() || seq < history.evicted {
+            return None;
+        }
+        Some(
+            history
+                .events
+                .iter()
+                .filter(|envelope| envelope.seq > seq)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Every event but market data, see [`Event::is_market_data`].
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Envelope>> {
+        self.sender.subscribe()
+    }
+
+    pub fn subscribe_market_data(&self) -> broadcast::Receiver<Arc<Envelope>> {
+        self.market_data.subscribe()
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account_event() -> Event {
+        Event::Account {
+            account: Account {
+                id: Uuid::new_v4(),
+                owner: "owner".to_string(),
+                account_state: None,
+                collateral: Decimal::ZERO,
+                positions: Default::default(),
+            },
+        }
+    }
+
+    fn price_event() -> Event {
+        Event::Price {
+            symbol: "BTC".to_string(),
+            price: Decimal::from(60_000),
+        }
+    }
+
+    #[test]
+    fn market_data_does_not_push_account_events_out_of_history() {
+        let bus = EventBus::new();
+        bus.publish(account_event());
+        for _ in 0..HISTORY_LEN * 3 {
+            bus.publish(price_event());
+        }
+        let missed = bus.replay_since(0).unwrap();
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].seq, 1);
+    }
+
+    #[test]
+    fn cursors_older_than_the_history_resync() {
+        let bus = EventBus::new();
+        for _ in 0..HISTORY_LEN + 1 {
+            bus.publish(account_event());
+        }
+        assert!(bus.replay_since(0).is_none());
+        assert_eq!(bus.replay_since(1).unwrap().len(), HISTORY_LEN);
+        assert!(bus.replay_since(bus.last_seq() + 1).is_none());
+    }
+}
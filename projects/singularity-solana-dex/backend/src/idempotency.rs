@@ -0,0 +1,342 @@
+use crate::clock::unix_now;
+use crate::errors::AppError;
+use crate::models::IdempotentResponse;
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LEN: usize = 255;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+pub struct IdempotencyGuard {
+    window_secs: i64,
+    in_flight: Mutex<HashSet<String>>,
+    /// Responses the store refused, replayed from here until a later save goes through.
+    unsaved: Mutex<HashMap<String, IdempotentResponse>>,
+}
+
+impl IdempotencyGuard {
+    pub fn new(window_secs: u64) -> Self {
+        Self {
+            window_secs: window_secs as i64,
+            in_flight: Mutex::new(HashSet::new()),
+            unsaved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_fresh(&self, response: &IdempotentResponse, now: i64) -> bool {
+        now - response.created_at < self.window_secs
+    }
+
+    /// Claims `key` until the returned claim is dropped, so a handler that panics or a client
+    /// that disconnects can't leave the key stuck in progress.
+    fn acquire(&self, key: &str) -> Option<InFlight<'_>> {
+        if !self.in_flight.lock().unwrap().insert(key.to_string()) {
+            return None;
+        }
+        Some(InFlight {
+            guard: self,
+            key: key.to_string(),
+        })
+    }
+}
+
+struct InFlight<'a> {
+    guard: &'a IdempotencyGuard,
+    key: String,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.guard.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Replays the first stored response for a repeated `Idempotency-Key` on POST routes. A key
+/// reused with another method, path or body is refused rather than replayed.
+pub async fn idempotency_layer(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if request.method() != Method::POST {
+        return Ok(next.run(request).await);
+    }
+
+    let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty() && value.len() <= MAX_KEY_LEN)
+            .ok_or(AppError::InvalidIdempotencyKey)?,
+        None => return Ok(next.run(request).await),
+    };
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| AppError::InvalidParameter {
+            name: "body",
+            reason: format!("larger than {} bytes", MAX_BODY_BYTES),
+        })?;
+    let request_hash = sha256_hex(&body);
+    let now = unix_now();
+
+    // Claimed before the store is checked, so two concurrent requests can't both miss it.
+    let _claim = state.idempotency.acquire(&key).ok_or(AppError::IdempotencyInProgress)?;
+
+    let unsaved = state.idempotency.unsaved.lock().unwrap().get(&key).cloned();
+    let stored = match unsaved {
+        Some(unsaved) => Some(unsaved),
+        None => state.store.load_idempotent_response(&key).await?,
+    };
+    if let Some(stored) = stored {
+        if state.idempotency.is_fresh(&stored, now) {
+            let same_body = stored.request_hash.is_empty() || stored.request_hash == request_hash;
+            if stored.method != method || stored.path != path || !same_body {
+                return Err(AppError::IdempotencyKeyReused);
+            }
+            return Ok(replay(stored));
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    record(
+        &state,
+        IdempotentResponse {
+            key,
+            method,
+            path,
+            request_hash,
+            status_code: 0,
+            body: String::new(),
+            created_at: now,
+        },
+        response,
+    )
+    .await
+}
+
+/// Stores `response` under the key described by `entry`, unless the handler failed. The
+/// handler's effects are already applied, so a store failure still returns its response and
+/// keeps it in memory for replays.
+async fn record(state: &AppState, entry: IdempotentResponse, response: Response) -> Result<Response, AppError> {
+    let status = response.status();
+    if status.is_server_error() {
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|err| AppError::Internal(err.to_string()))?;
+
+    let entry = IdempotentResponse {
+        status_code: status.as_u16(),
+        body: String::from_utf8_lossy(&bytes).into_owned(),
+        ..entry
+    };
+    if let Err(err) = state.store.save_idempotent_response(&entry).await {
+        error!(key = %entry.key, error = %err, "idempotent response not stored; replaying it from memory");
+        state.idempotency.unsaved.lock().unwrap().insert(entry.key.clone(), entry);
+    }
+
+    Ok(Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// Every `every`, drops stored responses older than the window and retries saving the ones
+/// the store refused.
+pub fn spawn_pruner(state: Arc<AppState>, every: Duration) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(every).await;
+            prune(&state, unix_now()).await;
+        }
+    });
+}
+
+async fn prune(state: &AppState, now: i64) {
+    let guard = &state.idempotency;
+    let cutoff = now - guard.window_secs;
+    let unsaved: Vec<IdempotentResponse> = {
+        let mut unsaved = guard.unsaved.lock().unwrap();
+        unsaved.retain(|_, response| response.created_at >= cutoff);
+        unsaved.values().cloned().collect()
+    };
+    for response in unsaved {
+        if state.store.save_idempotent_response(&response).await.is_ok() {
+            guard.unsaved.lock().unwrap().remove(&response.key);
+        }
+    }
+    match state.store.delete_idempotent_responses_before(cutoff).await {
+        Ok(0) => {}
+        Ok(pruned) => info!(pruned, "expired idempotency keys pruned"),
+        Err(err) => warn!(error = %err, "cannot prune idempotency keys"),
+    }
+}
+
+fn replay(stored: IdempotentResponse) -> Response {
+    let status = StatusCode::from_u16(stored.status_code).unwrap_or(StatusCode::OK);
+    let mut response = (status, stored.body).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(IDEMPOTENT_REPLAY_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Store;
+    use crate::test_support::{app, request, send, state, state_with_store, FlakyStore};
+    use serde_json::json;
+
+    fn create_account(key: &str, owner: &str) -> Request {
+        let mut request = request(Method::POST, "/accounts", json!({ "owner": owner }));
+        request
+            .headers_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_str(key).unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn repeated_key_replays_the_first_response() {
+        let state = state();
+        let app = app(&state);
+
+        let (status, headers, first) = send(&app, create_account("k1", "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(IDEMPOTENT_REPLAY_HEADER).is_none());
+
+        let (status, headers, second) = send(&app, create_account("k1", "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(IDEMPOTENT_REPLAY_HEADER).unwrap(), "true");
+        assert_eq!(first, second);
+        assert_eq!(state.accounts.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn key_reused_with_another_body_is_refused() {
+        let state = state();
+        let app = app(&state);
+
+        send(&app, create_account("k2", "alice")).await;
+        let (status, _, body) = send(&app, create_account("k2", "bob")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "IDEMPOTENCY_KEY_REUSED");
+        assert_eq!(state.accounts.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn key_in_progress_is_refused() {
+        let state = state();
+        let app = app(&state);
+
+        let _claim = state.idempotency.acquire("k3").unwrap();
+        let (status, _, body) = send(&app, create_account("k3", "alice")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "IDEMPOTENCY_IN_PROGRESS");
+    }
+
+    #[test]
+    fn dropped_claim_releases_the_key() {
+        let guard = IdempotencyGuard::new(60);
+        let claim = guard.acquire("k4").unwrap();
+        assert!(guard.acquire("k4").is_none());
+        drop(claim);
+        assert!(guard.acquire("k4").is_some());
+    }
+
+    /// A route that never answers and one that panics, behind the idempotency layer.
+    fn failing_app(state: &Arc<AppState>) -> axum::Router {
+        use axum::routing::post;
+        axum::Router::new()
+            .route("/hang", post(std::future::pending::<()>))
+            .route("/panic", post(|| async { panic!("handler failed") as () }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), idempotency_layer))
+            .with_state(state.clone())
+    }
+
+    fn keyed(uri: &str, key: &str) -> Request {
+        let mut request = request(Method::POST, uri, json!({}));
+        request
+            .headers_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_str(key).unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_releases_the_key() {
+        let state = state();
+        let app = failing_app(&state);
+
+        // The timeout drops the request mid-handler, as a client disconnecting would.
+        let result = tokio::time::timeout(std::time::Duration::from_millis(50), send(&app, keyed("/hang", "k5"))).await;
+        assert!(result.is_err());
+        assert!(state.idempotency.acquire("k5").is_some());
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_releases_the_key() {
+        let state = state();
+        let app = failing_app(&state);
+
+        let result = tokio::spawn(async move { send(&app, keyed("/panic", "k6")).await }).await;
+        assert!(result.unwrap_err().is_panic());
+        assert!(state.idempotency.acquire("k6").is_some());
+    }
+
+    #[tokio::test]
+    async fn a_failed_save_still_answers_and_replays_from_memory() {
+        let store = Arc::new(FlakyStore::failing(&["save_idempotent_response"]));
+        let state = state_with_store(store.clone(), None, Vec::new());
+        let app = app(&state);
+
+        let (status, headers, first) = send(&app, create_account("k7", "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(IDEMPOTENT_REPLAY_HEADER).is_none());
+
+        let (status, headers, second) = send(&app, create_account("k7", "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(IDEMPOTENT_REPLAY_HEADER).unwrap(), "true");
+        assert_eq!(first, second);
+        assert_eq!(state.accounts.read().await.len(), 1);
+
+        // Once the store is back the pruner saves it.
+        store.recover();
+        prune(&state, unix_now()).await;
+        assert!(state.idempotency.unsaved.lock().unwrap().is_empty());
+        assert!(store.load_idempotent_response("k7").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn keys_past_the_window_are_pruned() {
+        let state = state();
+        let app = app(&state);
+        send(&app, create_account("k8", "alice")).await;
+
+        let window = state.idempotency.window_secs;
+        prune(&state, unix_now() + window - 5).await;
+        assert!(state.store.load_idempotent_response("k8").await.unwrap().is_some());
+        prune(&state, unix_now() + window + 5).await;
+        assert!(state.store.load_idempotent_response("k8").await.unwrap().is_none());
+    }
+}
@@ -1,165 +1,735 @@
-use crate::config::OracleMarketConfig;
-use crate::oracle::{OracleClient, OracleError};
-use crate::solana::SolanaGateway;
+use crate::clock::unix_now;
+use crate::config::{env_or, LiquidationTrigger};
+use crate::events::Event;
+use crate::liquidation_queue::{by_priority, CrankMarks, CrankPlan, PlannedLiquidation};
+use crate::models::{Account, LiquidationAttempt, PendingLiquidation, PendingOutcome, PositionHealth, Simulation};
+use crate::oracle::{OracleClient, OracleUpdate};
+use crate::oracle_publisher::OracleUpdates;
+use crate::solana::{SolanaGateway, TxSender};
 use crate::state::AppState;
 use rust_decimal::Decimal;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::signature::{read_keypair_file, Keypair};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::signature::Signature;
 use solana_sdk::signer::Signer;
 use solana_sdk::transaction::Transaction;
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
+/// How often the crank runs and how hard it retries, from `LIQUIDATION_*` variables.
+#[derive(Clone, Debug)]
+pub struct CrankConfig {
+    /// Shortest gap between cycles; a cycle also waits for a new oracle reading.
+    pub interval: Duration,
+    /// Attempts per transaction before the item is given up for this cycle.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for each later one.
+    pub retry_backoff: Duration,
+    /// Liquidations sent per cycle; the least urgent of the rest wait for the next one.
+    pub max_liquidations: usize,
+    /// Simulate every transaction instead of sending it, see [`CrankPlan`].
+    pub dry_run: bool,
+}
+
+impl CrankConfig {
+    pub fn from_env() -> Self {
+        Self {
+            interval: Duration::from_secs(env_or("LIQUIDATION_INTERVAL_SECS", 5)),
+            max_attempts: env_or("LIQUIDATION_MAX_ATTEMPTS", 3u32).max(1),
+            retry_backoff: Duration::from_millis(env_or("LIQUIDATION_RETRY_BACKOFF_MS", 500)),
+            max_liquidations: env_or("LIQUIDATION_MAX_PER_CYCLE", 20usize).max(1),
+            dry_run: env_or("LIQUIDATION_DRY_RUN", false),
+        }
+    }
+}
+
+/// Signs and sends the crank's transactions with the keypair loaded at startup.
+struct Crank {
+    sender: TxSender,
+    solana: SolanaGateway,
+    config: CrankConfig,
+    /// Expected time between cycles: the crank's interval plus the wait for a new reading.
+    cycle: Duration,
+}
+
+/// Loads `keypair_path`, resolves liquidations left pending by the last run and spawns the
+/// crank, which liquidates against the readings the oracle publisher sends every
+/// `publisher_interval` and never writes prices itself; fails only if the keypair can't be read.
 pub async fn start_liquidation_crank(
     state: Arc<AppState>,
     oracle: OracleClient,
+    mut updates: OracleUpdates,
     solana: SolanaGateway,
     keypair_path: String,
-    interval_secs: u64,
-) {
+    config: CrankConfig,
+    publisher_interval: Duration,
+) -> Result<(), String> {
+    let sender = TxSender::new(&solana.rpc_url, &keypair_path, config.max_attempts, config.retry_backoff)?;
+    let crank = Crank {
+        sender,
+        solana,
+        cycle: config.interval + publisher_interval,
+        config,
+    };
+    crank.sweep_pending(&state).await;
     tokio::spawn(async move {
-        loop {
-            if let Err(err) = run_cycle(&state, &oracle, &solana, &keypair_path).await {
-                error!(error = %err, "liquidation crank cycle failed");
-            }
-            sleep(Duration::from_secs(interval_secs)).await;
+        // Stops when the publisher does, since no new prices would come.
+        while updates.changed().await.is_ok() {
+            let Some(update) = updates.borrow_and_update().clone() else {
+                continue;
+            };
+            run_cycle(&state, &oracle, &crank, &update).await;
+            sleep(crank.config.interval).await;
         }
+        error!("oracle publisher stopped; liquidation crank stopped");
     });
+    Ok(())
 }
 
-async fn run_cycle(
-    state: &Arc<AppState>,
-    oracle: &OracleClient,
-    solana: &SolanaGateway,
-    keypair_path: &str,
-) -> Result<(), String> {
-    let prices = oracle.fetch_prices().await.map_err(|err| err.to_string())?;
-    push_oracle_prices(oracle.markets(), &prices, solana, keypair_path)?;
-    process_liquidations(state, oracle, &prices, solana, keypair_path).await?;
-    Ok(())
+async fn run_cycle(state: &Arc<AppState>, oracle: &OracleClient, crank: &Crank, update: &OracleUpdate) {
+    crank.sweep_pending(state).await;
+    let liquidations = process_liquidations(state, oracle, update, crank).await;
+    if crank.config.dry_run {
+        info!(liquidations = liquidations.len(), "dry run: cycle planned");
+        *state.crank_plan.lock().unwrap() = Some(CrankPlan {
+            planned_at: unix_now(),
+            liquidations,
+        });
+    }
 }
 
-fn push_oracle_prices(
-    markets: &[OracleMarketConfig],
-    prices: &HashMap<String, Decimal>,
-    solana: &SolanaGateway,
-    keypair_path: &str,
-) -> Result<(), String> {
-    let keypair = read_keypair_file(keypair_path).map_err(|err| err.to_string())?;
-    let client = RpcClient::new(solana.rpc_url.clone());
-
-    for market in markets {
-        let price = prices
-            .get(&market.symbol)
-            .ok_or_else(|| OracleError::MissingStaticPrice(market.symbol.clone()))?;
-        let market_account = solana
-            .parse_pubkey(&market.market_account)
+impl Crank {
+    /// Sends a liquidate transaction until it confirms. Each attempt is added to the position's
+    /// pending liquidation before it goes out and none is forgotten, so one that lands after a
+    /// retry was sent, or after we gave up, still counts here and in [`Crank::sweep_pending`].
+    async fn submit_liquidation(&self, state: &AppState, pending: &mut PendingLiquidation, ix: Instruction) -> Result<Signature, String> {
+        let mut delay = self.config.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.attempt_liquidation(state, pending, &ix).await {
+                Ok(signature) => return Ok(signature),
+                Err(err) if attempt < self.config.max_attempts => {
+                    warn!(account = %pending.account_id, market = %pending.market, attempt, error = %err, "liquidation not confirmed; retrying");
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn attempt_liquidation(&self, state: &AppState, pending: &mut PendingLiquidation, ix: &Instruction) -> Result<Signature, String> {
+        let (recent, last_valid_block_height) = self
+            .sender
+            .client
+            .get_latest_blockhash_with_commitment(self.sender.client.commitment())
+            .await
             .map_err(|err| err.to_string())?;
-        let ix = solana.build_update_price_ix(
-            keypair.pubkey(),
-            market_account,
-            market.market_id,
-            price,
-        );
-        send_transaction(&client, &keypair, ix)?;
+        let tx = Transaction::new_signed_with_payer(std::slice::from_ref(ix), Some(&self.sender.keypair.pubkey()), &[&self.sender.keypair], recent);
+        let signature = tx.signatures[0];
+        let mut attempt = pending.clone();
+        attempt.attempts.push(LiquidationAttempt {
+            signature: signature.to_string(),
+            last_valid_block_height,
+        });
+        state.store.save_pending_liquidation(&attempt).await.map_err(|err| err.to_string())?;
+        *pending = attempt;
+
+        match self.sender.client.send_and_confirm_transaction(&tx).await {
+            Ok(signature) => Ok(signature),
+            // The send can fail after the transaction landed, e.g. on a confirmation timeout,
+            // and an earlier attempt may have landed since it was given up on.
+            Err(err) => match self.landed(pending).await {
+                Some(signature) => Ok(signature),
+                None => Err(err.to_string()),
+            },
+        }
     }
 
-    Ok(())
+    /// The attempt of `pending` that landed, if any has.
+    async fn landed(&self, pending: &PendingLiquidation) -> Option<Signature> {
+        for attempt in pending.attempts.iter().rev() {
+            let Ok(signature) = Signature::from_str(&attempt.signature) else {
+                continue;
+            };
+            if let Ok(Some(Ok(()))) = self.sender.client.get_signature_status(&signature).await {
+                return Some(signature);
+            }
+        }
+        None
+    }
+
+    /// Resolves every pending liquidation it can: confirmed ones are applied to the backend,
+    /// failed or expired ones are dropped so the crank may try the position again. The rest
+    /// stay pending until their transaction lands or its blockhash expires.
+    async fn sweep_pending(&self, state: &AppState) {
+        // A dry run leaves them to the live crank.
+        if self.config.dry_run {
+            return;
+        }
+        let pending = match state.store.load_pending_liquidations().await {
+            Ok(pending) => pending,
+            Err(err) => {
+                warn!(error = %err, "cannot load pending liquidations");
+                return;
+            }
+        };
+        if pending.is_empty() {
+            return;
+        }
+        let block_height = match self.sender.client.get_block_height().await {
+            Ok(height) => height,
+            Err(err) => {
+                warn!(error = %err, pending = pending.len(), "cannot read the block height; pending liquidations unresolved");
+                for entry in &pending {
+                    state.risk.mark_liquidating(entry.account_id, &entry.market);
+                }
+                return;
+            }
+        };
+        for entry in pending {
+            state.risk.mark_liquidating(entry.account_id, &entry.market);
+            if let Err(err) = self.resolve_pending(state, &entry, block_height).await {
+                warn!(account = %entry.account_id, market = %entry.market, attempts = entry.attempts.len(), error = %err, "cannot resolve pending liquidation");
+            }
+        }
+    }
+
+    async fn resolve_pending(&self, state: &AppState, entry: &PendingLiquidation, block_height: u64) -> Result<(), String> {
+        let mut statuses = Vec::with_capacity(entry.attempts.len());
+        for attempt in &entry.attempts {
+            let signature = Signature::from_str(&attempt.signature).map_err(|err| err.to_string())?;
+            let status = self
+                .sender
+                .client
+                .get_signature_status_with_commitment_and_history(&signature, self.sender.client.commitment(), true)
+                .await
+                .map_err(|err| err.to_string())?;
+            if let Some(Err(err)) = &status {
+                warn!(account = %entry.account_id, market = %entry.market, %signature, error = %err, "liquidation attempt failed on-chain");
+            }
+            statuses.push(status);
+        }
+        match entry.outcome(&statuses, block_height) {
+            PendingOutcome::Confirmed(signature) => {
+                let mut accounts = state.accounts.write().await;
+                match accounts.get_mut(&entry.account_id) {
+                    Some(account) => {
+                        let pnl = apply_liquidation(state, account, &entry.market, entry.exit_price).await?;
+                        info!(account = %entry.account_id, market = %entry.market, %signature, pnl = ?pnl, "pending liquidation confirmed");
+                    }
+                    None => drop_pending(state, entry.account_id, &entry.market).await?,
+                }
+            }
+            PendingOutcome::Dropped => {
+                warn!(account = %entry.account_id, market = %entry.market, attempts = entry.attempts.len(), "every liquidation attempt failed or expired; position kept");
+                drop_pending(state, entry.account_id, &entry.market).await?;
+            }
+            PendingOutcome::Waiting => {}
+        }
+        Ok(())
+    }
 }
 
+/// Liquidates the positions whose market's trigger fires, deepest margin deficit first and at
+/// most `max_liquidations` per cycle. A position that can't be liquidated is logged and left
+/// for the next cycle; the others go ahead. In a dry run the liquidations are simulated and
+/// returned instead, and no position changes. The accounts are only locked to build the
+/// queue and to apply each confirmed liquidation, never across sends and retries.
 async fn process_liquidations(
     state: &Arc<AppState>,
     oracle: &OracleClient,
-    prices: &HashMap<String, Decimal>,
-    solana: &SolanaGateway,
-    keypair_path: &str,
-) -> Result<(), String> {
-    let keypair = read_keypair_file(keypair_path).map_err(|err| err.to_string())?;
-    let client = RpcClient::new(solana.rpc_url.clone());
-    let mut accounts = state.accounts.write().await;
-    let account_ids: Vec<_> = accounts.keys().cloned().collect();
-
-    for account_id in account_ids {
-        if let Some(account) = accounts.get_mut(&account_id) {
-            let risk_result = state
-                .risk
-                .check_risk(account, crate::models::RiskCheckRequest {
-                    mark_prices: prices.clone(),
-                })
-                .map_err(|err| err.to_string())?;
+    update: &OracleUpdate,
+    crank: &Crank,
+) -> Vec<PlannedLiquidation> {
+    let prices = &update.prices;
+    let halted = update.halted();
+    let marks = state.marks.marks_for_index(prices);
+    let twap_marks = state.marks.marks_for_index(&update.twaps);
+    let triggers = marks
+        .keys()
+        .filter_map(|market| Some((market.clone(), oracle.market_by_symbol(market)?.twap.liquidate_on)))
+        .collect();
+    let crank_marks = CrankMarks {
+        spot: marks.clone(),
+        twap: twap_marks.clone(),
+        triggers,
+        as_of: std::time::Instant::now(),
+        cycle: crank.cycle,
+    };
+    *state.crank_marks.lock().unwrap() = Some(crank_marks.clone());
+    let accounts = state.accounts.read().await;
 
-            for market in risk_result.liquidatable_positions {
-                if let Some(position) = account.positions.get(&market).cloned() {
-                    let exit_price = prices.get(&market).copied().unwrap_or(Decimal::ZERO);
-                    let position_account = position
-                        .position_account
-                        .clone()
-                        .ok_or_else(|| "missing position account".to_string())?;
-                    let market_config = oracle
-                        .market_by_symbol(&market)
-                        .ok_or_else(|| "missing market config".to_string())?;
-                    let market_account = solana
-                        .parse_pubkey(&market_config.market_account)
-                        .map_err(|err| err.to_string())?;
-                    let position_pubkey = solana
-                        .parse_pubkey(&position_account)
-                        .map_err(|err| err.to_string())?;
-
-                    let pnl = state
-                        .risk
-                        .force_liquidate(account, &market, exit_price)
-                        .map_err(|err| err.to_string())?;
-
-                    state
-                        .store
-                        .delete_position(account.id, &market)
-                        .await
-                        .map_err(|err| err.to_string())?;
-                    state
-                        .store
-                        .update_account_collateral(account.id, account.collateral)
-                        .await
-                        .map_err(|err| err.to_string())?;
-
-                    let account_state = account
-                        .account_state
-                        .clone()
-                        .ok_or_else(|| "missing account state".to_string())?;
-                    let ix = solana.build_liquidate_ix(
-                        keypair.pubkey(),
-                        solana.parse_pubkey(&account_state).map_err(|err| err.to_string())?,
-                        market_account,
-                        position_pubkey,
-                        market_config.market_id,
-                        exit_price,
-                    );
-                    send_transaction(&client, &keypair, ix)?;
-                    info!(account = %account.id, market = %market, pnl = %pnl, "liquidated");
-                }
+    let mut queue: Vec<(PositionHealth, LiquidationTrigger)> = Vec::new();
+    for (account_id, account) in accounts.iter() {
+        // An account holding a market without any accepted price can't be valued yet.
+        let risk_result = match state.risk.check_risk(account, &marks) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!(account = %account_id, error = %err, "skipping risk check");
+                continue;
+            }
+        };
+
+        // Positions valued at the TWAP, for markets whose liquidations wait on it.
+        let twap_liquidatable: HashSet<String> = match state.risk.check_risk(account, &twap_marks) {
+            Ok(result) => result.liquidatable_positions.into_iter().collect(),
+            Err(err) => {
+                warn!(account = %account_id, error = %err, "no twap risk check");
+                HashSet::new()
+            }
+        };
+        let spot_liquidatable: HashSet<String> = risk_result.liquidatable_positions.into_iter().collect();
+
+        for market in spot_liquidatable.union(&twap_liquidatable) {
+            let trigger = oracle
+                .market_by_symbol(market)
+                .map(|config| config.twap.liquidate_on)
+                .unwrap_or_default();
+            if !trigger.triggers(spot_liquidatable.contains(market), twap_liquidatable.contains(market)) {
+                continue;
+            }
+            if halted.contains(market) {
+                warn!(account = %account_id, market = %market, "liquidation halted by oracle guard");
+                continue;
+            }
+            if state.risk.is_liquidating(*account_id, market) {
+                info!(account = %account_id, market = %market, "liquidation awaiting confirmation");
+                continue;
+            }
+            if !state.risk.allows_liquidation(market) {
+                warn!(account = %account_id, market = %market, "liquidation held while the market is paused or settled");
+                continue;
+            }
+            // Ranked at the price that triggered it, so a TWAP-only breach isn't last in line.
+            match crank_marks.health(&state.risk, account, market) {
+                Ok(health) => queue.push((health, trigger)),
+                Err(err) => warn!(account = %account_id, market = %market, error = %err, "cannot rank position"),
             }
         }
     }
 
-    Ok(())
+    queue.sort_by(|(a, _), (b, _)| by_priority(a, b));
+    if queue.len() > crank.config.max_liquidations {
+        warn!(
+            queued = queue.len(),
+            budget = crank.config.max_liquidations,
+            "more liquidatable positions than the cycle budget; the rest wait for the next cycle"
+        );
+        queue.truncate(crank.config.max_liquidations);
+    }
+    drop(accounts);
+
+    let mut planned = Vec::new();
+    for (health, trigger) in queue {
+        let market = &health.market;
+        let exit_price = prices.get(market).copied().unwrap_or(Decimal::ZERO);
+        if crank.config.dry_run {
+            let simulation = match account_liquidate_ix(state, oracle, crank, health.account_id, market, exit_price).await {
+                Ok(ix) => crank.sender.simulate(&[ix]).await,
+                Err(err) => Simulation::failed(err),
+            };
+            info!(
+                account = %health.account_id,
+                market = %market,
+                trigger = trigger.as_str(),
+                deficit = %health.margin_deficit,
+                %exit_price,
+                error = simulation.error.as_deref().unwrap_or("-"),
+                "dry run: would liquidate"
+            );
+            planned.push(PlannedLiquidation {
+                account_id: health.account_id,
+                market: market.clone(),
+                exit_price,
+                margin_deficit: health.margin_deficit,
+                trigger: trigger.as_str().to_string(),
+                simulation,
+            });
+            continue;
+        }
+        match liquidate(state, oracle, crank, health.account_id, market, exit_price).await {
+            Ok(pnl) => info!(
+                account = %health.account_id,
+                market = %market,
+                trigger = trigger.as_str(),
+                deficit = %health.margin_deficit,
+                pnl = %pnl,
+                "liquidated"
+            ),
+            Err(err) => error!(account = %health.account_id, market = %market, error = %err, "liquidation failed"),
+        }
+    }
+    planned
 }
 
-fn send_transaction(
-    client: &RpcClient,
-    keypair: &Keypair,
-    ix: solana_sdk::instruction::Instruction,
-) -> Result<(), String> {
-    let recent = client.get_latest_blockhash().map_err(|err| err.to_string())?;
-    let tx = Transaction::new_signed_with_payer(
-        &[ix],
-        Some(&keypair.pubkey()),
-        &[keypair],
-        recent,
-    );
-    client
-        .send_and_confirm_transaction(&tx)
+async fn liquidate(
+    state: &AppState,
+    oracle: &OracleClient,
+    crank: &Crank,
+    account_id: Uuid,
+    market: &str,
+    exit_price: Decimal,
+) -> Result<Decimal, String> {
+    // Marked first, so the position can't be closed between reading it and sending.
+    state.risk.mark_liquidating(account_id, market);
+    let ix = match account_liquidate_ix(state, oracle, crank, account_id, market, exit_price).await {
+        Ok(ix) => ix,
+        Err(err) => {
+            state.risk.clear_liquidating(account_id, market);
+            return Err(err);
+        }
+    };
+
+    // The chain goes first: the backend position is only closed once the transaction is
+    // confirmed, and an unconfirmed one stays pending for the sweep.
+    let mut pending = PendingLiquidation {
+        account_id,
+        market: market.to_string(),
+        exit_price,
+        attempts: Vec::new(),
+        created_at: unix_now(),
+    };
+    let signature = match crank.submit_liquidation(state, &mut pending, ix).await {
+        Ok(signature) => signature,
+        // Nothing was recorded, so nothing can have been sent either.
+        Err(err) if pending.attempts.is_empty() => {
+            state.risk.clear_liquidating(account_id, market);
+            return Err(err);
+        }
+        Err(err) => return Err(format!("{}; left pending", err)),
+    };
+    info!(account = %account_id, market = %market, %signature, "liquidation confirmed");
+    let mut accounts = state.accounts.write().await;
+    let Some(account) = accounts.get_mut(&account_id) else {
+        drop_pending(state, account_id, market).await?;
+        return Err("account not found".to_string());
+    };
+    apply_liquidation(state, account, market, exit_price)
+        .await?
+        .ok_or_else(|| "position already closed".to_string())
+}
+
+/// [`liquidate_ix`] for the account as it is now, read under a short lock.
+async fn account_liquidate_ix(
+    state: &AppState,
+    oracle: &OracleClient,
+    crank: &Crank,
+    account_id: Uuid,
+    market: &str,
+    exit_price: Decimal,
+) -> Result<Instruction, String> {
+    let accounts = state.accounts.read().await;
+    let account = accounts
+        .get(&account_id)
+        .ok_or_else(|| "account not found".to_string())?;
+    liquidate_ix(oracle, crank, account, market, exit_price)
+}
+
+fn liquidate_ix(
+    oracle: &OracleClient,
+    crank: &Crank,
+    account: &Account,
+    market: &str,
+    exit_price: Decimal,
+) -> Result<Instruction, String> {
+    let solana = &crank.solana;
+    let position = account
+        .positions
+        .get(market)
+        .ok_or_else(|| "position already closed".to_string())?;
+    let position_account = position
+        .position_account
+        .as_deref()
+        .ok_or_else(|| "missing position account".to_string())?;
+    let account_state = account
+        .account_state
+        .as_deref()
+        .ok_or_else(|| "missing account state".to_string())?;
+    let market_config = oracle
+        .market_by_symbol(market)
+        .ok_or_else(|| "missing market config".to_string())?;
+    let parse = |key: &str| solana.parse_pubkey(key).map_err(|err| err.to_string());
+    Ok(solana.build_liquidate_ix(
+        crank.sender.pubkey(),
+        parse(account_state)?,
+        parse(&market_config.market_account)?,
+        parse(position_account)?,
+        market_config.market_id,
+        exit_price,
+    ))
+}
+
+/// Closes the position in the backend after its liquidation confirmed on-chain. Safe to repeat:
+/// a position that is already gone yields `None` and only the pending entry is cleared. The
+/// account only changes once the store has it, so a failed write leaves both as they were and
+/// the entry pending for the next sweep.
+async fn apply_liquidation(
+    state: &AppState,
+    account: &mut Account,
+    market: &str,
+    exit_price: Decimal,
+) -> Result<Option<Decimal>, String> {
+    let mut liquidated = account.clone();
+    let pnl = state.risk.force_liquidate(&mut liquidated, market, exit_price).ok();
+    state
+        .store
+        .delete_position(account.id, market)
+        .await
         .map_err(|err| err.to_string())?;
+    state
+        .store
+        .update_account_collateral(account.id, liquidated.collateral)
+        .await
+        .map_err(|err| err.to_string())?;
+    *account = liquidated;
+    drop_pending(state, account.id, market).await?;
+
+    if let Some(pnl) = pnl {
+        state.events.publish(Event::Liquidation {
+            account_id: account.id,
+            market: market.to_string(),
+            exit_price,
+            pnl,
+        });
+        state.events.publish(Event::Position {
+            account_id: account.id,
+            market: market.to_string(),
+            position: None,
+        });
+        state.events.publish(Event::Account {
+            account: account.clone(),
+        });
+    }
+    Ok(pnl)
+}
+
+async fn drop_pending(state: &AppState, account_id: Uuid, market: &str) -> Result<(), String> {
+    state
+        .store
+        .delete_pending_liquidation(account_id, market)
+        .await
+        .map_err(|err| err.to_string())?;
+    state.risk.clear_liquidating(account_id, market);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{OracleConfig, OracleSource, ViolationAction};
+    use crate::mock_rpc;
+    use crate::oracle::{GuardViolation, OracleError};
+    use crate::models::{Position, Side};
+    use crate::test_support::{state_with, state_with_store, FlakyStore};
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{write_keypair_file, Keypair};
+    use std::collections::HashMap;
+
+    /// Long 1 BTC at 60000 with 100 of collateral, held on-chain.
+    fn account() -> Account {
+        let position = Position {
+            market: "BTC".to_string(),
+            side: Side::Long,
+            base_qty: Decimal::ONE,
+            entry_price: Decimal::from(60_000),
+            leverage_bps: 100_000,
+            position_account: Some(Pubkey::new_unique().to_string()),
+        };
+        Account {
+            id: Uuid::new_v4(),
+            owner: Pubkey::new_unique().to_string(),
+            account_state: Some(Pubkey::new_unique().to_string()),
+            collateral: Decimal::from(100),
+            positions: HashMap::from([("BTC".to_string(), position)]),
+        }
+    }
+
+    /// A crank signing with a fresh keypair against the mock RPC at `url`.
+    fn crank(url: &str, config: CrankConfig) -> Crank {
+        let keypair_path = std::env::temp_dir().join(format!("crank-{}.json", Uuid::new_v4()));
+        write_keypair_file(&Keypair::new(), &keypair_path).unwrap();
+        let sender = TxSender::new(url, keypair_path.to_str().unwrap(), config.max_attempts, config.retry_backoff).unwrap();
+        std::fs::remove_file(&keypair_path).ok();
+        Crank {
+            sender,
+            solana: SolanaGateway::new(url, &Pubkey::new_unique().to_string()),
+            cycle: config.interval,
+            config,
+        }
+    }
+
+    fn dry_run_config() -> CrankConfig {
+        CrankConfig {
+            interval: Duration::ZERO,
+            max_attempts: 1,
+            retry_backoff: Duration::ZERO,
+            max_liquidations: 20,
+            dry_run: true,
+        }
+    }
+
+    fn oracle(state: &AppState, url: &str) -> OracleClient {
+        let market = serde_json::from_value(serde_json::json!({
+            "symbol": "BTC",
+            "market_id": 1,
+            "oracle_pubkey": Pubkey::new_unique().to_string(),
+            "market_account": Pubkey::new_unique().to_string(),
+        }))
+        .unwrap();
+        OracleClient::new(
+            OracleConfig { markets: vec![market] },
+            vec![OracleSource::Static],
+            Some(url.to_string()),
+            state.prices.clone(),
+        )
+    }
+
+    /// BTC at 59000.
+    fn update() -> OracleUpdate {
+        OracleUpdate {
+            prices: HashMap::from([("BTC".to_string(), Decimal::from(59_000))]),
+            ..Default::default()
+        }
+    }
+
+    /// Runs one cycle of `config` against the mock RPC with `update` and returns its plan.
+    async fn plan(state: &Arc<AppState>, reject_simulations: bool, config: CrankConfig, update: OracleUpdate) -> CrankPlan {
+        let url = mock_rpc::spawn(reject_simulations).await;
+        run_cycle(state, &oracle(state, &url), &crank(&url, config), &update).await;
+        state.crank_plan.lock().unwrap().clone().expect("dry run publishes its plan")
+    }
+
+    /// Runs one dry-run cycle against the mock RPC with BTC at 59000 and returns its plan.
+    async fn dry_run(state: &Arc<AppState>, reject_simulations: bool) -> CrankPlan {
+        plan(state, reject_simulations, dry_run_config(), update()).await
+    }
+
+    #[tokio::test]
+    async fn a_dry_run_plans_and_simulates_without_liquidating() {
+        let account = account();
+        let state = state_with(vec![account.clone()]);
+
+        let plan = dry_run(&state, false).await;
+        assert_eq!(plan.liquidations.len(), 1);
+        let planned = &plan.liquidations[0];
+        assert_eq!((planned.account_id, planned.market.as_str()), (account.id, "BTC"));
+        assert_eq!(planned.exit_price, Decimal::from(59_000));
+        assert_eq!(planned.trigger, "spot");
+        assert!(planned.margin_deficit > Decimal::ZERO);
+        assert_eq!(planned.simulation.error, None);
+        assert!(planned.simulation.logs.iter().any(|log| log.contains("mock-rpc simulated")));
+
+        // Nothing was sent, recorded or closed.
+        assert!(state.accounts.read().await[&account.id].positions.contains_key("BTC"));
+        assert!(!state.risk.is_liquidating(account.id, "BTC"));
+        assert!(state.store.load_pending_liquidations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_simulations_are_reported_in_the_plan() {
+        let account = account();
+        let state = state_with(vec![account.clone()]);
+
+        let plan = dry_run(&state, true).await;
+        assert_eq!(plan.liquidations.len(), 1);
+        let error = plan.liquidations[0].simulation.error.as_deref().unwrap();
+        assert!(error.contains("custom program error"), "{}", error);
+        assert!(state.accounts.read().await[&account.id].positions.contains_key("BTC"));
+    }
+
+    #[tokio::test]
+    async fn healthy_positions_are_not_planned() {
+        let mut account = account();
+        account.collateral = Decimal::from(10_000);
+        let state = state_with(vec![account]);
+
+        assert!(dry_run(&state, false).await.liquidations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_cycle_budget_keeps_the_deepest_deficits() {
+        let deepest = account();
+        let mut shallower = account();
+        shallower.collateral = Decimal::from(200);
+        let state = state_with(vec![shallower, deepest.clone()]);
+        assert_eq!(plan(&state, false, dry_run_config(), update()).await.liquidations.len(), 2);
+
+        let config = CrankConfig {
+            max_liquidations: 1,
+            ..dry_run_config()
+        };
+        let plan = plan(&state, false, config, update()).await;
+        assert_eq!(plan.liquidations.len(), 1);
+        assert_eq!(plan.liquidations[0].account_id, deepest.id);
+    }
+
+    #[tokio::test]
+    async fn halted_markets_are_skipped() {
+        let state = state_with(vec![account()]);
+        let update = OracleUpdate {
+            violations: vec![GuardViolation {
+                symbol: "BTC".to_string(),
+                error: OracleError::InvalidPrice("BTC".to_string()),
+                action: ViolationAction::HaltLiquidations,
+            }],
+            ..update()
+        };
+        assert!(plan(&state, false, dry_run_config(), update).await.liquidations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_liquidations_back_off_and_keep_every_attempt() {
+        let account = account();
+        let state = state_with(vec![account.clone()]);
+        let url = mock_rpc::spawn(false).await;
+        let config = CrankConfig {
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(50),
+            dry_run: false,
+            ..dry_run_config()
+        };
+        let crank = crank(&url, config);
+        let ix = liquidate_ix(&oracle(&state, &url), &crank, &account, "BTC", Decimal::from(59_000)).unwrap();
+        let mut pending = PendingLiquidation {
+            account_id: account.id,
+            market: "BTC".to_string(),
+            exit_price: Decimal::from(59_000),
+            attempts: Vec::new(),
+            created_at: unix_now(),
+        };
+
+        // The mock refuses every send: two retries, 50 and 100 ms apart.
+        let started = std::time::Instant::now();
+        let err = crank.submit_liquidation(&state, &mut pending, ix).await.unwrap_err();
+        assert!(err.contains("does not accept transactions"), "{}", err);
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(pending.attempts.len(), 3);
+        let stored = state.store.load_pending_liquidations().await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].attempts.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_liquidation_the_store_refuses_leaves_the_account_as_it_was() {
+        let account = account();
+        let store = Arc::new(FlakyStore::failing(&["update_account_collateral"]));
+        let state = state_with_store(store.clone(), None, vec![account.clone()]);
+        let exit_price = Decimal::from(59_000);
+
+        let mut accounts = state.accounts.write().await;
+        let held = accounts.get_mut(&account.id).unwrap();
+        assert!(apply_liquidation(&state, held, "BTC", exit_price).await.is_err());
+        assert_eq!((held.collateral, held.positions.len()), (account.collateral, 1));
+
+        store.recover();
+        assert!(apply_liquidation(&state, held, "BTC", exit_price).await.unwrap().is_some());
+        assert!(held.positions.is_empty());
+        assert!(apply_liquidation(&state, held, "BTC", exit_price).await.unwrap().is_none());
+    }
+}
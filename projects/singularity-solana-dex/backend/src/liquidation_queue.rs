@@ -0,0 +1,292 @@
+//! Ranking positions for liquidation. The crank works through liquidatable positions deepest
+//! margin deficit first, the larger notional breaking ties, and `/liquidations/queue` shows
+//! every position near its liquidation price in that same order, valued at the prices the
+//! crank last liquidated against. A crank in dry-run mode publishes what it would have sent
+//! at `/liquidations/plan`.
+
+use crate::config::LiquidationTrigger;
+use crate::errors::{AppError, RiskError};
+use crate::models::{Account, PositionHealth, Simulation};
+use crate::risk::RiskEngine;
+use crate::state::AppState;
+use axum::extract::{Query, State};
+use axum::Json;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+const DEFAULT_MAX_HEALTH_RATIO: Decimal = Decimal::from_parts(15, 0, 0, false, 1);
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1_000;
+/// Crank cycles missed before the queue stops trusting the crank's prices.
+pub const STALE_AFTER_CYCLES: u32 = 3;
+
+/// Liquidation order: the deepest margin deficit first, then the larger notional, then the
+/// lower health ratio.
+pub fn by_priority(a: &PositionHealth, b: &PositionHealth) -> Ordering {
+    b.margin_deficit
+        .cmp(&a.margin_deficit)
+        .then_with(|| b.notional.cmp(&a.notional))
+        .then_with(|| a.health_ratio.cmp(&b.health_ratio))
+}
+
+/// The prices of the crank's latest cycle: each market's accepted oracle price and TWAP, and
+/// which of them its liquidations wait on.
+#[derive(Clone, Debug)]
+pub struct CrankMarks {
+    pub spot: HashMap<String, Decimal>,
+    pub twap: HashMap<String, Decimal>,
+    pub triggers: HashMap<String, LiquidationTrigger>,
+    pub as_of: Instant,
+    /// How long a cycle is expected to take; see [`STALE_AFTER_CYCLES`].
+    pub cycle: Duration,
+}
+
+impl CrankMarks {
+    /// Whether the crank has run recently enough to rank by its prices; a stalled crank's
+    /// last prices would keep showing positions at levels long gone.
+    pub fn is_current(&self) -> bool {
+        self.as_of.elapsed() <= self.cycle * STALE_AFTER_CYCLES
+    }
+
+    /// Whether the crank had an accepted price for `market`.
+    pub fn covers(&self, market: &str) -> bool {
+        self.spot.contains_key(market)
+    }
+
+    /// `market`'s position valued at the price its trigger watches: the TWAP for `twap`, and
+    /// for `both` whichever of the two shows it healthier, since both must breach.
+    pub fn health(&self, risk: &RiskEngine, account: &Account, market: &str) -> Result<PositionHealth, RiskError> {
+        let spot = || risk.position_health(account, market, &self.spot);
+        let twap = || risk.position_health(account, market, &self.twap);
+        match self.triggers.get(market).copied().unwrap_or_default() {
+            LiquidationTrigger::Spot => spot(),
+            LiquidationTrigger::Twap => twap(),
+            LiquidationTrigger::Both => match (spot(), twap()) {
+                (Ok(spot), Ok(twap)) => Ok(if twap.margin_deficit < spot.margin_deficit { twap } else { spot }),
+                (spot, _) => spot,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct QueueQuery {
+    /// Only positions at or below this health ratio (default 1.5); liquidatable ones are always listed.
+    max_health_ratio: Option<Decimal>,
+    /// Default 100, at most 1000.
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LiquidationQueue {
+    /// Liquidatable positions, including any cut off by `limit`.
+    pub liquidatable: usize,
+    /// In the order the crank would liquidate them.
+    pub positions: Vec<PositionHealth>,
+    /// Markets left out because they have no price.
+    pub unpriced: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/liquidations/queue",
+    tag = "accounts",
+    params(QueueQuery),
+    responses(
+        (status = 200, description = "Positions closest to liquidation, valued at the prices the crank last liquidated against, or at the mark for markets it doesn't price and while the crank is stalled", body = LiquidationQueue),
+        (status = 422, body = ErrorResponse)
+    )
+)]
+pub async fn get_queue(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<QueueQuery>,
+) -> Result<Json<LiquidationQueue>, AppError> {
+    let max_health_ratio = params.max_health_ratio.unwrap_or(DEFAULT_MAX_HEALTH_RATIO);
+    if max_health_ratio <= Decimal::ZERO {
+        return Err(AppError::InvalidParameter {
+            name: "max_health_ratio",
+            reason: "must be positive".to_string(),
+        });
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    let accounts: Vec<Account> = state.accounts.read().await.values().cloned().collect();
+    let mut symbols: Vec<String> = accounts
+        .iter()
+        .flat_map(|account| account.positions.keys().cloned())
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+
+    // Valued as the crank would: at its latest oracle prices and with the trigger each market
+    // waits on. Other markets, and every market once the crank stalls, are valued at the
+    // mark, one at a time, so a market without a price doesn't hide the others.
+    let crank = state
+        .crank_marks
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(CrankMarks::is_current);
+    let covered = |market: &str| crank.as_ref().is_some_and(|crank| crank.covers(market));
+    let mut marks = HashMap::new();
+    let mut unpriced = Vec::new();
+    for symbol in symbols.into_iter().filter(|symbol| !covered(symbol)) {
+        match state.marks.resolve(&state.prices, std::slice::from_ref(&symbol), &HashMap::new()).await {
+            Ok(resolved) => marks.extend(resolved),
+            Err(_) => unpriced.push(symbol),
+        }
+    }
+
+    let mut positions = Vec::new();
+    for account in &accounts {
+        for market in account.positions.keys() {
+            let health = match &crank {
+                Some(crank) if crank.covers(market) => crank.health(&state.risk, account, market),
+                _ if marks.contains_key(market) => state.risk.position_health(account, market, &marks),
+                _ => continue,
+            };
+            match health {
+                Ok(health) => positions.push(health),
+                Err(err) => warn!(account = %account.id, market = %market, error = %err, "cannot value position"),
+            }
+        }
+    }
+    let liquidatable = positions.iter().filter(|health| health.liquidatable).count();
+    positions.retain(|health| {
+        health.liquidatable || health.health_ratio.is_some_and(|ratio| ratio <= max_health_ratio)
+    });
+    positions.sort_by(by_priority);
+    positions.truncate(limit);
+
+    Ok(Json(LiquidationQueue {
+        liquidatable,
+        positions,
+        unpriced,
+    }))
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct PlannedLiquidation {
+    pub account_id: Uuid,
+    pub market: String,
+    pub exit_price: Decimal,
+    pub margin_deficit: Decimal,
+    /// `spot`, `twap` or `both`, see the market's `twap.liquidate_on`.
+    pub trigger: String,
+    pub simulation: Simulation,
+}
+
+/// What one dry-run cycle of the crank would have sent, in order.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct CrankPlan {
+    /// Unix seconds.
+    pub planned_at: i64,
+    pub liquidations: Vec<PlannedLiquidation>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/liquidations/plan",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "The latest dry-run cycle; `null` unless the crank runs with `LIQUIDATION_DRY_RUN`", body = Option<CrankPlan>)
+    )
+)]
+pub async fn get_plan(State(state): State<Arc<AppState>>) -> Json<Option<CrankPlan>> {
+    Json(state.crank_plan.lock().unwrap().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Position, Side};
+    use crate::test_support::{app, request, send, state_with};
+    use axum::http::{Method, StatusCode};
+
+    /// Long 1 BTC at 60000 with `collateral`; BTC's maintenance margin is 0.35%.
+    fn account(collateral: i64) -> Account {
+        let position = Position {
+            market: "BTC".to_string(),
+            side: Side::Long,
+            base_qty: Decimal::ONE,
+            entry_price: Decimal::from(60_000),
+            leverage_bps: 100_000,
+            position_account: None,
+        };
+        Account {
+            id: Uuid::new_v4(),
+            owner: "trader".to_string(),
+            account_state: None,
+            collateral: Decimal::from(collateral),
+            positions: HashMap::from([("BTC".to_string(), position)]),
+        }
+    }
+
+    fn crank(trigger: LiquidationTrigger) -> CrankMarks {
+        CrankMarks {
+            spot: HashMap::from([("BTC".to_string(), Decimal::from(60_000))]),
+            twap: HashMap::from([("BTC".to_string(), Decimal::from(59_000))]),
+            triggers: HashMap::from([("BTC".to_string(), trigger)]),
+            as_of: Instant::now(),
+            cycle: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn positions_are_valued_at_the_price_their_trigger_watches() {
+        let risk = RiskEngine::new(crate::risk::default_markets());
+        // Healthy at the spot price, wiped out at the TWAP.
+        let account = account(1_000);
+
+        let spot = crank(LiquidationTrigger::Spot).health(&risk, &account, "BTC").unwrap();
+        assert_eq!((spot.mark_price, spot.margin_deficit), (Decimal::from(60_000), Decimal::ZERO));
+
+        let twap = crank(LiquidationTrigger::Twap).health(&risk, &account, "BTC").unwrap();
+        assert_eq!(twap.mark_price, Decimal::from(59_000));
+        assert_eq!(twap.margin_deficit, Decimal::new(2065, 1));
+        assert!(twap.liquidatable);
+
+        // Both must breach, so the healthier reading is the binding one.
+        let both = crank(LiquidationTrigger::Both).health(&risk, &account, "BTC").unwrap();
+        assert_eq!(both.margin_deficit, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn the_queue_ranks_with_the_cranks_prices() {
+        let (twap_triggered, thin) = (account(1_000), account(1_100));
+        let state = state_with(vec![twap_triggered.clone(), thin.clone()]);
+        *state.crank_marks.lock().unwrap() = Some(crank(LiquidationTrigger::Twap));
+
+        let (status, _, body) = send(&app(&state), request(Method::GET, "/liquidations/queue", serde_json::Value::Null)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["liquidatable"], 2);
+        let order: Vec<&str> = body["positions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|health| health["account_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(order, [twap_triggered.id.to_string(), thin.id.to_string()]);
+        assert_eq!(body["positions"][0]["mark_price"], "59000");
+    }
+
+    #[tokio::test]
+    async fn a_stalled_cranks_prices_give_way_to_the_mark() {
+        let account = account(1_000);
+        let state = state_with(vec![account]);
+        let mut stalled = crank(LiquidationTrigger::Twap);
+        stalled.as_of -= stalled.cycle * (STALE_AFTER_CYCLES + 1);
+        *state.crank_marks.lock().unwrap() = Some(stalled);
+
+        let (status, _, body) = send(&app(&state), request(Method::GET, "/liquidations/queue", serde_json::Value::Null)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(body["positions"][0]["mark_price"], "59000");
+    }
+}
@@ -0,0 +1,293 @@
+//! Server-side mark prices: the index plus a smoothed, clamped basis, the way perp venues
+//! keep a single fill from moving everyone's margin.
+//!
+//! The basis is the premium of our fills over the index at the time of the fill, kept as a
+//! fraction of the index. Each fill moves it `alpha` of the way toward that fill's premium,
+//! it decays toward zero between fills, and it is clamped on both ends so the mark never
+//! strays more than `clamp_bps` from the index.
+//!
+//! Fill prices come from clients, so a fill further than `max_fill_deviation_bps` from the
+//! index is refused before it executes (see [`MarkPrices::check_fill`]). Simulation mode only
+//! lets risk checks value an account at client-supplied marks; it never changes what trades
+//! are accepted or how they move the basis.
+
+use crate::errors::{AppError, RiskError};
+use crate::price_feed::PriceFeed;
+use rust_decimal::prelude::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+const BPS_DIVISOR: i64 = 10_000;
+/// Marks carry more precision than any market's tick size.
+const MARK_SCALE: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkPriceMode {
+    /// Every risk check uses the server's marks; client-supplied marks are ignored.
+    Authoritative,
+    /// Risk checks may value an account at client-supplied marks, for what-if checks. Nothing
+    /// that changes an account uses them.
+    Simulation,
+}
+
+pub struct MarkPriceConfig {
+    pub mode: MarkPriceMode,
+    /// Weight of each new fill's premium in the basis.
+    pub alpha: f64,
+    /// Time for the basis to halve without fills.
+    pub half_life_secs: f64,
+    pub clamp_bps: u32,
+    /// Furthest a fill price may be from the index.
+    pub max_fill_deviation_bps: u32,
+}
+
+impl MarkPriceConfig {
+    /// Reads `MARK_PRICE_MODE` (`authoritative` or `simulation`), `MARK_BASIS_ALPHA` (default 0.1),
+    /// `MARK_BASIS_HALF_LIFE_SECS` (300), `MARK_BASIS_CLAMP_BPS` (50) and
+    /// `MARK_FILL_MAX_DEVIATION_BPS` (100).
+    pub fn from_env() -> Result<Self, std::io::Error> {
+        let mode = match std::env::var("MARK_PRICE_MODE")
+            .unwrap_or_else(|_| "authoritative".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "authoritative" => MarkPriceMode::Authoritative,
+            "simulation" => MarkPriceMode::Simulation,
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unknown mark price mode {:?}", other),
+                ))
+            }
+        };
+        let alpha = std::env::var("MARK_BASIS_ALPHA")
+            .ok()
+            .and_then(|val| val.parse::<f64>().ok())
+            .unwrap_or(0.1)
+            .clamp(0.0, 1.0);
+        let half_life_secs = std::env::var("MARK_BASIS_HALF_LIFE_SECS")
+            .ok()
+            .and_then(|val| val.parse::<f64>().ok())
+            .filter(|secs| *secs > 0.0)
+            .unwrap_or(300.0);
+        let clamp_bps = std::env::var("MARK_BASIS_CLAMP_BPS")
+            .ok()
+            .and_then(|val| val.parse::<u32>().ok())
+            .unwrap_or(50);
+        let max_fill_deviation_bps = std::env::var("MARK_FILL_MAX_DEVIATION_BPS")
+            .ok()
+            .and_then(|val| val.parse::<u32>().ok())
+            .unwrap_or(100);
+        Ok(Self {
+            mode,
+            alpha,
+            half_life_secs,
+            clamp_bps,
+            max_fill_deviation_bps,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct MarkPrice {
+    pub symbol: String,
+    pub mark: Decimal,
+    pub index: Decimal,
+    /// Premium of the mark over the index.
+    pub basis_bps: Decimal,
+    /// Fills folded into the basis since startup.
+    pub fills: u64,
+}
+
+struct Basis {
+    ratio: Decimal,
+    updated: Instant,
+    fills: u64,
+}
+
+pub struct MarkPrices {
+    config: MarkPriceConfig,
+    basis: Mutex<HashMap<String, Basis>>,
+}
+
+impl MarkPrices {
+    pub fn new(config: MarkPriceConfig) -> Self {
+        Self {
+            config,
+            basis: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Refuses a fill at `price` further than `max_fill_deviation_bps` from `index`.
+    pub fn check_fill(&self, symbol: &str, price: Decimal, index: Decimal) -> Result<(), RiskError> {
+        if index <= Decimal::ZERO {
+            return Ok(());
+        }
+        let deviation_bps = ((price - index) / index).abs() * Decimal::from(BPS_DIVISOR);
+        if deviation_bps > Decimal::from(self.config.max_fill_deviation_bps) {
+            return Err(RiskError::FillPriceOutOfRange {
+                market: symbol.to_string(),
+                price,
+                index,
+                max_deviation_bps: self.config.max_fill_deviation_bps,
+            });
+        }
+        Ok(())
+    }
+
+    /// Folds a fill at `price`, already passed by [`Self::check_fill`], into the symbol's basis.
+    pub fn record_fill(&self, symbol: &str, price: Decimal, index: Decimal) {
+        if index <= Decimal::ZERO {
+            return;
+        }
+        let premium = self.clamp((price - index) / index);
+        let alpha = Decimal::from_f64(self.config.alpha).unwrap_or_default();
+        let mut basis = self.basis.lock().unwrap();
+        let entry = basis.entry(symbol.to_string()).or_insert(Basis {
+            ratio: Decimal::ZERO,
+            updated: Instant::now(),
+            fills: 0,
+        });
+        let current = self.decayed(entry);
+        entry.ratio = self.clamp(current + (premium - current) * alpha);
+        entry.updated = Instant::now();
+        entry.fills += 1;
+    }
+
+    pub fn mark(&self, symbol: &str, index: Decimal) -> MarkPrice {
+        let basis = self.basis.lock().unwrap();
+        let (ratio, fills) = basis
+            .get(symbol)
+            .map(|entry| (self.decayed(entry), entry.fills))
+            .unwrap_or((Decimal::ZERO, 0));
+        MarkPrice {
+            symbol: symbol.to_string(),
+            mark: (index * (Decimal::ONE + ratio)).round_dp(MARK_SCALE),
+            index,
+            basis_bps: (ratio * Decimal::from(BPS_DIVISOR)).round_dp(2),
+            fills,
+        }
+    }
+
+    /// Marks for `symbols` from the current index, never from a cached price past its TTL. In
+    /// simulation mode, `supplied` marks take precedence; otherwise they are ignored. Only
+    /// read-only checks pass any.
+    pub async fn resolve(
+        &self,
+        feed: &PriceFeed,
+        symbols: &[String],
+        supplied: &HashMap<String, Decimal>,
+    ) -> Result<HashMap<String, Decimal>, AppError> {
+        let mut marks = HashMap::new();
+        let mut missing = Vec::new();
+        for symbol in symbols {
+            match supplied.get(symbol) {
+                Some(price) if self.config.mode == MarkPriceMode::Simulation => {
+                    marks.insert(symbol.clone(), *price);
+                }
+                _ => missing.push(symbol.clone()),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(marks);
+        }
+
+        let index = feed.fetch_fresh_prices(&missing).await?;
+        for symbol in missing {
+            let price = index
+                .get(&symbol)
+                .ok_or_else(|| RiskError::MissingMarkPrice(symbol.clone()))?;
+            marks.insert(symbol.clone(), self.mark(&symbol, *price).mark);
+        }
+        Ok(marks)
+    }
+
+    /// Marks for prices that already are index prices, such as the oracle's.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn marks_for_index(&self, index: &HashMap<String, Decimal>) -> HashMap<String, Decimal> {
+        index
+            .iter()
+            .map(|(symbol, price)| (symbol.clone(), self.mark(symbol, *price).mark))
+            .collect()
+    }
+
+    fn decayed(&self, basis: &Basis) -> Decimal {
+        let halvings = basis.updated.elapsed().as_secs_f64() / self.config.half_life_secs;
+        basis.ratio * Decimal::from_f64(0.5f64.powf(halvings)).unwrap_or_default()
+    }
+
+    fn clamp(&self, ratio: Decimal) -> Decimal {
+        let limit = Decimal::from(self.config.clamp_bps) / Decimal::from(BPS_DIVISOR);
+        ratio.clamp(-limit, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marks(mode: MarkPriceMode) -> MarkPrices {
+        MarkPrices::new(MarkPriceConfig {
+            mode,
+            alpha: 0.5,
+            half_life_secs: 1e9,
+            clamp_bps: 50,
+            max_fill_deviation_bps: 100,
+        })
+    }
+
+    #[test]
+    fn fills_are_checked_against_the_index_band() {
+        let marks = marks(MarkPriceMode::Authoritative);
+        let index = Decimal::from(1_000);
+        assert!(marks.check_fill("BTC", Decimal::from(1_010), index).is_ok());
+        assert!(marks.check_fill("BTC", Decimal::from(990), index).is_ok());
+        assert!(matches!(
+            marks.check_fill("BTC", Decimal::new(10_101, 1), index),
+            Err(RiskError::FillPriceOutOfRange { max_deviation_bps: 100, .. })
+        ));
+        assert!(marks.check_fill("BTC", Decimal::from(900), index).is_err());
+    }
+
+    #[test]
+    fn basis_follows_fills_within_the_clamp() {
+        let marks = marks(MarkPriceMode::Authoritative);
+        let index = Decimal::from(1_000);
+        // A 100 bps premium is clamped to 50 bps, and alpha 0.5 moves the basis half way there.
+        marks.record_fill("BTC", Decimal::from(1_010), index);
+        let mark = marks.mark("BTC", index);
+        assert_eq!(mark.basis_bps, Decimal::from(25));
+        assert_eq!(mark.fills, 1);
+        marks.record_fill("BTC", Decimal::from(1_010), index);
+        assert_eq!(marks.mark("BTC", index).basis_bps, Decimal::new(375, 1));
+    }
+
+    #[test]
+    fn simulation_fills_are_checked_and_move_the_basis() {
+        let marks = marks(MarkPriceMode::Simulation);
+        let index = Decimal::from(1_000);
+        assert!(marks.check_fill("BTC", Decimal::from(2_000), index).is_err());
+        marks.record_fill("BTC", Decimal::from(1_010), index);
+        assert_eq!(marks.mark("BTC", index).fills, 1);
+    }
+
+    #[tokio::test]
+    async fn supplied_marks_are_only_used_in_simulation() {
+        let feed = PriceFeed::new(
+            vec![std::sync::Arc::new(crate::price_feed::FixtureProvider::bundled())],
+            crate::price_feed::IndexConfig::from_env().unwrap(),
+            crate::price_feed::CacheConfig::from_env(),
+        );
+        let symbols = ["BTC".to_string()];
+        let supplied = HashMap::from([("BTC".to_string(), Decimal::from(1))]);
+
+        let server = marks(MarkPriceMode::Authoritative).resolve(&feed, &symbols, &supplied).await.unwrap();
+        assert_ne!(server["BTC"], Decimal::from(1));
+        let what_if = marks(MarkPriceMode::Simulation).resolve(&feed, &symbols, &supplied).await.unwrap();
+        assert_eq!(what_if["BTC"], Decimal::from(1));
+    }
+}
@@ -0,0 +1,110 @@
+//! Publishes market data on the event bus whether or not anyone calls the REST API: every
+//! listed market's index price on `prices:<SYMBOL>` and its indicative funding rate on
+//! `funding:<SYMBOL>`, once per `MARKET_EVENTS_INTERVAL_MS`.
+
+use crate::events::Event;
+use crate::state::AppState;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::warn;
+
+/// Reads `MARKET_EVENTS_INTERVAL_MS` (default 1000).
+pub fn interval_from_env() -> Duration {
+    Duration::from_millis(
+        std::env::var("MARKET_EVENTS_INTERVAL_MS")
+            .ok()
+            .and_then(|val| val.parse::<u64>().ok())
+            .unwrap_or(1_000)
+            .max(100),
+    )
+}
+
+pub fn spawn(state: Arc<AppState>, period: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            publish(&state).await;
+        }
+    });
+}
+
+/// One round of price and funding events. Markets without an index price this round are
+/// skipped; the next round picks them up.
+async fn publish(state: &AppState) {
+    // Re-read every round so markets added by a registry reload are published too.
+    let symbols: Vec<String> = state.risk.markets().into_iter().map(|market| market.symbol).collect();
+    let prices = match state.prices.fetch_prices(&symbols).await {
+        Ok(prices) => prices,
+        Err(err) => {
+            warn!(error = %err, "no index prices to publish");
+            return;
+        }
+    };
+    for symbol in &symbols {
+        let Some(index) = prices.get(symbol) else {
+            continue;
+        };
+        state.events.publish(Event::Price {
+            symbol: symbol.clone(),
+            price: *index,
+        });
+        let mark = state.marks.mark(symbol, *index);
+        state.events.publish(Event::Funding {
+            market: symbol.clone(),
+            rate_bps: mark.basis_bps,
+            mark: mark.mark,
+            index: *index,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Envelope;
+    use crate::test_support::state;
+    use rust_decimal::Decimal;
+
+    /// Runs one tick and returns what it published.
+    async fn published(state: &AppState) -> Vec<Arc<Envelope>> {
+        let mut events = state.events.subscribe_market_data();
+        publish(state).await;
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[tokio::test]
+    async fn publishes_price_and_funding_per_market() {
+        let state = state();
+        let events = published(&state).await;
+        let price = events.iter().find(|envelope| envelope.channel == "prices:BTC").unwrap();
+        assert!(matches!(&price.data, Event::Price { price, .. } if *price == Decimal::from(67_000)));
+        let funding = events.iter().find(|envelope| envelope.channel == "funding:BTC").unwrap();
+        match &funding.data {
+            Event::Funding { rate_bps, mark, index, .. } => {
+                // No fills yet, so the mark sits on the index and nobody pays.
+                assert_eq!(*rate_bps, Decimal::ZERO);
+                assert_eq!(mark, index);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn funding_follows_the_mark_basis() {
+        let state = state();
+        let index = Decimal::from(67_000);
+        state.marks.record_fill("BTC", Decimal::from(67_670), index);
+        let events = published(&state).await;
+        let funding = events.iter().find(|envelope| envelope.channel == "funding:BTC").unwrap();
+        match &funding.data {
+            Event::Funding { rate_bps, mark, .. } => {
+                assert!(*rate_bps > Decimal::ZERO);
+                assert!(*mark > index);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
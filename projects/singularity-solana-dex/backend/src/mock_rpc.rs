@@ -0,0 +1,157 @@
+//! Stand-in for a Solana JSON-RPC node, so tests can run the liquidation crank in dry-run mode
+//! without a cluster. It answers the calls the crank and the oracle make with fixed values:
+//! every account is empty, no signature is known and simulations succeed (or, with
+//! `reject_simulations`, fail with a custom program error). `sendTransaction` is always
+//! refused and logged, since a dry run must never get that far.
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// All-zero hash; the mock never checks blockhashes.
+const BLOCKHASH: &str = "11111111111111111111111111111111";
+const BLOCK_HEIGHT: u64 = 1_000;
+/// Blocks a mock blockhash stays valid for, as on a real cluster.
+const BLOCKHASH_LIFETIME: u64 = 150;
+
+struct MockRpc {
+    reject_simulations: bool,
+    slot: AtomicU64,
+}
+
+/// Serves JSON-RPC on a free local port for the rest of the test and returns its URL.
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
+pub async fn spawn(reject_simulations: bool) -> String {
+    let mock = Arc::new(MockRpc {
+        reject_simulations,
+        slot: AtomicU64::new(BLOCK_HEIGHT),
+    });
+    let app = Router::new().route("/", post(handle)).with_state(mock);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let bind = listener.local_addr().unwrap();
+    info!(%bind, reject_simulations, "mock Solana RPC listening");
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    format!("http://{}", bind)
+}
+
+async fn handle(State(mock): State<Arc<MockRpc>>, Json(request): Json<Value>) -> Json<Value> {
+    // Batched requests get one response per call, in order.
+    match request {
+        Value::Array(calls) => Json(Value::Array(calls.iter().map(|call| respond(&mock, call)).collect())),
+        call => Json(respond(&mock, &call)),
+    }
+}
+
+fn respond(mock: &MockRpc, call: &Value) -> Value {
+    let id = call.get("id").cloned().unwrap_or(Value::Null);
+    let method = call["method"].as_str().unwrap_or_default();
+    let params = &call["params"];
+    let slot = mock.slot.fetch_add(1, Ordering::Relaxed);
+    let context = json!({ "slot": slot });
+
+    let result = match method {
+        "getVersion" => json!({ "solana-core": "1.18.26", "feature-set": 0 }),
+        "getHealth" => json!("ok"),
+        "getSlot" => json!(slot),
+        "getBlockHeight" => json!(BLOCK_HEIGHT),
+        "getLatestBlockhash" => json!({
+            "context": context,
+            "value": { "blockhash": BLOCKHASH, "lastValidBlockHeight": BLOCK_HEIGHT + BLOCKHASH_LIFETIME },
+        }),
+        "getBalance" => json!({ "context": context, "value": 0 }),
+        "getAccountInfo" => json!({ "context": context, "value": null }),
+        "getMultipleAccounts" => {
+            let count = params[0].as_array().map(Vec::len).unwrap_or_default();
+            json!({ "context": context, "value": vec![Value::Null; count] })
+        }
+        "getSignatureStatuses" => {
+            let count = params[0].as_array().map(Vec::len).unwrap_or_default();
+            json!({ "context": context, "value": vec![Value::Null; count] })
+        }
+        "simulateTransaction" => {
+            info!(reject = mock.reject_simulations, "simulateTransaction");
+            let (err, logs) = if mock.reject_simulations {
+                (
+                    json!({ "InstructionError": [0, { "Custom": 0 }] }),
+                    json!(["Program log: mock-rpc rejected the simulation"]),
+                )
+            } else {
+                (Value::Null, json!(["Program log: mock-rpc simulated the transaction"]))
+            };
+            json!({
+                "context": context,
+                "value": { "err": err, "logs": logs, "accounts": null, "unitsConsumed": 0, "returnData": null },
+            })
+        }
+        "sendTransaction" => {
+            warn!("sendTransaction refused; a dry run should only simulate");
+            return error(id, -32002, "mock-rpc does not accept transactions");
+        }
+        _ => {
+            warn!(method, "unsupported method");
+            return error(id, -32601, "Method not found");
+        }
+    };
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn call(url: &str, body: Value) -> Value {
+        reqwest::Client::new()
+            .post(url)
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    fn simulate(id: u64) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": "simulateTransaction", "params": ["AQ==", {}] })
+    }
+
+    #[tokio::test]
+    async fn simulations_pass_or_fail_as_configured() {
+        let url = spawn(false).await;
+        let response = call(&url, simulate(1)).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["value"]["err"], Value::Null);
+
+        let url = spawn(true).await;
+        let response = call(&url, simulate(2)).await;
+        assert_eq!(response["result"]["value"]["err"], json!({ "InstructionError": [0, { "Custom": 0 }] }));
+    }
+
+    #[tokio::test]
+    async fn batches_are_answered_in_order_and_sends_refused() {
+        let url = spawn(false).await;
+        let response = call(
+            &url,
+            json!([
+                { "jsonrpc": "2.0", "id": 1, "method": "getBlockHeight" },
+                { "jsonrpc": "2.0", "id": 2, "method": "sendTransaction", "params": ["AQ=="] },
+                { "jsonrpc": "2.0", "id": 3, "method": "getLatestBlockhash" },
+            ]),
+        )
+        .await;
+        assert_eq!(response[0]["result"], BLOCK_HEIGHT);
+        assert_eq!(response[1]["error"]["code"], -32002);
+        assert_eq!(
+            response[2]["result"]["value"]["lastValidBlockHeight"],
+            BLOCK_HEIGHT + BLOCKHASH_LIFETIME
+        );
+    }
+}
@@ -1,25 +1,53 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct MarketConfig {
     pub symbol: String,
     pub max_leverage_bps: u32,
     pub initial_margin_bps: u32,
     pub maintenance_margin_bps: u32,
     pub max_open_interest: Decimal,
+    /// Smallest price increment accepted for this market.
+    pub tick_size: Decimal,
+    /// Smallest base quantity increment accepted for this market.
+    pub lot_size: Decimal,
+    #[serde(default)]
+    pub status: MarketStatus,
+    /// Final price every position was closed at; set once the market is `settled`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settlement_price: Option<Decimal>,
+}
+
+/// Where a market is in its lifecycle; `settled` is final.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketStatus {
+    /// Everything is allowed.
+    #[default]
+    Active,
+    /// New positions only at prices that wouldn't take liquidity: longs at or below the mark,
+    /// shorts at or above it.
+    PostOnly,
+    /// No new positions or leverage increases; closes and liquidations go through.
+    ReduceOnly,
+    /// Trading halted: no opens, closes, leverage changes or liquidations.
+    Paused,
+    /// Delisted: every position is closed at `settlement_price` and nothing else is allowed.
+    Settled,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Side {
     Long,
     Short,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct Position {
     pub market: String,
     pub side: Side,
@@ -29,7 +57,7 @@ pub struct Position {
     pub position_account: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct Account {
     pub id: Uuid,
     pub owner: String,
@@ -38,65 +66,263 @@ pub struct Account {
     pub positions: HashMap<String, Position>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateAccountRequest {
     pub owner: String,
     pub account_state: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct DepositRequest {
     pub amount: Decimal,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct WithdrawRequest {
     pub amount: Decimal,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct SetCollateralRequest {
     pub amount: Decimal,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct OpenPositionRequest {
     pub market: String,
     pub side: Side,
     pub base_qty: Decimal,
     pub entry_price: Decimal,
     pub leverage_bps: u32,
-    pub mark_price: Decimal,
     pub position_account: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ClosePositionRequest {
     pub exit_price: Decimal,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct AdjustLeverageRequest {
     pub new_leverage_bps: u32,
-    pub mark_price: Decimal,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct RiskCheckRequest {
+    /// What-if marks replacing the server's for this check only. Ignored unless the server
+    /// runs with `MARK_PRICE_MODE=simulation`.
+    #[serde(default)]
     pub mark_prices: HashMap<String, Decimal>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct RiskCheckResponse {
     pub equity: Decimal,
     pub used_margin: Decimal,
     pub free_collateral: Decimal,
     pub liquidatable_positions: Vec<String>,
+    /// Mark price each position was valued at.
+    pub mark_prices: HashMap<String, Decimal>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// How close one position is to liquidation, valued at a mark price.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct PositionHealth {
+    pub account_id: Uuid,
+    pub market: String,
+    pub side: Side,
+    pub base_qty: Decimal,
+    pub mark_price: Decimal,
+    pub notional: Decimal,
+    pub maintenance_margin: Decimal,
+    /// Collateral plus this position's unrealized PnL, what the maintenance check compares.
+    pub equity: Decimal,
+    /// `equity / maintenance_margin`; the position is liquidatable below 1.
+    pub health_ratio: Option<Decimal>,
+    /// How far `equity` is below `maintenance_margin`; zero for healthy positions.
+    pub margin_deficit: Decimal,
+    pub liquidation_price: Decimal,
+    /// How far the mark can move against the position before it is liquidatable, in bps of
+    /// the mark; negative once it is.
+    pub distance_bps: Decimal,
+    pub liquidatable: bool,
+    /// A liquidation was sent and awaits confirmation.
+    pub liquidation_pending: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct PositionOutcome {
     pub position: Position,
     pub used_margin: Decimal,
     pub free_collateral: Decimal,
 }
+
+/// Result of simulating one transaction.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Simulation {
+    /// Why the transaction would fail, or couldn't be built; `None` if it would succeed.
+    pub error: Option<String>,
+    pub units_consumed: Option<u64>,
+    pub logs: Vec<String>,
+}
+
+impl Simulation {
+    /// A transaction that couldn't be simulated at all.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn failed(error: String) -> Self {
+        Self {
+            error: Some(error),
+            units_consumed: None,
+            logs: Vec::new(),
+        }
+    }
+}
+
+/// Health of the oracle publisher, see `GET /oracle/publisher`.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct PublisherStatus {
+    /// Public key signing `UpdatePrice`; `None` when prices are read but not pushed on-chain.
+    pub authority: Option<String>,
+    pub dry_run: bool,
+    pub interval_secs: u64,
+    pub deviation_bps: u32,
+    pub heartbeat_secs: u64,
+    /// Unix seconds of the last successful oracle read.
+    pub last_cycle_at: Option<i64>,
+    /// Why the last oracle read failed, if it did.
+    pub last_error: Option<String>,
+    /// The oracle was read recently, no market's push is overdue and every market accepts the
+    /// publisher's key.
+    pub healthy: bool,
+    pub markets: Vec<PublishedMarket>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct PublishedMarket {
+    pub symbol: String,
+    /// Last price pushed on-chain, or simulated in a dry run.
+    pub price: Option<Decimal>,
+    /// Unix seconds.
+    pub pushed_at: Option<i64>,
+    pub signature: Option<String>,
+    /// Why the latest push failed, if it did.
+    pub last_error: Option<String>,
+    /// Latest simulation, in a dry run.
+    pub simulation: Option<Simulation>,
+    /// No push for longer than the heartbeat allows.
+    pub stale: bool,
+    /// The market account's `oracle_authority`, as last read.
+    pub oracle_authority: Option<String>,
+    /// The market only takes prices from another key, so pushes are skipped until it is
+    /// handed over with `POST /admin/markets/{symbol}/oracle-authority`.
+    pub authority_mismatch: bool,
+}
+
+/// A liquidate transaction the crank sent but hasn't seen confirmed; the backend position is
+/// only closed once it is.
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct PendingLiquidation {
+    pub account_id: Uuid,
+    pub market: String,
+    pub exit_price: Decimal,
+    /// Every transaction sent for it, oldest first. Any of them may still land.
+    pub attempts: Vec<LiquidationAttempt>,
+    pub created_at: i64,
+}
+
+/// One signed liquidate transaction.
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiquidationAttempt {
+    pub signature: String,
+    /// The transaction can't land once the chain is past this block height.
+    pub last_valid_block_height: u64,
+}
+
+/// Where a pending liquidation stands, from the statuses of its attempts.
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PendingOutcome {
+    /// This attempt landed; the position is liquidated on-chain.
+    Confirmed(String),
+    /// Every attempt failed on-chain or expired, so none can land any more.
+    Dropped,
+    Waiting,
+}
+
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
+impl PendingLiquidation {
+    /// `statuses` holds each attempt's status in order: `None` while unknown to the chain,
+    /// else whether it succeeded.
+    pub fn outcome<E>(&self, statuses: &[Option<Result<(), E>>], block_height: u64) -> PendingOutcome {
+        let mut settled = true;
+        for (attempt, status) in self.attempts.iter().zip(statuses) {
+            match status {
+                Some(Ok(())) => return PendingOutcome::Confirmed(attempt.signature.clone()),
+                Some(Err(_)) => {}
+                None => settled &= block_height > attempt.last_valid_block_height,
+            }
+        }
+        if settled {
+            PendingOutcome::Dropped
+        } else {
+            PendingOutcome::Waiting
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdempotentResponse {
+    pub key: String,
+    pub method: String,
+    pub path: String,
+    /// Hex SHA-256 of the request body; empty for keys stored before it was recorded.
+    pub request_hash: String,
+    pub status_code: u16,
+    pub body: String,
+    pub created_at: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(expiries: &[u64]) -> PendingLiquidation {
+        PendingLiquidation {
+            account_id: Uuid::new_v4(),
+            market: "BTC".to_string(),
+            exit_price: Decimal::from(60_000),
+            attempts: expiries
+                .iter()
+                .enumerate()
+                .map(|(n, &last_valid_block_height)| LiquidationAttempt {
+                    signature: format!("sig-{}", n),
+                    last_valid_block_height,
+                })
+                .collect(),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn any_landed_attempt_confirms_the_liquidation() {
+        let pending = pending(&[100, 200, 300]);
+        // The first attempt landed after the crank had moved on to a retry.
+        let statuses = [Some(Ok(())), Some(Err("blockhash not found")), None];
+        assert_eq!(pending.outcome(&statuses, 150), PendingOutcome::Confirmed("sig-0".to_string()));
+    }
+
+    #[test]
+    fn a_liquidation_waits_while_any_attempt_can_land() {
+        let pending = pending(&[100, 200]);
+        let statuses: [Option<Result<(), &str>>; 2] = [Some(Err("custom program error")), None];
+        assert_eq!(pending.outcome(&statuses, 150), PendingOutcome::Waiting);
+        assert_eq!(pending.outcome(&statuses, 200), PendingOutcome::Waiting);
+        assert_eq!(pending.outcome(&statuses, 201), PendingOutcome::Dropped);
+
+        let unknown: [Option<Result<(), &str>>; 2] = [None, None];
+        assert_eq!(pending.outcome(&unknown, 150), PendingOutcome::Waiting);
+        assert_eq!(pending.outcome(&unknown, 250), PendingOutcome::Dropped);
+    }
+}
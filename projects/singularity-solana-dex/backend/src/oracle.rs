@@ -1,10 +1,18 @@
-use crate::config::{OracleConfig, OracleMarketConfig};
+use crate::clock::unix_now;
+use crate::config::{OracleConfig, OracleGuards, OracleMarketConfig, OracleSource, ViolationAction};
+use crate::price_feed::PriceFeed;
+use crate::pyth::{self, PythPrice};
+use crate::switchboard::{self, SwitchboardPrice};
 use rust_decimal::Decimal;
-use solana_client::rpc_client::RpcClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
 use thiserror::Error;
+use tracing::{info, warn};
+
+const BPS_DIVISOR: i64 = 10_000;
 
 #[derive(Debug, Error)]
 pub enum OracleError {
@@ -14,101 +22,754 @@ pub enum OracleError {
     InvalidPrice(String),
     #[error("invalid pubkey for {0}")]
     InvalidPubkey(String),
-    #[error("pyth feature not enabled")]
-    PythFeatureDisabled,
+    #[error("no switchboard_pubkey for {0}")]
+    MissingSwitchboardPubkey(String),
+    #[error("no index price for {symbol}: {reason}")]
+    MissingIndexPrice { symbol: String, reason: String },
+    #[error("cannot decode {source_name} account for {symbol}: {reason}")]
+    Decode {
+        symbol: String,
+        source_name: &'static str,
+        reason: String,
+    },
+    #[error("oracle price for {symbol} is {age_secs}s old, max {max_age_secs}s")]
+    StalePrice {
+        symbol: String,
+        age_secs: i64,
+        max_age_secs: i64,
+    },
+    #[error("oracle confidence for {symbol} is {conf} around {price}, max {max_conf_bps} bps")]
+    WideConfidence {
+        symbol: String,
+        price: Decimal,
+        conf: Decimal,
+        max_conf_bps: u32,
+    },
+    #[error("oracle price for {symbol} jumped {jump_bps} bps from {last} to {price}, max {max_jump_bps} bps")]
+    PriceJump {
+        symbol: String,
+        last: Decimal,
+        price: Decimal,
+        jump_bps: Decimal,
+        max_jump_bps: u32,
+    },
+    #[error("every oracle source for {symbol} failed: {failures}")]
+    SourcesExhausted { symbol: String, failures: String },
     #[error("rpc error: {0}")]
     Rpc(String),
 }
 
+/// Defaults for the age and confidence guards of markets that don't set their own.
 #[derive(Clone, Debug)]
-pub enum OracleMode {
-    Static,
-    PythRpc,
+pub struct PythLimits {
+    pub max_age_secs: i64,
+    /// Largest confidence interval, relative to the price.
+    pub max_conf_bps: u32,
+    /// Fewest guardian signatures a partially verified pull-oracle update may carry.
+    pub min_signatures: u8,
+}
+
+impl PythLimits {
+    /// Reads `PYTH_MAX_AGE_SECS` (default 60), `PYTH_MAX_CONF_BPS` (200) and
+    /// `PYTH_MIN_SIGNATURES` (13, the Wormhole guardian quorum).
+    pub fn from_env() -> Self {
+        Self {
+            max_age_secs: std::env::var("PYTH_MAX_AGE_SECS")
+                .ok()
+                .and_then(|val| val.parse::<i64>().ok())
+                .unwrap_or(60),
+            max_conf_bps: std::env::var("PYTH_MAX_CONF_BPS")
+                .ok()
+                .and_then(|val| val.parse::<u32>().ok())
+                .unwrap_or(200),
+            min_signatures: std::env::var("PYTH_MIN_SIGNATURES")
+                .ok()
+                .and_then(|val| val.parse::<u8>().ok())
+                .unwrap_or(13),
+        }
+    }
+}
+
+/// Reads `ORACLE_TWAP_WINDOW_SECS` (default 300), the window of markets that don't set their own.
+fn twap_window_from_env() -> i64 {
+    std::env::var("ORACLE_TWAP_WINDOW_SECS")
+        .ok()
+        .and_then(|val| val.parse::<i64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(300)
+}
+
+/// One market's raw reading, before guards.
+struct Reading {
+    price: Decimal,
+    conf: Option<Decimal>,
+    /// Unix seconds; `None` for a static price, which the age guard lets through.
+    publish_time: Option<i64>,
+}
+
+impl From<PythPrice> for Reading {
+    fn from(price: PythPrice) -> Self {
+        Self {
+            price: price.price,
+            conf: Some(price.conf),
+            publish_time: Some(price.publish_time),
+        }
+    }
+}
+
+impl From<SwitchboardPrice> for Reading {
+    fn from(price: SwitchboardPrice) -> Self {
+        Self {
+            price: price.price,
+            conf: Some(price.std_deviation),
+            publish_time: Some(price.round_open_timestamp),
+        }
+    }
+}
+
+/// A market whose reading failed this cycle, and what the market's guards say to do about it.
+#[derive(Debug)]
+pub struct GuardViolation {
+    pub symbol: String,
+    pub error: OracleError,
+    pub action: ViolationAction,
+}
+
+/// Outcome of one polling cycle.
+#[derive(Debug, Default)]
+pub struct OracleUpdate {
+    /// Readings that passed every guard; only these are pushed on-chain.
+    pub fresh: HashMap<String, Decimal>,
+    /// Which source in the market's chain produced each fresh reading.
+    pub sources: HashMap<String, OracleSource>,
+    /// Last accepted price of every market that has one, including markets skipped this cycle.
+    pub prices: HashMap<String, Decimal>,
+    /// Time-weighted average of the accepted prices over each market's TWAP window.
+    pub twaps: HashMap<String, Decimal>,
+    pub violations: Vec<GuardViolation>,
+}
+
+impl OracleUpdate {
+    /// Markets that must not be liquidated this cycle.
+    pub fn halted(&self) -> HashSet<String> {
+        self.violations
+            .iter()
+            .filter(|violation| violation.action != ViolationAction::SkipUpdate)
+            .map(|violation| violation.symbol.clone())
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct MarketTrack {
+    last_accepted: Option<Decimal>,
+    /// Level of a jump being confirmed, and how many consecutive readings agreed with it.
+    pending_jump: Option<(Decimal, u32)>,
+    last_source: Option<OracleSource>,
+    /// Accepted prices and the unix seconds they were accepted at, oldest first.
+    history: VecDeque<(i64, Decimal)>,
 }
 
+impl MarketTrack {
+    /// Unix seconds the last accepted price was accepted at.
+    fn accepted_at(&self) -> Option<i64> {
+        self.history.back().map(|(at, _)| *at)
+    }
+
+    fn record(&mut self, now: i64, price: Decimal, window_secs: i64) {
+        self.last_accepted = Some(price);
+        self.history.push_back((now, price));
+        // Keep the newest sample at or before the window start: its price holds into the window.
+        while self.history.len() > 1 && self.history[1].0 <= now - window_secs {
+            self.history.pop_front();
+        }
+    }
+
+    /// Each sample weighs as long as it stayed the latest price within the window. Until the
+    /// history spans a full window the average covers what there is.
+    fn twap(&self, now: i64, window_secs: i64) -> Option<Decimal> {
+        let start = now - window_secs;
+        let mut weighted = Decimal::ZERO;
+        let mut total = 0i64;
+        for (i, (at, price)) in self.history.iter().enumerate() {
+            let from = (*at).max(start);
+            let until = self.history.get(i + 1).map(|(next, _)| *next).unwrap_or(now);
+            if until > from {
+                weighted += *price * Decimal::from(until - from);
+                total += until - from;
+            }
+        }
+        if total == 0 {
+            return self.last_accepted;
+        }
+        Some(weighted / Decimal::from(total))
+    }
+}
+
+/// Off-chain index prices and the unix seconds of their oldest quote, fetched at most once per
+/// cycle and only if some market falls through to them.
+#[derive(Default)]
+struct IndexCache(Option<IndexPrices>);
+
+type IndexPrices = HashMap<String, Result<(Decimal, i64), String>>;
+
 #[derive(Clone)]
 pub struct OracleClient {
-    config: OracleConfig,
-    mode: OracleMode,
+    /// Shared by every clone, so a registry reload reaches the crank's copy too.
+    config: Arc<RwLock<OracleConfig>>,
+    default_sources: Vec<OracleSource>,
     rpc_url: Option<String>,
+    index: Arc<PriceFeed>,
+    pyth_limits: PythLimits,
+    twap_window_secs: i64,
+    tracks: Arc<Mutex<HashMap<String, MarketTrack>>>,
 }
 
 impl OracleClient {
-    pub fn new(config: OracleConfig, mode: OracleMode, rpc_url: Option<String>) -> Self {
-        Self {
-            config,
-            mode,
+    pub fn new(
+        config: OracleConfig,
+        default_sources: Vec<OracleSource>,
+        rpc_url: Option<String>,
+        index: Arc<PriceFeed>,
+    ) -> Self {
+        let client = Self {
+            config: Arc::new(RwLock::new(OracleConfig { markets: Vec::new() })),
+            default_sources,
             rpc_url,
-        }
+            index,
+            pyth_limits: PythLimits::from_env(),
+            twap_window_secs: twap_window_from_env(),
+            tracks: Arc::new(Mutex::new(HashMap::new())),
+        };
+        client.replace_markets(config.markets);
+        client
     }
 
-    pub fn markets(&self) -> &[OracleMarketConfig] {
-        &self.config.markets
+    pub fn markets(&self) -> Vec<OracleMarketConfig> {
+        self.config.read().unwrap().markets.clone()
     }
 
-    pub fn market_by_symbol(&self, symbol: &str) -> Option<&OracleMarketConfig> {
+    pub fn market_by_symbol(&self, symbol: &str) -> Option<OracleMarketConfig> {
         self.config
+            .read()
+            .unwrap()
             .markets
             .iter()
             .find(|market| market.symbol.eq_ignore_ascii_case(symbol))
+            .cloned()
     }
 
-    pub async fn fetch_prices(&self) -> Result<HashMap<String, Decimal>, OracleError> {
-        match self.mode {
-            OracleMode::Static => self.fetch_static_prices(),
-            OracleMode::PythRpc => self.fetch_pyth_prices().await,
+    /// Swaps in a reloaded market list; readings already in flight finish with the old one.
+    pub fn replace_markets(&self, markets: Vec<OracleMarketConfig>) {
+        self.config.write().unwrap().markets = markets;
+    }
+
+    pub fn twap_window_secs(&self, market: &OracleMarketConfig) -> i64 {
+        market.twap.window_secs.filter(|secs| *secs > 0).unwrap_or(self.twap_window_secs)
+    }
+
+    /// The market's source chain, in the order sources are tried.
+    pub fn sources_for<'a>(&'a self, market: &'a OracleMarketConfig) -> &'a [OracleSource] {
+        market.sources.as_deref().unwrap_or(&self.default_sources)
+    }
+
+    /// Reads every market through its source chain and runs its guards. Per-market failures
+    /// become violations; only a failure that affects every market is returned as an error.
+    pub async fn fetch_prices(&self) -> Result<OracleUpdate, OracleError> {
+        let markets = self.markets();
+        let needs_rpc = markets
+            .iter()
+            .any(|market| self.sources_for(market).iter().any(|source| source.needs_rpc()));
+        let client = match (&self.rpc_url, needs_rpc) {
+            (Some(rpc_url), true) => Some(RpcClient::new(rpc_url.clone())),
+            (None, true) => return Err(OracleError::Rpc("missing rpc url".to_string())),
+            (_, false) => None,
+        };
+
+        let now = unix_now();
+        let mut index = IndexCache::default();
+        let mut update = OracleUpdate::default();
+        for market in &markets {
+            // Age and confidence say whether a source is usable; fall through to the next if not.
+            let mut attempts = Vec::new();
+            for &source in self.sources_for(market) {
+                let reading = match self.read(client.as_ref(), &mut index, &markets, market, source).await {
+                    Ok(reading) => self.check_quality(market, reading, now),
+                    Err(error) => Err(error),
+                };
+                let usable = reading.is_ok();
+                attempts.push((source, reading));
+                if usable {
+                    break;
+                }
+            }
+            self.judge(&mut update, market, attempts, now);
         }
+        Ok(update)
     }
 
-    fn fetch_static_prices(&self) -> Result<HashMap<String, Decimal>, OracleError> {
-        let mut prices = HashMap::new();
-        for market in &self.config.markets {
-            let price_str = market
-                .static_price
-                .as_ref()
-                .ok_or_else(|| OracleError::MissingStaticPrice(market.symbol.clone()))?;
-            let price = Decimal::from_str(price_str)
-                .map_err(|_| OracleError::InvalidPrice(market.symbol.clone()))?;
-            prices.insert(market.symbol.clone(), price);
+    /// Takes the first usable reading of `attempts`, the market's sources in chain order, through
+    /// the jump guard and records the outcome in `update`.
+    fn judge(
+        &self,
+        update: &mut OracleUpdate,
+        market: &OracleMarketConfig,
+        attempts: Vec<(OracleSource, Result<Reading, OracleError>)>,
+        now: i64,
+    ) {
+        let mut failures = Vec::new();
+        let mut chosen = None;
+        for (source, reading) in attempts {
+            match reading {
+                Ok(reading) => {
+                    chosen = Some((source, reading));
+                    break;
+                }
+                Err(error) => failures.push((source, error)),
+            }
+        }
+
+        let mut tracks = self.tracks.lock().unwrap();
+        let track = tracks.entry(market.symbol.clone()).or_default();
+        let result = match chosen {
+            Some((source, reading)) => self.check_jump(market, track, reading).map(|price| (source, price)),
+            None => Err(exhausted(&market.symbol, std::mem::take(&mut failures))),
+        };
+        match result {
+            Ok((source, price)) => {
+                if let Some(previous) = track.last_source.filter(|previous| *previous != source) {
+                    if failures.is_empty() {
+                        info!(market = %market.symbol, from = previous.as_str(), to = source.as_str(), "oracle source recovered");
+                    } else {
+                        let skipped = exhausted(&market.symbol, std::mem::take(&mut failures));
+                        warn!(market = %market.symbol, from = previous.as_str(), to = source.as_str(), error = %skipped, "oracle falling back");
+                    }
+                }
+                track.last_source = Some(source);
+                track.record(now, price, self.twap_window_secs(market));
+                update.fresh.insert(market.symbol.clone(), price);
+                update.sources.insert(market.symbol.clone(), source);
+            }
+            Err(error) => update.violations.push(GuardViolation {
+                symbol: market.symbol.clone(),
+                error,
+                action: self.violation_action(market, track, now),
+            }),
+        }
+        if let Some(price) = track.last_accepted {
+            update.prices.insert(market.symbol.clone(), price);
+        }
+        if let Some(twap) = track.twap(now, self.twap_window_secs(market)) {
+            update.twaps.insert(market.symbol.clone(), twap);
         }
-        Ok(prices)
     }
 
-    async fn fetch_pyth_prices(&self) -> Result<HashMap<String, Decimal>, OracleError> {
-        let rpc_url = self
-            .rpc_url
-            .clone()
-            .ok_or_else(|| OracleError::Rpc("missing rpc url".to_string()))?;
-        let client = RpcClient::new(rpc_url);
-        let mut prices = HashMap::new();
+    /// Skipping an update only keeps liquidating while the last accepted price is itself within
+    /// the age limit; past it the market halts.
+    fn violation_action(&self, market: &OracleMarketConfig, track: &MarketTrack, now: i64) -> ViolationAction {
+        let max_age_secs = market.guards.max_age_secs.unwrap_or(self.pyth_limits.max_age_secs);
+        match (market.guards.on_violation, track.accepted_at()) {
+            (ViolationAction::SkipUpdate, Some(at)) if now - at <= max_age_secs => ViolationAction::SkipUpdate,
+            (ViolationAction::SkipUpdate, _) => ViolationAction::HaltLiquidations,
+            (action, _) => action,
+        }
+    }
 
-        for market in &self.config.markets {
-            let oracle_pubkey = Pubkey::from_str(&market.oracle_pubkey)
-                .map_err(|_| OracleError::InvalidPubkey(market.symbol.clone()))?;
-            let account = client
-                .get_account(&oracle_pubkey)
-                .map_err(|err| OracleError::Rpc(err.to_string()))?;
+    async fn read(
+        &self,
+        client: Option<&RpcClient>,
+        index: &mut IndexCache,
+        markets: &[OracleMarketConfig],
+        market: &OracleMarketConfig,
+        source: OracleSource,
+    ) -> Result<Reading, OracleError> {
+        match source {
+            OracleSource::Pyth => {
+                let data = read_account(client, &market.oracle_pubkey, &market.symbol).await?;
+                let price = pyth::decode(&data)
+                    .and_then(|price| price.check_verification(self.pyth_limits.min_signatures).map(|()| price))
+                    .map_err(|err| OracleError::Decode {
+                        symbol: market.symbol.clone(),
+                        source_name: source.as_str(),
+                        reason: err.to_string(),
+                    })?;
+                Ok(Reading::from(price))
+            }
+            OracleSource::Switchboard => {
+                let pubkey = market
+                    .switchboard_pubkey
+                    .as_ref()
+                    .ok_or_else(|| OracleError::MissingSwitchboardPubkey(market.symbol.clone()))?;
+                let data = read_account(client, pubkey, &market.symbol).await?;
+                let price = switchboard::decode(&data).map_err(|err| OracleError::Decode {
+                    symbol: market.symbol.clone(),
+                    source_name: source.as_str(),
+                    reason: err.to_string(),
+                })?;
+                Ok(Reading::from(price))
+            }
+            OracleSource::Index => {
+                let (price, quoted_at) = match self.index_prices(index, markets).await.get(&market.symbol.to_uppercase()) {
+                    Some(Ok(quote)) => *quote,
+                    Some(Err(reason)) => {
+                        return Err(OracleError::MissingIndexPrice {
+                            symbol: market.symbol.clone(),
+                            reason: reason.clone(),
+                        })
+                    }
+                    None => {
+                        return Err(OracleError::MissingIndexPrice {
+                            symbol: market.symbol.clone(),
+                            reason: "not quoted by enough providers".to_string(),
+                        })
+                    }
+                };
+                // The index keeps using a failing provider's last quote for a while, so its
+                // age is that of the oldest quote in it.
+                Ok(Reading {
+                    price,
+                    conf: None,
+                    publish_time: Some(quoted_at),
+                })
+            }
+            OracleSource::Static => {
+                let price_str = market
+                    .static_price
+                    .as_ref()
+                    .ok_or_else(|| OracleError::MissingStaticPrice(market.symbol.clone()))?;
+                let price =
+                    Decimal::from_str(price_str).map_err(|_| OracleError::InvalidPrice(market.symbol.clone()))?;
+                Ok(Reading {
+                    price,
+                    conf: None,
+                    publish_time: None,
+                })
+            }
+        }
+    }
+
+    /// Index prices for every market whose chain includes the index, fetched on first use.
+    async fn index_prices<'a>(
+        &self,
+        index: &'a mut IndexCache,
+        markets: &[OracleMarketConfig],
+    ) -> &'a HashMap<String, Result<(Decimal, i64), String>> {
+        if index.0.is_none() {
+            let symbols: Vec<String> = markets
+                .iter()
+                .filter(|market| self.sources_for(market).contains(&OracleSource::Index))
+                .map(|market| market.symbol.to_uppercase())
+                .collect();
+            let prices = self
+                .index
+                .index_prices(&symbols)
+                .await
+                .into_iter()
+                .map(|(symbol, index)| {
+                    let quote = index
+                        .map(|index| (index.price, (index.quoted_at() / 1000) as i64))
+                        .map_err(|err| err.to_string());
+                    (symbol, quote)
+                })
+                .collect();
+            index.0 = Some(prices);
+        }
+        index.0.as_ref().unwrap()
+    }
+
+    /// Age and confidence guards, which judge a single source's reading.
+    fn check_quality(&self, market: &OracleMarketConfig, reading: Reading, now: i64) -> Result<Reading, OracleError> {
+        let guards: &OracleGuards = &market.guards;
+        let symbol = market.symbol.clone();
+
+        let max_age_secs = guards.max_age_secs.unwrap_or(self.pyth_limits.max_age_secs);
+        if let Some(publish_time) = reading.publish_time {
+            let age_secs = (now - publish_time).max(0);
+            if age_secs > max_age_secs {
+                return Err(OracleError::StalePrice {
+                    symbol,
+                    age_secs,
+                    max_age_secs,
+                });
+            }
+        }
 
-            let price = fetch_pyth_price_from_account(&market.symbol, &account.data)?;
-            prices.insert(market.symbol.clone(), price);
+        if let Some(conf) = reading.conf {
+            let max_conf_bps = guards.max_conf_bps.unwrap_or(self.pyth_limits.max_conf_bps);
+            if conf > reading.price * bps(max_conf_bps) {
+                return Err(OracleError::WideConfidence {
+                    symbol,
+                    price: reading.price,
+                    conf,
+                    max_conf_bps,
+                });
+            }
         }
 
-        Ok(prices)
+        Ok(reading)
+    }
+
+    /// Jump guard, which judges the market's price against its history whichever source it
+    /// came from.
+    fn check_jump(
+        &self,
+        market: &OracleMarketConfig,
+        track: &mut MarketTrack,
+        reading: Reading,
+    ) -> Result<Decimal, OracleError> {
+        let guards: &OracleGuards = &market.guards;
+        let symbol = market.symbol.clone();
+
+        let last_accepted = track.last_accepted.filter(|last| !last.is_zero());
+        if let (Some(max_jump_bps), Some(last)) = (guards.max_jump_bps, last_accepted) {
+            let jump = (reading.price - last).abs() / last;
+            if jump > bps(max_jump_bps) {
+                // A move that holds for `jump_confirmations` readings is a new level, not a wick.
+                let confirmations = match track.pending_jump {
+                    Some((level, count))
+                        if !level.is_zero() && (reading.price - level).abs() / level <= bps(max_jump_bps) =>
+                    {
+                        count + 1
+                    }
+                    _ => 1,
+                };
+                if confirmations < guards.jump_confirmations.max(1) {
+                    track.pending_jump = Some((reading.price, confirmations));
+                    return Err(OracleError::PriceJump {
+                        symbol,
+                        last,
+                        price: reading.price,
+                        jump_bps: (jump * Decimal::from(BPS_DIVISOR)).round_dp(2),
+                        max_jump_bps,
+                    });
+                }
+            }
+        }
+
+        track.pending_jump = None;
+        Ok(reading.price)
     }
 }
 
-#[cfg(feature = "pyth")]
-fn fetch_pyth_price_from_account(symbol: &str, data: &[u8]) -> Result<Decimal, OracleError> {
-    let price_feed = pyth_sdk_solana::load_price_feed_from_account_data(data)
-        .map_err(|_| OracleError::InvalidPrice(symbol.to_string()))?;
-    let price = price_feed
-        .get_current_price()
-        .ok_or_else(|| OracleError::InvalidPrice(symbol.to_string()))?;
-    let price_str = format!("{}", price.price);
-    Decimal::from_str(&price_str).map_err(|_| OracleError::InvalidPrice(symbol.to_string()))
+async fn read_account(client: Option<&RpcClient>, pubkey: &str, symbol: &str) -> Result<Vec<u8>, OracleError> {
+    let client = client.ok_or_else(|| OracleError::Rpc("missing rpc url".to_string()))?;
+    let pubkey = Pubkey::from_str(pubkey).map_err(|_| OracleError::InvalidPubkey(symbol.to_string()))?;
+    let account = client
+        .get_account(&pubkey)
+        .await
+        .map_err(|err| OracleError::Rpc(err.to_string()))?;
+    Ok(account.data)
 }
 
-#[cfg(not(feature = "pyth"))]
-fn fetch_pyth_price_from_account(_symbol: &str, _data: &[u8]) -> Result<Decimal, OracleError> {
-    Err(OracleError::PythFeatureDisabled)
+/// A single failure is reported as is.
+fn exhausted(symbol: &str, mut failures: Vec<(OracleSource, OracleError)>) -> OracleError {
+    if failures.len() == 1 {
+        return failures.remove(0).1;
+    }
+    if failures.is_empty() {
+        return OracleError::SourcesExhausted {
+            symbol: symbol.to_string(),
+            failures: "no sources configured".to_string(),
+        };
+    }
+    OracleError::SourcesExhausted {
+        symbol: symbol.to_string(),
+        failures: failures
+            .iter()
+            .map(|(source, error)| format!("{}: {}", source.as_str(), error))
+            .collect::<Vec<_>>()
+            .join("; "),
+    }
+}
+
+fn bps(value: u32) -> Decimal {
+    Decimal::from(value) / Decimal::from(BPS_DIVISOR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_feed::{CacheConfig, FixtureProvider, IndexConfig};
+    use serde_json::json;
+
+    fn client() -> OracleClient {
+        let feed = PriceFeed::new(
+            vec![Arc::new(FixtureProvider::bundled())],
+            IndexConfig::from_env().unwrap(),
+            CacheConfig::from_env(),
+        );
+        let mut client = OracleClient::new(OracleConfig { markets: Vec::new() }, Vec::new(), None, Arc::new(feed));
+        client.pyth_limits = PythLimits {
+            max_age_secs: 60,
+            max_conf_bps: 200,
+            min_signatures: 13,
+        };
+        client
+    }
+
+    fn market(guards: serde_json::Value) -> OracleMarketConfig {
+        let mut market: OracleMarketConfig = serde_json::from_value(json!({
+            "market_id": 1,
+            "oracle_pubkey": "oracle",
+            "market_account": "market",
+            "guards": guards,
+        }))
+        .unwrap();
+        market.symbol = "BTC".to_string();
+        market
+    }
+
+    fn reading(price: i64, conf: Option<i64>, publish_time: i64) -> Reading {
+        Reading {
+            price: Decimal::from(price),
+            conf: conf.map(Decimal::from),
+            publish_time: Some(publish_time),
+        }
+    }
+
+    #[test]
+    fn quality_guards_refuse_old_and_uncertain_readings() {
+        let client = client();
+        let defaults = market(json!({}));
+        assert!(client.check_quality(&defaults, reading(100, Some(2), 940), 1_000).is_ok());
+        assert!(matches!(
+            client.check_quality(&defaults, reading(100, None, 939), 1_000),
+            Err(OracleError::StalePrice { age_secs: 61, max_age_secs: 60, .. })
+        ));
+        assert!(matches!(
+            client.check_quality(&defaults, reading(100, Some(3), 1_000), 1_000),
+            Err(OracleError::WideConfidence { max_conf_bps: 200, .. })
+        ));
+
+        // The market's own guards replace the defaults.
+        let strict = market(json!({ "max_age_secs": 5, "max_conf_bps": 50 }));
+        assert!(client.check_quality(&strict, reading(100, None, 990), 1_000).is_err());
+        assert!(client.check_quality(&strict, reading(1_000, Some(6), 1_000), 1_000).is_err());
+        assert!(client.check_quality(&strict, reading(1_000, Some(5), 995), 1_000).is_ok());
+    }
+
+    #[test]
+    fn jumps_are_accepted_once_confirmed() {
+        let client = client();
+        let market = market(json!({ "max_jump_bps": 500, "jump_confirmations": 2 }));
+        let mut track = MarketTrack::default();
+        assert_eq!(client.check_jump(&market, &mut track, reading(100, None, 0)).unwrap(), Decimal::from(100));
+        track.record(0, Decimal::from(100), 300);
+
+        // A wick that doesn't hold is refused and forgotten.
+        assert!(matches!(
+            client.check_jump(&market, &mut track, reading(120, None, 1)),
+            Err(OracleError::PriceJump { max_jump_bps: 500, .. })
+        ));
+        assert!(client.check_jump(&market, &mut track, reading(101, None, 2)).is_ok());
+        assert_eq!(track.pending_jump, None);
+
+        // A second reading at the new level confirms it.
+        assert!(client.check_jump(&market, &mut track, reading(120, None, 3)).is_err());
+        assert_eq!(client.check_jump(&market, &mut track, reading(121, None, 4)).unwrap(), Decimal::from(121));
+    }
+
+    #[test]
+    fn skipped_updates_halt_liquidations_once_the_last_price_is_too_old() {
+        let client = client();
+        let market = market(json!({ "max_age_secs": 60, "on_violation": "skip_update" }));
+        let stale = || vec![(OracleSource::Pyth, Err(OracleError::Rpc("down".to_string())))];
+
+        // Nothing accepted yet, so there is no price to keep liquidating at.
+        let mut update = OracleUpdate::default();
+        client.judge(&mut update, &market, stale(), 1_000);
+        assert_eq!(update.violations[0].action, ViolationAction::HaltLiquidations);
+
+        let mut update = OracleUpdate::default();
+        client.judge(&mut update, &market, vec![(OracleSource::Pyth, Ok(reading(100, None, 1_000)))], 1_000);
+        assert_eq!(update.fresh["BTC"], Decimal::from(100));
+
+        let mut update = OracleUpdate::default();
+        client.judge(&mut update, &market, stale(), 1_060);
+        assert_eq!(update.violations[0].action, ViolationAction::SkipUpdate);
+        assert!(update.halted().is_empty());
+        assert_eq!(update.prices["BTC"], Decimal::from(100));
+
+        let mut update = OracleUpdate::default();
+        client.judge(&mut update, &market, stale(), 1_061);
+        assert_eq!(update.violations[0].action, ViolationAction::HaltLiquidations);
+        assert!(update.halted().contains("BTC"));
+    }
+
+    #[test]
+    fn a_reading_failing_its_guard_falls_through_to_the_next_source() {
+        let client = client();
+        let market = market(json!({}));
+        let stale = client.check_quality(&market, reading(100, None, 900), 1_000);
+        assert!(stale.is_err());
+        let attempts = vec![
+            (OracleSource::Pyth, stale),
+            (OracleSource::Index, client.check_quality(&market, reading(101, None, 1_000), 1_000)),
+        ];
+        let mut update = OracleUpdate::default();
+        client.judge(&mut update, &market, attempts, 1_000);
+        assert_eq!(update.fresh["BTC"], Decimal::from(101));
+        assert_eq!(update.sources["BTC"], OracleSource::Index);
+        assert!(update.violations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn static_prices_do_not_age() {
+        let mut unquoted = market(json!({ "max_age_secs": 0 }));
+        unquoted.symbol = "NOTLISTED".to_string();
+        unquoted.static_price = Some("42".to_string());
+        unquoted.sources = Some(vec![OracleSource::Index, OracleSource::Static]);
+        let client = client();
+        client.replace_markets(vec![unquoted.clone()]);
+
+        let update = client.fetch_prices().await.unwrap();
+        assert!(update.violations.is_empty(), "{:?}", update.violations);
+        assert_eq!(update.fresh["NOTLISTED"], Decimal::from(42));
+        assert_eq!(update.sources["NOTLISTED"], OracleSource::Static);
+
+        // A day later it still passes.
+        let markets = [unquoted.clone()];
+        let reading = client
+            .read(None, &mut IndexCache::default(), &markets, &unquoted, OracleSource::Static)
+            .await
+            .unwrap();
+        assert!(client.check_quality(&unquoted, reading, unix_now() + 86_400).is_ok());
+    }
+
+    fn twap_of(track: &MarketTrack, now: i64) -> Option<Decimal> {
+        track.twap(now, 100)
+    }
+
+    #[test]
+    fn history_keeps_the_sample_holding_into_the_window() {
+        let mut track = MarketTrack::default();
+        for (at, price) in [(0, 100), (50, 200), (150, 300)] {
+            track.record(at, Decimal::from(price), 100);
+        }
+        // The sample at 0 was superseded before the window start at 50.
+        assert_eq!(track.history.iter().map(|(at, _)| *at).collect::<Vec<_>>(), [50, 150]);
+
+        track.record(200, Decimal::from(400), 100);
+        // The window starts at 100, and the price from 50 held until 150.
+        assert_eq!(track.history.front(), Some(&(50, Decimal::from(200))));
+        assert_eq!(twap_of(&track, 200), Some(Decimal::from(250)));
+        assert_eq!(twap_of(&track, 250), Some(Decimal::from(350)));
+    }
+
+    #[test]
+    fn twap_covers_a_partial_window() {
+        let mut track = MarketTrack::default();
+        assert_eq!(twap_of(&track, 0), None);
+        track.record(0, Decimal::from(100), 100);
+        track.record(30, Decimal::from(200), 100);
+        assert_eq!(twap_of(&track, 60), Some(Decimal::from(150)));
+    }
+
+    #[test]
+    fn twap_falls_back_to_the_last_price_without_elapsed_time() {
+        let mut track = MarketTrack::default();
+        track.record(10, Decimal::from(100), 100);
+        track.record(10, Decimal::from(110), 100);
+        assert_eq!(twap_of(&track, 10), Some(Decimal::from(110)));
+    }
 }
@@ -0,0 +1,442 @@
+//! Reads the oracle and keeps the on-chain market prices current. A market's price is pushed
+//! only when it moved at least `deviation_bps` from the last push or `heartbeat` has passed
+//! since, so quiet markets cost a transaction per heartbeat rather than one per cycle. Every
+//! reading is handed on to the liquidation crank after the pushes it triggered, and the
+//! publisher's health is reported at `/oracle/publisher`.
+
+use crate::clock::unix_now;
+use crate::config::{env_or, OracleMarketConfig, ViolationAction};
+use crate::events::Event;
+use crate::models::{MarketStatus, PublishedMarket, PublisherStatus};
+use crate::oracle::{OracleClient, OracleUpdate};
+use crate::solana::{market_oracle_authority, SolanaGateway, TxSender};
+use crate::state::AppState;
+use rust_decimal::Decimal;
+use solana_sdk::instruction::Instruction;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Cadence, thresholds and signer of the publisher, from `ORACLE_PUBLISHER_*` variables.
+#[derive(Clone, Debug)]
+pub struct PublisherConfig {
+    /// Signs `UpdatePrice`; without it prices are read and handed on but not pushed.
+    pub keypair_path: Option<String>,
+    pub interval: Duration,
+    /// `UpdatePrice` instructions sent per transaction.
+    pub batch_size: usize,
+    /// Move from the last pushed price, in basis points, that triggers a push.
+    pub deviation_bps: u32,
+    /// Longest a market goes without a push while it has fresh readings.
+    pub heartbeat: Duration,
+    /// Attempts per transaction before the push is given up for this cycle.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for each later one.
+    pub retry_backoff: Duration,
+    /// Simulate every push instead of sending it.
+    pub dry_run: bool,
+}
+
+impl PublisherConfig {
+    pub fn from_env() -> Self {
+        Self {
+            keypair_path: std::env::var("ORACLE_PUBLISHER_KEYPAIR").ok(),
+            interval: Duration::from_secs(env_or("ORACLE_PUBLISHER_INTERVAL_SECS", 5)),
+            batch_size: env_or("ORACLE_PUBLISHER_BATCH_SIZE", 8usize).max(1),
+            deviation_bps: env_or("ORACLE_PUBLISHER_DEVIATION_BPS", 25),
+            heartbeat: Duration::from_secs(env_or("ORACLE_PUBLISHER_HEARTBEAT_SECS", 60)),
+            max_attempts: env_or("ORACLE_PUBLISHER_MAX_ATTEMPTS", 3u32).max(1),
+            retry_backoff: Duration::from_millis(env_or("ORACLE_PUBLISHER_RETRY_BACKOFF_MS", 500)),
+            dry_run: env_or("ORACLE_PUBLISHER_DRY_RUN", false),
+        }
+    }
+}
+
+/// Every oracle reading the publisher made, newest only; `None` until the first one.
+pub type OracleUpdates = watch::Receiver<Option<Arc<OracleUpdate>>>;
+
+struct Publisher {
+    /// `None` when no keypair is configured.
+    sender: Option<TxSender>,
+    solana: SolanaGateway,
+    config: PublisherConfig,
+    markets: BTreeMap<String, PublishedMarket>,
+}
+
+/// Loads the publisher keypair, if any, and spawns the publisher; fails only if the keypair
+/// can't be read.
+pub fn start_oracle_publisher(
+    state: Arc<AppState>,
+    oracle: OracleClient,
+    solana: SolanaGateway,
+    config: PublisherConfig,
+) -> Result<OracleUpdates, String> {
+    let sender = match &config.keypair_path {
+        Some(path) => Some(TxSender::new(&solana.rpc_url, path, config.max_attempts, config.retry_backoff)?),
+        None => None,
+    };
+    let mut publisher = Publisher {
+        sender,
+        solana,
+        config,
+        markets: BTreeMap::new(),
+    };
+    let (tx, rx) = watch::channel(None);
+    tokio::spawn(async move {
+        let mut last_cycle_at = None;
+        loop {
+            let last_error = match oracle.fetch_prices().await {
+                Ok(update) => {
+                    last_cycle_at = Some(unix_now());
+                    publisher.publish(&state, &oracle, &update).await;
+                    tx.send_replace(Some(Arc::new(update)));
+                    None
+                }
+                Err(err) => {
+                    error!(error = %err, "oracle read failed");
+                    Some(err.to_string())
+                }
+            };
+            *state.publisher.lock().unwrap() = Some(publisher.status(last_cycle_at, last_error));
+            sleep(publisher.config.interval).await;
+        }
+    });
+    Ok(rx)
+}
+
+impl Publisher {
+    async fn publish(&mut self, state: &AppState, oracle: &OracleClient, update: &OracleUpdate) {
+        // Prices reach the event bus from the market events loop, at the index.
+        for symbol in update.fresh.keys() {
+            if state.risk.unfreeze_market(symbol) {
+                let source = update.sources.get(symbol).map(|source| source.as_str()).unwrap_or_default();
+                info!(market = %symbol, source, "oracle recovered; market unfrozen");
+            }
+        }
+        for violation in &update.violations {
+            warn!(market = %violation.symbol, action = violation.action.as_str(), error = %violation.error, "oracle guard violated");
+            if violation.action == ViolationAction::FreezeMarket {
+                state.risk.freeze_market(&violation.symbol);
+            }
+            state.events.publish(Event::OracleAlert {
+                market: violation.symbol.clone(),
+                action: violation.action.as_str().to_string(),
+                reason: violation.error.to_string(),
+            });
+        }
+
+        // A settled market's on-chain price stays at its settlement price.
+        let live: Vec<OracleMarketConfig> = oracle
+            .markets()
+            .into_iter()
+            .filter(|market| state.risk.market(&market.symbol).map(|config| config.status) != Some(MarketStatus::Settled))
+            .collect();
+        self.markets.retain(|symbol, _| live.iter().any(|market| &market.symbol == symbol));
+        for market in &live {
+            self.markets.entry(market.symbol.clone()).or_insert_with(|| PublishedMarket {
+                symbol: market.symbol.clone(),
+                price: None,
+                pushed_at: None,
+                signature: None,
+                last_error: None,
+                simulation: None,
+                stale: false,
+                oracle_authority: None,
+                authority_mismatch: false,
+            });
+        }
+        self.check_authorities(&live).await;
+        self.push_prices(&live, &update.fresh).await;
+    }
+
+    /// Reads each market account's `oracle_authority`, so a market still pointing at another
+    /// key, e.g. one created before the publisher had its own, is reported rather than failing
+    /// every push. A failed read keeps the previous result.
+    async fn check_authorities(&mut self, markets: &[OracleMarketConfig]) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let publisher = sender.pubkey();
+        let mut keys = Vec::new();
+        let mut symbols = Vec::new();
+        for market in markets {
+            if let Ok(key) = self.solana.parse_pubkey(&market.market_account) {
+                keys.push(key);
+                symbols.push(market.symbol.as_str());
+            }
+        }
+        let accounts = match sender.client.get_multiple_accounts(&keys).await {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                warn!(error = %err, "cannot read market accounts; oracle authorities not checked");
+                return;
+            }
+        };
+        for (symbol, account) in symbols.into_iter().zip(accounts) {
+            let Some(published) = self.markets.get_mut(symbol) else {
+                continue;
+            };
+            let authority = account.and_then(|account| market_oracle_authority(&account.data));
+            let mismatch = authority.is_some_and(|authority| authority != publisher);
+            if mismatch && !published.authority_mismatch {
+                error!(market = %symbol, authority = ?authority, publisher = %publisher, "market takes prices from another key; pushes skipped");
+            }
+            published.oracle_authority = authority.map(|authority| authority.to_string());
+            published.authority_mismatch = mismatch;
+        }
+    }
+
+    /// Sends the prices that are due in batches of `batch_size` markets. A batch that still
+    /// fails after its retries is split up, so one bad market account doesn't hold back the
+    /// others. In a dry run the batches are simulated instead.
+    async fn push_prices(&mut self, markets: &[OracleMarketConfig], prices: &HashMap<String, Decimal>) {
+        let Some(authority) = self.sender.as_ref().map(TxSender::pubkey) else {
+            return;
+        };
+        let now = unix_now();
+        // Markets without a fresh reading keep their last on-chain price.
+        let mut updates = Vec::new();
+        for market in markets {
+            let Some(price) = prices.get(&market.symbol) else {
+                continue;
+            };
+            if !self.is_due(&market.symbol, *price, now) {
+                continue;
+            }
+            if self.markets.get(&market.symbol).is_some_and(|published| published.authority_mismatch) {
+                self.record_failure(&market.symbol, format!("{} is not the market's oracle authority", authority));
+                continue;
+            }
+            match self.solana.parse_pubkey(&market.market_account) {
+                Ok(market_account) => updates.push((
+                    market.symbol.clone(),
+                    *price,
+                    self.solana
+                        .build_update_price_ix(authority, market_account, market.market_id, *price),
+                )),
+                Err(err) => {
+                    warn!(market = %market.symbol, error = %err, "invalid market account; price not pushed");
+                    self.record_failure(&market.symbol, err.to_string());
+                }
+            }
+        }
+
+        for batch in updates.chunks(self.config.batch_size) {
+            if self.push_batch(batch).await || batch.len() == 1 {
+                continue;
+            }
+            for update in batch {
+                self.push_batch(std::slice::from_ref(update)).await;
+            }
+        }
+    }
+
+    /// Sends, or in a dry run simulates, one transaction and records the outcome on each of its
+    /// markets. Returns whether it went through.
+    async fn push_batch(&mut self, batch: &[(String, Decimal, Instruction)]) -> bool {
+        let Some(sender) = &self.sender else {
+            return false;
+        };
+        let ixs: Vec<Instruction> = batch.iter().map(|(_, _, ix)| ix.clone()).collect();
+        let symbols: Vec<&str> = batch.iter().map(|(symbol, _, _)| symbol.as_str()).collect();
+        let label = format!("price update {}", symbols.join(","));
+        let (result, simulation) = if self.config.dry_run {
+            let simulation = sender.simulate(&ixs).await;
+            match &simulation.error {
+                None => info!(markets = %symbols.join(","), units = ?simulation.units_consumed, "dry run: would push prices"),
+                Some(err) => warn!(markets = %symbols.join(","), error = %err, "dry run: price update would fail"),
+            }
+            (simulation.error.clone().map_or(Ok(None), Err), Some(simulation))
+        } else {
+            (sender.send_with_retry(&label, &ixs).await.map(|signature| Some(signature.to_string())), None)
+        };
+
+        let now = unix_now();
+        for (symbol, price, _) in batch {
+            let Some(published) = self.markets.get_mut(symbol) else {
+                continue;
+            };
+            published.simulation = simulation.clone();
+            match &result {
+                Ok(signature) => {
+                    published.price = Some(*price);
+                    published.pushed_at = Some(now);
+                    published.signature = signature.clone();
+                    published.last_error = None;
+                }
+                Err(err) => {
+                    if batch.len() == 1 {
+                        error!(market = %symbol, error = %err, "price update failed");
+                    }
+                    published.last_error = Some(err.clone());
+                }
+            }
+        }
+        result.is_ok()
+    }
+
+    /// Whether `price` has moved far enough, or the last push is old enough, to push again.
+    fn is_due(&self, symbol: &str, price: Decimal, now: i64) -> bool {
+        let Some(published) = self.markets.get(symbol) else {
+            return true;
+        };
+        let (Some(last), Some(pushed_at)) = (published.price, published.pushed_at) else {
+            return true;
+        };
+        if now - pushed_at >= self.config.heartbeat.as_secs() as i64 || last.is_zero() {
+            return true;
+        }
+        let moved_bps = ((price - last) / last).abs() * Decimal::from(10_000);
+        moved_bps >= Decimal::from(self.config.deviation_bps)
+    }
+
+    fn record_failure(&mut self, symbol: &str, error: String) {
+        if let Some(published) = self.markets.get_mut(symbol) {
+            published.last_error = Some(error);
+        }
+    }
+
+    /// A market counts as stale once its last push is older than the heartbeat plus two
+    /// cycles of slack; without a keypair nothing is pushed, so nothing is stale.
+    fn status(&self, last_cycle_at: Option<i64>, last_error: Option<String>) -> PublisherStatus {
+        let now = unix_now();
+        let allowed = (self.config.heartbeat + 2 * self.config.interval).as_secs() as i64;
+        let markets: Vec<PublishedMarket> = self
+            .markets
+            .values()
+            .map(|published| PublishedMarket {
+                stale: self.sender.is_some() && published.pushed_at.is_none_or(|at| now - at > allowed),
+                ..published.clone()
+            })
+            .collect();
+        PublisherStatus {
+            authority: self.sender.as_ref().map(|sender| sender.pubkey().to_string()),
+            dry_run: self.config.dry_run,
+            interval_secs: self.config.interval.as_secs(),
+            deviation_bps: self.config.deviation_bps,
+            heartbeat_secs: self.config.heartbeat.as_secs(),
+            last_cycle_at,
+            healthy: last_error.is_none()
+                && !markets.iter().any(|market| market.stale || market.authority_mismatch),
+            last_error,
+            markets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{write_keypair_file, Keypair};
+
+    /// Pushes on a 25 bps move or every 60s, every 5s cycle; `signing` gives it a keypair.
+    fn publisher(signing: bool) -> Publisher {
+        let config = PublisherConfig {
+            keypair_path: None,
+            interval: Duration::from_secs(5),
+            batch_size: 8,
+            deviation_bps: 25,
+            heartbeat: Duration::from_secs(60),
+            max_attempts: 1,
+            retry_backoff: Duration::ZERO,
+            dry_run: false,
+        };
+        let sender = signing.then(|| {
+            let path = std::env::temp_dir().join(format!("publisher-{}.json", uuid::Uuid::new_v4()));
+            write_keypair_file(&Keypair::new(), &path).unwrap();
+            let sender = TxSender::new("http://127.0.0.1:1", path.to_str().unwrap(), 1, Duration::ZERO).unwrap();
+            std::fs::remove_file(&path).ok();
+            sender
+        });
+        Publisher {
+            sender,
+            solana: SolanaGateway::new("http://127.0.0.1:1", &Pubkey::new_unique().to_string()),
+            config,
+            markets: BTreeMap::new(),
+        }
+    }
+
+    fn published(price: Option<i64>, pushed_at: Option<i64>) -> PublishedMarket {
+        PublishedMarket {
+            symbol: "BTC".to_string(),
+            price: price.map(Decimal::from),
+            pushed_at,
+            signature: None,
+            last_error: None,
+            simulation: None,
+            stale: false,
+            oracle_authority: None,
+            authority_mismatch: false,
+        }
+    }
+
+    #[test]
+    fn prices_are_due_on_a_deviation_or_the_heartbeat() {
+        let mut publisher = publisher(true);
+        let now = 1_000_000;
+        assert!(publisher.is_due("BTC", Decimal::from(60_000), now));
+        publisher.markets.insert("BTC".to_string(), published(None, None));
+        assert!(publisher.is_due("BTC", Decimal::from(60_000), now));
+
+        publisher.markets.insert("BTC".to_string(), published(Some(60_000), Some(now - 30)));
+        // 24 and 25 bps moves, either way.
+        assert!(!publisher.is_due("BTC", Decimal::from(60_144), now));
+        assert!(!publisher.is_due("BTC", Decimal::from(59_856), now));
+        assert!(publisher.is_due("BTC", Decimal::from(60_150), now));
+        assert!(publisher.is_due("BTC", Decimal::from(59_850), now));
+
+        // An unchanged price is pushed again once the heartbeat is up.
+        assert!(!publisher.is_due("BTC", Decimal::from(60_000), now + 29));
+        assert!(publisher.is_due("BTC", Decimal::from(60_000), now + 30));
+
+        publisher.markets.insert("BTC".to_string(), published(Some(0), Some(now)));
+        assert!(publisher.is_due("BTC", Decimal::from(60_000), now));
+    }
+
+    #[test]
+    fn markets_go_stale_after_the_heartbeat_and_two_cycles() {
+        let mut publisher = publisher(true);
+        let now = unix_now();
+        // 60s heartbeat plus two 5s cycles; a second of slack for the clock ticking over.
+        publisher.markets.insert("BTC".to_string(), published(Some(60_000), Some(now - 69)));
+        let status = publisher.status(Some(now), None);
+        assert!(!status.markets[0].stale);
+        assert!(status.healthy);
+
+        publisher.markets.insert("BTC".to_string(), published(Some(60_000), Some(now - 71)));
+        let status = publisher.status(Some(now), None);
+        assert!(status.markets[0].stale);
+        assert!(!status.healthy);
+
+        publisher.markets.insert("BTC".to_string(), published(None, None));
+        assert!(publisher.status(Some(now), None).markets[0].stale);
+    }
+
+    #[test]
+    fn health_reflects_errors_and_foreign_authorities() {
+        let mut publisher = publisher(true);
+        let now = unix_now();
+        publisher.markets.insert("BTC".to_string(), published(Some(60_000), Some(now)));
+        assert!(publisher.status(Some(now), None).healthy);
+        assert!(!publisher.status(Some(now), Some("rpc down".to_string())).healthy);
+
+        let mut foreign = published(Some(60_000), Some(now));
+        foreign.authority_mismatch = true;
+        publisher.markets.insert("BTC".to_string(), foreign);
+        assert!(!publisher.status(Some(now), None).healthy);
+    }
+
+    #[test]
+    fn nothing_is_stale_without_a_keypair() {
+        let mut publisher = publisher(false);
+        publisher.markets.insert("BTC".to_string(), published(None, None));
+        let status = publisher.status(None, None);
+        assert_eq!(status.authority, None);
+        assert!(!status.markets[0].stale);
+        assert!(status.healthy);
+    }
+}
@@ -0,0 +1,145 @@
+use super::{fetch_json, parse_decimal, parse_level, OrderBook, PriceProvider, Trade};
+use crate::errors::UpstreamError;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const BINANCE_BASES: [&str; 2] = ["https://api.binance.com", "https://api.binance.us"];
+const SERVICE: &str = "binance";
+
+#[derive(Debug, Deserialize)]
+struct BinanceTicker {
+    symbol: String,
+    price: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinanceDepth {
+    bids: Vec<Vec<String>>,
+    asks: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinanceTrade {
+    price: String,
+    qty: String,
+    time: u64,
+    #[serde(rename = "isBuyerMaker")]
+    is_buyer_maker: bool,
+}
+
+/// Binance spot REST, falling back to binance.us where binance.com is geo-blocked.
+pub struct BinanceProvider {
+    bases: Vec<String>,
+}
+
+impl BinanceProvider {
+    /// Uses `BINANCE_REST_URL` instead of the public endpoints when it is set.
+    pub fn from_env() -> Self {
+        let bases = match std::env::var("BINANCE_REST_URL") {
+            Ok(base) => vec![base.trim_end_matches('/').to_string()],
+            Err(_) => BINANCE_BASES.iter().map(|base| base.to_string()).collect(),
+        };
+        Self { bases }
+    }
+
+    async fn fetch_any<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T, UpstreamError> {
+        let mut last_error = None;
+        for base in &self.bases {
+            match fetch_json::<T>(SERVICE, &format!("{}{}", base, path)).await {
+                Ok(value) => return Ok(value),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| UpstreamError::Unavailable {
+            service: SERVICE.to_string(),
+            reason: "no endpoints tried".to_string(),
+        }))
+    }
+}
+
+pub(super) fn pair(symbol: &str) -> String {
+    format!("{}USDT", symbol.to_uppercase())
+}
+
+#[async_trait]
+impl PriceProvider for BinanceProvider {
+    fn name(&self) -> &'static str {
+        SERVICE
+    }
+
+    async fn tickers(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        let mut prices = HashMap::new();
+        if symbols.is_empty() {
+            return Ok(prices);
+        }
+
+        let pairs: Vec<String> = symbols.iter().map(|symbol| format!("\"{}\"", pair(symbol))).collect();
+        let batch_path = format!(
+            "/api/v3/ticker/price?symbols={}",
+            urlencoding::encode(&format!("[{}]", pairs.join(",")))
+        );
+        // The batch endpoint rejects the whole request if one symbol is unlisted, so fall back
+        // to one request per symbol.
+        if let Ok(tickers) = self.fetch_any::<Vec<BinanceTicker>>(&batch_path).await {
+            for ticker in tickers {
+                let symbol = ticker.symbol.trim_end_matches("USDT").to_string();
+                prices.insert(symbol, parse_decimal(SERVICE, &ticker.price)?);
+            }
+            return Ok(prices);
+        }
+
+        let mut last_error = None;
+        for symbol in symbols {
+            let path = format!("/api/v3/ticker/price?symbol={}", pair(symbol));
+            match self.fetch_any::<BinanceTicker>(&path).await {
+                Ok(ticker) => {
+                    prices.insert(symbol.to_string(), parse_decimal(SERVICE, &ticker.price)?);
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        match last_error {
+            Some(err) if prices.is_empty() => Err(err),
+            _ => Ok(prices),
+        }
+    }
+
+    async fn depth(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError> {
+        let path = format!("/api/v3/depth?symbol={}&limit={}", pair(symbol), limit);
+        let depth = self.fetch_any::<BinanceDepth>(&path).await?;
+
+        let bids = depth
+            .bids
+            .iter()
+            .map(|level| parse_level(SERVICE, level))
+            .collect::<Result<Vec<_>, _>>()?;
+        let asks = depth
+            .asks
+            .iter()
+            .map(|level| parse_level(SERVICE, level))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(OrderBook { bids, asks })
+    }
+
+    async fn trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError> {
+        let path = format!("/api/v3/trades?symbol={}&limit={}", pair(symbol), limit);
+        let trades = self.fetch_any::<Vec<BinanceTrade>>(&path).await?;
+
+        trades
+            .into_iter()
+            .map(|trade| {
+                let side = if trade.is_buyer_maker { "sell" } else { "buy" };
+                Ok(Trade {
+                    price: parse_decimal(SERVICE, &trade.price)?,
+                    qty: parse_decimal(SERVICE, &trade.qty)?,
+                    time: trade.time,
+                    side: side.to_string(),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,727 @@
+use super::binance::{pair, BinanceProvider};
+use super::local_book::{ApplyOutcome, LocalBook};
+use super::{fetch_json, parse_decimal, parse_level, OrderBook, OrderLevel, PriceProvider, SymbolTrade, Trade};
+use crate::errors::UpstreamError;
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Notify};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+const SERVICE: &str = "binance_ws";
+const DEFAULT_WS_URL: &str = "wss://stream.binance.com:9443";
+const DEFAULT_REST_URL: &str = "https://api.binance.com";
+const SNAPSHOT_DEPTH: usize = 1000;
+const MAX_TRADES: usize = 500;
+/// Trades a slow [`PriceProvider::subscribe_trades`] subscriber may fall behind by.
+const TRADE_CHANNEL_CAPACITY: usize = 4096;
+/// Diffs kept per symbol while its snapshot is in flight.
+const MAX_PENDING_DIFFS: usize = 2000;
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Caps both the reconnect backoff and the per-symbol snapshot retry backoff.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// A connection that stayed up this long resets the reconnect backoff.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct StreamConfig {
+    pub ws_url: String,
+    pub rest_url: String,
+    /// Local state is only served while the stream delivered a message within this window.
+    pub max_silence: Duration,
+}
+
+impl StreamConfig {
+    /// Reads `BINANCE_WS_URL`, `BINANCE_REST_URL` (depth snapshots) and `MARKET_STREAM_MAX_SILENCE_MS`.
+    pub fn from_env() -> Self {
+        Self {
+            ws_url: std::env::var("BINANCE_WS_URL").unwrap_or_else(|_| DEFAULT_WS_URL.to_string()),
+            rest_url: std::env::var("BINANCE_REST_URL").unwrap_or_else(|_| DEFAULT_REST_URL.to_string()),
+            max_silence: Duration::from_millis(
+                std::env::var("MARKET_STREAM_MAX_SILENCE_MS")
+                    .ok()
+                    .and_then(|val| val.parse::<u64>().ok())
+                    .unwrap_or(5_000),
+            ),
+        }
+    }
+}
+
+/// Combined stream payloads are wrapped as `{"stream": "btcusdt@trade", "data": {...}}`.
+#[derive(Debug, Deserialize)]
+struct Frame {
+    data: StreamEvent,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "e")]
+enum StreamEvent {
+    #[serde(rename = "depthUpdate")]
+    Depth(DepthDiff),
+    #[serde(rename = "trade")]
+    Trade(TradeEvent),
+    #[serde(rename = "24hrTicker")]
+    Ticker(TickerEvent),
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthDiff {
+    #[serde(rename = "s")]
+    pair: String,
+    #[serde(rename = "U")]
+    first_id: u64,
+    #[serde(rename = "u")]
+    final_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<Vec<String>>,
+    #[serde(rename = "a")]
+    asks: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeEvent {
+    #[serde(rename = "s")]
+    pair: String,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    qty: String,
+    #[serde(rename = "T")]
+    time: u64,
+    #[serde(rename = "m")]
+    is_buyer_maker: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerEvent {
+    #[serde(rename = "s")]
+    pair: String,
+    #[serde(rename = "c")]
+    last: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DepthSnapshot {
+    last_update_id: u64,
+    bids: Vec<Vec<String>>,
+    asks: Vec<Vec<String>>,
+}
+
+struct Diff {
+    first_id: u64,
+    final_id: u64,
+    bids: Vec<OrderLevel>,
+    asks: Vec<OrderLevel>,
+}
+
+#[derive(Default)]
+struct SymbolState {
+    /// `None` until a snapshot has been merged with the buffered diffs.
+    book: Option<LocalBook>,
+    pending: VecDeque<Diff>,
+    snapshot_requested: bool,
+    snapshot_failures: u32,
+    price: Option<Decimal>,
+    trades: VecDeque<Trade>,
+}
+
+struct Shared {
+    symbols: Mutex<HashMap<String, SymbolState>>,
+    /// When the current connection last delivered a message; `None` while disconnected.
+    last_message: Mutex<Option<Instant>>,
+    trades: broadcast::Sender<SymbolTrade>,
+    /// Woken when `symbols` changes, so the open connection can follow it.
+    resubscribe: Notify,
+}
+
+impl Shared {
+    fn symbols(&self) -> MutexGuard<'_, HashMap<String, SymbolState>> {
+        self.symbols.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn touch(&self, at: Option<Instant>) {
+        *self.last_message.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = at;
+    }
+
+    fn is_live(&self, max_silence: Duration) -> bool {
+        self.last_message
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .is_some_and(|at| at.elapsed() < max_silence)
+    }
+
+    fn reset(&self) {
+        self.touch(None);
+        for state in self.symbols().values_mut() {
+            *state = SymbolState::default();
+        }
+    }
+}
+
+/// Binance market data maintained locally from the combined WebSocket stream (ticker, depth
+/// diffs and trades). Anything the local state can't answer, because the stream is down or a
+/// book is resyncing, is fetched over REST instead.
+pub struct BinanceStreamProvider {
+    shared: Arc<Shared>,
+    max_silence: Duration,
+    rest: BinanceProvider,
+}
+
+impl BinanceStreamProvider {
+    /// Starts ingesting `symbols` in the background.
+    pub fn spawn(config: StreamConfig, symbols: &[String]) -> Self {
+        let shared = Arc::new(Shared {
+            symbols: Mutex::new(
+                symbols
+                    .iter()
+                    .map(|symbol| (symbol.to_uppercase(), SymbolState::default()))
+                    .collect(),
+            ),
+            last_message: Mutex::new(None),
+            trades: broadcast::channel(TRADE_CHANNEL_CAPACITY).0,
+            resubscribe: Notify::new(),
+        });
+        let max_silence = config.max_silence;
+        tokio::spawn(run(shared.clone(), config));
+        Self {
+            shared,
+            max_silence,
+            rest: BinanceProvider::from_env(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for BinanceStreamProvider {
+    fn name(&self) -> &'static str {
+        // Named apart from the REST provider, so the index and the logs tell them apart.
+        SERVICE
+    }
+
+    async fn tickers(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        let mut prices = HashMap::new();
+        if self.shared.is_live(self.max_silence) {
+            let states = self.shared.symbols();
+            for symbol in symbols {
+                if let Some(price) = states.get(symbol).and_then(|state| state.price) {
+                    prices.insert(symbol.clone(), price);
+                }
+            }
+        }
+
+        let missing: Vec<String> = symbols
+            .iter()
+            .filter(|symbol| !prices.contains_key(*symbol))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return Ok(prices);
+        }
+        match self.rest.tickers(&missing).await {
+            Ok(fetched) => {
+                prices.extend(fetched);
+                Ok(prices)
+            }
+            Err(_) if !prices.is_empty() => Ok(prices),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn depth(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError> {
+        if self.shared.is_live(self.max_silence) {
+            let states = self.shared.symbols();
+            if let Some(book) = states.get(symbol).and_then(|state| state.book.as_ref()) {
+                return Ok(book.top(limit));
+            }
+        }
+        self.rest.depth(symbol, limit).await
+    }
+
+    async fn trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError> {
+        if self.shared.is_live(self.max_silence) {
+            let states = self.shared.symbols();
+            // Right after connecting the buffer holds fewer trades than asked for.
+            if let Some(trades) = states.get(symbol).map(|state| &state.trades).filter(|trades| trades.len() >= limit) {
+                return Ok(trades.iter().skip(trades.len() - limit).cloned().collect());
+            }
+        }
+        self.rest.trades(symbol, limit).await
+    }
+
+    fn subscribe_trades(&self) -> Option<broadcast::Receiver<SymbolTrade>> {
+        Some(self.shared.trades.subscribe())
+    }
+
+    /// New symbols start without a book and are subscribed on the open connection; removed
+    /// ones are dropped and unsubscribed.
+    fn set_symbols(&self, symbols: &[String]) {
+        let wanted: Vec<String> = symbols.iter().map(|symbol| symbol.to_uppercase()).collect();
+        let mut states = self.shared.symbols();
+        let before = states.len();
+        states.retain(|symbol, _| wanted.contains(symbol));
+        let removed = before - states.len();
+        let mut added = 0;
+        for symbol in wanted {
+            states.entry(symbol).or_insert_with(|| {
+                added += 1;
+                SymbolState::default()
+            });
+        }
+        drop(states);
+        if added > 0 || removed > 0 {
+            info!(added, removed, "market stream symbols changed");
+            self.shared.resubscribe.notify_one();
+        }
+    }
+}
+
+async fn run(shared: Arc<Shared>, config: StreamConfig) {
+    let mut delay = SNAPSHOT_RETRY_DELAY;
+    loop {
+        let started = Instant::now();
+        match ingest(&shared, &config).await {
+            Ok(()) => warn!("market stream closed by server"),
+            Err(err) => warn!(error = %err, "market stream failed"),
+        }
+        shared.reset();
+        if started.elapsed() >= STABLE_CONNECTION {
+            delay = SNAPSHOT_RETRY_DELAY;
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+type SnapshotResult = (String, Result<DepthSnapshot, UpstreamError>);
+
+async fn ingest(shared: &Arc<Shared>, config: &StreamConfig) -> Result<(), UpstreamError> {
+    let mut symbols: Vec<String> = shared.symbols().keys().cloned().collect();
+    let url = format!(
+        "{}/stream?streams={}",
+        config.ws_url.trim_end_matches('/'),
+        streams(&symbols).join("/")
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str())
+        .await
+        .map_err(unavailable)?;
+    info!(symbols = symbols.len(), "market stream connected");
+    shared.touch(Some(Instant::now()));
+
+    // Diffs are buffered from here on, so snapshots fetched now can be bridged to the stream.
+    let (snapshots, mut snapshot_results) = unbounded_channel::<SnapshotResult>();
+    {
+        let mut states = shared.symbols();
+        for symbol in &symbols {
+            if let Some(state) = states.get_mut(symbol) {
+                request_snapshot(config, symbol, state, &snapshots, Duration::ZERO);
+            }
+        }
+    }
+
+    loop {
+        tokio::select! {
+            message = socket.next() => {
+                let message = match message {
+                    Some(Ok(message)) => message,
+                    Some(Err(err)) => return Err(unavailable(err)),
+                    None => return Ok(()),
+                };
+                shared.touch(Some(Instant::now()));
+                match message {
+                    Message::Text(text) => handle_frame(shared, config, &text, &snapshots),
+                    Message::Ping(payload) => socket.send(Message::Pong(payload)).await.map_err(unavailable)?,
+                    Message::Close(_) => return Ok(()),
+                    _ => {}
+                }
+            }
+            Some((symbol, snapshot)) = snapshot_results.recv() => {
+                apply_snapshot(shared, config, &symbol, snapshot, &snapshots);
+            }
+            _ = shared.resubscribe.notified() => {
+                let wanted: Vec<String> = shared.symbols().keys().cloned().collect();
+                let removed: Vec<String> = symbols.iter().filter(|symbol| !wanted.contains(symbol)).cloned().collect();
+                let added: Vec<String> = wanted.iter().filter(|symbol| !symbols.contains(symbol)).cloned().collect();
+                for (method, changed) in [("UNSUBSCRIBE", &removed), ("SUBSCRIBE", &added)] {
+                    if changed.is_empty() {
+                        continue;
+                    }
+                    let request = serde_json::json!({ "method": method, "params": streams(changed), "id": 1 });
+                    socket.send(Message::Text(request.to_string())).await.map_err(unavailable)?;
+                }
+                let mut states = shared.symbols();
+                for symbol in &added {
+                    if let Some(state) = states.get_mut(symbol) {
+                        request_snapshot(config, symbol, state, &snapshots, Duration::ZERO);
+                    }
+                }
+                symbols = wanted;
+            }
+        }
+    }
+}
+
+/// Depth diffs, trades and tickers of each symbol, as named on the combined stream.
+fn streams(symbols: &[String]) -> Vec<String> {
+    symbols
+        .iter()
+        .flat_map(|symbol| {
+            let stream = pair(symbol).to_lowercase();
+            [
+                format!("{}@depth@100ms", stream),
+                format!("{}@trade", stream),
+                format!("{}@ticker", stream),
+            ]
+        })
+        .collect()
+}
+
+fn handle_frame(shared: &Shared, config: &StreamConfig, text: &str, snapshots: &UnboundedSender<SnapshotResult>) {
+    let frame: Frame = match serde_json::from_str(text) {
+        Ok(frame) => frame,
+        Err(err) => {
+            debug!(error = %err, "ignoring market stream frame");
+            return;
+        }
+    };
+
+    let mut states = shared.symbols();
+    match frame.data {
+        StreamEvent::Depth(diff) => {
+            let symbol = base_symbol(&diff.pair);
+            let Some(state) = states.get_mut(&symbol) else { return };
+            let diff = match parse_diff(diff) {
+                Ok(diff) => diff,
+                Err(err) => {
+                    warn!(symbol, error = %err, "malformed depth diff, resyncing");
+                    state.book = None;
+                    state.pending.clear();
+                    request_snapshot(config, &symbol, state, snapshots, Duration::ZERO);
+                    return;
+                }
+            };
+            let Some(book) = state.book.as_mut() else {
+                if state.pending.len() >= MAX_PENDING_DIFFS {
+                    // The snapshot can no longer be bridged; the gap check will ask for another.
+                    state.pending.pop_front();
+                }
+                state.pending.push_back(diff);
+                return;
+            };
+            match book.apply(diff.first_id, diff.final_id, &diff.bids, &diff.asks) {
+                ApplyOutcome::Applied | ApplyOutcome::Outdated => {}
+                ApplyOutcome::Gap { expected, received } => {
+                    warn!(symbol, expected, received, "order book sequence gap, resyncing");
+                    state.book = None;
+                    state.pending.clear();
+                    state.pending.push_back(diff);
+                    request_snapshot(config, &symbol, state, snapshots, Duration::ZERO);
+                }
+            }
+        }
+        StreamEvent::Trade(event) => {
+            let symbol = base_symbol(&event.pair);
+            let Some(state) = states.get_mut(&symbol) else { return };
+            let (Ok(price), Ok(qty)) = (parse_decimal(SERVICE, &event.price), parse_decimal(SERVICE, &event.qty)) else {
+                debug!(symbol, "ignoring malformed trade");
+                return;
+            };
+            if state.trades.len() >= MAX_TRADES {
+                state.trades.pop_front();
+            }
+            let trade = Trade {
+                price,
+                qty,
+                time: event.time,
+                side: if event.is_buyer_maker { "sell" } else { "buy" }.to_string(),
+            };
+            state.trades.push_back(trade.clone());
+            state.price = Some(price);
+            // Nobody subscribed is fine.
+            let _ = shared.trades.send(SymbolTrade { symbol, trade });
+        }
+        StreamEvent::Ticker(event) => {
+            let symbol = base_symbol(&event.pair);
+            if let (Some(state), Ok(price)) = (states.get_mut(&symbol), parse_decimal(SERVICE, &event.last)) {
+                state.price = Some(price);
+            }
+        }
+    }
+}
+
+fn apply_snapshot(
+    shared: &Shared,
+    config: &StreamConfig,
+    symbol: &str,
+    snapshot: Result<DepthSnapshot, UpstreamError>,
+    snapshots: &UnboundedSender<SnapshotResult>,
+) {
+    let mut states = shared.symbols();
+    let Some(state) = states.get_mut(symbol) else { return };
+    state.snapshot_requested = false;
+
+    let book = snapshot.and_then(|snapshot| {
+        let bids = parse_levels(&snapshot.bids)?;
+        let asks = parse_levels(&snapshot.asks)?;
+        Ok(LocalBook::from_snapshot(snapshot.last_update_id, bids, asks))
+    });
+    let mut book = match book {
+        Ok(book) => book,
+        Err(err) => {
+            // Pairs the venue doesn't list fail every time, so only the first failure is loud.
+            if state.snapshot_failures == 0 {
+                warn!(symbol, error = %err, "depth snapshot failed, retrying");
+            } else {
+                debug!(symbol, error = %err, "depth snapshot failed, retrying");
+            }
+            let delay = (SNAPSHOT_RETRY_DELAY * 2u32.saturating_pow(state.snapshot_failures)).min(MAX_RETRY_DELAY);
+            state.snapshot_failures = state.snapshot_failures.saturating_add(1);
+            request_snapshot(config, symbol, state, snapshots, delay);
+            return;
+        }
+    };
+    state.snapshot_failures = 0;
+
+    while let Some(diff) = state.pending.pop_front() {
+        if let ApplyOutcome::Gap { expected, received } =
+            book.apply(diff.first_id, diff.final_id, &diff.bids, &diff.asks)
+        {
+            // The snapshot predates the oldest buffered diff; a later one will overlap.
+            debug!(symbol, expected, received, "snapshot too old for buffered diffs");
+            state.pending.push_front(diff);
+            request_snapshot(config, symbol, state, snapshots, SNAPSHOT_RETRY_DELAY);
+            return;
+        }
+    }
+    info!(symbol, last_update_id = book.last_update_id(), "order book synced");
+    state.book = Some(book);
+}
+
+fn request_snapshot(
+    config: &StreamConfig,
+    symbol: &str,
+    state: &mut SymbolState,
+    snapshots: &UnboundedSender<SnapshotResult>,
+    delay: Duration,
+) {
+    if state.snapshot_requested {
+        return;
+    }
+    state.snapshot_requested = true;
+    let url = format!(
+        "{}/api/v3/depth?symbol={}&limit={}",
+        config.rest_url.trim_end_matches('/'),
+        pair(symbol),
+        SNAPSHOT_DEPTH
+    );
+    let symbol = symbol.to_string();
+    let snapshots = snapshots.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let snapshot = fetch_json::<DepthSnapshot>(SERVICE, &url).await;
+        // The receiver is gone once the connection dropped; its books are rebuilt on reconnect.
+        let _ = snapshots.send((symbol, snapshot));
+    });
+}
+
+fn parse_diff(diff: DepthDiff) -> Result<Diff, UpstreamError> {
+    Ok(Diff {
+        first_id: diff.first_id,
+        final_id: diff.final_id,
+        bids: parse_levels(&diff.bids)?,
+        asks: parse_levels(&diff.asks)?,
+    })
+}
+
+fn parse_levels(levels: &[Vec<String>]) -> Result<Vec<OrderLevel>, UpstreamError> {
+    levels.iter().map(|level| parse_level(SERVICE, level)).collect()
+}
+
+fn base_symbol(pair: &str) -> String {
+    pair.trim_end_matches("USDT").to_string()
+}
+
+fn unavailable(err: impl std::fmt::Display) -> UpstreamError {
+    UpstreamError::Unavailable {
+        service: SERVICE.to_string(),
+        reason: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_feed::replay;
+
+    fn shared(symbols: &[&str]) -> Shared {
+        Shared {
+            symbols: Mutex::new(symbols.iter().map(|symbol| (symbol.to_string(), SymbolState::default())).collect()),
+            last_message: Mutex::new(None),
+            trades: broadcast::channel(TRADE_CHANNEL_CAPACITY).0,
+            resubscribe: Notify::new(),
+        }
+    }
+
+    fn depth_frame(first_id: u64, final_id: u64, bid: &str) -> String {
+        serde_json::json!({
+            "stream": "btcusdt@depth@100ms",
+            "data": { "e": "depthUpdate", "E": 0, "s": "BTCUSDT", "U": first_id, "u": final_id, "b": [[bid, "1"]], "a": [] },
+        })
+        .to_string()
+    }
+
+    fn snapshot(last_update_id: u64) -> Result<DepthSnapshot, UpstreamError> {
+        Ok(DepthSnapshot {
+            last_update_id,
+            bids: vec![vec!["99".to_string(), "1".to_string()]],
+            asks: vec![vec!["101".to_string(), "1".to_string()]],
+        })
+    }
+
+    fn last_update_id(shared: &Shared) -> Option<u64> {
+        shared.symbols()["BTC"].book.as_ref().map(LocalBook::last_update_id)
+    }
+
+    #[tokio::test]
+    async fn sequence_gap_drops_the_book_and_resyncs_from_a_snapshot() {
+        // Nothing listens here, so the snapshot requests fail; the test hands snapshots in itself.
+        let config = StreamConfig {
+            ws_url: String::new(),
+            rest_url: "http://127.0.0.1:9".to_string(),
+            max_silence: Duration::from_secs(5),
+        };
+        let (snapshots, _results) = unbounded_channel();
+        let shared = shared(&["BTC"]);
+
+        // Diffs arriving before the snapshot are buffered and bridged onto it.
+        handle_frame(&shared, &config, &depth_frame(1001, 1002, "100"), &snapshots);
+        assert_eq!(shared.symbols()["BTC"].pending.len(), 1);
+        apply_snapshot(&shared, &config, "BTC", snapshot(1000), &snapshots);
+        assert_eq!(last_update_id(&shared), Some(1002));
+        assert!(shared.symbols()["BTC"].pending.is_empty());
+
+        // 1003 and 1004 never arrive.
+        handle_frame(&shared, &config, &depth_frame(1005, 1005, "98"), &snapshots);
+        {
+            let states = shared.symbols();
+            let state = &states["BTC"];
+            assert!(state.book.is_none(), "a book with a gap must not be served");
+            assert!(state.snapshot_requested);
+            assert_eq!(state.pending.len(), 1);
+        }
+
+        apply_snapshot(&shared, &config, "BTC", snapshot(1004), &snapshots);
+        assert_eq!(last_update_id(&shared), Some(1005));
+        let states = shared.symbols();
+        let book = states["BTC"].book.as_ref().unwrap().top(10);
+        assert_eq!(book.bids.len(), 2, "the buffered diff is applied on top of the new snapshot");
+    }
+
+    #[tokio::test]
+    async fn snapshot_older_than_the_buffer_is_retried() {
+        let config = StreamConfig {
+            ws_url: String::new(),
+            rest_url: "http://127.0.0.1:9".to_string(),
+            max_silence: Duration::from_secs(5),
+        };
+        let (snapshots, _results) = unbounded_channel();
+        let shared = shared(&["BTC"]);
+
+        handle_frame(&shared, &config, &depth_frame(1010, 1011, "100"), &snapshots);
+        apply_snapshot(&shared, &config, "BTC", snapshot(1000), &snapshots);
+        let states = shared.symbols();
+        assert!(states["BTC"].book.is_none());
+        assert!(states["BTC"].snapshot_requested);
+        assert_eq!(states["BTC"].pending.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replayed_stream_resyncs_after_a_dropped_frame() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            replay::serve_on(listener, "fixtures/binance_stream.jsonl", Duration::from_millis(2))
+                .await
+                .unwrap()
+        });
+        let provider = BinanceStreamProvider::spawn(
+            StreamConfig {
+                ws_url: format!("ws://{}", addr),
+                rest_url: format!("http://{}", addr),
+                max_silence: Duration::from_secs(5),
+            },
+            &["BTC".to_string(), "ETH".to_string()],
+        );
+        let mut trades = provider.subscribe_trades().unwrap();
+
+        // The recording drops BTC update 1023 and spans ids 1001..=1047, shifted by 47 on every
+        // pass. A book past the second pass's dropped update has bridged at least one gap while
+        // synced, which it can only do by resyncing.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if last_update_id(&provider.shared).is_some_and(|id| id > 1023 + 47) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "BTC book never got past the dropped update");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let book = provider.depth("BTC", 5).await.unwrap();
+        assert_eq!(book.bids.len(), 5);
+        assert!(book.bids[0].price < book.asks[0].price);
+        assert!(provider.tickers(&["BTC".to_string()]).await.unwrap().contains_key("BTC"));
+        let streamed = trades.try_recv().expect("trades reach subscribers");
+        assert!(["BTC", "ETH"].contains(&streamed.symbol.as_str()));
+    }
+
+    async fn wait_for(what: &str, done: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "{}", what);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn symbols_follow_the_registry_on_the_open_connection() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            replay::serve_on(listener, "fixtures/binance_stream.jsonl", Duration::from_millis(2))
+                .await
+                .unwrap()
+        });
+        let provider = BinanceStreamProvider::spawn(
+            StreamConfig {
+                ws_url: format!("ws://{}", addr),
+                rest_url: format!("http://{}", addr),
+                max_silence: Duration::from_secs(5),
+            },
+            &["BTC".to_string()],
+        );
+        let synced = |symbol: &str| {
+            provider.shared.symbols().get(symbol).is_some_and(|state| state.book.is_some())
+        };
+        wait_for("BTC book never synced", || synced("BTC")).await;
+        assert!(!provider.shared.symbols().contains_key("ETH"));
+
+        provider.set_symbols(&["BTC".to_string(), "ETH".to_string()]);
+        wait_for("ETH was never subscribed", || synced("ETH")).await;
+        assert!(provider.shared.symbols().contains_key("BTC"));
+
+        let mut trades = provider.subscribe_trades().unwrap();
+        provider.set_symbols(&["ETH".to_string()]);
+        assert!(!provider.shared.symbols().contains_key("BTC"));
+        // Only BTC trades are recorded, so once the unsubscribe lands nothing more arrives.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        while trades.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(trades.try_recv().is_err(), "BTC trades still streamed after unsubscribing");
+        assert!(synced("ETH"));
+    }
+}
@@ -0,0 +1,109 @@
+use super::{parse_decimal, parse_level, OrderBook, PriceProvider, Trade};
+use crate::errors::UpstreamError;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const SERVICE: &str = "fixture";
+const BUNDLED: &str = include_str!("../../fixtures/price_feed.json");
+
+#[derive(Debug, Deserialize)]
+struct FixtureDepth {
+    bids: Vec<Vec<String>>,
+    asks: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FixtureTrade {
+    price: String,
+    qty: String,
+    time: u64,
+    side: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FixtureData {
+    #[serde(default)]
+    tickers: HashMap<String, String>,
+    #[serde(default)]
+    depth: HashMap<String, FixtureDepth>,
+    #[serde(default)]
+    trades: HashMap<String, Vec<FixtureTrade>>,
+}
+
+/// Serves canned market data from a JSON file so the API can run deterministically offline.
+pub struct FixtureProvider {
+    data: FixtureData,
+}
+
+impl FixtureProvider {
+    /// The fixtures checked in at `fixtures/price_feed.json`.
+    pub fn bundled() -> Self {
+        let data = serde_json::from_str(BUNDLED).expect("bundled price fixtures are valid");
+        Self { data }
+    }
+
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        let raw = fs::read_to_string(Path::new(path))?;
+        let data = serde_json::from_str(&raw)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        Ok(Self { data })
+    }
+}
+
+fn unknown_symbol(symbol: &str) -> UpstreamError {
+    UpstreamError::invalid(SERVICE, format!("no fixture for {}", symbol))
+}
+
+#[async_trait]
+impl PriceProvider for FixtureProvider {
+    fn name(&self) -> &'static str {
+        SERVICE
+    }
+
+    async fn tickers(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        let mut prices = HashMap::new();
+        for symbol in symbols {
+            if let Some(price) = self.data.tickers.get(symbol) {
+                prices.insert(symbol.clone(), parse_decimal(SERVICE, price)?);
+            }
+        }
+        Ok(prices)
+    }
+
+    async fn depth(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError> {
+        let depth = self.data.depth.get(symbol).ok_or_else(|| unknown_symbol(symbol))?;
+        let bids = depth
+            .bids
+            .iter()
+            .take(limit)
+            .map(|level| parse_level(SERVICE, level))
+            .collect::<Result<Vec<_>, _>>()?;
+        let asks = depth
+            .asks
+            .iter()
+            .take(limit)
+            .map(|level| parse_level(SERVICE, level))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(OrderBook { bids, asks })
+    }
+
+    async fn trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError> {
+        let trades = self.data.trades.get(symbol).ok_or_else(|| unknown_symbol(symbol))?;
+        let skip = trades.len().saturating_sub(limit);
+        trades[skip..]
+            .iter()
+            .map(|trade| {
+                Ok(Trade {
+                    price: parse_decimal(SERVICE, &trade.price)?,
+                    qty: parse_decimal(SERVICE, &trade.qty)?,
+                    time: trade.time,
+                    side: trade.side.clone(),
+                })
+            })
+            .collect()
+    }
+}
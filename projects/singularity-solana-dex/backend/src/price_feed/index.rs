@@ -0,0 +1,364 @@
+use crate::errors::UpstreamError;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+const BPS: i64 = 10_000;
+/// Outliers can only be told apart from the rest once there is a majority to compare against.
+pub const MIN_SOURCES_FOR_OUTLIERS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexMethod {
+    Median,
+    WeightedMean,
+}
+
+pub struct IndexConfig {
+    pub method: IndexMethod,
+    /// Sources further than this from the median are left out of the index.
+    pub max_deviation_bps: u32,
+    /// How long a provider's last quote keeps counting after its requests start failing.
+    pub max_staleness: Duration,
+    pub min_sources: usize,
+    /// Per-provider weight for `WeightedMean`; unlisted providers weigh 1.
+    pub weights: HashMap<String, Decimal>,
+}
+
+impl IndexConfig {
+    /// Reads `INDEX_METHOD` (`median` or `weighted_mean`), `INDEX_MAX_DEVIATION_BPS`,
+    /// `INDEX_MAX_STALENESS_SECS`, `INDEX_MIN_SOURCES` and `INDEX_WEIGHTS` (`binance=2,okx=1`).
+    pub fn from_env() -> Result<Self, std::io::Error> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+
+        let method = match std::env::var("INDEX_METHOD")
+            .unwrap_or_else(|_| "median".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "median" => IndexMethod::Median,
+            "weighted_mean" => IndexMethod::WeightedMean,
+            other => return Err(invalid(format!("unknown index method {:?}", other))),
+        };
+        let max_deviation_bps = std::env::var("INDEX_MAX_DEVIATION_BPS")
+            .ok()
+            .and_then(|val| val.parse::<u32>().ok())
+            .unwrap_or(200);
+        let max_staleness_secs = std::env::var("INDEX_MAX_STALENESS_SECS")
+            .ok()
+            .and_then(|val| val.parse::<u64>().ok())
+            .unwrap_or(30);
+        let min_sources = std::env::var("INDEX_MIN_SOURCES")
+            .ok()
+            .and_then(|val| val.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let mut weights = HashMap::new();
+        if let Ok(raw) = std::env::var("INDEX_WEIGHTS") {
+            for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                let (provider, weight) = entry
+                    .split_once('=')
+                    .and_then(|(provider, weight)| Some((provider.trim(), Decimal::from_str(weight.trim()).ok()?)))
+                    .filter(|(_, weight)| *weight >= Decimal::ZERO)
+                    .ok_or_else(|| invalid(format!("invalid index weight {:?}", entry)))?;
+                weights.insert(provider.to_lowercase(), weight);
+            }
+        }
+
+        Ok(Self {
+            method,
+            max_deviation_bps,
+            max_staleness: Duration::from_secs(max_staleness_secs),
+            min_sources,
+            weights,
+        })
+    }
+
+    pub fn weight(&self, provider: &str) -> Decimal {
+        self.weights.get(provider).copied().unwrap_or(Decimal::ONE)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    /// Quoted in this lookup and used.
+    Fresh,
+    /// The provider failed, its last quote is within the staleness limit and was used.
+    Stale,
+    /// The last quote is older than the staleness limit.
+    Expired,
+    /// Too far from the median of the other sources.
+    Outlier,
+    /// The provider does not list the symbol.
+    Missing,
+    /// The provider failed and has no usable quote.
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SourceContribution {
+    pub provider: String,
+    pub status: SourceStatus,
+    pub price: Option<Decimal>,
+    pub weight: Decimal,
+    /// Milliseconds since the quote was fetched.
+    pub age_ms: Option<u64>,
+    /// Signed distance from the index price in basis points.
+    pub deviation_bps: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct IndexPrice {
+    pub symbol: String,
+    pub price: Decimal,
+    pub method: IndexMethod,
+    /// Unix milliseconds when the index was computed.
+    pub timestamp: u64,
+    pub sources: Vec<SourceContribution>,
+}
+
+impl IndexPrice {
+    /// Unix milliseconds of the oldest quote the index was computed from, which is how old
+    /// the index is once a provider has fallen back to its last quote.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn quoted_at(&self) -> u64 {
+        let oldest = self
+            .sources
+            .iter()
+            .filter(|source| matches!(source.status, SourceStatus::Fresh | SourceStatus::Stale))
+            .filter_map(|source| source.age_ms)
+            .max()
+            .unwrap_or_default();
+        self.timestamp.saturating_sub(oldest)
+    }
+}
+
+/// Combines per-provider quotes for one symbol. Sources marked `Fresh` or `Stale` are
+/// candidates; the rest are only reported.
+pub fn aggregate(
+    config: &IndexConfig,
+    symbol: &str,
+    mut sources: Vec<SourceContribution>,
+    timestamp: u64,
+) -> Result<IndexPrice, UpstreamError> {
+    let usable = |source: &SourceContribution| matches!(source.status, SourceStatus::Fresh | SourceStatus::Stale);
+
+    let candidates: Vec<Decimal> = sources.iter().filter(|s| usable(s)).filter_map(|s| s.price).collect();
+    if candidates.len() >= MIN_SOURCES_FOR_OUTLIERS {
+        let reference = median(candidates);
+        let max_deviation = Decimal::from(config.max_deviation_bps);
+        for source in sources.iter_mut().filter(|s| usable(s)) {
+            if let Some(price) = source.price {
+                if deviation_bps(price, reference).abs() > max_deviation {
+                    source.status = SourceStatus::Outlier;
+                }
+            }
+        }
+    }
+
+    let included: Vec<(Decimal, Decimal)> = sources
+        .iter()
+        .filter(|s| usable(s))
+        .filter_map(|s| s.price.map(|price| (price, s.weight)))
+        .collect();
+    if included.len() < config.min_sources {
+        return Err(UpstreamError::Unavailable {
+            service: "index".to_string(),
+            reason: format!(
+                "{} has {} usable sources, {} required",
+                symbol,
+                included.len(),
+                config.min_sources
+            ),
+        });
+    }
+
+    let price = match config.method {
+        IndexMethod::Median => median(included.iter().map(|(price, _)| *price).collect()),
+        IndexMethod::WeightedMean => {
+            let total_weight: Decimal = included.iter().map(|(_, weight)| *weight).sum();
+            if total_weight.is_zero() {
+                median(included.iter().map(|(price, _)| *price).collect())
+            } else {
+                included.iter().map(|(price, weight)| *price * *weight).sum::<Decimal>() / total_weight
+            }
+        }
+    };
+
+    for source in &mut sources {
+        source.deviation_bps = source.price.map(|quote| deviation_bps(quote, price).round_dp(2));
+    }
+
+    Ok(IndexPrice {
+        symbol: symbol.to_string(),
+        price: price.normalize(),
+        method: config.method,
+        timestamp,
+        sources,
+    })
+}
+
+fn median(mut values: Vec<Decimal>) -> Decimal {
+    values.sort();
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / Decimal::TWO
+    } else {
+        values[mid]
+    }
+}
+
+fn deviation_bps(price: Decimal, reference: Decimal) -> Decimal {
+    if reference.is_zero() {
+        return Decimal::ZERO;
+    }
+    (price - reference) / reference * Decimal::from(BPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(provider: &str, status: SourceStatus, price: &str, age_ms: u64) -> SourceContribution {
+        SourceContribution {
+            provider: provider.to_string(),
+            status,
+            price: Some(Decimal::from_str(price).unwrap()),
+            weight: Decimal::ONE,
+            age_ms: Some(age_ms),
+            deviation_bps: None,
+            error: None,
+        }
+    }
+
+    fn config() -> IndexConfig {
+        IndexConfig {
+            method: IndexMethod::Median,
+            max_deviation_bps: 200,
+            max_staleness: Duration::from_secs(30),
+            min_sources: 1,
+            weights: HashMap::new(),
+        }
+    }
+
+    fn statuses(index: &IndexPrice) -> Vec<(&str, SourceStatus)> {
+        index
+            .sources
+            .iter()
+            .map(|source| (source.provider.as_str(), source.status))
+            .collect()
+    }
+
+    #[test]
+    fn median_of_odd_and_even_source_counts() {
+        let odd = vec![
+            source("a", SourceStatus::Fresh, "100", 0),
+            source("b", SourceStatus::Fresh, "101", 0),
+            source("c", SourceStatus::Fresh, "100.5", 0),
+        ];
+        assert_eq!(aggregate(&config(), "BTC", odd, 0).unwrap().price, Decimal::from_str("100.5").unwrap());
+
+        let even = vec![
+            source("a", SourceStatus::Fresh, "100", 0),
+            source("b", SourceStatus::Fresh, "101", 0),
+        ];
+        assert_eq!(aggregate(&config(), "BTC", even, 0).unwrap().price, Decimal::from_str("100.5").unwrap());
+    }
+
+    #[test]
+    fn one_outlier_among_three_venues_is_left_out() {
+        let sources = vec![
+            source("binance", SourceStatus::Fresh, "100", 0),
+            source("okx", SourceStatus::Fresh, "100.4", 0),
+            source("fixture", SourceStatus::Fresh, "110", 0),
+        ];
+        let index = aggregate(&config(), "BTC", sources, 0).unwrap();
+        assert_eq!(index.price, Decimal::from_str("100.2").unwrap());
+        assert_eq!(
+            statuses(&index),
+            [
+                ("binance", SourceStatus::Fresh),
+                ("okx", SourceStatus::Fresh),
+                ("fixture", SourceStatus::Outlier)
+            ]
+        );
+
+        // With two venues there is no majority, so both count.
+        let sources = vec![
+            source("binance", SourceStatus::Fresh, "100", 0),
+            source("fixture", SourceStatus::Fresh, "110", 0),
+        ];
+        let index = aggregate(&config(), "BTC", sources, 0).unwrap();
+        assert_eq!(index.price, Decimal::from(105));
+        assert!(index.sources.iter().all(|source| source.status == SourceStatus::Fresh));
+    }
+
+    #[test]
+    fn weighted_mean_splits_equal_weights_evenly() {
+        let mut config = config();
+        config.method = IndexMethod::WeightedMean;
+        let mut sources = vec![
+            source("binance", SourceStatus::Fresh, "100", 0),
+            source("okx", SourceStatus::Fresh, "101", 0),
+        ];
+        assert_eq!(
+            aggregate(&config, "BTC", sources.clone(), 0).unwrap().price,
+            Decimal::from_str("100.5").unwrap()
+        );
+
+        sources[0].weight = Decimal::from(3);
+        assert_eq!(
+            aggregate(&config, "BTC", sources.clone(), 0).unwrap().price,
+            Decimal::from_str("100.25").unwrap()
+        );
+
+        // All weights zero falls back to the median.
+        for source in &mut sources {
+            source.weight = Decimal::ZERO;
+        }
+        assert_eq!(
+            aggregate(&config, "BTC", sources, 0).unwrap().price,
+            Decimal::from_str("100.5").unwrap()
+        );
+    }
+
+    #[test]
+    fn too_few_usable_sources_is_an_error() {
+        let mut config = config();
+        config.min_sources = 2;
+        let sources = vec![
+            source("binance", SourceStatus::Fresh, "100", 0),
+            source("okx", SourceStatus::Expired, "101", 60_000),
+        ];
+        assert!(aggregate(&config, "BTC", sources, 0).is_err());
+    }
+
+    #[test]
+    fn index_is_as_old_as_its_oldest_used_quote() {
+        let sources = vec![
+            source("binance", SourceStatus::Fresh, "100", 0),
+            source("okx", SourceStatus::Stale, "100.5", 12_000),
+            source("fixture", SourceStatus::Expired, "90", 90_000),
+        ];
+        let index = aggregate(&config(), "BTCUSDT", sources, 1_000_000).unwrap();
+        assert_eq!(index.quoted_at(), 988_000);
+    }
+
+    #[test]
+    fn outliers_do_not_age_the_index() {
+        let sources = vec![
+            source("binance", SourceStatus::Fresh, "100", 0),
+            source("okx", SourceStatus::Fresh, "100.1", 0),
+            source("fixture", SourceStatus::Stale, "150", 20_000),
+        ];
+        let index = aggregate(&config(), "BTCUSDT", sources, 1_000_000).unwrap();
+        assert_eq!(index.quoted_at(), 1_000_000);
+    }
+}
@@ -0,0 +1,76 @@
+use super::{OrderBook, OrderLevel};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+/// An order book kept current from a REST snapshot plus streamed diffs, where each diff
+/// covers the update ids `first_id..=final_id` and sets absolute sizes per price level.
+pub struct LocalBook {
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    last_update_id: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApplyOutcome {
+    Applied,
+    /// Entirely covered by the snapshot or an earlier diff; ignored.
+    Outdated,
+    /// Updates between the book and this diff were missed; the book must be rebuilt.
+    Gap { expected: u64, received: u64 },
+}
+
+impl LocalBook {
+    pub fn from_snapshot(last_update_id: u64, bids: Vec<OrderLevel>, asks: Vec<OrderLevel>) -> Self {
+        let mut book = Self {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            last_update_id,
+        };
+        set_levels(&mut book.bids, &bids);
+        set_levels(&mut book.asks, &asks);
+        book
+    }
+
+    pub fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+
+    pub fn apply(&mut self, first_id: u64, final_id: u64, bids: &[OrderLevel], asks: &[OrderLevel]) -> ApplyOutcome {
+        if final_id <= self.last_update_id {
+            return ApplyOutcome::Outdated;
+        }
+        let expected = self.last_update_id + 1;
+        if first_id > expected {
+            return ApplyOutcome::Gap {
+                expected,
+                received: first_id,
+            };
+        }
+        set_levels(&mut self.bids, bids);
+        set_levels(&mut self.asks, asks);
+        self.last_update_id = final_id;
+        ApplyOutcome::Applied
+    }
+
+    /// Best `limit` levels per side, bids descending and asks ascending.
+    pub fn top(&self, limit: usize) -> OrderBook {
+        let level = |(price, size): (&Decimal, &Decimal)| OrderLevel {
+            price: *price,
+            size: *size,
+        };
+        OrderBook {
+            bids: self.bids.iter().rev().take(limit).map(level).collect(),
+            asks: self.asks.iter().take(limit).map(level).collect(),
+        }
+    }
+}
+
+fn set_levels(side: &mut BTreeMap<Decimal, Decimal>, levels: &[OrderLevel]) {
+    for level in levels {
+        if level.size.is_zero() {
+            side.remove(&level.price);
+        } else {
+            side.insert(level.price, level.size);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+mod binance;
+mod binance_ws;
+mod fixture;
+mod index;
+mod local_book;
+mod okx;
+pub mod replay;
+
+use crate::cache::{CacheStats, TtlCache};
+use crate::errors::UpstreamError;
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::warn;
+use utoipa::ToSchema;
+
+pub use binance::BinanceProvider;
+pub use binance_ws::{BinanceStreamProvider, StreamConfig};
+pub use fixture::FixtureProvider;
+pub use index::{IndexConfig, IndexMethod, IndexPrice, SourceContribution, SourceStatus, MIN_SOURCES_FOR_OUTLIERS};
+pub use okx::OkxProvider;
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct OrderLevel {
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct OrderBook {
+    pub bids: Vec<OrderLevel>,
+    pub asks: Vec<OrderLevel>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Trade {
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub time: u64,
+    pub side: String,
+}
+
+/// A trade as a streaming provider received it.
+#[derive(Clone, Debug)]
+pub struct SymbolTrade {
+    pub symbol: String,
+    pub trade: Trade,
+}
+
+/// A source of spot market data quoted in USDT. Symbols are our base symbols (`BTC`), each
+/// provider maps them to its own instrument names.
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Last traded price per symbol; symbols the venue does not list are left out.
+    async fn tickers(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError>;
+
+    async fn depth(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError>;
+
+    /// Most recent trades, oldest first.
+    async fn trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError>;
+
+    /// Every trade as it arrives, for providers that stream them; polled providers have none.
+    fn subscribe_trades(&self) -> Option<broadcast::Receiver<SymbolTrade>> {
+        None
+    }
+
+    /// Replaces the symbols a streaming provider follows; polled providers look up whatever
+    /// they are asked for and ignore it.
+    fn set_symbols(&self, _symbols: &[String]) {}
+}
+
+/// How long responses are reused before going upstream again.
+pub struct CacheConfig {
+    pub prices_ttl: Duration,
+    pub orderbook_ttl: Duration,
+    pub trades_ttl: Duration,
+    /// How long an expired response may still be served while upstream is failing.
+    pub max_stale: Duration,
+}
+
+impl CacheConfig {
+    /// Reads `PRICES_CACHE_TTL_MS` (default 1000), `ORDERBOOK_CACHE_TTL_MS` (500),
+    /// `TRADES_CACHE_TTL_MS` (1000) and `MARKET_DATA_MAX_STALE_SECS` (60).
+    pub fn from_env() -> Self {
+        let millis = |name: &str, default: u64| {
+            Duration::from_millis(
+                std::env::var(name)
+                    .ok()
+                    .and_then(|val| val.parse::<u64>().ok())
+                    .unwrap_or(default),
+            )
+        };
+        Self {
+            prices_ttl: millis("PRICES_CACHE_TTL_MS", 1_000),
+            orderbook_ttl: millis("ORDERBOOK_CACHE_TTL_MS", 500),
+            trades_ttl: millis("TRADES_CACHE_TTL_MS", 1_000),
+            max_stale: Duration::from_secs(
+                std::env::var("MARKET_DATA_MAX_STALE_SECS")
+                    .ok()
+                    .and_then(|val| val.parse::<u64>().ok())
+                    .unwrap_or(60),
+            ),
+        }
+    }
+}
+
+/// The configured providers in priority order. Prices are an index across every provider;
+/// depth and trades are answered by the first provider that succeeds. Results are cached
+/// per request, see [`TtlCache`].
+pub struct PriceFeed {
+    providers: Vec<Arc<dyn PriceProvider>>,
+    index: IndexConfig,
+    prices_cache: TtlCache<HashMap<String, Decimal>>,
+    orderbook_cache: TtlCache<OrderBook>,
+    trades_cache: TtlCache<Vec<Trade>>,
+    /// Last good quote per (provider, symbol), used while a provider is failing.
+    last_quotes: Mutex<HashMap<QuoteKey, (Decimal, Instant)>>,
+}
+
+/// Provider name and symbol.
+type QuoteKey = (&'static str, String);
+
+impl PriceFeed {
+    pub fn new(providers: Vec<Arc<dyn PriceProvider>>, index: IndexConfig, cache: CacheConfig) -> Self {
+        Self {
+            providers,
+            index,
+            prices_cache: TtlCache::new(cache.prices_ttl, cache.max_stale),
+            orderbook_cache: TtlCache::new(cache.orderbook_ttl, cache.max_stale),
+            trades_cache: TtlCache::new(cache.trades_ttl, cache.max_stale),
+            last_quotes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Builds the feed from `PRICE_PROVIDERS`, a comma separated list of `binance`, `binance_ws`,
+    /// `okx` and `fixture` (default `binance`). `binance_ws` streams `symbols` into local books;
+    /// the fixture provider reads `PRICE_FIXTURE_FILE` when set.
+    /// See [`IndexConfig::from_env`] for how the index combines them; outliers are only rejected
+    /// with at least [`MIN_SOURCES_FOR_OUTLIERS`] providers, which the default doesn't have.
+    pub fn from_env(symbols: &[String]) -> Result<Self, std::io::Error> {
+        let names = std::env::var("PRICE_PROVIDERS").unwrap_or_else(|_| "binance".to_string());
+        let mut providers: Vec<Arc<dyn PriceProvider>> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let provider: Arc<dyn PriceProvider> = match name.to_lowercase().as_str() {
+                "binance" => Arc::new(BinanceProvider::from_env()),
+                "binance_ws" => Arc::new(BinanceStreamProvider::spawn(StreamConfig::from_env(), symbols)),
+                "okx" => Arc::new(OkxProvider::new()),
+                "fixture" => match std::env::var("PRICE_FIXTURE_FILE") {
+                    Ok(path) => Arc::new(FixtureProvider::load(&path)?),
+                    Err(_) => Arc::new(FixtureProvider::bundled()),
+                },
+                other => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("unknown price provider {:?}", other),
+                    ))
+                }
+            };
+            providers.push(provider);
+        }
+        if providers.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "PRICE_PROVIDERS lists no providers",
+            ));
+        }
+        if providers.len() < MIN_SOURCES_FOR_OUTLIERS {
+            warn!(
+                providers = providers.len(),
+                required = MIN_SOURCES_FOR_OUTLIERS,
+                "too few price providers to reject outliers; the index follows any single bad quote"
+            );
+        }
+        Ok(Self::new(providers, IndexConfig::from_env()?, CacheConfig::from_env()))
+    }
+
+    /// Points streaming providers at the markets in effect, after a registry change.
+    pub fn set_symbols(&self, symbols: &[String]) {
+        for provider in &self.providers {
+            provider.set_symbols(symbols);
+        }
+    }
+
+    pub fn cache_stats(&self) -> BTreeMap<&'static str, CacheStats> {
+        BTreeMap::from([
+            ("prices", self.prices_cache.stats()),
+            ("orderbook", self.orderbook_cache.stats()),
+            ("trades", self.trades_cache.stats()),
+        ])
+    }
+
+    /// Index prices for the symbols that have enough usable sources. Unknown symbols are
+    /// skipped, but if nothing could be priced at all the error says why.
+    pub async fn fetch_prices(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        self.prices_cache
+            .get_or_fetch(&prices_key(symbols), || self.load_prices(symbols))
+            .await
+    }
+
+    /// Like [`Self::fetch_prices`], but fails instead of falling back to prices older than the
+    /// cache TTL. Risk checks and marks use this; a stale price there would misjudge margin.
+    pub async fn fetch_fresh_prices(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        self.prices_cache
+            .get_fresh_or_fetch(&prices_key(symbols), || self.load_prices(symbols))
+            .await
+    }
+
+    async fn load_prices(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        let mut prices = HashMap::new();
+        let mut last_error = None;
+        for (symbol, index) in self.index_prices(symbols).await {
+            match index {
+                Ok(index) => {
+                    prices.insert(symbol, index.price);
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            Some(err) if prices.is_empty() => Err(err),
+            _ => Ok(prices),
+        }
+    }
+
+    pub async fn index_price(&self, symbol: &str) -> Result<IndexPrice, UpstreamError> {
+        let symbol = symbol.to_uppercase();
+        self.index_prices(std::slice::from_ref(&symbol))
+            .await
+            .remove(&symbol)
+            .unwrap_or_else(|| Err(no_providers()))
+    }
+
+    /// Queries every provider concurrently and aggregates the quotes per symbol.
+    pub async fn index_prices(&self, symbols: &[String]) -> HashMap<String, Result<IndexPrice, UpstreamError>> {
+        let results = join_all(self.providers.iter().map(|provider| provider.tickers(symbols))).await;
+        let now = Instant::now();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+
+        let mut last_quotes = self.last_quotes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (provider, result) in self.providers.iter().zip(&results) {
+            match result {
+                Ok(quotes) => {
+                    for (symbol, price) in quotes {
+                        last_quotes.insert((provider.name(), symbol.clone()), (*price, now));
+                    }
+                }
+                Err(err) => warn!(provider = provider.name(), error = %err, "price provider failed"),
+            }
+        }
+
+        let mut indexes = HashMap::new();
+        for symbol in symbols {
+            let mut provider_error = None;
+            let sources = self
+                .providers
+                .iter()
+                .zip(&results)
+                .map(|(provider, result)| {
+                    let mut source = SourceContribution {
+                        provider: provider.name().to_string(),
+                        status: SourceStatus::Missing,
+                        price: None,
+                        weight: self.index.weight(provider.name()),
+                        age_ms: None,
+                        deviation_bps: None,
+                        error: None,
+                    };
+                    if let Ok(quotes) = result {
+                        if let Some(price) = quotes.get(symbol) {
+                            source.status = SourceStatus::Fresh;
+                            source.price = Some(*price);
+                            source.age_ms = Some(0);
+                            return source;
+                        }
+                    }
+                    if let Err(err) = result {
+                        source.status = SourceStatus::Error;
+                        source.error = Some(err.to_string());
+                        provider_error = Some(err.clone());
+                    }
+                    if let Some((price, fetched_at)) = last_quotes.get(&(provider.name(), symbol.clone())) {
+                        let age = now.duration_since(*fetched_at);
+                        source.status = if age <= self.index.max_staleness {
+                            SourceStatus::Stale
+                        } else {
+                            SourceStatus::Expired
+                        };
+                        source.price = Some(*price);
+                        source.age_ms = Some(age.as_millis() as u64);
+                    }
+                    source
+                })
+                .collect();
+
+            let index = index::aggregate(&self.index, symbol, sources, timestamp).map_err(|err| {
+                // When the providers themselves failed, their error is more useful than the count.
+                provider_error.unwrap_or(err)
+            });
+            indexes.insert(symbol.clone(), index);
+        }
+        indexes
+    }
+
+    pub async fn fetch_orderbook(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError> {
+        let symbol = symbol.to_uppercase();
+        self.orderbook_cache
+            .get_or_fetch(&format!("{}:{}", symbol, limit), || self.load_orderbook(&symbol, limit))
+            .await
+    }
+
+    async fn load_orderbook(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError> {
+        let mut last_error = None;
+        for provider in &self.providers {
+            match provider.depth(symbol, limit).await {
+                Ok(book) => return Ok(book),
+                Err(err) => {
+                    warn!(provider = provider.name(), error = %err, "price provider failed");
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(no_providers))
+    }
+
+    /// The trade stream of the first provider, the one `/trades` asks first, if it streams.
+    pub fn subscribe_trades(&self) -> Option<broadcast::Receiver<SymbolTrade>> {
+        self.providers.first().and_then(|provider| provider.subscribe_trades())
+    }
+
+    pub async fn fetch_trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError> {
+        let symbol = symbol.to_uppercase();
+        self.trades_cache
+            .get_or_fetch(&format!("{}:{}", symbol, limit), || self.load_trades(&symbol, limit))
+            .await
+    }
+
+    async fn load_trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError> {
+        let mut last_error = None;
+        for provider in &self.providers {
+            match provider.trades(symbol, limit).await {
+                Ok(trades) => return Ok(trades),
+                Err(err) => {
+                    warn!(provider = provider.name(), error = %err, "price provider failed");
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(no_providers))
+    }
+}
+
+fn no_providers() -> UpstreamError {
+    UpstreamError::Unavailable {
+        service: "price-feed".to_string(),
+        reason: "no price providers configured".to_string(),
+    }
+}
+
+async fn fetch_json<T: for<'de> Deserialize<'de>>(service: &str, url: &str) -> Result<T, UpstreamError> {
+    let response = reqwest::get(url)
+        .await
+        .map_err(|err| UpstreamError::from_reqwest(service, err))?;
+    if !response.status().is_success() {
+        return Err(UpstreamError::Status {
+            service: service.to_string(),
+            status: response.status().as_u16(),
+        });
+    }
+    response
+        .json::<T>()
+        .await
+        .map_err(|err| UpstreamError::from_reqwest(service, err))
+}
+
+fn parse_decimal(service: &str, value: &str) -> Result<Decimal, UpstreamError> {
+    Decimal::from_str(value).map_err(|_| UpstreamError::invalid(service, format!("invalid decimal {:?}", value)))
+}
+
+fn parse_level(service: &str, level: &[String]) -> Result<OrderLevel, UpstreamError> {
+    if level.len() < 2 {
+        return Err(UpstreamError::invalid(service, "truncated depth level"));
+    }
+    Ok(OrderLevel {
+        price: parse_decimal(service, &level[0])?,
+        size: parse_decimal(service, &level[1])?,
+    })
+}
+
+/// One cache entry per set of symbols, whatever order they were asked in.
+fn prices_key(symbols: &[String]) -> String {
+    let mut key: Vec<&str> = symbols.iter().map(String::as_str).collect();
+    key.sort_unstable();
+    key.dedup();
+    key.join(",")
+}
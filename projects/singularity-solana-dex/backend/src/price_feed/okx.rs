@@ -0,0 +1,136 @@
+use super::{fetch_json, parse_decimal, parse_level, OrderBook, PriceProvider, Trade};
+use crate::errors::UpstreamError;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const OKX_BASE: &str = "https://www.okx.com";
+const SERVICE: &str = "okx";
+
+/// OKX wraps every payload as `{"code": "0", "msg": "", "data": [...]}`.
+#[derive(Debug, Deserialize)]
+struct OkxResponse<T> {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxTicker {
+    inst_id: String,
+    last: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OkxBook {
+    bids: Vec<Vec<String>>,
+    asks: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OkxTrade {
+    px: String,
+    sz: String,
+    side: String,
+    ts: String,
+}
+
+pub struct OkxProvider {
+    base: String,
+}
+
+impl OkxProvider {
+    pub fn new() -> Self {
+        Self {
+            base: OKX_BASE.to_string(),
+        }
+    }
+
+    async fn fetch<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<Vec<T>, UpstreamError> {
+        let response = fetch_json::<OkxResponse<T>>(SERVICE, &format!("{}{}", self.base, path)).await?;
+        if response.code != "0" {
+            return Err(UpstreamError::invalid(
+                SERVICE,
+                format!("error code {}: {}", response.code, response.msg),
+            ));
+        }
+        Ok(response.data)
+    }
+}
+
+fn inst_id(symbol: &str) -> String {
+    format!("{}-USDT", symbol.to_uppercase())
+}
+
+#[async_trait]
+impl PriceProvider for OkxProvider {
+    fn name(&self) -> &'static str {
+        SERVICE
+    }
+
+    async fn tickers(&self, symbols: &[String]) -> Result<HashMap<String, Decimal>, UpstreamError> {
+        let mut prices = HashMap::new();
+        if symbols.is_empty() {
+            return Ok(prices);
+        }
+
+        // One request returns every spot ticker, which is cheaper than a request per symbol.
+        let wanted: HashMap<String, &String> = symbols.iter().map(|symbol| (inst_id(symbol), symbol)).collect();
+        for ticker in self.fetch::<OkxTicker>("/api/v5/market/tickers?instType=SPOT").await? {
+            if let Some(symbol) = wanted.get(&ticker.inst_id) {
+                prices.insert((*symbol).clone(), parse_decimal(SERVICE, &ticker.last)?);
+            }
+        }
+        Ok(prices)
+    }
+
+    async fn depth(&self, symbol: &str, limit: usize) -> Result<OrderBook, UpstreamError> {
+        let path = format!("/api/v5/market/books?instId={}&sz={}", inst_id(symbol), limit);
+        let book = self
+            .fetch::<OkxBook>(&path)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| UpstreamError::invalid(SERVICE, "empty order book response"))?;
+
+        let bids = book
+            .bids
+            .iter()
+            .map(|level| parse_level(SERVICE, level))
+            .collect::<Result<Vec<_>, _>>()?;
+        let asks = book
+            .asks
+            .iter()
+            .map(|level| parse_level(SERVICE, level))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(OrderBook { bids, asks })
+    }
+
+    async fn trades(&self, symbol: &str, limit: usize) -> Result<Vec<Trade>, UpstreamError> {
+        let path = format!("/api/v5/market/trades?instId={}&limit={}", inst_id(symbol), limit);
+        let mut trades = self
+            .fetch::<OkxTrade>(&path)
+            .await?
+            .into_iter()
+            .map(|trade| {
+                let time = trade
+                    .ts
+                    .parse::<u64>()
+                    .map_err(|_| UpstreamError::invalid(SERVICE, format!("invalid timestamp {:?}", trade.ts)))?;
+                Ok(Trade {
+                    price: parse_decimal(SERVICE, &trade.px)?,
+                    qty: parse_decimal(SERVICE, &trade.sz)?,
+                    time,
+                    side: trade.side,
+                })
+            })
+            .collect::<Result<Vec<_>, UpstreamError>>()?;
+        // OKX returns newest first.
+        trades.reverse();
+        Ok(trades)
+    }
+}
@@ -0,0 +1,263 @@
+//! Stand-in for the Binance market stream that replays recorded frames, so the `binance_ws`
+//! provider can run against a local server.
+//!
+//! The recording is JSON lines: depth snapshots (`{"symbol", "lastUpdateId", "bids", "asks"}`)
+//! seed the server's books, and combined-stream frames (`{"stream", "data"}`) are replayed in
+//! order. A frame marked `"dropped": true` advances the server's book without being sent,
+//! which makes clients see a sequence gap and resync from `/api/v3/depth`. Each client only
+//! gets the streams it named in `?streams=` or added with a `SUBSCRIBE` request since.
+
+use super::local_book::LocalBook;
+use super::{parse_level, OrderLevel};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Notify};
+use tracing::info;
+
+const SERVICE: &str = "replay";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Snapshot {
+        symbol: String,
+        #[serde(rename = "lastUpdateId")]
+        last_update_id: u64,
+        bids: Vec<Vec<String>>,
+        asks: Vec<Vec<String>>,
+    },
+    Frame {
+        stream: String,
+        data: Value,
+        #[serde(default)]
+        dropped: bool,
+    },
+}
+
+struct RecordedFrame {
+    stream: String,
+    data: Value,
+    dropped: bool,
+}
+
+struct Replay {
+    books: Mutex<HashMap<String, LocalBook>>,
+    /// Stream name and the frame to send for it.
+    frames: broadcast::Sender<(String, String)>,
+    client_connected: Notify,
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    #[serde(default)]
+    streams: String,
+}
+
+/// A client's `SUBSCRIBE` or `UNSUBSCRIBE` request.
+#[derive(Deserialize)]
+struct Subscription {
+    method: String,
+    #[serde(default)]
+    params: Vec<String>,
+    id: Value,
+}
+
+#[derive(Deserialize)]
+struct DepthQuery {
+    symbol: String,
+    limit: Option<usize>,
+}
+
+/// Serves `/stream` (WebSocket) and `/api/v3/depth` on `bind`, sending one recorded frame per
+/// `interval` once the first client connects and looping over the recording indefinitely.
+pub async fn serve(path: &str, bind: SocketAddr, interval: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    serve_on(listener, path, interval).await
+}
+
+/// [`serve`] on a listener that is already bound.
+pub async fn serve_on(
+    listener: tokio::net::TcpListener,
+    path: &str,
+    interval: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let raw = std::fs::read_to_string(path)?;
+    let mut books = HashMap::new();
+    let mut frames = Vec::new();
+    for (number, line) in raw.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match serde_json::from_str::<Line>(line).map_err(|err| format!("{}:{}: {}", path, number + 1, err))? {
+            Line::Snapshot {
+                symbol,
+                last_update_id,
+                bids,
+                asks,
+            } => {
+                books.insert(
+                    symbol,
+                    LocalBook::from_snapshot(last_update_id, parse_levels(&bids)?, parse_levels(&asks)?),
+                );
+            }
+            Line::Frame { stream, data, dropped } => frames.push(RecordedFrame { stream, data, dropped }),
+        }
+    }
+    if frames.is_empty() {
+        return Err(format!("{} contains no frames", path).into());
+    }
+
+    let (sender, _) = broadcast::channel(1024);
+    let replay = Arc::new(Replay {
+        books: Mutex::new(books),
+        frames: sender,
+        client_connected: Notify::new(),
+    });
+    tokio::spawn(replay_frames(replay.clone(), frames, interval));
+
+    let app = Router::new()
+        .route("/stream", get(stream))
+        .route("/api/v3/depth", get(depth))
+        .with_state(replay);
+    info!(bind = %listener.local_addr()?, "replaying {}", path);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn replay_frames(replay: Arc<Replay>, frames: Vec<RecordedFrame>, interval: Duration) {
+    replay.client_connected.notified().await;
+
+    // Each pass shifts update ids past the previous one so the sequence stays contiguous.
+    let mut spans: HashMap<String, (u64, u64)> = HashMap::new();
+    for frame in &frames {
+        if let (Some(pair), Some(first), Some(last)) = depth_ids(&frame.data) {
+            let span = spans.entry(pair).or_insert((first, last));
+            span.0 = span.0.min(first);
+            span.1 = span.1.max(last);
+        }
+    }
+    let mut offsets: HashMap<String, u64> = HashMap::new();
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        for frame in &frames {
+            ticker.tick().await;
+            let mut data = frame.data.clone();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default();
+            data["E"] = json!(now);
+            if data.get("T").is_some() {
+                data["T"] = json!(now);
+            }
+
+            if let (Some(pair), Some(first), Some(last)) = depth_ids(&data) {
+                let offset = offsets.get(&pair).copied().unwrap_or(0);
+                data["U"] = json!(first + offset);
+                data["u"] = json!(last + offset);
+                let bids = levels_of(&data["b"]);
+                let asks = levels_of(&data["a"]);
+                if let Some(book) = replay.books.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get_mut(&pair) {
+                    book.apply(first + offset, last + offset, &bids, &asks);
+                }
+            }
+
+            if !frame.dropped {
+                let message = json!({ "stream": frame.stream, "data": data }).to_string();
+                // Nobody listening is fine; frames keep advancing the books.
+                let _ = replay.frames.send((frame.stream.clone(), message));
+            }
+        }
+        for (pair, (first, last)) in &spans {
+            *offsets.entry(pair.clone()).or_insert(0) += last - first + 1;
+        }
+    }
+}
+
+async fn stream(
+    State(replay): State<Arc<Replay>>,
+    Query(query): Query<StreamQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let streams = query.streams.split('/').filter(|name| !name.is_empty()).map(str::to_string).collect();
+    upgrade.on_upgrade(move |socket| forward(replay, socket, streams))
+}
+
+async fn forward(replay: Arc<Replay>, mut socket: WebSocket, mut streams: HashSet<String>) {
+    let mut frames = replay.frames.subscribe();
+    replay.client_connected.notify_one();
+    loop {
+        tokio::select! {
+            frame = frames.recv() => {
+                let Ok((stream, frame)) = frame else { break };
+                if streams.contains(&stream) && socket.send(Message::Text(frame)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                let Some(Ok(message)) = message else { break };
+                let Message::Text(text) = message else { continue };
+                let Ok(request) = serde_json::from_str::<Subscription>(&text) else { continue };
+                match request.method.as_str() {
+                    "SUBSCRIBE" => streams.extend(request.params),
+                    "UNSUBSCRIBE" => streams.retain(|stream| !request.params.contains(stream)),
+                    _ => continue,
+                }
+                let reply = json!({ "result": null, "id": request.id }).to_string();
+                if socket.send(Message::Text(reply)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn depth(State(replay): State<Arc<Replay>>, Query(query): Query<DepthQuery>) -> Response {
+    let books = replay.books.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let Some(book) = books.get(&query.symbol) else {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "code": -1121, "msg": "Invalid symbol." }))).into_response();
+    };
+    let top = book.top(query.limit.unwrap_or(100));
+    let side = |levels: &[OrderLevel]| -> Vec<[String; 2]> {
+        levels
+            .iter()
+            .map(|level| [level.price.to_string(), level.size.to_string()])
+            .collect()
+    };
+    Json(json!({
+        "lastUpdateId": book.last_update_id(),
+        "bids": side(&top.bids),
+        "asks": side(&top.asks),
+    }))
+    .into_response()
+}
+
+fn depth_ids(data: &Value) -> (Option<String>, Option<u64>, Option<u64>) {
+    if data["e"] != "depthUpdate" {
+        return (None, None, None);
+    }
+    (
+        data["s"].as_str().map(str::to_string),
+        data["U"].as_u64(),
+        data["u"].as_u64(),
+    )
+}
+
+fn levels_of(value: &Value) -> Vec<OrderLevel> {
+    serde_json::from_value::<Vec<Vec<String>>>(value.clone())
+        .ok()
+        .and_then(|levels| parse_levels(&levels).ok())
+        .unwrap_or_default()
+}
+
+fn parse_levels(levels: &[Vec<String>]) -> Result<Vec<OrderLevel>, crate::errors::UpstreamError> {
+    levels.iter().map(|level| parse_level(SERVICE, level)).collect()
+}
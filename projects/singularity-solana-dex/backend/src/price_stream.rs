@@ -0,0 +1,237 @@
+use crate::errors::AppError;
+use crate::events::{Envelope, Event as BusEvent};
+use crate::state::AppState;
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::{interval, Instant, Interval, MissedTickBehavior};
+use utoipa::IntoParams;
+
+const DEFAULT_INTERVAL_MS: u64 = 1_000;
+const MIN_INTERVAL_MS: u64 = 250;
+const MAX_INTERVAL_MS: u64 = 60_000;
+
+#[derive(serde::Deserialize, IntoParams)]
+pub struct PriceStreamQuery {
+    /// Comma separated symbols, defaults to every listed market.
+    symbols: Option<String>,
+    /// Minimum time between two pushes.
+    interval_ms: Option<u64>,
+}
+
+struct PriceStream {
+    state: Arc<AppState>,
+    symbols: Vec<String>,
+    events: Receiver<Arc<Envelope>>,
+    ticker: Interval,
+    period: Duration,
+    pending: BTreeMap<String, Decimal>,
+    last_sent: HashMap<String, Decimal>,
+    last_update: HashMap<String, Instant>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/prices/stream",
+    tag = "streaming",
+    params(PriceStreamQuery),
+    responses((status = 200, description = "`prices` events mapping symbol to price", content_type = "text/event-stream", body = String))
+)]
+/// Pushes price changes for the requested symbols at most once per throttle interval.
+///
+/// Prices published on the event bus by the market events loop are forwarded as they arrive;
+/// symbols that saw no update within the interval, such as unlisted ones, are polled from the
+/// price feed.
+pub async fn stream_prices(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PriceStreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let symbols: Vec<String> = match params.symbols {
+        Some(symbols) => symbols
+            .split(',')
+            .map(|item| item.trim().to_uppercase())
+            .filter(|item| !item.is_empty())
+            .collect(),
+        None => state
+            .risk
+            .markets()
+            .into_iter()
+            .map(|market| market.symbol)
+            .collect(),
+    };
+    if symbols.is_empty() {
+        return Err(AppError::InvalidParameter {
+            name: "symbols",
+            reason: "no symbols requested".to_string(),
+        });
+    }
+
+    let default_ms = std::env::var("PRICE_STREAM_INTERVAL_MS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_INTERVAL_MS);
+    let period = Duration::from_millis(
+        params
+            .interval_ms
+            .unwrap_or(default_ms)
+            .clamp(MIN_INTERVAL_MS, MAX_INTERVAL_MS),
+    );
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let stream = PriceStream {
+        events: state.events.subscribe_market_data(),
+        state,
+        symbols,
+        ticker,
+        period,
+        pending: BTreeMap::new(),
+        last_sent: HashMap::new(),
+        last_update: HashMap::new(),
+    };
+
+    let events = stream::unfold(stream, |mut stream| async move {
+        let event = stream.next_event().await;
+        Some((Ok(event), stream))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+impl PriceStream {
+    async fn next_event(&mut self) -> Event {
+        loop {
+            tokio::select! {
+                received = self.events.recv() => match received {
+                    Ok(envelope) => {
+                        if let BusEvent::Price { symbol, price } = &envelope.data {
+                            self.record(symbol, *price);
+                        }
+                    }
+                    // Missing a few intermediate prices is fine; the next poll catches up.
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => {
+                        self.events = self.state.events.subscribe_market_data();
+                    }
+                },
+                _ = self.ticker.tick() => {
+                    self.poll_stale().await;
+                    if !self.pending.is_empty() {
+                        let batch = std::mem::take(&mut self.pending);
+                        self.last_sent.extend(batch.iter().map(|(symbol, price)| (symbol.clone(), *price)));
+                        return Event::default()
+                            .event("prices")
+                            .json_data(&batch)
+                            .unwrap_or_else(|_| Event::default().comment("serialization error"));
+                    }
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, symbol: &str, price: Decimal) {
+        if !self.symbols.iter().any(|item| item == symbol) {
+            return;
+        }
+        self.last_update.insert(symbol.to_string(), Instant::now());
+        if self.last_sent.get(symbol) == Some(&price) {
+            self.pending.remove(symbol);
+        } else {
+            self.pending.insert(symbol.to_string(), price);
+        }
+    }
+
+    async fn poll_stale(&mut self) {
+        let stale: Vec<String> = self
+            .symbols
+            .iter()
+            .filter(|symbol| {
+                self.last_update
+                    .get(*symbol)
+                    .is_none_or(|updated| updated.elapsed() >= self.period)
+            })
+            .cloned()
+            .collect();
+        if stale.is_empty() {
+            return;
+        }
+        if let Ok(prices) = self.state.prices.fetch_prices(&stale).await {
+            for (symbol, price) in prices {
+                self.record(&symbol, price);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::Event;
+    use crate::test_support::{app, request, state};
+    use axum::body::BodyDataStream;
+    use axum::http::{Method, StatusCode};
+    use futures_util::StreamExt;
+    use rust_decimal::Decimal;
+    use serde_json::Value;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// The `data` of the next `prices` event on an SSE body.
+    async fn next_prices(body: &mut BodyDataStream, buffer: &mut String) -> Value {
+        loop {
+            if let Some(end) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..end + 2).collect();
+                if let Some(data) = event.lines().find_map(|line| line.strip_prefix("data: ")) {
+                    assert!(event.contains("event: prices"), "{}", event);
+                    return serde_json::from_str(data).unwrap();
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+                .await
+                .expect("no prices event")
+                .unwrap()
+                .unwrap();
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_only_the_requested_symbols_and_coalesces_bursts() {
+        let state = state();
+        let response = app(&state)
+            .oneshot(request(Method::GET, "/prices/stream?symbols=btc&interval_ms=250", Value::Null))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let mut buffer = String::new();
+
+        for price in [101, 102, 103] {
+            for symbol in ["BTC", "ETH"] {
+                state.events.publish(Event::Price {
+                    symbol: symbol.to_string(),
+                    price: Decimal::from(price),
+                });
+            }
+        }
+
+        // The first tick may already carry the polled fixture price; the burst lands as its
+        // last price in one event either way.
+        loop {
+            let prices = next_prices(&mut body, &mut buffer).await;
+            let prices = prices.as_object().unwrap();
+            assert_eq!(prices.keys().collect::<Vec<_>>(), ["BTC"]);
+            let price = prices["BTC"].as_str().unwrap();
+            assert!(price != "101" && price != "102", "burst not coalesced: {}", price);
+            if price == "103" {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Program-wide admin: the emergency pause, the guardian key and the two-step admin handover.
+//!
+//! The pause is enforced twice: the program rejects opens and withdrawals while its config
+//! says so, and the backend answers them with `503 PROTOCOL_PAUSED`. Closes, deposits and
+//! liquidations keep working. With an on-chain admin configured, `SetPause` lands first and
+//! the backend follows; without one the pause is kept in the store, so it survives a restart.
+
+use crate::admin::{MarketChain, ProgramConfig, Role};
+use crate::errors::AppError;
+use crate::events::Event;
+use crate::state::AppState;
+use crate::validation::ValidatedJson;
+use axum::extract::State;
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct ProgramStatus {
+    /// Whether the backend is rejecting opens and withdrawals.
+    pub paused: bool,
+    /// The on-chain config, when the backend has an on-chain admin and the config exists.
+    pub config: Option<ProgramConfig>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PauseRequest {
+    pub paused: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PauseResponse {
+    pub paused: bool,
+    /// `SetPause` transaction, when one was sent.
+    pub signature: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GuardianRequest {
+    pub guardian: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ProposeAdminRequest {
+    pub new_admin: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SignatureResponse {
+    pub signature: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/program",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, body = ProgramStatus),
+        (status = 401, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "The config account couldn't be read")
+    )
+)]
+pub async fn get_program(State(state): State<Arc<AppState>>) -> Result<Json<ProgramStatus>, AppError> {
+    let config = match &state.market_chain {
+        Some(chain) => chain.program_config().await.map_err(AppError::Chain)?,
+        None => None,
+    };
+    Ok(Json(ProgramStatus {
+        paused: state.risk.is_paused(),
+        config,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/program/pause",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body = PauseRequest,
+    responses(
+        (status = 200, body = PauseResponse),
+        (status = 401, body = ErrorResponse, description = "Wrong key, or the guardian key tried to unpause"),
+        (status = 500, body = ErrorResponse, description = "Without an on-chain admin, the pause couldn't be stored; nothing was changed"),
+        (status = 502, body = ErrorResponse, description = "`SetPause` failed; nothing was changed")
+    )
+)]
+pub async fn set_pause(
+    State(state): State<Arc<AppState>>,
+    Extension(role): Extension<Role>,
+    ValidatedJson(payload): ValidatedJson<PauseRequest>,
+) -> Result<Json<PauseResponse>, AppError> {
+    if role == Role::Guardian && !payload.paused {
+        return Err(AppError::Unauthorized("the guardian key can only pause".to_string()));
+    }
+    let signature = match &state.market_chain {
+        Some(chain) => Some(chain.set_pause(payload.paused).await.map_err(AppError::Chain)?),
+        None => {
+            state.store.save_paused(payload.paused).await?;
+            None
+        }
+    };
+
+    state.risk.set_paused(payload.paused);
+    let signature_log = signature.as_deref().unwrap_or("-");
+    if payload.paused {
+        warn!(?role, signature = signature_log, "program paused");
+    } else {
+        info!(?role, signature = signature_log, "program unpaused");
+    }
+    state.events.publish(Event::ProgramPause {
+        paused: payload.paused,
+    });
+    Ok(Json(PauseResponse {
+        paused: payload.paused,
+        signature,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/program/initialize",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body = GuardianRequest,
+    responses(
+        (status = 200, body = SignatureResponse, description = "The backend's keypair is now the program admin"),
+        (status = 401, body = ErrorResponse),
+        (status = 501, body = ErrorResponse, description = "No on-chain admin keypair is configured"),
+        (status = 502, body = ErrorResponse, description = "Rejected unless the backend's keypair is the program's upgrade authority")
+    )
+)]
+pub async fn initialize_config(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<GuardianRequest>,
+) -> Result<Json<SignatureResponse>, AppError> {
+    let signature = chain(&state)?
+        .initialize_config(&payload.guardian)
+        .await
+        .map_err(AppError::Chain)?;
+    info!(guardian = %payload.guardian, %signature, "program config initialized");
+    Ok(Json(SignatureResponse { signature }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/program/guardian",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body = GuardianRequest,
+    responses(
+        (status = 200, body = SignatureResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 501, body = ErrorResponse, description = "No on-chain admin keypair is configured"),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+pub async fn set_guardian(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<GuardianRequest>,
+) -> Result<Json<SignatureResponse>, AppError> {
+    let signature = chain(&state)?
+        .set_guardian(&payload.guardian)
+        .await
+        .map_err(AppError::Chain)?;
+    info!(guardian = %payload.guardian, %signature, "guardian changed");
+    Ok(Json(SignatureResponse { signature }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/program/propose-admin",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body = ProposeAdminRequest,
+    responses(
+        (status = 200, body = SignatureResponse, description = "Proposed; the new admin takes over once it sends `AcceptAdmin`"),
+        (status = 401, body = ErrorResponse),
+        (status = 501, body = ErrorResponse, description = "No on-chain admin keypair is configured"),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+pub async fn propose_admin(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<ProposeAdminRequest>,
+) -> Result<Json<SignatureResponse>, AppError> {
+    let signature = chain(&state)?
+        .propose_admin(&payload.new_admin)
+        .await
+        .map_err(AppError::Chain)?;
+    info!(new_admin = %payload.new_admin, %signature, "program admin proposed");
+    Ok(Json(SignatureResponse { signature }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/program/accept-admin",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, body = SignatureResponse, description = "The backend's keypair is now the program admin"),
+        (status = 401, body = ErrorResponse),
+        (status = 501, body = ErrorResponse, description = "No on-chain admin keypair is configured"),
+        (status = 502, body = ErrorResponse, description = "Rejected unless the backend's keypair was proposed")
+    )
+)]
+pub async fn accept_admin(State(state): State<Arc<AppState>>) -> Result<Json<SignatureResponse>, AppError> {
+    let signature = chain(&state)?.accept_admin().await.map_err(AppError::Chain)?;
+    info!(%signature, "program admin accepted");
+    Ok(Json(SignatureResponse { signature }))
+}
+
+/// Follows the on-chain pause at startup, or without an on-chain admin the stored one, so a
+/// restart doesn't silently resume trading.
+pub async fn sync_pause(state: &AppState) {
+    if state.market_chain.is_none() {
+        match state.store.load_paused().await {
+            Ok(paused) => {
+                state.risk.set_paused(paused);
+                if paused {
+                    warn!("program is paused; opens and withdrawals are rejected");
+                }
+            }
+            Err(err) => warn!(error = %err, "cannot read the stored pause; pause state unknown"),
+        }
+        return;
+    }
+    if let Some(Ok(None)) = follow_pause(state).await {
+        warn!("program config is not initialized; send POST /admin/program/initialize");
+    }
+}
+
+/// Reads the program config again every `poll`, so a `SetPause` sent without this backend,
+/// from the guardian's own tooling or another instance, reaches it too.
+pub fn spawn_pause_watcher(state: Arc<AppState>, poll: Duration) {
+    if state.market_chain.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(poll).await;
+            follow_pause(&state).await;
+        }
+    });
+}
+
+/// Applies the on-chain `paused` flag to the backend, returning the config read, or `None`
+/// without an on-chain admin. Changes are logged and published like an admin's pause.
+async fn follow_pause(state: &AppState) -> Option<Result<Option<ProgramConfig>, String>> {
+    let chain = state.market_chain.as_ref()?;
+    let config = chain.program_config().await;
+    match &config {
+        Ok(Some(config)) if config.paused != state.risk.is_paused() => {
+            state.risk.set_paused(config.paused);
+            if config.paused {
+                warn!("program is paused on-chain; opens and withdrawals are rejected");
+            } else {
+                info!("program is unpaused on-chain");
+            }
+            state.events.publish(Event::ProgramPause {
+                paused: config.paused,
+            });
+        }
+        Ok(_) => {}
+        Err(err) => warn!(error = %err, "cannot read the program config; pause state unknown"),
+    }
+    Some(config)
+}
+
+fn chain(state: &AppState) -> Result<&Arc<dyn MarketChain>, AppError> {
+    state.market_chain.as_ref().ok_or(AppError::ChainNotConfigured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{MemoryStore, Store};
+    use crate::test_support::{admin_request, app, send, state_with_store, FlakyStore, StubChain};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    fn config(paused: bool) -> Option<ProgramConfig> {
+        Some(ProgramConfig {
+            admin: "admin".to_string(),
+            pending_admin: None,
+            guardian: "guardian".to_string(),
+            paused,
+        })
+    }
+
+    #[tokio::test]
+    async fn the_backend_follows_the_on_chain_pause() {
+        let chain = Arc::new(StubChain::default());
+        let state = state_with_store(Arc::new(MemoryStore::new()), Some(chain.clone()), Vec::new());
+
+        // No config yet: nothing is paused.
+        sync_pause(&state).await;
+        assert!(!state.risk.is_paused());
+
+        *chain.config.lock().unwrap() = config(true);
+        follow_pause(&state).await;
+        assert!(state.risk.is_paused());
+        assert_eq!(state.events.last_seq(), 1);
+
+        // Unchanged reads aren't published again.
+        follow_pause(&state).await;
+        assert_eq!(state.events.last_seq(), 1);
+
+        *chain.config.lock().unwrap() = config(false);
+        follow_pause(&state).await;
+        assert!(!state.risk.is_paused());
+        assert_eq!(state.events.last_seq(), 2);
+    }
+
+    #[tokio::test]
+    async fn without_a_chain_the_pause_survives_a_restart() {
+        let store = Arc::new(MemoryStore::new());
+        let state = state_with_store(store.clone(), None, Vec::new());
+        let request = admin_request(Method::POST, "/admin/program/pause", json!({ "paused": true }));
+        let (status, _, _) = send(&app(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(store.load_paused().await.unwrap());
+
+        let restarted = state_with_store(store, None, Vec::new());
+        assert!(!restarted.risk.is_paused());
+        sync_pause(&restarted).await;
+        assert!(restarted.risk.is_paused());
+    }
+
+    #[tokio::test]
+    async fn a_pause_that_cannot_be_stored_is_not_applied() {
+        let store = Arc::new(FlakyStore::failing(&["save_paused"]));
+        let state = state_with_store(store, None, Vec::new());
+        let request = admin_request(Method::POST, "/admin/program/pause", json!({ "paused": true }));
+        let (status, _, _) = send(&app(&state), request).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!state.risk.is_paused());
+        assert_eq!(state.events.last_seq(), 0);
+    }
+}
@@ -0,0 +1,368 @@
+//! Decoding of Pyth price accounts without the Pyth SDK.
+//!
+//! Two layouts are understood: the legacy push-oracle price account (magic `0xa1b2c3d4`,
+//! version 2, account type 3) and the pull-oracle `PriceUpdateV2` account written by the
+//! Pyth receiver program (8-byte Anchor discriminator). Both carry an integer price and
+//! confidence scaled by `10^expo`, and the unix time they were published.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const LEGACY_MAGIC: u32 = 0xa1b2_c3d4;
+const LEGACY_VERSION: u32 = 2;
+const LEGACY_PRICE_ACCOUNT: u32 = 3;
+/// Header through the aggregate price; component prices follow and are not needed.
+const LEGACY_MIN_LEN: usize = 240;
+const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PythError {
+    #[error("account data is {0} bytes, too short for a price account")]
+    TooShort(usize),
+    #[error("not a Pyth price account")]
+    UnknownLayout,
+    #[error("unsupported price account version {0}")]
+    UnsupportedVersion(u32),
+    #[error("exponent {0} is out of range")]
+    ExponentOutOfRange(i32),
+    #[error("price is not positive")]
+    NonPositivePrice,
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    #[error("partially verified update with {num_signatures} guardian signatures, {min_signatures} required")]
+    InsufficientSignatures { num_signatures: u8, min_signatures: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PythStatus {
+    Unknown,
+    Trading,
+    Halted,
+    Auction,
+    Ignored,
+}
+
+impl PythStatus {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => PythStatus::Trading,
+            2 => PythStatus::Halted,
+            3 => PythStatus::Auction,
+            4 => PythStatus::Ignored,
+            _ => PythStatus::Unknown,
+        }
+    }
+}
+
+/// How much of the Wormhole guardian set signed a pull-oracle update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+/// A decoded price with the exponent applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PythPrice {
+    pub price: Decimal,
+    /// One standard deviation around `price`, in the same units.
+    pub conf: Decimal,
+    pub expo: i32,
+    /// Unix seconds.
+    pub publish_time: i64,
+    /// Status of the aggregate. Pull-oracle updates are only posted while trading.
+    pub status: PythStatus,
+    /// `None` for legacy accounts, which the publishers write directly.
+    pub verification: Option<Verification>,
+}
+
+impl PythPrice {
+    /// Refuses pull-oracle updates signed by fewer than `min_signatures` guardians. Fully
+    /// verified updates and legacy accounts always pass.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn check_verification(&self, min_signatures: u8) -> Result<(), PythError> {
+        match self.verification {
+            Some(Verification::Partial { num_signatures }) if num_signatures < min_signatures => {
+                Err(PythError::InsufficientSignatures {
+                    num_signatures,
+                    min_signatures,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<PythPrice, PythError> {
+    if data.len() >= 8 && data[..8] == PRICE_UPDATE_V2_DISCRIMINATOR {
+        return decode_price_update(data);
+    }
+    if data.len() >= 4 && read_u32(data, 0) == LEGACY_MAGIC {
+        return decode_legacy(data);
+    }
+    if data.len() < 8 {
+        return Err(PythError::TooShort(data.len()));
+    }
+    Err(PythError::UnknownLayout)
+}
+
+/// Legacy price accounts keep the previous aggregate alongside the current one; like the SDK,
+/// the previous one is reported while the aggregate isn't trading.
+fn decode_legacy(data: &[u8]) -> Result<PythPrice, PythError> {
+    if data.len() < LEGACY_MIN_LEN {
+        return Err(PythError::TooShort(data.len()));
+    }
+    let version = read_u32(data, 4);
+    if version != LEGACY_VERSION {
+        return Err(PythError::UnsupportedVersion(version));
+    }
+    if read_u32(data, 8) != LEGACY_PRICE_ACCOUNT {
+        return Err(PythError::UnknownLayout);
+    }
+    let expo = read_i32(data, 20);
+    let timestamp = read_i64(data, 96);
+    let prev_price = read_i64(data, 184);
+    let prev_conf = read_u64(data, 192);
+    let prev_timestamp = read_i64(data, 200);
+    let agg_price = read_i64(data, 208);
+    let agg_conf = read_u64(data, 216);
+    let status = PythStatus::from_u32(read_u32(data, 224));
+
+    let (price, conf, publish_time) = if status == PythStatus::Trading {
+        (agg_price, agg_conf, timestamp)
+    } else {
+        (prev_price, prev_conf, prev_timestamp)
+    };
+    scaled(price, conf, expo, publish_time, status, None)
+}
+
+fn decode_price_update(data: &[u8]) -> Result<PythPrice, PythError> {
+    // discriminator, write authority, then a borsh enum: `Partial { num_signatures: u8 }` or `Full`.
+    let mut offset = 8 + 32;
+    let variant = *data.get(offset).ok_or(PythError::TooShort(data.len()))?;
+    let verification = if variant == 0 {
+        let num_signatures = *data.get(offset + 1).ok_or(PythError::TooShort(data.len()))?;
+        offset += 2;
+        Verification::Partial { num_signatures }
+    } else {
+        offset += 1;
+        Verification::Full
+    };
+    // feed id, price, conf, exponent, publish time
+    if data.len() < offset + 32 + 8 + 8 + 4 + 8 {
+        return Err(PythError::TooShort(data.len()));
+    }
+    offset += 32;
+    let price = read_i64(data, offset);
+    let conf = read_u64(data, offset + 8);
+    let expo = read_i32(data, offset + 16);
+    let publish_time = read_i64(data, offset + 20);
+    scaled(price, conf, expo, publish_time, PythStatus::Trading, Some(verification))
+}
+
+fn scaled(
+    price: i64,
+    conf: u64,
+    expo: i32,
+    publish_time: i64,
+    status: PythStatus,
+    verification: Option<Verification>,
+) -> Result<PythPrice, PythError> {
+    if price <= 0 {
+        return Err(PythError::NonPositivePrice);
+    }
+    let apply = |value: i128| -> Result<Decimal, PythError> {
+        if expo <= 0 {
+            Decimal::try_from_i128_with_scale(value, expo.unsigned_abs())
+                .map_err(|_| PythError::ExponentOutOfRange(expo))
+        } else {
+            let factor = 10i128.checked_pow(expo as u32).ok_or(PythError::ExponentOutOfRange(expo))?;
+            let value = value.checked_mul(factor).ok_or(PythError::ExponentOutOfRange(expo))?;
+            Decimal::try_from_i128_with_scale(value, 0).map_err(|_| PythError::ExponentOutOfRange(expo))
+        }
+    };
+    Ok(PythPrice {
+        price: apply(price as i128)?.normalize(),
+        conf: apply(conf as i128)?.normalize(),
+        expo,
+        publish_time,
+        status,
+        verification,
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// One entry of a fixture manifest: an account dump and what it must decode to.
+#[derive(Deserialize)]
+pub struct FixtureCase {
+    pub file: String,
+    #[serde(default)]
+    pub price: Option<Decimal>,
+    #[serde(default)]
+    pub conf: Option<Decimal>,
+    #[serde(default)]
+    pub expo: Option<i32>,
+    #[serde(default)]
+    pub publish_time: Option<i64>,
+    #[serde(default)]
+    pub status: Option<PythStatus>,
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    /// Expected decode error, matched against its message.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Decodes every account listed in `manifest` (paths relative to it) and reports each
+/// mismatch; an empty result means all fixtures decode as recorded.
+pub fn check_fixtures(manifest: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let cases: Vec<FixtureCase> = serde_json::from_str(&std::fs::read_to_string(manifest)?)?;
+    let dir = std::path::Path::new(manifest).parent().unwrap_or(std::path::Path::new("."));
+    let mut failures = Vec::new();
+    for case in cases {
+        let data = std::fs::read(dir.join(&case.file))?;
+        match (decode(&data), &case.error) {
+            (Ok(decoded), None) => {
+                let mut mismatch = |field: &str, expected: String, actual: String| {
+                    if expected != actual {
+                        failures.push(format!("{}: {} is {}, expected {}", case.file, field, actual, expected));
+                    }
+                };
+                if let Some(price) = case.price {
+                    mismatch("price", price.normalize().to_string(), decoded.price.to_string());
+                }
+                if let Some(conf) = case.conf {
+                    mismatch("conf", conf.normalize().to_string(), decoded.conf.to_string());
+                }
+                if let Some(expo) = case.expo {
+                    mismatch("expo", expo.to_string(), decoded.expo.to_string());
+                }
+                if let Some(publish_time) = case.publish_time {
+                    mismatch("publish_time", publish_time.to_string(), decoded.publish_time.to_string());
+                }
+                if let Some(status) = case.status {
+                    mismatch("status", format!("{:?}", status), format!("{:?}", decoded.status));
+                }
+                if let Some(verification) = case.verification {
+                    mismatch(
+                        "verification",
+                        format!("{:?}", Some(verification)),
+                        format!("{:?}", decoded.verification),
+                    );
+                }
+            }
+            (Err(err), Some(expected)) if err.to_string().contains(expected.as_str()) => {}
+            (Ok(decoded), Some(expected)) => {
+                failures.push(format!("{}: decoded {:?}, expected error {:?}", case.file, decoded, expected))
+            }
+            (Err(err), _) => failures.push(format!("{}: {}", case.file, err)),
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(data: &[u8]) -> PythPrice {
+        decode(data).unwrap()
+    }
+
+    #[test]
+    fn bundled_fixtures_decode_as_recorded() {
+        let failures = check_fixtures("fixtures/pyth/manifest.json").unwrap();
+        assert!(failures.is_empty(), "{:#?}", failures);
+    }
+
+    #[test]
+    fn legacy_trading_account() {
+        let price = decoded(include_bytes!("../fixtures/pyth/sol_usd_legacy.bin"));
+        assert_eq!(price.price, Decimal::new(14_237, 2));
+        assert_eq!(price.conf, Decimal::new(71, 3));
+        assert_eq!(price.expo, -8);
+        assert_eq!(price.publish_time, 1_792_000_000);
+        assert_eq!(price.status, PythStatus::Trading);
+        assert_eq!(price.verification, None);
+    }
+
+    #[test]
+    fn legacy_halted_account_reports_the_previous_aggregate() {
+        let price = decoded(include_bytes!("../fixtures/pyth/btc_usd_legacy_halted.bin"));
+        assert_eq!(price.price, Decimal::new(6_705_012_345_678, 8));
+        assert_eq!(price.conf, Decimal::from(25));
+        assert_eq!(price.expo, -8);
+        assert_eq!(price.publish_time, 1_791_999_990);
+        assert_eq!(price.status, PythStatus::Halted);
+    }
+
+    #[test]
+    fn fully_verified_price_update() {
+        let price = decoded(include_bytes!("../fixtures/pyth/eth_usd_price_update_v2.bin"));
+        assert_eq!(price.price, Decimal::new(35_001_234, 4));
+        assert_eq!(price.conf, Decimal::new(175, 2));
+        assert_eq!(price.expo, -8);
+        assert_eq!(price.publish_time, 1_792_000_005);
+        assert_eq!(price.verification, Some(Verification::Full));
+        assert_eq!(price.check_verification(u8::MAX), Ok(()));
+    }
+
+    #[test]
+    fn partially_verified_price_update_needs_enough_signatures() {
+        let price = decoded(include_bytes!("../fixtures/pyth/pepe_usd_price_update_v2_partial.bin"));
+        assert_eq!(price.price, Decimal::new(1_234, 10));
+        assert_eq!(price.conf, Decimal::new(3, 10));
+        assert_eq!(price.expo, -10);
+        assert_eq!(price.publish_time, 1_792_000_007);
+        assert_eq!(price.verification, Some(Verification::Partial { num_signatures: 5 }));
+
+        assert_eq!(price.check_verification(5), Ok(()));
+        assert_eq!(
+            price.check_verification(13),
+            Err(PythError::InsufficientSignatures {
+                num_signatures: 5,
+                min_signatures: 13
+            })
+        );
+    }
+
+    #[test]
+    fn other_accounts_are_rejected() {
+        let truncated = include_bytes!("../fixtures/pyth/truncated.bin");
+        assert_eq!(decode(truncated), Err(PythError::TooShort(truncated.len())));
+        assert_eq!(
+            decode(include_bytes!("../fixtures/pyth/mapping_account.bin")),
+            Err(PythError::UnknownLayout)
+        );
+    }
+
+    #[test]
+    fn positive_exponents_and_non_positive_prices() {
+        let mut data = include_bytes!("../fixtures/pyth/eth_usd_price_update_v2.bin").to_vec();
+        // Full verification: discriminator, authority, variant, then the 32-byte feed id.
+        let price_at = 8 + 32 + 1 + 32;
+        data[price_at..price_at + 8].copy_from_slice(&7i64.to_le_bytes());
+        data[price_at + 16..price_at + 20].copy_from_slice(&3i32.to_le_bytes());
+        assert_eq!(decoded(&data).price, Decimal::from(7_000));
+
+        data[price_at..price_at + 8].copy_from_slice(&0i64.to_le_bytes());
+        assert_eq!(decode(&data), Err(PythError::NonPositivePrice));
+    }
+}
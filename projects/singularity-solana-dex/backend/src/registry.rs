@@ -0,0 +1,631 @@
+//! The market registry: every market's risk parameters and, for markets priced on-chain, its
+//! oracle settings. `MARKET_REGISTRY` names a JSON file holding it, which is polled so edits
+//! take effect without a restart; the admin API edits the same registry and writes it back.
+//!
+//! A change is validated as a whole, diffed against the registry in effect, and refused if it
+//! drops a market that still has positions, reopens a settled market, or would make an open
+//! position liquidatable on the spot. Accepted changes replace the [`RiskEngine`] markets and notify the listeners
+//! (the oracle client) while account writes are blocked, so no request sees half of a change.
+
+use crate::config::{OracleConfig, OracleMarketConfig, OracleSource};
+use crate::models::{Account, MarketConfig, MarketStatus};
+use crate::risk::RiskEngine;
+use crate::state::AppState;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Mutex, MutexGuard};
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const MAX_MARGIN_BPS: u32 = 10_000;
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("cannot read market registry: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cannot parse market registry: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("invalid market registry: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+    #[error("market registry change rejected: {}", .0.join("; "))]
+    Unsafe(Vec<String>),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MarketRegistry {
+    pub markets: Vec<MarketEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MarketEntry {
+    #[serde(flatten)]
+    pub market: MarketConfig,
+    /// On-chain ids and oracle settings, as in an `ORACLE_CONFIG` entry without `symbol`.
+    /// Markets without it are only priced off-chain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oracle: Option<OracleMarketConfig>,
+}
+
+impl MarketRegistry {
+    /// Reads and validates the registry at `path`.
+    pub fn load(path: &str) -> Result<Self, RegistryError> {
+        let data = std::fs::read_to_string(path)?;
+        let mut registry: MarketRegistry = serde_json::from_str(&data)?;
+        for entry in &mut registry.markets {
+            entry.market.symbol = entry.market.symbol.to_uppercase();
+            if let Some(oracle) = &mut entry.oracle {
+                oracle.symbol = entry.market.symbol.clone();
+            }
+        }
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    /// The built-in markets with the `ORACLE_CONFIG` entries of the same symbols. Oracle
+    /// entries for markets that aren't listed are dropped.
+    pub fn from_parts(markets: Vec<MarketConfig>, oracles: Vec<OracleMarketConfig>) -> Self {
+        let markets = markets
+            .into_iter()
+            .map(|market| {
+                let oracle = oracles
+                    .iter()
+                    .find(|oracle| oracle.symbol.eq_ignore_ascii_case(&market.symbol))
+                    .cloned()
+                    .map(|oracle| OracleMarketConfig {
+                        symbol: market.symbol.clone(),
+                        ..oracle
+                    });
+                MarketEntry { market, oracle }
+            })
+            .collect();
+        Self { markets }
+    }
+
+    pub fn entry(&self, symbol: &str) -> Option<&MarketEntry> {
+        self.markets.iter().find(|entry| entry.market.symbol == symbol)
+    }
+
+    pub fn entry_mut(&mut self, symbol: &str) -> Option<&mut MarketEntry> {
+        self.markets.iter_mut().find(|entry| entry.market.symbol == symbol)
+    }
+
+    /// Checks every entry and reports all problems at once.
+    pub fn validate(&self) -> Result<(), RegistryError> {
+        let mut problems = Vec::new();
+        if self.markets.is_empty() {
+            problems.push("no markets listed".to_string());
+        }
+
+        let mut symbols = HashSet::new();
+        let mut market_ids = HashSet::new();
+        for entry in &self.markets {
+            let market = &entry.market;
+            let symbol = &market.symbol;
+            if symbol.is_empty() {
+                problems.push("market with an empty symbol".to_string());
+            } else if !symbols.insert(symbol.clone()) {
+                problems.push(format!("{} is listed twice", symbol));
+            }
+            if market.max_leverage_bps == 0 {
+                problems.push(format!("{}: max_leverage_bps must be positive", symbol));
+            }
+            if market.maintenance_margin_bps == 0 || market.maintenance_margin_bps >= market.initial_margin_bps {
+                problems.push(format!(
+                    "{}: maintenance_margin_bps must be positive and below initial_margin_bps",
+                    symbol
+                ));
+            }
+            if market.initial_margin_bps > MAX_MARGIN_BPS {
+                problems.push(format!("{}: initial_margin_bps above {}", symbol, MAX_MARGIN_BPS));
+            }
+            for (field, value) in [
+                ("max_open_interest", market.max_open_interest),
+                ("tick_size", market.tick_size),
+                ("lot_size", market.lot_size),
+            ] {
+                if value <= Decimal::ZERO {
+                    problems.push(format!("{}: {} must be positive", symbol, field));
+                }
+            }
+            match (market.status, market.settlement_price) {
+                (MarketStatus::Settled, Some(price)) if price > Decimal::ZERO => {}
+                (MarketStatus::Settled, _) => {
+                    problems.push(format!("{}: settled without a positive settlement_price", symbol));
+                }
+                (_, Some(_)) => problems.push(format!("{}: settlement_price on a market that isn't settled", symbol)),
+                (_, None) => {}
+            }
+
+            let Some(oracle) = &entry.oracle else {
+                continue;
+            };
+            if !market_ids.insert(oracle.market_id) {
+                problems.push(format!("{}: market_id {} is already used", symbol, oracle.market_id));
+            }
+            if oracle.oracle_pubkey.is_empty() || oracle.market_account.is_empty() {
+                problems.push(format!("{}: oracle_pubkey and market_account are required", symbol));
+            }
+            let sources = oracle.sources.as_deref().unwrap_or_default();
+            if oracle.sources.is_some() && sources.is_empty() {
+                problems.push(format!("{}: sources is empty", symbol));
+            }
+            if sources.contains(&OracleSource::Switchboard) && oracle.switchboard_pubkey.is_none() {
+                problems.push(format!("{}: switchboard source without switchboard_pubkey", symbol));
+            }
+            if let Some(price) = &oracle.static_price {
+                if !Decimal::from_str(price).is_ok_and(|price| price > Decimal::ZERO) {
+                    problems.push(format!("{}: static_price {:?} is not a positive number", symbol, price));
+                }
+            } else if sources.contains(&OracleSource::Static) {
+                problems.push(format!("{}: static source without static_price", symbol));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(RegistryError::Invalid(problems))
+        }
+    }
+
+    pub fn risk_markets(&self) -> Vec<MarketConfig> {
+        self.markets.iter().map(|entry| entry.market.clone()).collect()
+    }
+
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn oracle_config(&self) -> OracleConfig {
+        OracleConfig {
+            markets: self.markets.iter().filter_map(|entry| entry.oracle.clone()).collect(),
+        }
+    }
+}
+
+/// Symbols whose entries differ between two registries.
+#[derive(Debug, Default)]
+pub struct RegistryDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl RegistryDiff {
+    pub fn between(current: &MarketRegistry, next: &MarketRegistry) -> Self {
+        let current: HashMap<&str, &MarketEntry> = by_symbol(current);
+        let next: HashMap<&str, &MarketEntry> = by_symbol(next);
+        let mut diff = RegistryDiff::default();
+        for (symbol, entry) in &next {
+            match current.get(symbol) {
+                None => diff.added.push(symbol.to_string()),
+                Some(old) if old != entry => diff.changed.push(symbol.to_string()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = current
+            .keys()
+            .filter(|symbol| !next.contains_key(*symbol))
+            .map(|symbol| symbol.to_string())
+            .collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for RegistryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "added [{}], removed [{}], changed [{}]",
+            self.added.join(", "),
+            self.removed.join(", "),
+            self.changed.join(", ")
+        )
+    }
+}
+
+fn by_symbol(registry: &MarketRegistry) -> HashMap<&str, &MarketEntry> {
+    registry
+        .markets
+        .iter()
+        .map(|entry| (entry.market.symbol.as_str(), entry))
+        .collect()
+}
+
+/// Called with every registry that has been applied to the risk engine.
+pub type ReloadListener = Box<dyn Fn(&MarketRegistry) + Send + Sync>;
+
+/// The registry in effect, shared by the file watcher and the admin API. Changes are made
+/// one at a time through a [`RegistryGuard`].
+pub struct Registry {
+    /// File the registry is watched at and written back to; `None` for the built-in markets.
+    path: Option<String>,
+    current: Mutex<MarketRegistry>,
+    listeners: RwLock<Vec<ReloadListener>>,
+}
+
+impl Registry {
+    pub fn new(path: Option<String>, registry: MarketRegistry) -> Self {
+        Self {
+            path,
+            current: Mutex::new(registry),
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Markets of the registry in effect, for building the risk engine before it is shared.
+    pub fn risk_markets(&mut self) -> Vec<MarketConfig> {
+        self.current.get_mut().risk_markets()
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn add_listener(&self, listener: ReloadListener) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Holds off every other change until the guard is dropped.
+    pub async fn lock(&self) -> RegistryGuard<'_> {
+        RegistryGuard {
+            registry: self,
+            current: self.current.lock().await,
+        }
+    }
+}
+
+pub struct RegistryGuard<'a> {
+    registry: &'a Registry,
+    current: MutexGuard<'a, MarketRegistry>,
+}
+
+impl RegistryGuard<'_> {
+    pub fn current(&self) -> &MarketRegistry {
+        &self.current
+    }
+
+    /// Whether `next` could be committed now, without applying it.
+    pub async fn check(&self, state: &AppState, next: &MarketRegistry) -> Result<RegistryDiff, RegistryError> {
+        self.apply(state, next, false).await
+    }
+
+    /// Applies `next` unless it is unsafe for the open positions, writing it back to the
+    /// registry file when `persist` is set, and returns what changed.
+    pub async fn commit(
+        &mut self,
+        state: &AppState,
+        next: MarketRegistry,
+        persist: bool,
+    ) -> Result<RegistryDiff, RegistryError> {
+        let diff = self.apply(state, &next, true).await?;
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+        if let (true, Some(path)) = (persist, self.registry.path()) {
+            // Write then rename, so the watcher never reads half a file.
+            let tmp = format!("{}.tmp", path);
+            std::fs::write(&tmp, serde_json::to_string_pretty(&next)?)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        *self.current = next;
+        Ok(diff)
+    }
+
+    async fn apply(&self, state: &AppState, next: &MarketRegistry, commit: bool) -> Result<RegistryDiff, RegistryError> {
+        next.validate()?;
+        let diff = RegistryDiff::between(&self.current, next);
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+
+        // Price what the affected accounts hold before taking the lock; a market opened in
+        // between can't be priced and is refused below rather than checked at a guess.
+        let touched: HashSet<&String> = diff.removed.iter().chain(&diff.changed).collect();
+        let held: Vec<String> = {
+            let accounts = state.accounts.read().await;
+            affected(&accounts, &touched)
+                .flat_map(|account| account.positions.keys().cloned())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect()
+        };
+        let index = if held.is_empty() {
+            HashMap::new()
+        } else {
+            state.prices.fetch_fresh_prices(&held).await.unwrap_or_default()
+        };
+        let marks = state.marks.marks_for_index(&index);
+
+        let accounts = state.accounts.write().await;
+        let next_risk = RiskEngine::new(next.risk_markets());
+        let mut problems = reopened_settlements(&self.current, next);
+        problems.extend(unsafe_changes(&state.risk, &next_risk, &accounts, &touched, &marks));
+        if !problems.is_empty() {
+            return Err(RegistryError::Unsafe(problems));
+        }
+        if commit {
+            state.risk.replace_markets(next.risk_markets());
+            for listener in self.registry.listeners.read().unwrap().iter() {
+                listener(next);
+            }
+        }
+        drop(accounts);
+        Ok(diff)
+    }
+}
+
+fn affected<'a>(
+    accounts: &'a HashMap<Uuid, Account>,
+    touched: &'a HashSet<&String>,
+) -> impl Iterator<Item = &'a Account> {
+    accounts
+        .values()
+        .filter(|account| account.positions.keys().any(|symbol| touched.contains(symbol)))
+}
+
+/// Settled markets in `current` whose status or settlement price `next` changes.
+fn reopened_settlements(current: &MarketRegistry, next: &MarketRegistry) -> Vec<String> {
+    current
+        .markets
+        .iter()
+        .filter(|entry| entry.market.status == MarketStatus::Settled)
+        .filter_map(|entry| {
+            let after = &next.entry(&entry.market.symbol)?.market;
+            (after.status != entry.market.status || after.settlement_price != entry.market.settlement_price)
+                .then(|| format!("{} is settled; its status and settlement_price are final", entry.market.symbol))
+        })
+        .collect()
+}
+
+/// Why `next` can't replace `current` while these accounts are open: a held market is
+/// removed, or a position liquidatable under `next` isn't under `current`.
+fn unsafe_changes(
+    current: &RiskEngine,
+    next: &RiskEngine,
+    accounts: &HashMap<Uuid, Account>,
+    touched: &HashSet<&String>,
+    marks: &HashMap<String, Decimal>,
+) -> Vec<String> {
+    let mut problems = BTreeSet::new();
+    for account in affected(accounts, touched) {
+        for symbol in account.positions.keys() {
+            if next.market(symbol).is_none() {
+                problems.insert(format!("{} is removed but account {} holds it", symbol, account.id));
+            }
+        }
+        let before = match current.check_risk(account, marks) {
+            Ok(result) => result.liquidatable_positions,
+            Err(err) => {
+                problems.insert(format!("cannot value account {}: {}", account.id, err));
+                continue;
+            }
+        };
+        if let Ok(after) = next.check_risk(account, marks) {
+            for symbol in after.liquidatable_positions {
+                if !before.contains(&symbol) {
+                    problems.insert(format!("account {} would become liquidatable in {}", account.id, symbol));
+                }
+            }
+        }
+    }
+    problems.into_iter().collect()
+}
+
+/// Polls the registry file every `poll` and applies each edit. A rejected edit is logged and
+/// the registry in effect stays until the file changes again.
+pub fn spawn_watcher(state: Arc<AppState>, poll: Duration) {
+    let Some(path) = state.registry.path().map(str::to_string) else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        loop {
+            tokio::time::sleep(poll).await;
+            let modified = modified(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let next = match MarketRegistry::load(&path) {
+                Ok(next) => next,
+                Err(err) => {
+                    warn!(path = %path, error = %err, "market registry not reloaded");
+                    continue;
+                }
+            };
+            // The admin API's own writes come back here as empty diffs.
+            let mut registry = state.registry.lock().await;
+            match registry.commit(&state, next, false).await {
+                Ok(diff) if diff.is_empty() => {}
+                Ok(diff) => {
+                    info!(path = %path, %diff, "market registry reloaded");
+                    drop(registry);
+                    crate::settlement::settle_pending(&state).await;
+                }
+                Err(err) => warn!(path = %path, error = %err, "market registry not reloaded"),
+            }
+        }
+    });
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Position, Side};
+    use crate::risk::default_markets;
+    use crate::test_support::state_with;
+    use serde_json::json;
+
+    fn registry(symbols: &[&str]) -> MarketRegistry {
+        let markets = default_markets()
+            .into_iter()
+            .filter(|market| symbols.contains(&market.symbol.as_str()))
+            .collect();
+        MarketRegistry::from_parts(markets, Vec::new())
+    }
+
+    fn oracle(market_id: u16, sources: &[&str]) -> OracleMarketConfig {
+        serde_json::from_value(json!({
+            "market_id": market_id,
+            "oracle_pubkey": "oracle",
+            "market_account": "market",
+            "static_price": null,
+            "sources": sources,
+        }))
+        .unwrap()
+    }
+
+    fn problems(registry: &MarketRegistry) -> Vec<String> {
+        match registry.validate() {
+            Err(RegistryError::Invalid(problems)) => problems,
+            other => panic!("expected an invalid registry, got {:?}", other),
+        }
+    }
+
+    /// An account with 1000 of collateral holding one BTC long from 60000 at 100x.
+    fn btc_holder() -> Account {
+        Account {
+            id: Uuid::new_v4(),
+            owner: "owner".to_string(),
+            account_state: None,
+            collateral: Decimal::from(1_000),
+            positions: HashMap::from([(
+                "BTC".to_string(),
+                Position {
+                    market: "BTC".to_string(),
+                    side: Side::Long,
+                    base_qty: Decimal::ONE,
+                    entry_price: Decimal::from(60_000),
+                    leverage_bps: 1_000_000,
+                    position_account: None,
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        assert!(registry(&["BTC", "ETH"]).validate().is_ok());
+        assert_eq!(problems(&registry(&[])), vec!["no markets listed"]);
+
+        let mut next = registry(&["BTC", "ETH", "SOL"]);
+        next.markets[0].market.maintenance_margin_bps = next.markets[0].market.initial_margin_bps;
+        next.markets[1].market.symbol = "BTC".to_string();
+        next.markets[2].market.status = MarketStatus::Settled;
+        next.markets[0].oracle = Some(oracle(1, &["static"]));
+        next.markets[2].oracle = Some(oracle(1, &["pyth"]));
+        assert_eq!(
+            problems(&next),
+            vec![
+                "BTC: maintenance_margin_bps must be positive and below initial_margin_bps",
+                "BTC: static source without static_price",
+                "BTC is listed twice",
+                "SOL: settled without a positive settlement_price",
+                "SOL: market_id 1 is already used",
+            ]
+        );
+    }
+
+    #[test]
+    fn settlement_prices_belong_to_settled_markets_only() {
+        let mut next = registry(&["BTC"]);
+        next.markets[0].market.settlement_price = Some(Decimal::from(50_000));
+        assert_eq!(
+            problems(&next),
+            vec!["BTC: settlement_price on a market that isn't settled"]
+        );
+        next.markets[0].market.status = MarketStatus::Settled;
+        assert!(next.validate().is_ok());
+    }
+
+    #[test]
+    fn settled_markets_stay_settled() {
+        let mut current = registry(&["BTC", "ETH"]);
+        current.markets[0].market.status = MarketStatus::Settled;
+        current.markets[0].market.settlement_price = Some(Decimal::from(50_000));
+        assert!(reopened_settlements(&current, &current.clone()).is_empty());
+
+        let mut reopened = current.clone();
+        reopened.markets[0].market.status = MarketStatus::Active;
+        reopened.markets[0].market.settlement_price = None;
+        let mut repriced = current.clone();
+        repriced.markets[0].market.settlement_price = Some(Decimal::from(51_000));
+        for next in [reopened, repriced] {
+            assert_eq!(
+                reopened_settlements(&current, &next),
+                vec!["BTC is settled; its status and settlement_price are final"]
+            );
+        }
+
+        // Other markets are free to change.
+        let mut tightened = current.clone();
+        tightened.markets[1].market.maintenance_margin_bps += 10;
+        assert!(reopened_settlements(&current, &tightened).is_empty());
+    }
+
+    #[test]
+    fn changes_may_not_strand_or_liquidate_open_positions() {
+        let current = registry(&["BTC", "ETH"]);
+        let current_risk = RiskEngine::new(current.risk_markets());
+        let accounts = HashMap::from([{
+            let account = btc_holder();
+            (account.id, account)
+        }]);
+        let btc = "BTC".to_string();
+        let touched = HashSet::from([&btc]);
+        // Equity 1000 against a 210 maintenance margin at 35 bps.
+        let marks = HashMap::from([(btc.clone(), Decimal::from(60_000))]);
+
+        let mut loosened = current.clone();
+        loosened.markets[0].market.maintenance_margin_bps = 100;
+        let next = RiskEngine::new(loosened.risk_markets());
+        assert!(unsafe_changes(&current_risk, &next, &accounts, &touched, &marks).is_empty());
+
+        let mut tightened = current.clone();
+        tightened.markets[0].market.initial_margin_bps = 200;
+        tightened.markets[0].market.maintenance_margin_bps = 199;
+        let next = RiskEngine::new(tightened.risk_markets());
+        let id = accounts.keys().next().unwrap();
+        assert_eq!(
+            unsafe_changes(&current_risk, &next, &accounts, &touched, &marks),
+            vec![format!("account {} would become liquidatable in BTC", id)]
+        );
+
+        // Only accounts holding a touched market are checked.
+        assert!(unsafe_changes(&current_risk, &next, &accounts, &HashSet::new(), &marks).is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_refused_change_leaves_the_registry_and_risk_engine_alone() {
+        let account = btc_holder();
+        let id = account.id;
+        let state = state_with(vec![account]);
+        let mut guard = state.registry.lock().await;
+        let mut next = guard.current().clone();
+        next.markets.retain(|entry| entry.market.symbol != "BTC");
+
+        let err = guard.commit(&state, next, false).await.unwrap_err();
+        let RegistryError::Unsafe(problems) = err else {
+            panic!("expected an unsafe change, got {:?}", err);
+        };
+        assert!(problems.contains(&format!("BTC is removed but account {} holds it", id)));
+        assert!(guard.current().entry("BTC").is_some());
+        assert!(state.risk.market("BTC").is_some());
+    }
+}
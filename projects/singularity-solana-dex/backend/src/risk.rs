@@ -1,14 +1,29 @@
 use crate::errors::RiskError;
-use crate::models::{Account, MarketConfig, OpenPositionRequest, Position, PositionOutcome, RiskCheckRequest, RiskCheckResponse, Side};
+use crate::models::{
+    Account, MarketConfig, MarketStatus, OpenPositionRequest, Position, PositionHealth, PositionOutcome, RiskCheckResponse,
+    Side,
+};
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use uuid::Uuid;
 
 const BPS_DIVISOR: i64 = 10_000;
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
 const LIQUIDATION_FEE_BPS: i64 = 50;
 
 pub struct RiskEngine {
-    markets: HashMap<String, MarketConfig>,
+    /// Swapped whole by [`RiskEngine::replace_markets`] when the market registry reloads.
+    markets: RwLock<HashMap<String, MarketConfig>>,
+    /// Markets where positions can't be opened or levered up, see [`RiskEngine::freeze_market`].
+    frozen: RwLock<HashSet<String>>,
+    /// Program-wide emergency pause, see [`RiskEngine::ensure_not_paused`].
+    paused: AtomicBool,
+    /// Positions with a liquidation sent but not yet confirmed or expired; they can't be
+    /// closed or re-levered meanwhile.
+    liquidating: RwLock<HashSet<(Uuid, String)>>,
 }
 
 impl RiskEngine {
@@ -17,45 +32,168 @@ impl RiskEngine {
             .into_iter()
             .map(|market| (market.symbol.clone(), market))
             .collect();
-        Self { markets: markets_map }
+        Self {
+            markets: RwLock::new(markets_map),
+            frozen: RwLock::new(HashSet::new()),
+            paused: AtomicBool::new(false),
+            liquidating: RwLock::new(HashSet::new()),
+        }
+    }
+
+    pub fn market(&self, symbol: &str) -> Option<MarketConfig> {
+        self.markets.read().unwrap().get(symbol).cloned()
+    }
+
+    /// Replaces every market at once; callers validate the new set against open positions first.
+    pub fn replace_markets(&self, markets: Vec<MarketConfig>) {
+        *self.markets.write().unwrap() = markets
+            .into_iter()
+            .map(|market| (market.symbol.clone(), market))
+            .collect();
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Fails while the program is paused: no opens, leverage increases or withdrawals.
+    pub fn ensure_not_paused(&self) -> Result<(), RiskError> {
+        if self.is_paused() {
+            return Err(RiskError::ProgramPaused);
+        }
+        Ok(())
+    }
+
+    /// Stops new exposure in `symbol` until [`RiskEngine::unfreeze_market`]; closes still work.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn freeze_market(&self, symbol: &str) -> bool {
+        self.frozen.write().unwrap().insert(symbol.to_string())
+    }
+
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn unfreeze_market(&self, symbol: &str) -> bool {
+        self.frozen.write().unwrap().remove(symbol)
+    }
+
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn mark_liquidating(&self, account_id: Uuid, market: &str) {
+        self.liquidating.write().unwrap().insert((account_id, market.to_string()));
+    }
+
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn clear_liquidating(&self, account_id: Uuid, market: &str) {
+        self.liquidating.write().unwrap().remove(&(account_id, market.to_string()));
+    }
+
+    pub fn is_liquidating(&self, account_id: Uuid, market: &str) -> bool {
+        self.liquidating.read().unwrap().contains(&(account_id, market.to_string()))
+    }
+
+    fn ensure_not_liquidating(&self, account_id: Uuid, market: &str) -> Result<(), RiskError> {
+        if self.is_liquidating(account_id, market) {
+            return Err(RiskError::LiquidationPending(market.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether `symbol` accepts new exposure: the program isn't paused, the market's status
+    /// allows it and its oracle hasn't frozen it. Post-only markets are checked against the entry price separately.
+    fn ensure_not_frozen(&self, symbol: &str) -> Result<(), RiskError> {
+        self.ensure_not_paused()?;
+        match self.status(symbol) {
+            MarketStatus::Active | MarketStatus::PostOnly => {}
+            MarketStatus::ReduceOnly => return Err(RiskError::MarketReduceOnly(symbol.to_string())),
+            MarketStatus::Paused => return Err(RiskError::MarketPaused(symbol.to_string())),
+            MarketStatus::Settled => return Err(RiskError::MarketSettled(symbol.to_string())),
+        }
+        if self.frozen.read().unwrap().contains(symbol) {
+            return Err(RiskError::MarketFrozen(symbol.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether positions in `symbol` may be closed or re-levered at all.
+    fn ensure_trading(&self, symbol: &str) -> Result<(), RiskError> {
+        match self.status(symbol) {
+            MarketStatus::Paused => Err(RiskError::MarketPaused(symbol.to_string())),
+            MarketStatus::Settled => Err(RiskError::MarketSettled(symbol.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Status of `symbol`; markets that are no longer listed count as active so their
+    /// positions can still be closed.
+    fn status(&self, symbol: &str) -> MarketStatus {
+        self.market(symbol).map(|market| market.status).unwrap_or_default()
+    }
+
+    /// Whether the crank may liquidate positions in `symbol`.
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
+    pub fn allows_liquidation(&self, symbol: &str) -> bool {
+        self.ensure_trading(symbol).is_ok()
     }
 
     pub fn markets(&self) -> Vec<MarketConfig> {
-        let mut items: Vec<MarketConfig> = self.markets.values().cloned().collect();
+        let mut items: Vec<MarketConfig> = self.markets.read().unwrap().values().cloned().collect();
         items.sort_by(|a, b| a.symbol.cmp(&b.symbol));
         items
     }
 
+    /// Opens `req` with margin valued at `marks`, which must cover the requested market and
+    /// every position the account already holds.
     pub fn open_position(
         &self,
         account: &mut Account,
         req: OpenPositionRequest,
+        marks: &HashMap<String, Decimal>,
     ) -> Result<PositionOutcome, RiskError> {
         let market = self
-            .markets
-            .get(&req.market)
-            .ok_or(RiskError::MarketNotFound)?;
+            .market(&req.market)
+            .ok_or_else(|| RiskError::MarketNotFound(req.market.clone()))?;
 
         if req.base_qty <= Decimal::ZERO {
             return Err(RiskError::InvalidQuantity);
         }
 
         if req.leverage_bps == 0 || req.leverage_bps > market.max_leverage_bps {
-            return Err(RiskError::InvalidLeverage);
+            return Err(RiskError::InvalidLeverage {
+                requested_bps: req.leverage_bps,
+                max_bps: market.max_leverage_bps,
+            });
         }
 
         if account.positions.contains_key(&req.market) {
-            return Err(RiskError::InvalidQuantity);
+            return Err(RiskError::PositionExists(req.market.clone()));
         }
+        self.ensure_not_frozen(&req.market)?;
 
-        let notional = abs_decimal(req.base_qty) * req.mark_price;
+        let mark_price = mark_for(marks, &req.market)?;
+        let crosses = match req.side {
+            Side::Long => req.entry_price > mark_price,
+            Side::Short => req.entry_price < mark_price,
+        };
+        if market.status == MarketStatus::PostOnly && crosses {
+            return Err(RiskError::PostOnlyWouldCross {
+                market: req.market.clone(),
+                entry_price: req.entry_price,
+                mark_price,
+            });
+        }
+        let notional = abs_decimal(req.base_qty) * mark_price;
         let required_margin = notional / leverage_decimal(req.leverage_bps);
 
-        let (equity, used_margin) = self.equity_and_margin(account, Some((&req.market, req.mark_price)));
+        let (equity, used_margin) = self.equity_and_margin_with_prices(account, marks)?;
         let free_collateral = equity - used_margin;
 
         if free_collateral < required_margin {
-            return Err(RiskError::InsufficientCollateral);
+            return Err(RiskError::InsufficientCollateral {
+                required: required_margin,
+                available: free_collateral,
+            });
         }
 
         let position = Position {
@@ -71,8 +209,8 @@ impl RiskEngine {
             .positions
             .insert(req.market.clone(), position.clone());
 
-        let (_, used_margin_after) = self.equity_and_margin(account, Some((&req.market, req.mark_price)));
-        let free_after = equity - used_margin_after;
+        let (equity_after, used_margin_after) = self.equity_and_margin_with_prices(account, marks)?;
+        let free_after = equity_after - used_margin_after;
 
         Ok(PositionOutcome {
             position,
@@ -87,16 +225,40 @@ impl RiskEngine {
         market: &str,
         exit_price: Decimal,
     ) -> Result<Decimal, RiskError> {
+        self.ensure_trading(market)?;
+        self.ensure_not_liquidating(account.id, market)?;
         let position = account
             .positions
             .remove(market)
-            .ok_or(RiskError::PositionNotFound)?;
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
 
         let pnl = position_pnl(&position, exit_price);
         account.collateral += pnl;
         Ok(pnl)
     }
 
+    /// Closes the account's position in a settled market at its settlement price, without a
+    /// fee. Losses beyond the collateral are written off.
+    pub fn settle_position(&self, account: &mut Account, market: &str) -> Result<Decimal, RiskError> {
+        let settlement_price = self
+            .market(market)
+            .filter(|config| config.status == MarketStatus::Settled)
+            .and_then(|config| config.settlement_price)
+            .ok_or_else(|| RiskError::MarketNotFound(market.to_string()))?;
+        let position = account
+            .positions
+            .remove(market)
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
+
+        let pnl = position_pnl(&position, settlement_price);
+        account.collateral += pnl;
+        if account.collateral < Decimal::ZERO {
+            account.collateral = Decimal::ZERO;
+        }
+        Ok(pnl)
+    }
+
+    #[cfg_attr(not(feature = "solana"), allow(dead_code))]
     pub fn force_liquidate(
         &self,
         account: &mut Account,
@@ -106,7 +268,7 @@ impl RiskEngine {
         let position = account
             .positions
             .remove(market)
-            .ok_or(RiskError::PositionNotFound)?;
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
 
         let pnl = position_pnl(&position, exit_price);
         let notional = abs_decimal(position.base_qty) * exit_price;
@@ -126,36 +288,47 @@ impl RiskEngine {
         account: &mut Account,
         market: &str,
         new_leverage_bps: u32,
-        mark_price: Decimal,
+        marks: &HashMap<String, Decimal>,
     ) -> Result<(), RiskError> {
         let market_config = self
-            .markets
-            .get(market)
-            .ok_or(RiskError::MarketNotFound)?;
+            .market(market)
+            .ok_or_else(|| RiskError::MarketNotFound(market.to_string()))?;
 
         if new_leverage_bps == 0 || new_leverage_bps > market_config.max_leverage_bps {
-            return Err(RiskError::InvalidLeverage);
+            return Err(RiskError::InvalidLeverage {
+                requested_bps: new_leverage_bps,
+                max_bps: market_config.max_leverage_bps,
+            });
         }
 
         let position_snapshot = account
             .positions
             .get(market)
             .cloned()
-            .ok_or(RiskError::PositionNotFound)?;
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
+        self.ensure_not_liquidating(account.id, market)?;
+        if new_leverage_bps > position_snapshot.leverage_bps {
+            self.ensure_not_frozen(market)?;
+        } else {
+            self.ensure_trading(market)?;
+        }
 
-        let notional = abs_decimal(position_snapshot.base_qty) * mark_price;
+        let notional = abs_decimal(position_snapshot.base_qty) * mark_for(marks, market)?;
         let required_margin = notional / leverage_decimal(new_leverage_bps);
-        let (equity, used_margin) = self.equity_and_margin(account, Some((market, mark_price)));
+        let (equity, used_margin) = self.equity_and_margin_with_prices(account, marks)?;
         let free_collateral = equity - used_margin;
 
         if free_collateral < required_margin {
-            return Err(RiskError::InsufficientCollateral);
+            return Err(RiskError::InsufficientCollateral {
+                required: required_margin,
+                available: free_collateral,
+            });
         }
 
         let position = account
             .positions
             .get_mut(market)
-            .ok_or(RiskError::PositionNotFound)?;
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
         position.leverage_bps = new_leverage_bps;
         Ok(())
     }
@@ -163,21 +336,21 @@ impl RiskEngine {
     pub fn check_risk(
         &self,
         account: &Account,
-        req: RiskCheckRequest,
+        marks: &HashMap<String, Decimal>,
     ) -> Result<RiskCheckResponse, RiskError> {
-        let (equity, used_margin) = self.equity_and_margin_with_prices(account, &req.mark_prices)?;
+        let (equity, used_margin) = self.equity_and_margin_with_prices(account, marks)?;
         let free_collateral = equity - used_margin;
         let mut liquidatable = Vec::new();
+        let mut mark_prices = HashMap::new();
 
         for (symbol, position) in &account.positions {
-            let mark_price = req
-                .mark_prices
+            let mark_price = marks
                 .get(symbol)
                 .ok_or_else(|| RiskError::MissingMarkPrice(symbol.clone()))?;
+            mark_prices.insert(symbol.clone(), *mark_price);
             let market = self
-                .markets
-                .get(symbol)
-                .ok_or(RiskError::MarketNotFound)?;
+                .market(symbol)
+                .ok_or_else(|| RiskError::MarketNotFound(symbol.clone()))?;
             let notional = abs_decimal(position.base_qty) * *mark_price;
             let maintenance = notional * bps_decimal(market.maintenance_margin_bps);
             let pnl = position_pnl(position, *mark_price);
@@ -192,9 +365,61 @@ impl RiskEngine {
             used_margin,
             free_collateral,
             liquidatable_positions: liquidatable,
+            mark_prices,
         })
     }
 
+    /// Health of the account's position in `market` at its mark, by the same rule as
+    /// [`RiskEngine::check_risk`].
+    pub fn position_health(
+        &self,
+        account: &Account,
+        market: &str,
+        marks: &HashMap<String, Decimal>,
+    ) -> Result<PositionHealth, RiskError> {
+        let position = account
+            .positions
+            .get(market)
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
+        let market_config = self
+            .market(market)
+            .ok_or_else(|| RiskError::MarketNotFound(market.to_string()))?;
+        let mark_price = mark_for(marks, market)?;
+
+        let notional = abs_decimal(position.base_qty) * mark_price;
+        let maintenance_margin = notional * bps_decimal(market_config.maintenance_margin_bps);
+        let equity = account.collateral + position_pnl(position, mark_price);
+        let health_ratio = (!maintenance_margin.is_zero()).then(|| (equity / maintenance_margin).round_dp(4));
+        let liquidation_price = self.liquidation_price(account, market)?.round_dp(6);
+        let distance = match position.side {
+            Side::Long => mark_price - liquidation_price,
+            Side::Short => liquidation_price - mark_price,
+        };
+        let distance_bps = if mark_price.is_zero() {
+            Decimal::ZERO
+        } else {
+            (distance / mark_price * Decimal::from(BPS_DIVISOR)).round_dp(2)
+        };
+
+        Ok(PositionHealth {
+            account_id: account.id,
+            market: market.to_string(),
+            side: position.side.clone(),
+            base_qty: position.base_qty,
+            mark_price,
+            notional,
+            maintenance_margin,
+            equity,
+            health_ratio,
+            margin_deficit: (maintenance_margin - equity).max(Decimal::ZERO),
+            liquidation_price,
+            distance_bps,
+            liquidatable: equity < maintenance_margin,
+            liquidation_pending: self.is_liquidating(account.id, market),
+        })
+    }
+
+    /// Mark at which the position's equity meets its maintenance margin.
     pub fn liquidation_price(
         &self,
         account: &Account,
@@ -203,11 +428,10 @@ impl RiskEngine {
         let position = account
             .positions
             .get(market)
-            .ok_or(RiskError::PositionNotFound)?;
+            .ok_or_else(|| RiskError::PositionNotFound(market.to_string()))?;
         let market_config = self
-            .markets
-            .get(market)
-            .ok_or(RiskError::MarketNotFound)?;
+            .market(market)
+            .ok_or_else(|| RiskError::MarketNotFound(market.to_string()))?;
 
         let maintenance_rate = bps_decimal(market_config.maintenance_margin_bps);
         let qty = abs_decimal(position.base_qty);
@@ -219,41 +443,13 @@ impl RiskEngine {
         let entry = position.entry_price;
 
         let price = match position.side {
-            Side::Long => (collateral / qty) + (entry * (Decimal::ONE - maintenance_rate)),
-            Side::Short => (entry * (Decimal::ONE + maintenance_rate)) - (collateral / qty),
+            Side::Long => (entry - collateral / qty) / (Decimal::ONE - maintenance_rate),
+            Side::Short => (entry + collateral / qty) / (Decimal::ONE + maintenance_rate),
         };
 
         Ok(price.max(Decimal::ZERO))
     }
 
-    fn equity_and_margin(
-        &self,
-        account: &Account,
-        price_override: Option<(&str, Decimal)>,
-    ) -> (Decimal, Decimal) {
-        let mut equity = account.collateral;
-        let mut used_margin = Decimal::ZERO;
-
-        for (symbol, position) in &account.positions {
-            let mark_price = if let Some((override_symbol, price)) = price_override {
-                if override_symbol == symbol {
-                    price
-                } else {
-                    position.entry_price
-                }
-            } else {
-                position.entry_price
-            };
-
-            equity += position_pnl(position, mark_price);
-            let notional = abs_decimal(position.base_qty) * mark_price;
-            let margin = notional / leverage_decimal(position.leverage_bps);
-            used_margin += margin;
-        }
-
-        (equity, used_margin)
-    }
-
     fn equity_and_margin_with_prices(
         &self,
         account: &Account,
@@ -276,6 +472,13 @@ impl RiskEngine {
     }
 }
 
+fn mark_for(marks: &HashMap<String, Decimal>, market: &str) -> Result<Decimal, RiskError> {
+    marks
+        .get(market)
+        .copied()
+        .ok_or_else(|| RiskError::MissingMarkPrice(market.to_string()))
+}
+
 fn abs_decimal(value: Decimal) -> Decimal {
     if value.is_sign_negative() {
         value.abs()
@@ -300,6 +503,7 @@ fn position_pnl(position: &Position, mark_price: Decimal) -> Decimal {
     }
 }
 
+#[cfg_attr(not(feature = "solana"), allow(dead_code))]
 pub fn liquidation_fee(notional: Decimal) -> Decimal {
     notional * (Decimal::from_i64(LIQUIDATION_FEE_BPS).unwrap() / Decimal::from_i64(BPS_DIVISOR).unwrap())
 }
@@ -310,66 +514,260 @@ pub fn default_markets() -> Vec<MarketConfig> {
     let high_oi = Decimal::from_i64(5_000_000).unwrap();
     let standard_oi = Decimal::from_i64(1_000_000).unwrap();
 
-    let mut push_market = |symbol: &str, max_lev: u32, im_bps: u32, mm_bps: u32, oi: Decimal| {
+    // (tick size, lot size) by price band, so ticks stay near 1 bp of price and lots near $1-$100.
+    let large = (Decimal::new(1, 1), Decimal::new(1, 3));
+    let high = (Decimal::new(1, 2), Decimal::new(1, 2));
+    let mid = (Decimal::new(1, 3), Decimal::new(1, 1));
+    let low = (Decimal::new(1, 4), Decimal::ONE);
+    let micro = (Decimal::new(1, 5), Decimal::from(10));
+    let nano = (Decimal::new(1, 8), Decimal::from(1000));
+
+    let mut push_market = |symbol: &str, max_lev: u32, im_bps: u32, mm_bps: u32, oi: Decimal, sizes: (Decimal, Decimal)| {
         markets.push(MarketConfig {
             symbol: symbol.to_string(),
             max_leverage_bps: max_lev,
             initial_margin_bps: im_bps,
             maintenance_margin_bps: mm_bps,
             max_open_interest: oi,
+            tick_size: sizes.0,
+            lot_size: sizes.1,
+            status: MarketStatus::Active,
+            settlement_price: None,
         });
     };
 
-    push_market("BTC", 1_000_000, 100, 35, high_oi);
-    push_market("ETH", 800_000, 125, 40, high_oi);
-    push_market("SOL", 500_000, 200, 50, standard_oi);
-    push_market("BNB", 500_000, 200, 50, standard_oi);
-    push_market("XRP", 300_000, 300, 60, standard_oi);
-    push_market("ADA", 300_000, 300, 60, standard_oi);
-    push_market("DOGE", 300_000, 300, 60, standard_oi);
-    push_market("AVAX", 300_000, 300, 60, standard_oi);
-    push_market("MATIC", 300_000, 300, 60, standard_oi);
-    push_market("DOT", 300_000, 300, 60, standard_oi);
-    push_market("LINK", 300_000, 300, 60, standard_oi);
-    push_market("LTC", 250_000, 350, 70, standard_oi);
-    push_market("BCH", 250_000, 350, 70, standard_oi);
-    push_market("ATOM", 250_000, 350, 70, standard_oi);
-    push_market("TRX", 250_000, 350, 70, standard_oi);
-    push_market("NEAR", 200_000, 400, 80, standard_oi);
-    push_market("OP", 200_000, 400, 80, standard_oi);
-    push_market("ARB", 200_000, 400, 80, standard_oi);
-    push_market("APT", 200_000, 400, 80, standard_oi);
-    push_market("SUI", 200_000, 400, 80, standard_oi);
-    push_market("INJ", 200_000, 400, 80, standard_oi);
-    push_market("FIL", 200_000, 400, 80, standard_oi);
-    push_market("ICP", 200_000, 400, 80, standard_oi);
-    push_market("ETC", 200_000, 400, 80, standard_oi);
-    push_market("XLM", 200_000, 400, 80, standard_oi);
-    push_market("HBAR", 200_000, 400, 80, standard_oi);
-    push_market("UNI", 200_000, 450, 90, standard_oi);
-    push_market("AAVE", 200_000, 450, 90, standard_oi);
-    push_market("MKR", 200_000, 450, 90, standard_oi);
-    push_market("COMP", 200_000, 450, 90, standard_oi);
-    push_market("SNX", 150_000, 500, 100, standard_oi);
-    push_market("GMX", 150_000, 500, 100, standard_oi);
-    push_market("LDO", 150_000, 500, 100, standard_oi);
-    push_market("RUNE", 150_000, 500, 100, standard_oi);
-    push_market("KAS", 150_000, 550, 110, standard_oi);
-    push_market("STX", 150_000, 550, 110, standard_oi);
-    push_market("IMX", 150_000, 550, 110, standard_oi);
-    push_market("GRT", 150_000, 550, 110, standard_oi);
-    push_market("ALGO", 150_000, 550, 110, standard_oi);
-    push_market("VET", 150_000, 550, 110, standard_oi);
-    push_market("XTZ", 150_000, 550, 110, standard_oi);
-    push_market("EOS", 150_000, 550, 110, standard_oi);
-    push_market("KAVA", 120_000, 600, 120, standard_oi);
-    push_market("RSR", 120_000, 600, 120, standard_oi);
-    push_market("SEI", 120_000, 600, 120, standard_oi);
-    push_market("JUP", 120_000, 600, 120, standard_oi);
-    push_market("TIA", 120_000, 600, 120, standard_oi);
-    push_market("TAO", 120_000, 650, 130, standard_oi);
-    push_market("WIF", 100_000, 700, 140, standard_oi);
-    push_market("PEPE", 100_000, 700, 140, standard_oi);
+    push_market("BTC", 1_000_000, 100, 35, high_oi, large);
+    push_market("ETH", 800_000, 125, 40, high_oi, large);
+    push_market("SOL", 500_000, 200, 50, standard_oi, high);
+    push_market("BNB", 500_000, 200, 50, standard_oi, high);
+    push_market("XRP", 300_000, 300, 60, standard_oi, low);
+    push_market("ADA", 300_000, 300, 60, standard_oi, micro);
+    push_market("DOGE", 300_000, 300, 60, standard_oi, micro);
+    push_market("AVAX", 300_000, 300, 60, standard_oi, mid);
+    push_market("MATIC", 300_000, 300, 60, standard_oi, micro);
+    push_market("DOT", 300_000, 300, 60, standard_oi, low);
+    push_market("LINK", 300_000, 300, 60, standard_oi, mid);
+    push_market("LTC", 250_000, 350, 70, standard_oi, high);
+    push_market("BCH", 250_000, 350, 70, standard_oi, high);
+    push_market("ATOM", 250_000, 350, 70, standard_oi, low);
+    push_market("TRX", 250_000, 350, 70, standard_oi, micro);
+    push_market("NEAR", 200_000, 400, 80, standard_oi, low);
+    push_market("OP", 200_000, 400, 80, standard_oi, low);
+    push_market("ARB", 200_000, 400, 80, standard_oi, micro);
+    push_market("APT", 200_000, 400, 80, standard_oi, low);
+    push_market("SUI", 200_000, 400, 80, standard_oi, low);
+    push_market("INJ", 200_000, 400, 80, standard_oi, mid);
+    push_market("FIL", 200_000, 400, 80, standard_oi, low);
+    push_market("ICP", 200_000, 400, 80, standard_oi, mid);
+    push_market("ETC", 200_000, 400, 80, standard_oi, mid);
+    push_market("XLM", 200_000, 400, 80, standard_oi, micro);
+    push_market("HBAR", 200_000, 400, 80, standard_oi, micro);
+    push_market("UNI", 200_000, 450, 90, standard_oi, mid);
+    push_market("AAVE", 200_000, 450, 90, standard_oi, high);
+    push_market("MKR", 200_000, 450, 90, standard_oi, large);
+    push_market("COMP", 200_000, 450, 90, standard_oi, high);
+    push_market("SNX", 150_000, 500, 100, standard_oi, low);
+    push_market("GMX", 150_000, 500, 100, standard_oi, mid);
+    push_market("LDO", 150_000, 500, 100, standard_oi, low);
+    push_market("RUNE", 150_000, 500, 100, standard_oi, low);
+    push_market("KAS", 150_000, 550, 110, standard_oi, micro);
+    push_market("STX", 150_000, 550, 110, standard_oi, low);
+    push_market("IMX", 150_000, 550, 110, standard_oi, low);
+    push_market("GRT", 150_000, 550, 110, standard_oi, micro);
+    push_market("ALGO", 150_000, 550, 110, standard_oi, micro);
+    push_market("VET", 150_000, 550, 110, standard_oi, micro);
+    push_market("XTZ", 150_000, 550, 110, standard_oi, micro);
+    push_market("EOS", 150_000, 550, 110, standard_oi, micro);
+    push_market("KAVA", 120_000, 600, 120, standard_oi, micro);
+    push_market("RSR", 120_000, 600, 120, standard_oi, nano);
+    push_market("SEI", 120_000, 600, 120, standard_oi, micro);
+    push_market("JUP", 120_000, 600, 120, standard_oi, micro);
+    push_market("TIA", 120_000, 600, 120, standard_oi, low);
+    push_market("TAO", 120_000, 650, 130, standard_oi, high);
+    push_market("WIF", 100_000, 700, 140, standard_oi, low);
+    push_market("PEPE", 100_000, 700, 140, standard_oi, nano);
 
     markets
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(status: MarketStatus) -> RiskEngine {
+        let btc = default_markets()
+            .into_iter()
+            .find(|market| market.symbol == "BTC")
+            .unwrap();
+        let settlement_price = (status == MarketStatus::Settled).then(|| Decimal::from(50_000));
+        RiskEngine::new(vec![MarketConfig {
+            status,
+            settlement_price,
+            ..btc
+        }])
+    }
+
+    fn account(collateral: i64) -> Account {
+        Account {
+            id: Uuid::new_v4(),
+            owner: "owner".to_string(),
+            account_state: None,
+            collateral: Decimal::from(collateral),
+            positions: HashMap::new(),
+        }
+    }
+
+    fn marks(price: Decimal) -> HashMap<String, Decimal> {
+        HashMap::from([("BTC".to_string(), price)])
+    }
+
+    fn long(entry_price: i64, leverage_bps: u32) -> OpenPositionRequest {
+        OpenPositionRequest {
+            market: "BTC".to_string(),
+            side: Side::Long,
+            base_qty: Decimal::ONE,
+            entry_price: Decimal::from(entry_price),
+            leverage_bps,
+            position_account: None,
+        }
+    }
+
+    /// An account with 5000 of collateral holding one BTC long from 60000 at 50x.
+    fn holder(risk: &RiskEngine) -> Account {
+        let mut account = account(5_000);
+        let open = RiskEngine::new(vec![MarketConfig {
+            status: MarketStatus::Active,
+            settlement_price: None,
+            ..risk.market("BTC").unwrap()
+        }]);
+        open.open_position(&mut account, long(60_000, 500_000), &marks(Decimal::from(60_000)))
+            .unwrap();
+        account
+    }
+
+    #[test]
+    fn opens_need_free_collateral_for_their_margin_at_the_mark() {
+        let risk = engine(MarketStatus::Active);
+        let mut account = account(1_000);
+        let err = risk
+            .open_position(&mut account, long(60_000, 100_000), &marks(Decimal::from(60_000)))
+            .unwrap_err();
+        assert!(matches!(err, RiskError::InsufficientCollateral { required, .. } if required == Decimal::from(6_000)));
+        assert!(account.positions.is_empty());
+
+        let outcome = risk
+            .open_position(&mut account, long(60_000, 1_000_000), &marks(Decimal::from(60_000)))
+            .unwrap();
+        assert_eq!(outcome.used_margin, Decimal::from(600));
+        assert_eq!(outcome.free_collateral, Decimal::from(400));
+    }
+
+    #[test]
+    fn post_only_markets_refuse_entries_that_cross_the_mark() {
+        let risk = engine(MarketStatus::PostOnly);
+        let mark = marks(Decimal::from(60_000));
+        let err = risk
+            .open_position(&mut account(1_000), long(60_001, 1_000_000), &mark)
+            .unwrap_err();
+        assert!(matches!(err, RiskError::PostOnlyWouldCross { .. }));
+        risk.open_position(&mut account(1_000), long(59_999, 1_000_000), &mark)
+            .unwrap();
+    }
+
+    #[test]
+    fn market_status_limits_what_positions_can_do() {
+        let mark = marks(Decimal::from(60_000));
+
+        let reduce_only = engine(MarketStatus::ReduceOnly);
+        let err = reduce_only
+            .open_position(&mut account(1_000), long(60_000, 1_000_000), &mark)
+            .unwrap_err();
+        assert!(matches!(err, RiskError::MarketReduceOnly(_)));
+        let mut account = holder(&reduce_only);
+        let err = reduce_only
+            .adjust_leverage(&mut account, "BTC", 1_000_000, &mark)
+            .unwrap_err();
+        assert!(matches!(err, RiskError::MarketReduceOnly(_)));
+        reduce_only.adjust_leverage(&mut account, "BTC", 250_000, &mark).unwrap();
+        reduce_only
+            .close_position(&mut account, "BTC", Decimal::from(60_000))
+            .unwrap();
+
+        let paused = engine(MarketStatus::Paused);
+        let mut account = holder(&paused);
+        let err = paused
+            .close_position(&mut account, "BTC", Decimal::from(60_000))
+            .unwrap_err();
+        assert!(matches!(err, RiskError::MarketPaused(_)));
+        assert!(!paused.allows_liquidation("BTC"));
+    }
+
+    #[test]
+    fn the_program_pause_stops_new_exposure_but_not_closes() {
+        let risk = engine(MarketStatus::Active);
+        let mut holding = holder(&risk);
+        risk.set_paused(true);
+        let mark = marks(Decimal::from(60_000));
+        let err = risk
+            .adjust_leverage(&mut holding, "BTC", 1_000_000, &mark)
+            .unwrap_err();
+        assert!(matches!(err, RiskError::ProgramPaused));
+        let err = risk
+            .open_position(&mut account(1_000), long(60_000, 1_000_000), &mark)
+            .unwrap_err();
+        assert!(matches!(err, RiskError::ProgramPaused));
+        risk.close_position(&mut holding, "BTC", Decimal::from(60_000)).unwrap();
+    }
+
+    #[test]
+    fn pending_liquidations_block_closes_and_leverage_changes() {
+        let risk = engine(MarketStatus::Active);
+        let mut account = holder(&risk);
+        risk.mark_liquidating(account.id, "BTC");
+        let err = risk
+            .close_position(&mut account, "BTC", Decimal::from(60_000))
+            .unwrap_err();
+        assert!(matches!(err, RiskError::LiquidationPending(_)));
+        let err = risk
+            .adjust_leverage(&mut account, "BTC", 250_000, &marks(Decimal::from(60_000)))
+            .unwrap_err();
+        assert!(matches!(err, RiskError::LiquidationPending(_)));
+
+        risk.clear_liquidating(account.id, "BTC");
+        risk.close_position(&mut account, "BTC", Decimal::from(60_000)).unwrap();
+    }
+
+    #[test]
+    fn settlement_closes_at_the_settlement_price_and_writes_off_the_shortfall() {
+        let active = engine(MarketStatus::Active);
+        let mut account = holder(&active);
+        let err = active.settle_position(&mut account, "BTC").unwrap_err();
+        assert!(matches!(err, RiskError::MarketNotFound(_)));
+
+        let settled = engine(MarketStatus::Settled);
+        let pnl = settled.settle_position(&mut account, "BTC").unwrap();
+        assert_eq!(pnl, Decimal::from(-10_000));
+        assert_eq!(account.collateral, Decimal::ZERO);
+        assert!(account.positions.is_empty());
+    }
+
+    #[test]
+    fn positions_become_liquidatable_past_their_liquidation_price() {
+        let risk = engine(MarketStatus::Active);
+        let account = holder(&risk);
+        // (60000 - 5000) / (1 - 0.0035)
+        let price = risk.liquidation_price(&account, "BTC").unwrap();
+        assert_eq!(price.round_dp(2), Decimal::new(5_519_318, 2));
+
+        let above = risk.check_risk(&account, &marks(price + Decimal::ONE)).unwrap();
+        assert!(above.liquidatable_positions.is_empty());
+        let below = risk.check_risk(&account, &marks(price - Decimal::ONE)).unwrap();
+        assert_eq!(below.liquidatable_positions, vec!["BTC".to_string()]);
+
+        let health = risk
+            .position_health(&account, "BTC", &marks(price - Decimal::ONE))
+            .unwrap();
+        assert!(health.liquidatable);
+        assert!(health.distance_bps < Decimal::ZERO);
+    }
+}
@@ -1,21 +1,188 @@
-use crate::errors::AppError;
+use crate::admin::{
+    AdminKeys, AdminMarket, AdminMarketResponse, CreateMarketRequest, MarketStatusRequest, ProgramConfig,
+    SettleMarketRequest, SettleMarketResponse, UpdateMarketRequest,
+};
+use crate::cache::CacheStats;
+use crate::candles::CandlesResponse;
+use crate::errors::{AppError, ErrorResponse, RiskError};
+use crate::events::Event;
+use crate::idempotency::idempotency_layer;
+use crate::liquidation_queue::{CrankPlan, LiquidationQueue, PlannedLiquidation};
+use crate::mark_price::MarkPrice;
 use crate::models::{
-    AdjustLeverageRequest, ClosePositionRequest, CreateAccountRequest, DepositRequest, OpenPositionRequest,
-    RiskCheckRequest, SetCollateralRequest, WithdrawRequest,
+    Account, AdjustLeverageRequest, ClosePositionRequest, CreateAccountRequest, DepositRequest, MarketConfig,
+    MarketStatus, OpenPositionRequest, Position, PositionHealth, PositionOutcome, PublishedMarket, PublisherStatus,
+    RiskCheckRequest, RiskCheckResponse, SetCollateralRequest, Side, Simulation, WithdrawRequest,
+};
+use crate::price_feed::{IndexMethod, IndexPrice, OrderBook, OrderLevel, SourceContribution, SourceStatus, Trade};
+use crate::program_admin::{
+    GuardianRequest, PauseRequest, PauseResponse, ProgramStatus, ProposeAdminRequest, SignatureResponse,
 };
+use crate::settlement::{SettledPosition, SettlementFailure, SettlementReport};
 use crate::state::AppState;
-use axum::{extract::Path, extract::Query, extract::State, routing::get, routing::post, Json, Router};
+use crate::validation::{FieldError, ValidatedJson};
+use axum::{
+    extract::Path, extract::Query, extract::State, http::header, middleware, response::Html, routing::get,
+    routing::post, Json, Router,
+};
 use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
 use uuid::Uuid;
 
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Singularity Perps API"),
+    paths(
+        health,
+        cache_metrics,
+        crate::ws::ws_handler,
+        list_markets,
+        get_prices,
+        crate::price_stream::stream_prices,
+        get_index_price,
+        get_mark_price,
+        get_oracle_publisher,
+        get_orderbook,
+        get_trades,
+        crate::candles::get_candles,
+        get_usdc_balance,
+        create_account,
+        get_account,
+        deposit,
+        withdraw,
+        set_collateral,
+        open_position,
+        close_position,
+        adjust_leverage,
+        risk_check,
+        crate::liquidation_queue::get_queue,
+        crate::liquidation_queue::get_plan,
+        crate::admin::list_markets,
+        crate::admin::create_market,
+        crate::admin::update_market,
+        crate::admin::pause_market,
+        crate::admin::resume_market,
+        crate::admin::set_market_status,
+        crate::admin::settle_market,
+        crate::admin::migrate_market,
+        crate::admin::set_oracle_authority,
+        crate::program_admin::get_program,
+        crate::program_admin::set_pause,
+        crate::program_admin::initialize_config,
+        crate::program_admin::set_guardian,
+        crate::program_admin::propose_admin,
+        crate::program_admin::accept_admin,
+    ),
+    components(schemas(
+        Account,
+        AdjustLeverageRequest,
+        AdminMarket,
+        AdminMarketResponse,
+        CacheStats,
+        CandlesResponse,
+        ClosePositionRequest,
+        CrankPlan,
+        CreateAccountRequest,
+        CreateMarketRequest,
+        DepositRequest,
+        ErrorResponse,
+        FieldError,
+        GuardianRequest,
+        HealthResponse,
+        IndexMethod,
+        IndexPrice,
+        LiquidationQueue,
+        MarketConfig,
+        MarketStatus,
+        MarketStatusRequest,
+        MarkPrice,
+        OpenPositionRequest,
+        OrderBook,
+        OrderLevel,
+        PauseRequest,
+        PauseResponse,
+        PlannedLiquidation,
+        Position,
+        PositionHealth,
+        PositionOutcome,
+        ProgramConfig,
+        ProgramStatus,
+        ProposeAdminRequest,
+        PublishedMarket,
+        PublisherStatus,
+        RiskCheckRequest,
+        RiskCheckResponse,
+        SetCollateralRequest,
+        SettledPosition,
+        SettlementFailure,
+        SettlementReport,
+        SettleMarketRequest,
+        SettleMarketResponse,
+        Side,
+        SignatureResponse,
+        Simulation,
+        SourceContribution,
+        SourceStatus,
+        Trade,
+        UpdateMarketRequest,
+        WalletBalanceResponse,
+        WithdrawRequest,
+    )),
+    modifiers(&AdminKeyScheme)
+)]
+pub struct ApiDoc;
+
+/// Documents the `Authorization: Bearer <ADMIN_API_KEY>` the admin routes expect; the
+/// pause routes also take `GUARDIAN_API_KEY`.
+struct AdminKeyScheme;
+
+impl Modify for AdminKeyScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("admin_key", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+    }
+}
+
 pub fn router(state: Arc<AppState>) -> Router {
+    let admin = Router::new()
+        .route("/admin/markets", get(crate::admin::list_markets).post(crate::admin::create_market))
+        .route("/admin/markets/:symbol", post(crate::admin::update_market))
+        .route("/admin/markets/:symbol/pause", post(crate::admin::pause_market))
+        .route("/admin/markets/:symbol/resume", post(crate::admin::resume_market))
+        .route("/admin/markets/:symbol/status", post(crate::admin::set_market_status))
+        .route("/admin/markets/:symbol/settle", post(crate::admin::settle_market))
+        .route("/admin/markets/:symbol/migrate", post(crate::admin::migrate_market))
+        .route("/admin/markets/:symbol/oracle-authority", post(crate::admin::set_oracle_authority))
+        .route("/admin/program/initialize", post(crate::program_admin::initialize_config))
+        .route("/admin/program/guardian", post(crate::program_admin::set_guardian))
+        .route("/admin/program/propose-admin", post(crate::program_admin::propose_admin))
+        .route("/admin/program/accept-admin", post(crate::program_admin::accept_admin))
+        .route_layer(middleware::from_fn_with_state(AdminKeys::from_env(), crate::admin::require_admin));
+    // The guardian key is only good for these.
+    let guardian = Router::new()
+        .route("/admin/program", get(crate::program_admin::get_program))
+        .route("/admin/program/pause", post(crate::program_admin::set_pause))
+        .route_layer(middleware::from_fn_with_state(AdminKeys::from_env(), crate::admin::require_guardian));
+
     Router::new()
         .route("/health", get(health))
+        .route("/metrics/cache", get(cache_metrics))
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(docs))
+        .route("/docs/api.js", get(docs_script))
+        .route("/ws", get(crate::ws::ws_handler))
         .route("/markets", get(list_markets))
         .route("/prices", get(get_prices))
+        .route("/prices/stream", get(crate::price_stream::stream_prices))
+        .route("/prices/index/:symbol", get(get_index_price))
+        .route("/prices/mark/:symbol", get(get_mark_price))
+        .route("/oracle/publisher", get(get_oracle_publisher))
         .route("/orderbook", get(get_orderbook))
         .route("/trades", get(get_trades))
+        .route("/candles", get(crate::candles::get_candles))
         .route("/wallet/usdc", get(get_usdc_balance))
         .route("/accounts", post(create_account))
         .route("/accounts/:id", get(get_account))
@@ -26,31 +193,73 @@ pub fn router(state: Arc<AppState>) -> Router {
         .route("/accounts/:id/positions/:market/close", post(close_position))
         .route("/accounts/:id/positions/:market/adjust-leverage", post(adjust_leverage))
         .route("/accounts/:id/risk-check", post(risk_check))
+        .route("/liquidations/queue", get(crate::liquidation_queue::get_queue))
+        .route("/liquidations/plan", get(crate::liquidation_queue::get_plan))
+        .merge(admin)
+        .merge(guardian)
+        .layer(middleware::from_fn_with_state(state.clone(), idempotency_layer))
+        .layer(middleware::from_fn(crate::request_id::request_id_layer))
         .with_state(state)
 }
 
-#[derive(serde::Serialize)]
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+async fn docs() -> Html<&'static str> {
+    Html(include_str!("../docs/index.html"))
+}
+
+/// The docs page's renderer, bundled so the page loads nothing from third-party hosts.
+async fn docs_script() -> ([(header::HeaderName, &'static str); 1], &'static str) {
+    ([(header::CONTENT_TYPE, "text/javascript; charset=utf-8")], include_str!("../docs/api.js"))
+}
+
+#[derive(serde::Serialize, ToSchema)]
 struct HealthResponse {
     status: &'static str,
 }
 
+#[utoipa::path(get, path = "/health", tag = "system", responses((status = 200, body = HealthResponse)))]
 async fn health() -> Json<HealthResponse> {
     Json(HealthResponse { status: "ok" })
 }
 
-async fn list_markets(State(state): State<Arc<AppState>>) -> Json<Vec<crate::models::MarketConfig>> {
+#[utoipa::path(
+    get,
+    path = "/metrics/cache",
+    tag = "system",
+    responses((status = 200, description = "Market data cache counters by endpoint", body = HashMap<String, CacheStats>))
+)]
+async fn cache_metrics(State(state): State<Arc<AppState>>) -> Json<BTreeMap<&'static str, CacheStats>> {
+    Json(state.prices.cache_stats())
+}
+
+#[utoipa::path(get, path = "/markets", tag = "markets", responses((status = 200, body = Vec<MarketConfig>)))]
+async fn list_markets(State(state): State<Arc<AppState>>) -> Json<Vec<MarketConfig>> {
     Json(state.risk.markets())
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, IntoParams)]
 struct PricesQuery {
+    /// Comma separated symbols, defaults to every listed market.
     symbols: Option<String>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/prices",
+    tag = "markets",
+    params(PricesQuery),
+    responses(
+        (status = 200, description = "Latest price per symbol", body = HashMap<String, String>),
+        (status = 502, body = ErrorResponse)
+    )
+)]
 async fn get_prices(
     State(state): State<Arc<AppState>>,
     Query(params): Query<PricesQuery>,
-) -> Result<Json<std::collections::HashMap<String, rust_decimal::Decimal>>, AppError> {
+) -> Result<Json<HashMap<String, Decimal>>, AppError> {
     let symbols: Vec<String> = if let Some(symbols) = params.symbols {
         symbols
             .split(',')
@@ -66,42 +275,134 @@ async fn get_prices(
             .collect()
     };
 
-    let prices = crate::price_feed::fetch_prices(&symbols).await?;
-    Ok(Json(prices))
+    Ok(Json(state.prices.fetch_prices(&symbols).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/prices/index/{symbol}",
+    tag = "markets",
+    params(("symbol" = String, Path, description = "Market symbol, e.g. BTC")),
+    responses(
+        (status = 200, description = "Index price with each provider's contribution", body = IndexPrice),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+async fn get_index_price(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+) -> Result<Json<IndexPrice>, AppError> {
+    let symbol = symbol.to_uppercase();
+    if state.risk.market(&symbol).is_none() {
+        return Err(RiskError::MarketNotFound(symbol).into());
+    }
+    let index = state.prices.index_price(&symbol).await?;
+    Ok(Json(index))
+}
+
+#[utoipa::path(
+    get,
+    path = "/prices/mark/{symbol}",
+    tag = "markets",
+    params(("symbol" = String, Path, description = "Market symbol, e.g. BTC")),
+    responses(
+        (status = 200, description = "Mark price used for margin and liquidation checks", body = MarkPrice),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse)
+    )
+)]
+async fn get_mark_price(
+    State(state): State<Arc<AppState>>,
+    Path(symbol): Path<String>,
+) -> Result<Json<MarkPrice>, AppError> {
+    let symbol = symbol.to_uppercase();
+    if state.risk.market(&symbol).is_none() {
+        return Err(RiskError::MarketNotFound(symbol).into());
+    }
+    let index = state.prices.index_price(&symbol).await?;
+    Ok(Json(state.marks.mark(&symbol, index.price)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/oracle/publisher",
+    tag = "markets",
+    responses(
+        (status = 200, description = "The oracle publisher's cadence, thresholds and latest pushes; `null` when it isn't running", body = Option<PublisherStatus>)
+    )
+)]
+async fn get_oracle_publisher(State(state): State<Arc<AppState>>) -> Json<Option<PublisherStatus>> {
+    let mut status = state.publisher.lock().unwrap().clone();
+    // A publisher stuck mid-cycle stops reporting, so an old last read counts against it too.
+    if let Some(status) = status.as_mut() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or_default();
+        let overdue = status
+            .last_cycle_at
+            .is_none_or(|at| now - at > 3 * status.interval_secs.max(1) as i64);
+        status.healthy &= !overdue;
+    }
+    Json(status)
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, IntoParams)]
 struct MarketQuery {
     symbol: String,
     limit: Option<usize>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/orderbook",
+    tag = "markets",
+    params(MarketQuery),
+    responses((status = 200, body = OrderBook), (status = 502, body = ErrorResponse))
+)]
 async fn get_orderbook(
+    State(state): State<Arc<AppState>>,
     Query(params): Query<MarketQuery>,
-) -> Result<Json<crate::price_feed::OrderBook>, AppError> {
+) -> Result<Json<OrderBook>, AppError> {
     let limit = params.limit.unwrap_or(20).min(100);
-    let orderbook = crate::price_feed::fetch_orderbook(&params.symbol, limit).await?;
+    let orderbook = state.prices.fetch_orderbook(&params.symbol, limit).await?;
     Ok(Json(orderbook))
 }
 
+#[utoipa::path(
+    get,
+    path = "/trades",
+    tag = "markets",
+    params(MarketQuery),
+    responses((status = 200, body = Vec<Trade>), (status = 502, body = ErrorResponse))
+)]
 async fn get_trades(
+    State(state): State<Arc<AppState>>,
     Query(params): Query<MarketQuery>,
-) -> Result<Json<Vec<crate::price_feed::Trade>>, AppError> {
+) -> Result<Json<Vec<Trade>>, AppError> {
     let limit = params.limit.unwrap_or(20).min(100);
-    let trades = crate::price_feed::fetch_trades(&params.symbol, limit).await?;
+    let trades = state.prices.fetch_trades(&params.symbol, limit).await?;
     Ok(Json(trades))
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, IntoParams)]
 struct WalletQuery {
     owner: String,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, ToSchema)]
 struct WalletBalanceResponse {
-    balance: rust_decimal::Decimal,
+    balance: Decimal,
 }
 
+#[utoipa::path(
+    get,
+    path = "/wallet/usdc",
+    tag = "wallet",
+    params(WalletQuery),
+    responses((status = 200, body = WalletBalanceResponse), (status = 502, body = ErrorResponse))
+)]
 async fn get_usdc_balance(
     Query(params): Query<WalletQuery>,
 ) -> Result<Json<WalletBalanceResponse>, AppError> {
@@ -111,141 +412,550 @@ async fn get_usdc_balance(
     Ok(Json(WalletBalanceResponse { balance }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts",
+    tag = "accounts",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = CreateAccountRequest,
+    responses(
+        (status = 200, body = Account),
+        (status = 422, body = ErrorResponse, description = "Validation failed; `details.fields` lists every rejected field")
+    )
+)]
 async fn create_account(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<CreateAccountRequest>,
-) -> Result<Json<crate::models::Account>, AppError> {
-    let account = crate::models::Account {
+    ValidatedJson(payload): ValidatedJson<CreateAccountRequest>,
+) -> Result<Json<Account>, AppError> {
+    let account = Account {
         id: Uuid::new_v4(),
         owner: payload.owner,
         account_state: payload.account_state,
         collateral: Decimal::ZERO,
-        positions: std::collections::HashMap::new(),
+        positions: HashMap::new(),
     };
 
     state.store.create_account(&account).await?;
     state.accounts.write().await.insert(account.id, account.clone());
+    state.events.publish(Event::Account {
+        account: account.clone(),
+    });
 
     Ok(Json(account))
 }
 
+#[utoipa::path(
+    get,
+    path = "/accounts/{id}",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id")),
+    responses((status = 200, body = Account), (status = 404, body = ErrorResponse))
+)]
 async fn get_account(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
-) -> Result<Json<crate::models::Account>, AppError> {
+) -> Result<Json<Account>, AppError> {
     let accounts = state.accounts.read().await;
-    let account = accounts.get(&id).cloned().ok_or(AppError::NotFound)?;
+    let account = accounts.get(&id).cloned().ok_or(AppError::AccountNotFound(id))?;
     Ok(Json(account))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/deposit",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = DepositRequest,
+    responses(
+        (status = 200, body = Account),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed; `details.fields` lists every rejected field"),
+        (status = 404, body = ErrorResponse)
+    )
+)]
 async fn deposit(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
-    Json(payload): Json<DepositRequest>,
-) -> Result<Json<crate::models::Account>, AppError> {
+    ValidatedJson(payload): ValidatedJson<DepositRequest>,
+) -> Result<Json<Account>, AppError> {
     let mut accounts = state.accounts.write().await;
-    let account = accounts.get_mut(&id).ok_or(AppError::NotFound)?;
-    if payload.amount <= Decimal::ZERO {
-        return Err(AppError::Risk(crate::errors::RiskError::InvalidQuantity));
-    }
+    let account = accounts.get_mut(&id).ok_or(AppError::AccountNotFound(id))?;
     account.collateral += payload.amount;
     state
         .store
         .update_account_collateral(account.id, account.collateral)
         .await?;
+    state.events.publish(Event::Account {
+        account: account.clone(),
+    });
     Ok(Json(account.clone()))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/withdraw",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = WithdrawRequest,
+    responses(
+        (status = 200, body = Account),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed; `details.fields` lists every rejected field"),
+        (status = 404, body = ErrorResponse),
+        (status = 503, body = ErrorResponse, description = "The program is paused (`PROTOCOL_PAUSED`)")
+    )
+)]
 async fn withdraw(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
-    Json(payload): Json<WithdrawRequest>,
-) -> Result<Json<crate::models::Account>, AppError> {
+    ValidatedJson(payload): ValidatedJson<WithdrawRequest>,
+) -> Result<Json<Account>, AppError> {
     let mut accounts = state.accounts.write().await;
-    let account = accounts.get_mut(&id).ok_or(AppError::NotFound)?;
-    if payload.amount <= Decimal::ZERO {
-        return Err(AppError::Risk(crate::errors::RiskError::InvalidQuantity));
-    }
+    let account = accounts.get_mut(&id).ok_or(AppError::AccountNotFound(id))?;
+    state.risk.ensure_not_paused()?;
     if !account.positions.is_empty() {
         return Err(AppError::Risk(crate::errors::RiskError::MarginViolation));
     }
     if payload.amount > account.collateral {
-        return Err(AppError::Risk(crate::errors::RiskError::InsufficientCollateral));
+        return Err(AppError::Risk(crate::errors::RiskError::InsufficientCollateral {
+            required: payload.amount,
+            available: account.collateral,
+        }));
     }
     account.collateral -= payload.amount;
     state
         .store
         .update_account_collateral(account.id, account.collateral)
         .await?;
+    state.events.publish(Event::Account {
+        account: account.clone(),
+    });
     Ok(Json(account.clone()))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/set-collateral",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = SetCollateralRequest,
+    responses(
+        (status = 200, body = Account),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed; `details.fields` lists every rejected field"),
+        (status = 404, body = ErrorResponse)
+    )
+)]
 async fn set_collateral(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
-    Json(payload): Json<SetCollateralRequest>,
-) -> Result<Json<crate::models::Account>, AppError> {
+    ValidatedJson(payload): ValidatedJson<SetCollateralRequest>,
+) -> Result<Json<Account>, AppError> {
     let mut accounts = state.accounts.write().await;
-    let account = accounts.get_mut(&id).ok_or(AppError::NotFound)?;
+    let account = accounts.get_mut(&id).ok_or(AppError::AccountNotFound(id))?;
     account.collateral = payload.amount;
     state
         .store
         .update_account_collateral(account.id, account.collateral)
         .await?;
+    state.events.publish(Event::Account {
+        account: account.clone(),
+    });
     Ok(Json(account.clone()))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/positions",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = OpenPositionRequest,
+    responses(
+        (status = 200, body = PositionOutcome),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed (`details.fields` lists every rejected field), or the price is too far from the index (`FILL_PRICE_OUT_OF_RANGE`)"),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "No index price to check the fill against"),
+        (status = 503, body = ErrorResponse, description = "The program is paused (`PROTOCOL_PAUSED`)")
+    )
+)]
 async fn open_position(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
-    Json(payload): Json<OpenPositionRequest>,
-) -> Result<Json<crate::models::PositionOutcome>, AppError> {
+    ValidatedJson(payload): ValidatedJson<OpenPositionRequest>,
+) -> Result<Json<PositionOutcome>, AppError> {
+    let marks = account_marks(&state, id, Some(&payload.market), &HashMap::new()).await?;
+    let index = fill_index(&state, &payload.market, payload.entry_price).await?;
+
     let mut accounts = state.accounts.write().await;
-    let account = accounts.get_mut(&id).ok_or(AppError::NotFound)?;
-    let outcome = state.risk.open_position(account, payload)?;
+    let account = accounts.get_mut(&id).ok_or(AppError::AccountNotFound(id))?;
+    let outcome = state.risk.open_position(account, payload, &marks)?;
     state.store.upsert_position(account.id, &outcome.position).await?;
+    state.events.publish(Event::Position {
+        account_id: account.id,
+        market: outcome.position.market.clone(),
+        position: Some(outcome.position.clone()),
+    });
+    drop(accounts);
+    if let Some(index) = index {
+        state
+            .marks
+            .record_fill(&outcome.position.market, outcome.position.entry_price, index);
+    }
     Ok(Json(outcome))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/positions/{market}/close",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("market" = String, Path, description = "Market symbol"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = ClosePositionRequest,
+    responses(
+        (status = 200, body = Account),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed (`details.fields` lists every rejected field), or the price is too far from the index (`FILL_PRICE_OUT_OF_RANGE`)"),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse, description = "No index price to check the fill against"),
+        (status = 409, body = ErrorResponse, description = "The market is paused or settled, or a liquidation of the position awaits confirmation (`LIQUIDATION_PENDING`)")
+    )
+)]
 async fn close_position(
     State(state): State<Arc<AppState>>,
     Path((id, market)): Path<(Uuid, String)>,
-    Json(payload): Json<ClosePositionRequest>,
-) -> Result<Json<crate::models::Account>, AppError> {
+    ValidatedJson(payload): ValidatedJson<ClosePositionRequest>,
+) -> Result<Json<Account>, AppError> {
+    let index = fill_index(&state, &market, payload.exit_price).await?;
     let mut accounts = state.accounts.write().await;
-    let account = accounts.get_mut(&id).ok_or(AppError::NotFound)?;
+    let account = accounts.get_mut(&id).ok_or(AppError::AccountNotFound(id))?;
     let _pnl = state.risk.close_position(account, &market, payload.exit_price)?;
     state.store.delete_position(account.id, &market).await?;
     state
         .store
         .update_account_collateral(account.id, account.collateral)
         .await?;
-    Ok(Json(account.clone()))
+    state.events.publish(Event::Position {
+        account_id: account.id,
+        market: market.clone(),
+        position: None,
+    });
+    state.events.publish(Event::Account {
+        account: account.clone(),
+    });
+    let account = account.clone();
+    drop(accounts);
+    if let Some(index) = index {
+        state.marks.record_fill(&market, payload.exit_price, index);
+    }
+    Ok(Json(account))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/positions/{market}/adjust-leverage",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("market" = String, Path, description = "Market symbol"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = AdjustLeverageRequest,
+    responses(
+        (status = 200, body = Account),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed; `details.fields` lists every rejected field"),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse, description = "The market doesn't allow it, or a liquidation of the position awaits confirmation (`LIQUIDATION_PENDING`)"),
+        (status = 503, body = ErrorResponse, description = "The program is paused (`PROTOCOL_PAUSED`)")
+    )
+)]
 async fn adjust_leverage(
     State(state): State<Arc<AppState>>,
     Path((id, market)): Path<(Uuid, String)>,
-    Json(payload): Json<AdjustLeverageRequest>,
-) -> Result<Json<crate::models::Account>, AppError> {
+    ValidatedJson(payload): ValidatedJson<AdjustLeverageRequest>,
+) -> Result<Json<Account>, AppError> {
+    let marks = account_marks(&state, id, None, &HashMap::new()).await?;
+
     let mut accounts = state.accounts.write().await;
-    let account = accounts.get_mut(&id).ok_or(AppError::NotFound)?;
+    let account = accounts.get_mut(&id).ok_or(AppError::AccountNotFound(id))?;
     state
         .risk
-        .adjust_leverage(account, &market, payload.new_leverage_bps, payload.mark_price)?;
+        .adjust_leverage(account, &market, payload.new_leverage_bps, &marks)?;
     if let Some(position) = account.positions.get(&market) {
         state.store.upsert_position(account.id, position).await?;
+        state.events.publish(Event::Position {
+            account_id: account.id,
+            market: market.clone(),
+            position: Some(position.clone()),
+        });
     }
     Ok(Json(account.clone()))
 }
 
+#[utoipa::path(
+    post,
+    path = "/accounts/{id}/risk-check",
+    tag = "accounts",
+    params(("id" = Uuid, Path, description = "Account id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the first response for a repeated key")),
+    request_body = RiskCheckRequest,
+    responses(
+        (status = 200, body = RiskCheckResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 422, body = ErrorResponse, description = "Validation failed; `details.fields` lists every rejected field"),
+        (status = 404, body = ErrorResponse)
+    )
+)]
 async fn risk_check(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
-    Json(payload): Json<RiskCheckRequest>,
-) -> Result<Json<crate::models::RiskCheckResponse>, AppError> {
+    ValidatedJson(payload): ValidatedJson<RiskCheckRequest>,
+) -> Result<Json<RiskCheckResponse>, AppError> {
+    let marks = account_marks(&state, id, None, &payload.mark_prices).await?;
     let accounts = state.accounts.read().await;
-    let account = accounts.get(&id).ok_or(AppError::NotFound)?;
-    let response = state.risk.check_risk(account, payload)?;
+    let account = accounts.get(&id).ok_or(AppError::AccountNotFound(id))?;
+    let response = state.risk.check_risk(account, &marks)?;
     Ok(Json(response))
 }
+
+/// Marks for every market the account holds, plus `market` when it is listed.
+async fn account_marks(
+    state: &AppState,
+    id: Uuid,
+    market: Option<&str>,
+    supplied: &HashMap<String, Decimal>,
+) -> Result<HashMap<String, Decimal>, AppError> {
+    let mut symbols: Vec<String> = {
+        let accounts = state.accounts.read().await;
+        let account = accounts.get(&id).ok_or(AppError::AccountNotFound(id))?;
+        account.positions.keys().cloned().collect()
+    };
+    // Unlisted markets are left to the risk engine, which answers MARKET_NOT_FOUND.
+    if let Some(market) = market.filter(|market| state.risk.market(market).is_some()) {
+        if !symbols.iter().any(|symbol| symbol == market) {
+            symbols.push(market.to_string());
+        }
+    }
+    state.marks.resolve(&state.prices, &symbols, supplied).await
+}
+
+/// The index a fill at `price` is checked against and later folded into the mark basis with;
+/// fills too far from it are refused. Unlisted markets are left to the risk engine.
+async fn fill_index(state: &AppState, market: &str, price: Decimal) -> Result<Option<Decimal>, AppError> {
+    if state.risk.market(market).is_none() {
+        return Ok(None);
+    }
+    let prices = state.prices.fetch_fresh_prices(&[market.to_string()]).await?;
+    let index = *prices
+        .get(market)
+        .ok_or_else(|| RiskError::MissingMarkPrice(market.to_string()))?;
+    state.marks.check_fill(market, price, index)?;
+    Ok(Some(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app, request, send, state};
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Method, Request, StatusCode};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, Value) {
+        let (status, _, body) = send(app, request(Method::GET, uri, Value::Null)).await;
+        (status, body)
+    }
+
+    async fn post(app: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+        let (status, _, body) = send(app, request(Method::POST, uri, body)).await;
+        (status, body)
+    }
+
+    fn decimal(value: &Value) -> Decimal {
+        value.as_str().unwrap().parse().unwrap()
+    }
+
+    /// An account holding `collateral`, through the routes.
+    async fn funded_account(app: &Router, collateral: &str) -> String {
+        let (status, account) = post(app, "/accounts", json!({ "owner": "trader" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let id = account["id"].as_str().unwrap().to_string();
+        let (status, _) = post(app, &format!("/accounts/{}/deposit", id), json!({ "amount": collateral })).await;
+        assert_eq!(status, StatusCode::OK);
+        id
+    }
+
+    #[test]
+    fn checked_in_spec_matches_the_handlers() {
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap();
+        assert!(
+            include_str!("../openapi.json").trim_end() == spec.trim_end(),
+            "openapi.json is out of date; regenerate it with `cargo run -- openapi > openapi.json`"
+        );
+    }
+
+    #[tokio::test]
+    async fn docs_page_loads_nothing_from_other_hosts() {
+        let app = app(&state());
+        for (uri, content_type) in [("/docs", "text/html"), ("/docs/api.js", "text/javascript")] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let served = response.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+            assert!(served.starts_with(content_type), "{} served as {}", uri, served);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(!body.contains("http://") && !body.contains("https://"), "{} links another host", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn spec_is_served() {
+        let (status, _, body) = send(&app(&state()), Request::get("/openapi.json").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["paths"]["/markets"].is_object());
+    }
+
+    #[tokio::test]
+    async fn markets_and_prices_come_from_the_fixtures() {
+        let app = app(&state());
+        let (status, markets) = get(&app, "/markets").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(markets.as_array().unwrap().iter().any(|market| market["symbol"] == "BTC"));
+
+        let (status, prices) = get(&app, "/prices?symbols=btc,%20eth").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(prices, json!({ "BTC": "67000", "ETH": "3500" }));
+
+        let (status, index) = get(&app, "/prices/index/btc").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(index["price"], "67000");
+
+        // No fills yet, so the mark is the index.
+        let (status, mark) = get(&app, "/prices/mark/BTC").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(mark["mark"], "67000");
+        assert_eq!(mark["fills"], 0);
+
+        let (status, body) = get(&app, "/prices/index/NOPE").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "MARKET_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn orderbook_and_trades_come_from_the_fixtures() {
+        let app = app(&state());
+        let (status, book) = get(&app, "/orderbook?symbol=BTC&limit=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(book["bids"].as_array().unwrap().len(), 2);
+        assert_eq!(book["bids"][0]["price"], "66999.9");
+        assert_eq!(book["asks"][0]["price"], "67000.1");
+
+        let (status, trades) = get(&app, "/trades?symbol=BTC&limit=3").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!trades.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn positions_open_check_and_close() {
+        let app = app(&state());
+        let id = funded_account(&app, "1000").await;
+
+        let open = json!({
+            "market": "BTC",
+            "side": "long",
+            "base_qty": "0.1",
+            "entry_price": "67000",
+            "leverage_bps": 100_000,
+        });
+        let (status, outcome) = post(&app, &format!("/accounts/{}/positions", id), open.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", outcome);
+        assert_eq!(decimal(&outcome["used_margin"]), Decimal::from(670));
+        assert_eq!(decimal(&outcome["free_collateral"]), Decimal::from(330));
+
+        let (status, body) = post(&app, &format!("/accounts/{}/positions", id), open).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+        assert_eq!(body["code"], "POSITION_EXISTS");
+
+        let (status, risk) = post(&app, &format!("/accounts/{}/risk-check", id), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(decimal(&risk["equity"]), Decimal::from(1000));
+        assert_eq!(risk["liquidatable_positions"], json!([]));
+
+        let (status, account) = post(
+            &app,
+            &format!("/accounts/{}/positions/BTC/close", id),
+            json!({ "exit_price": "67100" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(decimal(&account["collateral"]), Decimal::from(1010));
+        assert_eq!(account["positions"], json!({}));
+
+        let (status, account) = get(&app, &format!("/accounts/{}", id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(decimal(&account["collateral"]), Decimal::from(1010));
+    }
+
+    #[tokio::test]
+    async fn opens_beyond_the_collateral_are_rejected() {
+        let app = app(&state());
+        let id = funded_account(&app, "100").await;
+        let (status, body) = post(
+            &app,
+            &format!("/accounts/{}/positions", id),
+            json!({ "market": "BTC", "side": "short", "base_qty": "1", "entry_price": "67000", "leverage_bps": 100_000 }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+        assert_eq!(body["code"], "INSUFFICIENT_COLLATERAL");
+
+        let (status, account) = get(&app, &format!("/accounts/{}", id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(account["positions"], json!({}));
+    }
+
+    #[tokio::test]
+    async fn unknown_accounts_are_not_found() {
+        let app = app(&state());
+        let (status, body) = get(&app, &format!("/accounts/{}", Uuid::nil())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "ACCOUNT_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn fills_far_from_the_index_are_refused_and_leave_the_mark_alone() {
+        let app = app(&state());
+        let id = funded_account(&app, "10000").await;
+        let (status, body) = post(
+            &app,
+            &format!("/accounts/{}/positions", id),
+            json!({ "market": "BTC", "side": "long", "base_qty": "0.1", "entry_price": "70000", "leverage_bps": 100_000 }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+        assert_eq!(body["code"], "FILL_PRICE_OUT_OF_RANGE");
+        assert_eq!(body["details"]["max_deviation_bps"], 100);
+
+        let (status, _) = post(
+            &app,
+            &format!("/accounts/{}/positions", id),
+            json!({ "market": "BTC", "side": "long", "base_qty": "0.1", "entry_price": "67500", "leverage_bps": 100_000 }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = post(
+            &app,
+            &format!("/accounts/{}/positions/BTC/close", id),
+            json!({ "exit_price": "60000" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "FILL_PRICE_OUT_OF_RANGE");
+
+        // Only the accepted open moved the basis.
+        let (_, mark) = get(&app, "/prices/mark/BTC").await;
+        assert_eq!(mark["fills"], 1);
+        let (_, account) = get(&app, &format!("/accounts/{}", id)).await;
+        assert!(account["positions"]["BTC"].is_object());
+    }
+}
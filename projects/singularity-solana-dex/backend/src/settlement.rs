@@ -0,0 +1,302 @@
+//! Closing out delisted markets. Once a market is `settled`, every position in it is closed at
+//! the settlement price and its PnL credited. Positions that live on-chain are settled there
+//! first; one that fails stays open in the backend and is retried by the next sweep.
+
+use crate::errors::AppError;
+use crate::events::Event;
+use crate::models::{MarketStatus, Position, Side};
+use crate::registry::MarketEntry;
+use crate::state::AppState;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SettledPosition {
+    pub account_id: Uuid,
+    pub side: Side,
+    pub base_qty: Decimal,
+    pub entry_price: Decimal,
+    pub pnl: Decimal,
+    /// `SettlePosition` transaction, for positions held on-chain.
+    pub signature: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SettlementFailure {
+    pub account_id: Uuid,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, ToSchema)]
+pub struct SettlementReport {
+    pub settled: Vec<SettledPosition>,
+    /// Positions still open because their on-chain settlement failed.
+    pub failed: Vec<SettlementFailure>,
+}
+
+/// Settles every open position in `entry`'s market, which must already be settled in the
+/// risk engine.
+pub async fn settle_positions(state: &AppState, entry: &MarketEntry) -> Result<SettlementReport, AppError> {
+    let market = &entry.market;
+    let settlement_price = match (market.status, market.settlement_price) {
+        (MarketStatus::Settled, Some(price)) => price,
+        _ => return Err(AppError::Internal(format!("{} is not settled", market.symbol))),
+    };
+    let symbol = &market.symbol;
+    let mut report = SettlementReport::default();
+
+    // The accounts lock isn't held across the RPCs: collect the positions, settle them
+    // on-chain, then apply each result on its own.
+    let targets: Vec<(Uuid, Option<String>, Position)> = {
+        let accounts = state.accounts.read().await;
+        accounts
+            .values()
+            .filter_map(|account| {
+                let position = account.positions.get(symbol)?;
+                Some((account.id, account.account_state.clone(), position.clone()))
+            })
+            .collect()
+    };
+
+    for (account_id, account_state, position) in targets {
+        let mut signature = None;
+        if let (Some(chain), Some(oracle), Some(account_state), Some(position_account)) = (
+            &state.market_chain,
+            &entry.oracle,
+            &account_state,
+            &position.position_account,
+        ) {
+            match chain
+                .settle_position(oracle.market_id, &oracle.market_account, account_state, position_account)
+                .await
+            {
+                Ok(sig) => signature = Some(sig),
+                Err(err) => {
+                    warn!(account = %account_id, market = %symbol, error = %err, "on-chain settlement failed");
+                    report.failed.push(SettlementFailure {
+                        account_id,
+                        error: err,
+                    });
+                    continue;
+                }
+            }
+        }
+
+        match apply(state, account_id, symbol, settlement_price).await {
+            Ok(Some(pnl)) => {
+                info!(account = %account_id, market = %symbol, price = %settlement_price, pnl = %pnl, "position settled");
+                report.settled.push(SettledPosition {
+                    account_id,
+                    side: position.side,
+                    base_qty: position.base_qty,
+                    entry_price: position.entry_price,
+                    pnl,
+                    signature,
+                });
+            }
+            // Closed by someone else since it was collected.
+            Ok(None) => {}
+            Err(err) => {
+                warn!(account = %account_id, market = %symbol, signature = ?signature, error = %err, "settled position not recorded");
+                report.failed.push(SettlementFailure {
+                    account_id,
+                    error: err.to_string(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Closes `account_id`'s position in the settled market `symbol` and credits its PnL, returning
+/// `None` if it's no longer open. The account is only changed once the store has the result.
+async fn apply(
+    state: &AppState,
+    account_id: Uuid,
+    symbol: &str,
+    settlement_price: Decimal,
+) -> Result<Option<Decimal>, AppError> {
+    let mut accounts = state.accounts.write().await;
+    let Some(account) = accounts.get_mut(&account_id) else {
+        return Ok(None);
+    };
+    if !account.positions.contains_key(symbol) {
+        return Ok(None);
+    }
+    let mut settled = account.clone();
+    let pnl = state.risk.settle_position(&mut settled, symbol)?;
+    state.store.delete_position(account_id, symbol).await?;
+    state
+        .store
+        .update_account_collateral(account_id, settled.collateral)
+        .await?;
+    *account = settled;
+
+    state.events.publish(Event::Settlement {
+        account_id,
+        market: symbol.to_string(),
+        settlement_price,
+        pnl,
+    });
+    state.events.publish(Event::Position {
+        account_id,
+        market: symbol.to_string(),
+        position: None,
+    });
+    state.events.publish(Event::Account {
+        account: account.clone(),
+    });
+    Ok(Some(pnl))
+}
+
+/// Settles whatever is still open in settled markets: positions loaded at startup, left by a
+/// failed on-chain settlement, or in a market settled by editing the registry file.
+pub async fn settle_pending(state: &AppState) {
+    let settled: Vec<MarketEntry> = {
+        let registry = state.registry.lock().await;
+        registry
+            .current()
+            .markets
+            .iter()
+            .filter(|entry| entry.market.status == MarketStatus::Settled)
+            .cloned()
+            .collect()
+    };
+    for entry in settled {
+        let held = {
+            let accounts = state.accounts.read().await;
+            accounts
+                .values()
+                .any(|account| account.positions.contains_key(&entry.market.symbol))
+        };
+        if !held {
+            continue;
+        }
+        match settle_positions(state, &entry).await {
+            Ok(report) if report.failed.is_empty() => {}
+            Ok(report) => warn!(market = %entry.market.symbol, failed = report.failed.len(), "positions left unsettled"),
+            Err(err) => warn!(market = %entry.market.symbol, error = %err, "settlement sweep failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryStore;
+    use crate::models::Account;
+    use crate::test_support::{state_with_store, FlakyStore, StubChain};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// An account long 0.1 BTC at 60000, with its position on-chain at `position_account`.
+    fn account(position_account: Option<&str>) -> Account {
+        let position = Position {
+            market: "BTC".to_string(),
+            side: Side::Long,
+            base_qty: Decimal::new(1, 1),
+            entry_price: Decimal::from(60000),
+            leverage_bps: 20_000,
+            position_account: position_account.map(str::to_string),
+        };
+        Account {
+            id: Uuid::new_v4(),
+            owner: "trader".to_string(),
+            account_state: position_account.map(|_| "account-state".to_string()),
+            collateral: Decimal::from(10000),
+            positions: HashMap::from([("BTC".to_string(), position)]),
+        }
+    }
+
+    /// Settles BTC at 61000 in the registry and returns its entry, with on-chain ids.
+    async fn settle_btc(state: &AppState) -> MarketEntry {
+        let mut registry = state.registry.lock().await;
+        let mut next = registry.current().clone();
+        let entry = next.entry_mut("BTC").unwrap();
+        entry.market.status = MarketStatus::Settled;
+        entry.market.settlement_price = Some(Decimal::from(61000));
+        let mut entry = entry.clone();
+        registry.commit(state, next, false).await.unwrap();
+        entry.oracle = Some(
+            serde_json::from_value(json!({
+                "market_id": 1,
+                "oracle_pubkey": "oracle",
+                "market_account": "market",
+            }))
+            .unwrap(),
+        );
+        entry
+    }
+
+    async fn collateral(state: &AppState, account_id: Uuid) -> (Decimal, bool) {
+        let accounts = state.accounts.read().await;
+        let account = &accounts[&account_id];
+        (account.collateral, account.positions.contains_key("BTC"))
+    }
+
+    #[tokio::test]
+    async fn positions_close_at_the_settlement_price() {
+        let trader = account(None);
+        let state = state_with_store(Arc::new(MemoryStore::new()), None, vec![trader.clone()]);
+        let entry = settle_btc(&state).await;
+
+        let report = settle_positions(&state, &entry).await.unwrap();
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].pnl, Decimal::from(100));
+        assert_eq!(report.settled[0].signature, None);
+        assert!(report.failed.is_empty());
+        assert_eq!(collateral(&state, trader.id).await, (Decimal::from(10100), false));
+
+        let report = settle_positions(&state, &entry).await.unwrap();
+        assert!(report.settled.is_empty() && report.failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn store_failures_are_reported_and_left_for_the_next_sweep() {
+        let trader = account(None);
+        let store = Arc::new(FlakyStore::failing(&["delete_position"]));
+        let state = state_with_store(store.clone(), None, vec![trader.clone()]);
+        let entry = settle_btc(&state).await;
+
+        let report = settle_positions(&state, &entry).await.unwrap();
+        assert!(report.settled.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].account_id, trader.id);
+        assert_eq!(collateral(&state, trader.id).await, (Decimal::from(10000), true));
+
+        store.recover();
+        let report = settle_positions(&state, &entry).await.unwrap();
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(collateral(&state, trader.id).await, (Decimal::from(10100), false));
+    }
+
+    #[tokio::test]
+    async fn on_chain_settlements_are_sent_without_holding_the_accounts() {
+        let (settled, rejected) = (account(Some("position-a")), account(Some("position-b")));
+        let chain = Arc::new(StubChain::rejecting("position-b"));
+        let state = state_with_store(
+            Arc::new(MemoryStore::new()),
+            Some(chain.clone()),
+            vec![settled.clone(), rejected.clone()],
+        );
+        chain.attach(&state);
+        let entry = settle_btc(&state).await;
+
+        let report = settle_positions(&state, &entry).await.unwrap();
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].account_id, settled.id);
+        assert_eq!(report.settled[0].signature.as_deref(), Some("sig-position-a"));
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].account_id, rejected.id);
+        assert_eq!(report.failed[0].error, "simulation failed");
+        assert_eq!(collateral(&state, rejected.id).await, (Decimal::from(10000), true));
+
+        let sent = chain.settled_positions.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|(_, unlocked)| *unlocked));
+    }
+}
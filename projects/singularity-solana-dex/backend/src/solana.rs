@@ -1,11 +1,29 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
+use crate::admin::{MarketChain, ProgramConfig};
+use crate::models::{MarketConfig, Simulation};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
+    bpf_loader_upgradeable,
     instruction::{AccountMeta, Instruction},
     pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    system_program,
+    transaction::Transaction,
 };
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// Seed of the program config PDA.
+const CONFIG_SEED: &[u8] = b"config";
+#[allow(dead_code)]
+const POSITION_SEED: &[u8] = b"position";
+/// Seed of market PDAs, followed by the little-endian market id.
+const MARKET_SEED: &[u8] = b"market";
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub enum PerpsInstruction {
@@ -29,6 +47,39 @@ pub enum PerpsInstruction {
     ClosePosition { market_id: u16, exit_price: u64 },
     Liquidate { market_id: u16, exit_price: u64 },
     UpdatePrice { market_id: u16, price: u64 },
+    UpdateMarketParams {
+        market_id: u16,
+        max_leverage_bps: u32,
+        initial_margin_bps: u32,
+        maintenance_margin_bps: u32,
+        status: MarketStatus,
+    },
+    SettleMarket { market_id: u16, settlement_price: u64 },
+    SettlePosition { market_id: u16 },
+    InitializeConfig { guardian: [u8; 32] },
+    SetPause { paused: bool },
+    SetGuardian { guardian: [u8; 32] },
+    ProposeAdmin { new_admin: [u8; 32] },
+    AcceptAdmin,
+    MigrateMarket { market_id: u16 },
+    SetOracleAuthority { market_id: u16, oracle_authority: [u8; 32] },
+}
+
+/// The key allowed to send `UpdatePrice` for a market account, in both the current and the
+/// legacy layout, which share its first fields.
+pub fn market_oracle_authority(data: &[u8]) -> Option<Pubkey> {
+    let bytes: [u8; 32] = data.get(3..35)?.try_into().ok()?;
+    (data.first() == Some(&1)).then(|| Pubkey::new_from_array(bytes))
+}
+
+/// Mirror of the program's config account.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct ProgramConfigState {
+    pub is_initialized: bool,
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub guardian: Pubkey,
+    pub paused: bool,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy)]
@@ -37,6 +88,28 @@ pub enum Side {
     Short,
 }
 
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy)]
+pub enum MarketStatus {
+    Active,
+    PostOnly,
+    ReduceOnly,
+    Paused,
+    Settled,
+}
+
+impl From<crate::models::MarketStatus> for MarketStatus {
+    fn from(status: crate::models::MarketStatus) -> Self {
+        match status {
+            crate::models::MarketStatus::Active => MarketStatus::Active,
+            crate::models::MarketStatus::PostOnly => MarketStatus::PostOnly,
+            crate::models::MarketStatus::ReduceOnly => MarketStatus::ReduceOnly,
+            crate::models::MarketStatus::Paused => MarketStatus::Paused,
+            crate::models::MarketStatus::Settled => MarketStatus::Settled,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct SolanaGateway {
     pub rpc_url: String,
     pub program_id: Pubkey,
@@ -50,10 +123,10 @@ impl SolanaGateway {
         }
     }
 
+    /// Creates the market at its PDA on first use, paid by `admin`.
     pub fn build_initialize_market_ix(
         &self,
         admin: Pubkey,
-        market: Pubkey,
         market_id: u16,
         oracle_authority: Pubkey,
         max_leverage_bps: u32,
@@ -70,6 +143,63 @@ impl SolanaGateway {
         .try_to_vec()
         .expect("serialize ix");
 
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new(self.market_address(market_id), false),
+                AccountMeta::new_readonly(self.config_address(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            data,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_update_market_params_ix(
+        &self,
+        admin: Pubkey,
+        market: Pubkey,
+        market_id: u16,
+        max_leverage_bps: u32,
+        initial_margin_bps: u32,
+        maintenance_margin_bps: u32,
+        status: MarketStatus,
+    ) -> Instruction {
+        let data = PerpsInstruction::UpdateMarketParams {
+            market_id,
+            max_leverage_bps,
+            initial_margin_bps,
+            maintenance_margin_bps,
+            status,
+        }
+        .try_to_vec()
+        .expect("serialize ix");
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new(market, false),
+            ],
+            data,
+        }
+    }
+
+    pub fn build_settle_market_ix(
+        &self,
+        admin: Pubkey,
+        market: Pubkey,
+        market_id: u16,
+        settlement_price: Decimal,
+    ) -> Instruction {
+        let data = PerpsInstruction::SettleMarket {
+            market_id,
+            settlement_price: settlement_price.round().to_u64().unwrap_or(0),
+        }
+        .try_to_vec()
+        .expect("serialize ix");
+
         Instruction {
             program_id: self.program_id,
             accounts: vec![
@@ -80,6 +210,32 @@ impl SolanaGateway {
         }
     }
 
+    pub fn build_settle_position_ix(
+        &self,
+        caller: Pubkey,
+        account: Pubkey,
+        market: Pubkey,
+        position: Pubkey,
+        market_id: u16,
+    ) -> Instruction {
+        let data = PerpsInstruction::SettlePosition { market_id }
+            .try_to_vec()
+            .expect("serialize ix");
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(caller, true),
+                AccountMeta::new(account, false),
+                AccountMeta::new(market, false),
+                AccountMeta::new(position, false),
+            ],
+            data,
+        }
+    }
+
+    /// Signed by the account's owner; the backend only builds it for clients.
+    #[allow(dead_code)]
     pub fn build_initialize_account_ix(&self, owner: Pubkey, account: Pubkey) -> Instruction {
         let data = PerpsInstruction::InitializeAccount
             .try_to_vec()
@@ -95,6 +251,8 @@ impl SolanaGateway {
         }
     }
 
+    /// Signed by the account's owner; the backend only builds it for clients.
+    #[allow(dead_code)]
     pub fn build_deposit_ix(&self, owner: Pubkey, account: Pubkey, amount: u64) -> Instruction {
         let data = PerpsInstruction::Deposit { amount }
             .try_to_vec()
@@ -110,12 +268,14 @@ impl SolanaGateway {
         }
     }
 
+    /// Opens a position at `account`'s position PDA for `market_id`, which the program creates.
+    /// Signed by the account's owner; the backend only builds it for clients.
+    #[allow(dead_code, clippy::too_many_arguments)]
     pub fn build_open_position_ix(
         &self,
         owner: Pubkey,
         account: Pubkey,
         market: Pubkey,
-        position: Pubkey,
         market_id: u16,
         base_qty: i64,
         entry_price: u64,
@@ -138,7 +298,9 @@ impl SolanaGateway {
                 AccountMeta::new(owner, true),
                 AccountMeta::new(account, false),
                 AccountMeta::new(market, false),
-                AccountMeta::new(position, false),
+                AccountMeta::new(self.position_address(&account, market_id), false),
+                AccountMeta::new_readonly(self.config_address(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
             ],
             data,
         }
@@ -198,7 +360,383 @@ impl SolanaGateway {
         }
     }
 
+    /// The PDA holding `account`'s position in `market_id`.
+    #[allow(dead_code)]
+    pub fn position_address(&self, account: &Pubkey, market_id: u16) -> Pubkey {
+        Pubkey::find_program_address(
+            &[POSITION_SEED, account.as_ref(), &market_id.to_le_bytes()],
+            &self.program_id,
+        )
+        .0
+    }
+
+    /// The PDA holding market `market_id`, the only account the program accepts for it.
+    pub fn market_address(&self, market_id: u16) -> Pubkey {
+        Pubkey::find_program_address(&[MARKET_SEED, &market_id.to_le_bytes()], &self.program_id).0
+    }
+
+    /// The program's ProgramData account, holding its upgrade authority.
+    pub fn program_data_address(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.program_id.as_ref()], &bpf_loader_upgradeable::id()).0
+    }
+
+    /// The program config PDA.
+    pub fn config_address(&self) -> Pubkey {
+        Pubkey::find_program_address(&[CONFIG_SEED], &self.program_id).0
+    }
+
+    pub fn build_initialize_config_ix(&self, admin: Pubkey, guardian: Pubkey) -> Instruction {
+        let data = PerpsInstruction::InitializeConfig {
+            guardian: guardian.to_bytes(),
+        }
+        .try_to_vec()
+        .expect("serialize ix");
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new(self.config_address(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(self.program_data_address(), false),
+            ],
+            data,
+        }
+    }
+
+    /// Only the market's admin may sign this.
+    pub fn build_set_oracle_authority_ix(
+        &self,
+        admin: Pubkey,
+        market: Pubkey,
+        market_id: u16,
+        oracle_authority: Pubkey,
+    ) -> Instruction {
+        let data = PerpsInstruction::SetOracleAuthority {
+            market_id,
+            oracle_authority: oracle_authority.to_bytes(),
+        }
+        .try_to_vec()
+        .expect("serialize ix");
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![AccountMeta::new(admin, true), AccountMeta::new(market, false)],
+            data,
+        }
+    }
+
+    /// Moves `market` to the market's PDA. The signer pays the PDA's rent, gets the old
+    /// account's back, and becomes the admin of a market still in the legacy layout.
+    pub fn build_migrate_market_ix(&self, authority: Pubkey, market: Pubkey, market_id: u16) -> Instruction {
+        let data = PerpsInstruction::MigrateMarket { market_id }
+            .try_to_vec()
+            .expect("serialize ix");
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(authority, true),
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(self.config_address(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(self.market_address(market_id), false),
+            ],
+            data,
+        }
+    }
+
+    /// `SetPause`, `SetGuardian`, `ProposeAdmin` and `AcceptAdmin` all take the signer and
+    /// the config account.
+    pub fn build_config_ix(&self, signer: Pubkey, instruction: PerpsInstruction) -> Instruction {
+        let data = instruction.try_to_vec().expect("serialize ix");
+
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(signer, true),
+                AccountMeta::new(self.config_address(), false),
+            ],
+            data,
+        }
+    }
+
     pub fn parse_pubkey(&self, value: &str) -> Result<Pubkey, solana_sdk::pubkey::ParsePubkeyError> {
         Pubkey::from_str(value)
     }
 }
+
+/// Signs with one keypair and retries with exponential backoff; the liquidation crank and the
+/// oracle publisher each have their own.
+pub struct TxSender {
+    pub client: RpcClient,
+    pub keypair: Keypair,
+    /// Attempts per transaction before it is given up.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for each later one.
+    pub retry_backoff: Duration,
+}
+
+impl TxSender {
+    /// Fails only if `keypair_path` can't be read.
+    pub fn new(rpc_url: &str, keypair_path: &str, max_attempts: u32, retry_backoff: Duration) -> Result<Self, String> {
+        let keypair = read_keypair_file(keypair_path).map_err(|err| format!("{}: {}", keypair_path, err))?;
+        Ok(Self {
+            client: RpcClient::new(rpc_url.to_string()),
+            keypair,
+            max_attempts,
+            retry_backoff,
+        })
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    pub async fn send(&self, ixs: &[Instruction]) -> Result<Signature, String> {
+        let recent = self.client.get_latest_blockhash().await.map_err(|err| err.to_string())?;
+        let tx = Transaction::new_signed_with_payer(ixs, Some(&self.keypair.pubkey()), &[&self.keypair], recent);
+        self.client
+            .send_and_confirm_transaction(&tx)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    /// Sends `ixs` in one transaction, with a fresh blockhash for every attempt.
+    pub async fn send_with_retry(&self, label: &str, ixs: &[Instruction]) -> Result<Signature, String> {
+        let mut delay = self.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.send(ixs).await {
+                Ok(signature) => return Ok(signature),
+                Err(err) if attempt < self.max_attempts => {
+                    warn!(what = label, attempt, error = %err, "transaction failed; retrying");
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    warn!(what = label, attempt, error = %err, "transaction failed; giving up");
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Runs `ixs` through `simulateTransaction` without sending them.
+    pub async fn simulate(&self, ixs: &[Instruction]) -> Simulation {
+        let result = async {
+            let recent = self.client.get_latest_blockhash().await?;
+            let tx = Transaction::new_signed_with_payer(ixs, Some(&self.keypair.pubkey()), &[&self.keypair], recent);
+            self.client.simulate_transaction(&tx).await
+        }
+        .await;
+        match result {
+            Ok(response) => Simulation {
+                error: response.value.err.map(|err| err.to_string()),
+                units_consumed: response.value.units_consumed,
+                logs: response.value.logs.unwrap_or_default(),
+            },
+            Err(err) => Simulation::failed(err.to_string()),
+        }
+    }
+}
+
+/// Sends admin changes with `MARKET_ADMIN_KEYPAIR`, which becomes the on-chain admin of every
+/// market it initializes and, through `InitializeConfig`, of the program.
+pub struct OnchainMarkets {
+    gateway: SolanaGateway,
+    admin: Keypair,
+    oracle_authority: Pubkey,
+    client: RpcClient,
+}
+
+impl OnchainMarkets {
+    /// `None` unless both `SOLANA_RPC_URL` and `MARKET_ADMIN_KEYPAIR` are set.
+    /// `MARKET_ORACLE_AUTHORITY` defaults to the admin key.
+    pub fn from_env() -> Result<Option<Arc<dyn MarketChain>>, Box<dyn std::error::Error>> {
+        let (Ok(rpc_url), Ok(keypair_path)) =
+            (std::env::var("SOLANA_RPC_URL"), std::env::var("MARKET_ADMIN_KEYPAIR"))
+        else {
+            return Ok(None);
+        };
+        let program_id = std::env::var("SOLANA_PROGRAM_ID")
+            .unwrap_or_else(|_| "11111111111111111111111111111111".to_string());
+        let admin = read_keypair_file(&keypair_path)
+            .map_err(|err| format!("MARKET_ADMIN_KEYPAIR {}: {}", keypair_path, err))?;
+        let oracle_authority = match std::env::var("MARKET_ORACLE_AUTHORITY") {
+            Ok(key) => Pubkey::from_str(&key).map_err(|err| format!("MARKET_ORACLE_AUTHORITY: {}", err))?,
+            Err(_) => admin.pubkey(),
+        };
+        Ok(Some(Arc::new(Self {
+            gateway: SolanaGateway::new(&rpc_url, &program_id),
+            admin,
+            oracle_authority,
+            client: RpcClient::new(rpc_url),
+        })))
+    }
+
+    async fn send(&self, ixs: &[Instruction], extra_signers: &[&Keypair]) -> Result<String, String> {
+        let recent = self.client.get_latest_blockhash().await.map_err(|err| err.to_string())?;
+        let mut signers = vec![&self.admin];
+        signers.extend_from_slice(extra_signers);
+        let tx = Transaction::new_signed_with_payer(ixs, Some(&self.admin.pubkey()), &signers, recent);
+        let signature = self
+            .client
+            .send_and_confirm_transaction(&tx)
+            .await
+            .map_err(|err| err.to_string())?;
+        Ok(signature.to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl MarketChain for OnchainMarkets {
+    async fn initialize_market(
+        &self,
+        market: &MarketConfig,
+        market_id: u16,
+        market_account: Option<&str>,
+    ) -> Result<(String, String), String> {
+        let account = self.gateway.market_address(market_id);
+        if let Some(key) = market_account {
+            if self.gateway.parse_pubkey(key).map_err(|err| err.to_string())? != account {
+                return Err(format!("{} is not the PDA of market {}, {}", key, market_id, account));
+            }
+        }
+        let ix = self.gateway.build_initialize_market_ix(
+            self.admin.pubkey(),
+            market_id,
+            self.oracle_authority,
+            market.max_leverage_bps,
+            market.initial_margin_bps,
+            market.maintenance_margin_bps,
+        );
+        let signature = self.send(&[ix], &[]).await?;
+        Ok((account.to_string(), signature))
+    }
+
+    async fn update_market_params(
+        &self,
+        market: &MarketConfig,
+        market_id: u16,
+        market_account: &str,
+    ) -> Result<String, String> {
+        let account = self.gateway.parse_pubkey(market_account).map_err(|err| err.to_string())?;
+        let ix = self.gateway.build_update_market_params_ix(
+            self.admin.pubkey(),
+            account,
+            market_id,
+            market.max_leverage_bps,
+            market.initial_margin_bps,
+            market.maintenance_margin_bps,
+            market.status.into(),
+        );
+        self.send(&[ix], &[]).await
+    }
+
+    async fn settle_market(&self, market: &MarketConfig, market_id: u16, market_account: &str) -> Result<String, String> {
+        let account = self.gateway.parse_pubkey(market_account).map_err(|err| err.to_string())?;
+        let settlement_price = market
+            .settlement_price
+            .ok_or_else(|| format!("{} has no settlement price", market.symbol))?;
+        let ix = self
+            .gateway
+            .build_settle_market_ix(self.admin.pubkey(), account, market_id, settlement_price);
+        self.send(&[ix], &[]).await
+    }
+
+    async fn settle_position(
+        &self,
+        market_id: u16,
+        market_account: &str,
+        account_state: &str,
+        position_account: &str,
+    ) -> Result<String, String> {
+        let parse = |key: &str| self.gateway.parse_pubkey(key).map_err(|err| err.to_string());
+        let ix = self.gateway.build_settle_position_ix(
+            self.admin.pubkey(),
+            parse(account_state)?,
+            parse(market_account)?,
+            parse(position_account)?,
+            market_id,
+        );
+        self.send(&[ix], &[]).await
+    }
+
+    async fn migrate_market(&self, market_id: u16, market_account: &str) -> Result<(String, String), String> {
+        let account = self.gateway.parse_pubkey(market_account).map_err(|err| err.to_string())?;
+        let ix = self.gateway.build_migrate_market_ix(self.admin.pubkey(), account, market_id);
+        let signature = self.send(&[ix], &[]).await?;
+        Ok((self.gateway.market_address(market_id).to_string(), signature))
+    }
+
+    async fn set_oracle_authority(&self, market_id: u16, market_account: &str) -> Result<String, String> {
+        let account = self.gateway.parse_pubkey(market_account).map_err(|err| err.to_string())?;
+        let ix = self
+            .gateway
+            .build_set_oracle_authority_ix(self.admin.pubkey(), account, market_id, self.oracle_authority);
+        self.send(&[ix], &[]).await
+    }
+
+    async fn program_config(&self) -> Result<Option<ProgramConfig>, String> {
+        let account = self
+            .client
+            .get_account_with_commitment(&self.gateway.config_address(), self.client.commitment())
+            .await
+            .map_err(|err| err.to_string())?
+            .value;
+        let Some(account) = account else {
+            return Ok(None);
+        };
+        let state = ProgramConfigState::try_from_slice(&account.data).map_err(|err| err.to_string())?;
+        Ok(Some(ProgramConfig {
+            admin: state.admin.to_string(),
+            pending_admin: (state.pending_admin != Pubkey::default()).then(|| state.pending_admin.to_string()),
+            guardian: state.guardian.to_string(),
+            paused: state.paused,
+        }))
+    }
+
+    async fn initialize_config(&self, guardian: &str) -> Result<String, String> {
+        let guardian = self.gateway.parse_pubkey(guardian).map_err(|err| err.to_string())?;
+        let ix = self.gateway.build_initialize_config_ix(self.admin.pubkey(), guardian);
+        self.send(&[ix], &[]).await
+    }
+
+    async fn set_pause(&self, paused: bool) -> Result<String, String> {
+        let ix = self
+            .gateway
+            .build_config_ix(self.admin.pubkey(), PerpsInstruction::SetPause { paused });
+        self.send(&[ix], &[]).await
+    }
+
+    async fn set_guardian(&self, guardian: &str) -> Result<String, String> {
+        let guardian = self.gateway.parse_pubkey(guardian).map_err(|err| err.to_string())?;
+        let ix = self.gateway.build_config_ix(
+            self.admin.pubkey(),
+            PerpsInstruction::SetGuardian {
+                guardian: guardian.to_bytes(),
+            },
+        );
+        self.send(&[ix], &[]).await
+    }
+
+    async fn propose_admin(&self, new_admin: &str) -> Result<String, String> {
+        let new_admin = self.gateway.parse_pubkey(new_admin).map_err(|err| err.to_string())?;
+        let ix = self.gateway.build_config_ix(
+            self.admin.pubkey(),
+            PerpsInstruction::ProposeAdmin {
+                new_admin: new_admin.to_bytes(),
+            },
+        );
+        self.send(&[ix], &[]).await
+    }
+
+    async fn accept_admin(&self) -> Result<String, String> {
+        let ix = self
+            .gateway
+            .build_config_ix(self.admin.pubkey(), PerpsInstruction::AcceptAdmin);
+        self.send(&[ix], &[]).await
+    }
+}
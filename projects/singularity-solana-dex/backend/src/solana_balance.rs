@@ -1,9 +1,10 @@
-use crate::errors::AppError;
+use crate::errors::{AppError, UpstreamError};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::str::FromStr;
 
 const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+const SERVICE: &str = "solana-rpc";
 
 #[derive(Debug, Deserialize)]
 struct RpcResponse {
@@ -65,21 +66,25 @@ pub async fn fetch_usdc_balance(owner: &str, rpc_url: &str) -> Result<Decimal, A
         .json(&body)
         .send()
         .await
-        .map_err(|_| AppError::Upstream)?;
+        .map_err(|err| UpstreamError::from_reqwest(SERVICE, err))?;
 
     if !response.status().is_success() {
-        return Err(AppError::Upstream);
+        return Err(UpstreamError::Status {
+            service: SERVICE.to_string(),
+            status: response.status().as_u16(),
+        }
+        .into());
     }
 
     let payload = response
         .json::<RpcResponse>()
         .await
-        .map_err(|_| AppError::Upstream)?;
+        .map_err(|err| UpstreamError::from_reqwest(SERVICE, err))?;
 
     let mut total = Decimal::ZERO;
     for account in payload.result.value {
         let amount = Decimal::from_str(&account.account.data.parsed.info.token_amount.ui_amount_string)
-            .map_err(|_| AppError::Upstream)?;
+            .map_err(|_| UpstreamError::invalid(SERVICE, "invalid token amount"))?;
         total += amount;
     }
 
@@ -1,24 +1,66 @@
+use crate::candles::CandleAggregator;
 use crate::db::Store;
-use crate::models::Account;
+use crate::events::EventBus;
+use crate::idempotency::IdempotencyGuard;
+use crate::liquidation_queue::{CrankMarks, CrankPlan};
+use crate::mark_price::{MarkPriceConfig, MarkPrices};
+use crate::models::{Account, PublisherStatus};
+use crate::price_feed::PriceFeed;
+use crate::admin::MarketChain;
+use crate::registry::Registry;
 use crate::risk::RiskEngine;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 pub struct AppState {
     pub store: Arc<dyn Store>,
     pub risk: RiskEngine,
+    pub registry: Registry,
+    /// Submits market changes on-chain; `None` when the backend has no admin keypair.
+    pub market_chain: Option<Arc<dyn MarketChain>>,
+    pub prices: Arc<PriceFeed>,
+    pub marks: MarkPrices,
     pub accounts: RwLock<HashMap<Uuid, Account>>,
+    pub idempotency: IdempotencyGuard,
+    pub events: EventBus,
+    pub candles: CandleAggregator,
+    /// Latest cycle of a crank running in dry-run mode.
+    pub crank_plan: Mutex<Option<CrankPlan>>,
+    /// Prices of the crank's latest cycle, which `/liquidations/queue` ranks with; `None`
+    /// when it isn't running.
+    pub crank_marks: Mutex<Option<CrankMarks>>,
+    /// Written by the oracle publisher after every cycle; `None` when it isn't running.
+    pub publisher: Mutex<Option<PublisherStatus>>,
 }
 
 impl AppState {
-    pub fn new(store: Arc<dyn Store>, risk: RiskEngine, accounts: Vec<Account>) -> Self {
+    pub fn new(
+        store: Arc<dyn Store>,
+        mut registry: Registry,
+        market_chain: Option<Arc<dyn MarketChain>>,
+        prices: Arc<PriceFeed>,
+        marks: MarkPriceConfig,
+        accounts: Vec<Account>,
+        idempotency_window_secs: u64,
+    ) -> Self {
+        let risk_markets = registry.risk_markets();
         let map = accounts.into_iter().map(|account| (account.id, account)).collect();
         Self {
             store,
-            risk,
+            risk: RiskEngine::new(risk_markets),
+            registry,
+            market_chain,
+            prices,
+            marks: MarkPrices::new(marks),
             accounts: RwLock::new(map),
+            idempotency: IdempotencyGuard::new(idempotency_window_secs),
+            events: EventBus::new(),
+            candles: CandleAggregator::new(),
+            crank_plan: Mutex::new(None),
+            crank_marks: Mutex::new(None),
+            publisher: Mutex::new(None),
         }
     }
 }
@@ -0,0 +1,106 @@
+//! Decoding of Switchboard V2 aggregator accounts, the second on-chain source in a market's
+//! oracle chain.
+//!
+//! Only the latest confirmed round is read: its median result and standard deviation are
+//! `SwitchboardDecimal`s (an `i128` mantissa and a `u32` scale), opened at a unix timestamp.
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+const AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+/// `latest_confirmed_round` within the packed account, after the discriminator and the
+/// aggregator's configuration fields.
+const LATEST_ROUND: usize = 8 + 333;
+const ROUND_OPEN_TIMESTAMP: usize = LATEST_ROUND + 17;
+const ROUND_RESULT: usize = LATEST_ROUND + 25;
+const ROUND_STD_DEVIATION: usize = LATEST_ROUND + 45;
+const MIN_LEN: usize = ROUND_STD_DEVIATION + 20;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SwitchboardError {
+    #[error("account data is {0} bytes, too short for an aggregator")]
+    TooShort(usize),
+    #[error("not a Switchboard aggregator account")]
+    UnknownLayout,
+    #[error("decimal with scale {0} is out of range")]
+    ScaleOutOfRange(u32),
+    #[error("aggregator has no confirmed round with a positive result")]
+    NoResult,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwitchboardPrice {
+    pub price: Decimal,
+    /// Standard deviation of the oracle responses in the round.
+    pub std_deviation: Decimal,
+    /// Unix seconds the round opened.
+    pub round_open_timestamp: i64,
+}
+
+pub fn decode(data: &[u8]) -> Result<SwitchboardPrice, SwitchboardError> {
+    if data.len() < 8 || data[..8] != AGGREGATOR_DISCRIMINATOR {
+        return Err(SwitchboardError::UnknownLayout);
+    }
+    if data.len() < MIN_LEN {
+        return Err(SwitchboardError::TooShort(data.len()));
+    }
+    let price = read_decimal(data, ROUND_RESULT)?;
+    if price <= Decimal::ZERO {
+        return Err(SwitchboardError::NoResult);
+    }
+    Ok(SwitchboardPrice {
+        price,
+        std_deviation: read_decimal(data, ROUND_STD_DEVIATION)?,
+        round_open_timestamp: i64::from_le_bytes(
+            data[ROUND_OPEN_TIMESTAMP..ROUND_OPEN_TIMESTAMP + 8].try_into().unwrap(),
+        ),
+    })
+}
+
+fn read_decimal(data: &[u8], offset: usize) -> Result<Decimal, SwitchboardError> {
+    let mantissa = i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap());
+    let scale = u32::from_le_bytes(data[offset + 16..offset + 20].try_into().unwrap());
+    Decimal::try_from_i128_with_scale(mantissa, scale)
+        .map(|value| value.normalize())
+        .map_err(|_| SwitchboardError::ScaleOutOfRange(scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregator_latest_round() {
+        let price = decode(include_bytes!("../fixtures/switchboard/sol_usd_aggregator.bin")).unwrap();
+        assert_eq!(price.price, Decimal::new(14_253, 2));
+        assert_eq!(price.std_deviation, Decimal::new(125, 4));
+        assert_eq!(price.round_open_timestamp, 1_792_000_010);
+
+        let price = decode(include_bytes!("../fixtures/switchboard/btc_usd_aggregator.bin")).unwrap();
+        assert_eq!(price.price, Decimal::new(670_423_456_789, 7));
+        assert_eq!(price.std_deviation, Decimal::new(152, 1));
+        assert_eq!(price.round_open_timestamp, 1_792_000_012);
+    }
+
+    #[test]
+    fn aggregator_without_a_confirmed_round() {
+        assert_eq!(
+            decode(include_bytes!("../fixtures/switchboard/empty_round_aggregator.bin")),
+            Err(SwitchboardError::NoResult)
+        );
+    }
+
+    #[test]
+    fn other_accounts_are_rejected() {
+        let data = include_bytes!("../fixtures/switchboard/sol_usd_aggregator.bin");
+        assert_eq!(decode(&data[..MIN_LEN - 1]), Err(SwitchboardError::TooShort(MIN_LEN - 1)));
+        assert_eq!(
+            decode(include_bytes!("../fixtures/pyth/sol_usd_legacy.bin")),
+            Err(SwitchboardError::UnknownLayout)
+        );
+
+        let mut data = data.to_vec();
+        data[ROUND_RESULT + 16..ROUND_RESULT + 20].copy_from_slice(&40u32.to_le_bytes());
+        assert_eq!(decode(&data), Err(SwitchboardError::ScaleOutOfRange(40)));
+    }
+}
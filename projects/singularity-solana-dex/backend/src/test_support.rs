@@ -0,0 +1,289 @@
+//! Shared setup for route tests: an in-memory store, the built-in markets and the bundled
+//! price fixtures, as `PRICE_PROVIDERS=fixture` would configure them.
+
+use crate::admin::{MarketChain, ProgramConfig};
+use crate::candles::{Candle, Resolution};
+use crate::db::{MemoryStore, Store};
+use crate::errors::AppError;
+use crate::mark_price::{MarkPriceConfig, MarkPriceMode};
+use crate::models::{Account, IdempotentResponse, MarketConfig, PendingLiquidation, Position};
+use crate::price_feed::{CacheConfig, FixtureProvider, IndexConfig, PriceFeed};
+use crate::registry::{MarketRegistry, Registry};
+use crate::risk::default_markets;
+use crate::state::AppState;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+pub fn state() -> Arc<AppState> {
+    state_with(Vec::new())
+}
+
+pub fn state_with(accounts: Vec<Account>) -> Arc<AppState> {
+    state_with_store(Arc::new(MemoryStore::new()), None, accounts)
+}
+
+/// Like [`state_with`], over `store` and with `chain` as the on-chain side of admin changes.
+pub fn state_with_store(
+    store: Arc<dyn Store>,
+    chain: Option<Arc<dyn MarketChain>>,
+    accounts: Vec<Account>,
+) -> Arc<AppState> {
+    let registry = Registry::new(None, MarketRegistry::from_parts(default_markets(), Vec::new()));
+    let prices = PriceFeed::new(
+        vec![Arc::new(FixtureProvider::bundled())],
+        IndexConfig::from_env().expect("default index config"),
+        CacheConfig::from_env(),
+    );
+    let marks = MarkPriceConfig {
+        mode: MarkPriceMode::Authoritative,
+        alpha: 0.1,
+        half_life_secs: 300.0,
+        clamp_bps: 50,
+        max_fill_deviation_bps: 100,
+    };
+    Arc::new(AppState::new(
+        store,
+        registry,
+        chain,
+        Arc::new(prices),
+        marks,
+        accounts,
+        86_400,
+    ))
+}
+
+pub fn app(state: &Arc<AppState>) -> Router {
+    crate::routes::router(state.clone())
+}
+
+/// A request with a JSON body, or none when `body` is `Value::Null`.
+pub fn request(method: Method, uri: &str, body: Value) -> Request<Body> {
+    let builder = Request::builder().method(method).uri(uri);
+    match body {
+        Value::Null => builder.body(Body::empty()).unwrap(),
+        body => builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+    }
+}
+
+pub const ADMIN_KEY: &str = "test-admin-key";
+
+/// Like [`request`], authorized with the admin key. The admin routes read `ADMIN_API_KEY` when
+/// the router is built, so this sets it for every test that builds one.
+pub fn admin_request(method: Method, uri: &str, body: Value) -> Request<Body> {
+    std::env::set_var("ADMIN_API_KEY", ADMIN_KEY);
+    let mut request = request(method, uri, body);
+    request
+        .headers_mut()
+        .insert("authorization", format!("Bearer {}", ADMIN_KEY).parse().unwrap());
+    request
+}
+
+/// Sends `request` and returns the status, headers and the body parsed as JSON (`Null` when
+/// it isn't JSON).
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, axum::http::HeaderMap, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, headers, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+/// A pretend chain for the admin and settlement paths. It settles positions, noting whether
+/// the accounts were free to lock while it did, fails for `rejected`'s position, and serves
+/// `config` as the program config. Anything else is unexpected.
+#[derive(Default)]
+pub struct StubChain {
+    state: OnceLock<Weak<AppState>>,
+    rejected: Option<String>,
+    pub settled_positions: Mutex<Vec<(String, bool)>>,
+    pub config: Mutex<Option<ProgramConfig>>,
+}
+
+impl StubChain {
+    pub fn rejecting(position_account: &str) -> Self {
+        Self {
+            rejected: Some(position_account.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Lets the chain look at `state` while a call is in flight.
+    pub fn attach(&self, state: &Arc<AppState>) {
+        self.state.set(Arc::downgrade(state)).ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl MarketChain for StubChain {
+    async fn initialize_market(&self, _: &MarketConfig, _: u16, _: Option<&str>) -> Result<(String, String), String> {
+        unreachable!()
+    }
+    async fn update_market_params(&self, _: &MarketConfig, _: u16, _: &str) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn settle_market(&self, _: &MarketConfig, _: u16, _: &str) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn settle_position(
+        &self,
+        _market_id: u16,
+        _market_account: &str,
+        _account_state: &str,
+        position_account: &str,
+    ) -> Result<String, String> {
+        let unlocked = match self.state.get().and_then(Weak::upgrade) {
+            Some(state) => state.accounts.try_write().is_ok(),
+            None => true,
+        };
+        self.settled_positions
+            .lock()
+            .unwrap()
+            .push((position_account.to_string(), unlocked));
+        match &self.rejected {
+            Some(rejected) if rejected == position_account => Err("simulation failed".to_string()),
+            _ => Ok(format!("sig-{}", position_account)),
+        }
+    }
+    async fn migrate_market(&self, _: u16, _: &str) -> Result<(String, String), String> {
+        unreachable!()
+    }
+    async fn set_oracle_authority(&self, _: u16, _: &str) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn program_config(&self) -> Result<Option<ProgramConfig>, String> {
+        Ok(self.config.lock().unwrap().clone())
+    }
+    async fn initialize_config(&self, _: &str) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn set_pause(&self, _: bool) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn set_guardian(&self, _: &str) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn propose_admin(&self, _: &str) -> Result<String, String> {
+        unreachable!()
+    }
+    async fn accept_admin(&self) -> Result<String, String> {
+        unreachable!()
+    }
+}
+
+/// A [`MemoryStore`] whose operations, named after the [`Store`] methods, fail while listed
+/// in `failing`.
+pub struct FlakyStore {
+    inner: MemoryStore,
+    failing: Mutex<HashSet<&'static str>>,
+}
+
+impl FlakyStore {
+    pub fn failing(operations: &[&'static str]) -> Self {
+        Self {
+            inner: MemoryStore::new(),
+            failing: Mutex::new(operations.iter().copied().collect()),
+        }
+    }
+
+    pub fn recover(&self) {
+        self.failing.lock().unwrap().clear();
+    }
+
+    fn check(&self, operation: &str) -> Result<(), AppError> {
+        if self.failing.lock().unwrap().contains(operation) {
+            return Err(AppError::Database(sqlx::Error::PoolTimedOut));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for FlakyStore {
+    async fn load_state(&self) -> Result<Vec<Account>, AppError> {
+        self.check("load_state")?;
+        self.inner.load_state().await
+    }
+    async fn create_account(&self, account: &Account) -> Result<(), AppError> {
+        self.check("create_account")?;
+        self.inner.create_account(account).await
+    }
+    async fn update_account_collateral(&self, account_id: Uuid, collateral: Decimal) -> Result<(), AppError> {
+        self.check("update_account_collateral")?;
+        self.inner.update_account_collateral(account_id, collateral).await
+    }
+    async fn upsert_position(&self, account_id: Uuid, position: &Position) -> Result<(), AppError> {
+        self.check("upsert_position")?;
+        self.inner.upsert_position(account_id, position).await
+    }
+    async fn delete_position(&self, account_id: Uuid, market: &str) -> Result<(), AppError> {
+        self.check("delete_position")?;
+        self.inner.delete_position(account_id, market).await
+    }
+    async fn load_idempotent_response(&self, key: &str) -> Result<Option<IdempotentResponse>, AppError> {
+        self.check("load_idempotent_response")?;
+        self.inner.load_idempotent_response(key).await
+    }
+    async fn save_idempotent_response(&self, response: &IdempotentResponse) -> Result<(), AppError> {
+        self.check("save_idempotent_response")?;
+        self.inner.save_idempotent_response(response).await
+    }
+    async fn delete_idempotent_responses_before(&self, cutoff: i64) -> Result<u64, AppError> {
+        self.check("delete_idempotent_responses_before")?;
+        self.inner.delete_idempotent_responses_before(cutoff).await
+    }
+    async fn merge_candles(&self, candles: &[Candle]) -> Result<(), AppError> {
+        self.check("merge_candles")?;
+        self.inner.merge_candles(candles).await
+    }
+    async fn load_candles(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Candle>, AppError> {
+        self.check("load_candles")?;
+        self.inner.load_candles(symbol, resolution, from, to).await
+    }
+    async fn last_candle_before(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        before: i64,
+    ) -> Result<Option<i64>, AppError> {
+        self.check("last_candle_before")?;
+        self.inner.last_candle_before(symbol, resolution, before).await
+    }
+    async fn save_pending_liquidation(&self, pending: &PendingLiquidation) -> Result<(), AppError> {
+        self.check("save_pending_liquidation")?;
+        self.inner.save_pending_liquidation(pending).await
+    }
+    async fn delete_pending_liquidation(&self, account_id: Uuid, market: &str) -> Result<(), AppError> {
+        self.check("delete_pending_liquidation")?;
+        self.inner.delete_pending_liquidation(account_id, market).await
+    }
+    async fn load_pending_liquidations(&self) -> Result<Vec<PendingLiquidation>, AppError> {
+        self.check("load_pending_liquidations")?;
+        self.inner.load_pending_liquidations().await
+    }
+
+    async fn save_paused(&self, paused: bool) -> Result<(), AppError> {
+        self.check("save_paused")?;
+        self.inner.save_paused(paused).await
+    }
+
+    async fn load_paused(&self) -> Result<bool, AppError> {
+        self.check("load_paused")?;
+        self.inner.load_paused().await
+    }
+}
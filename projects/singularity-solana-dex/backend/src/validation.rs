@@ -0,0 +1,303 @@
+use crate::admin::{CreateMarketRequest, MarketStatusRequest, SettleMarketRequest, UpdateMarketRequest};
+use crate::errors::AppError;
+use crate::models::{
+    AdjustLeverageRequest, ClosePositionRequest, CreateAccountRequest, DepositRequest, MarketConfig, MarketStatus,
+    OpenPositionRequest, RiskCheckRequest, SetCollateralRequest, WithdrawRequest,
+};
+use crate::program_admin::{GuardianRequest, PauseRequest, ProposeAdminRequest};
+use crate::risk::RiskEngine;
+use crate::state::AppState;
+use axum::extract::{FromRequest, FromRequestParts, RawPathParams, Request};
+use axum::Json;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Collateral is USDC, so amounts never need more than its 6 decimals.
+const MAX_AMOUNT_SCALE: u32 = 6;
+/// Used for prices when the market (and so its tick size) is unknown.
+const MAX_PRICE_SCALE: u32 = 10;
+const MAX_OWNER_LEN: usize = 64;
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// What a request is validated against: the market registry and the `:market` (or admin
+/// `:symbol`) path segment, if any.
+pub struct ValidationContext<'a> {
+    pub risk: &'a RiskEngine,
+    pub path_market: Option<&'a str>,
+}
+
+impl ValidationContext<'_> {
+    fn market(&self, symbol: &str) -> Option<MarketConfig> {
+        self.risk.market(symbol)
+    }
+}
+
+pub trait Validate {
+    fn validate(&self, ctx: &ValidationContext, v: &mut Validator);
+}
+
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn positive(&mut self, field: &str, value: Decimal) -> bool {
+        if value <= Decimal::ZERO {
+            self.error(field, "must be greater than zero");
+            return false;
+        }
+        true
+    }
+
+    pub fn non_negative(&mut self, field: &str, value: Decimal) -> bool {
+        if value.is_sign_negative() && !value.is_zero() {
+            self.error(field, "must not be negative");
+            return false;
+        }
+        true
+    }
+
+    pub fn max_scale(&mut self, field: &str, value: Decimal, max_scale: u32) {
+        if value.normalize().scale() > max_scale {
+            self.error(field, format!("must have at most {} decimal places", max_scale));
+        }
+    }
+
+    pub fn multiple_of(&mut self, field: &str, value: Decimal, step: Decimal, name: &str) {
+        if step > Decimal::ZERO && !(value % step).is_zero() {
+            self.error(field, format!("must be a multiple of the {} {}", name, step.normalize()));
+        }
+    }
+
+    fn amount(&mut self, field: &str, value: Decimal) {
+        if self.positive(field, value) {
+            self.max_scale(field, value, MAX_AMOUNT_SCALE);
+        }
+    }
+
+    fn price(&mut self, field: &str, value: Decimal, market: Option<&MarketConfig>) {
+        if !self.positive(field, value) {
+            return;
+        }
+        match market {
+            Some(market) => self.multiple_of(field, value, market.tick_size, "tick size"),
+            None => self.max_scale(field, value, MAX_PRICE_SCALE),
+        }
+    }
+
+    fn leverage(&mut self, field: &str, value: u32) {
+        if value == 0 {
+            self.error(field, "must be greater than zero");
+        }
+    }
+
+    /// Shape only: 32 to 44 base58 characters. Whether it decodes to a key is up to the chain.
+    fn pubkey(&mut self, field: &str, value: &str) {
+        if !(32..=44).contains(&value.len()) || !value.chars().all(|c| BASE58_ALPHABET.contains(c)) {
+            self.error(field, "must be a base58 public key");
+        }
+    }
+
+    pub fn finish(self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(self.errors))
+        }
+    }
+}
+
+impl Validate for CreateAccountRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        if self.owner.trim().is_empty() {
+            v.error("owner", "must not be empty");
+        } else if self.owner.len() > MAX_OWNER_LEN {
+            v.error("owner", format!("must be at most {} characters", MAX_OWNER_LEN));
+        }
+    }
+}
+
+impl Validate for DepositRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        v.amount("amount", self.amount);
+    }
+}
+
+impl Validate for WithdrawRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        v.amount("amount", self.amount);
+    }
+}
+
+impl Validate for SetCollateralRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        if v.non_negative("amount", self.amount) {
+            v.max_scale("amount", self.amount, MAX_AMOUNT_SCALE);
+        }
+    }
+}
+
+impl Validate for OpenPositionRequest {
+    fn validate(&self, ctx: &ValidationContext, v: &mut Validator) {
+        // Unknown markets are left to the risk engine, which answers MARKET_NOT_FOUND.
+        let market = ctx.market(&self.market);
+        if v.positive("base_qty", self.base_qty) {
+            match &market {
+                Some(market) => v.multiple_of("base_qty", self.base_qty, market.lot_size, "lot size"),
+                None => v.max_scale("base_qty", self.base_qty, MAX_PRICE_SCALE),
+            }
+        }
+        v.price("entry_price", self.entry_price, market.as_ref());
+        v.leverage("leverage_bps", self.leverage_bps);
+    }
+}
+
+impl Validate for ClosePositionRequest {
+    fn validate(&self, ctx: &ValidationContext, v: &mut Validator) {
+        let market = ctx.path_market.and_then(|symbol| ctx.market(symbol));
+        v.price("exit_price", self.exit_price, market.as_ref());
+    }
+}
+
+impl Validate for AdjustLeverageRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        v.leverage("new_leverage_bps", self.new_leverage_bps);
+    }
+}
+
+impl Validate for RiskCheckRequest {
+    fn validate(&self, ctx: &ValidationContext, v: &mut Validator) {
+        let mut symbols: Vec<&String> = self.mark_prices.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let field = format!("mark_prices.{}", symbol);
+            v.price(&field, self.mark_prices[symbol], ctx.market(symbol).as_ref());
+        }
+    }
+}
+
+/// Market parameters are checked against the whole registry when the market is added; this
+/// only catches what doesn't need it.
+impl Validate for CreateMarketRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        if self.market.symbol.trim().is_empty() {
+            v.error("symbol", "must not be empty");
+        }
+        if self.market.status == MarketStatus::Settled {
+            v.error("status", "a market can't be created settled");
+        }
+        if let Some(oracle) = &self.oracle {
+            if !oracle.market_account.is_empty() {
+                v.pubkey("oracle.market_account", &oracle.market_account);
+            }
+        }
+    }
+}
+
+impl Validate for UpdateMarketRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        for (field, value) in [
+            ("max_leverage_bps", self.max_leverage_bps),
+            ("initial_margin_bps", self.initial_margin_bps),
+            ("maintenance_margin_bps", self.maintenance_margin_bps),
+        ] {
+            if let Some(value) = value {
+                v.leverage(field, value);
+            }
+        }
+        for (field, value) in [
+            ("max_open_interest", self.max_open_interest),
+            ("tick_size", self.tick_size),
+            ("lot_size", self.lot_size),
+        ] {
+            if let Some(value) = value {
+                v.positive(field, value);
+            }
+        }
+    }
+}
+
+impl Validate for MarketStatusRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        if self.status == MarketStatus::Settled {
+            v.error("status", "markets are settled through /admin/markets/{symbol}/settle");
+        }
+    }
+}
+
+impl Validate for SettleMarketRequest {
+    fn validate(&self, ctx: &ValidationContext, v: &mut Validator) {
+        let market = ctx.path_market.and_then(|symbol| ctx.market(symbol));
+        v.price("settlement_price", self.settlement_price, market.as_ref());
+    }
+}
+
+impl Validate for PauseRequest {
+    fn validate(&self, _ctx: &ValidationContext, _v: &mut Validator) {}
+}
+
+impl Validate for GuardianRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        v.pubkey("guardian", &self.guardian);
+    }
+}
+
+impl Validate for ProposeAdminRequest {
+    fn validate(&self, _ctx: &ValidationContext, v: &mut Validator) {
+        v.pubkey("new_admin", &self.new_admin);
+    }
+}
+
+/// JSON body extractor that rejects the request with every field error when validation fails.
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T> FromRequest<Arc<AppState>> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = request.into_parts();
+        let path_params = RawPathParams::from_request_parts(&mut parts, state).await.ok();
+        let path_market = path_params
+            .as_ref()
+            .and_then(|params| params.iter().find(|(key, _)| *key == "market" || *key == "symbol").map(|(_, value)| value));
+
+        let Json(payload) = Json::<T>::from_request(Request::from_parts(parts, body), state)
+            .await
+            .map_err(|rejection| {
+                AppError::Validation(vec![FieldError {
+                    field: "body".to_string(),
+                    message: rejection.body_text(),
+                }])
+            })?;
+
+        let ctx = ValidationContext {
+            risk: &state.risk,
+            path_market,
+        };
+        let mut validator = Validator::default();
+        payload.validate(&ctx, &mut validator);
+        validator.finish()?;
+        Ok(ValidatedJson(payload))
+    }
+}
@@ -0,0 +1,350 @@
+use crate::events::Envelope;
+use crate::state::AppState;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use tracing::debug;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_SUBSCRIPTIONS: usize = 256;
+/// Channels anyone may subscribe to by prefix; account-scoped ones need the exact id.
+const WILDCARD_PREFIXES: [&str; 2] = ["prices:", "funding:"];
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe {
+        channels: Vec<String>,
+        since: Option<u64>,
+    },
+    Unsubscribe {
+        channels: Vec<String>,
+    },
+    Ping,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Welcome { seq: u64, heartbeat_secs: u64 },
+    Subscribed { channels: &'a BTreeSet<String> },
+    Unsubscribed { channels: &'a BTreeSet<String> },
+    Event(&'a Envelope),
+    Resync { seq: u64 },
+    Lagged { missed: u64 },
+    Pong,
+    Error { message: String },
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "streaming",
+    responses((status = 101, description = "WebSocket upgrade; see README for the subscribe protocol"))
+)]
+pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>) {
+    let mut events = state.events.subscribe();
+    let mut market_data = state.events.subscribe_market_data();
+    let mut subscriptions: BTreeSet<String> = BTreeSet::new();
+    let mut last_sent = state.events.last_seq();
+    let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    heartbeat.reset();
+    let mut last_seen = Instant::now();
+
+    let welcome = ServerMessage::Welcome {
+        seq: last_sent,
+        heartbeat_secs: HEARTBEAT_INTERVAL.as_secs(),
+    };
+    if send(&mut socket, &welcome).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let message = match incoming {
+                    Some(Ok(message)) => message,
+                    _ => break,
+                };
+                last_seen = Instant::now();
+                let text = match message {
+                    Message::Text(text) => text,
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                let result = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Subscribe { channels, since }) => {
+                        subscribe(&mut socket, &state, &mut subscriptions, &mut last_sent, channels, since).await
+                    }
+                    Ok(ClientMessage::Unsubscribe { channels }) => {
+                        let removed: BTreeSet<String> = channels
+                            .into_iter()
+                            .filter(|channel| subscriptions.remove(channel))
+                            .collect();
+                        send(&mut socket, &ServerMessage::Unsubscribed { channels: &removed }).await
+                    }
+                    Ok(ClientMessage::Ping) => send(&mut socket, &ServerMessage::Pong).await,
+                    Err(err) => {
+                        send(&mut socket, &ServerMessage::Error { message: err.to_string() }).await
+                    }
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            received = events.recv() => {
+                let result = match received {
+                    Ok(envelope) => {
+                        if envelope.seq <= last_sent {
+                            continue;
+                        }
+                        last_sent = envelope.seq;
+                        if !is_subscribed(&subscriptions, &envelope.channel) {
+                            continue;
+                        }
+                        send(&mut socket, &ServerMessage::Event(&envelope)).await
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        // Slow consumer: tell the client to refetch rather than buffering without bound.
+                        send(&mut socket, &ServerMessage::Lagged { missed }).await
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            received = market_data.recv() => {
+                let result = match received {
+                    Ok(envelope) if is_subscribed(&subscriptions, &envelope.channel) => {
+                        send(&mut socket, &ServerMessage::Event(&envelope)).await
+                    }
+                    // Prices missed by a slow client are superseded by the next tick.
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT {
+                    debug!("websocket client timed out");
+                    break;
+                }
+                if timeout(SEND_TIMEOUT, socket.send(Message::Ping(Vec::new()))).await.map_or(true, |res| res.is_err()) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn subscribe(
+    socket: &mut WebSocket,
+    state: &AppState,
+    subscriptions: &mut BTreeSet<String>,
+    last_sent: &mut u64,
+    channels: Vec<String>,
+    since: Option<u64>,
+) -> Result<(), ()> {
+    let added: BTreeSet<String> = channels
+        .into_iter()
+        .map(|channel| normalize_channel(&channel))
+        .filter(|channel| !channel.is_empty())
+        .collect();
+    if let Some(channel) = added.iter().find(|channel| !wildcard_allowed(channel)) {
+        let message = format!(
+            "{}: wildcards are only allowed on {}",
+            channel,
+            WILDCARD_PREFIXES.map(|prefix| format!("{}*", prefix)).join(", ")
+        );
+        return send(socket, &ServerMessage::Error { message }).await;
+    }
+    if subscriptions.len() + added.len() > MAX_SUBSCRIPTIONS {
+        let message = format!("at most {} subscriptions per connection", MAX_SUBSCRIPTIONS);
+        return send(socket, &ServerMessage::Error { message }).await;
+    }
+    let previous = subscriptions.clone();
+    subscriptions.extend(added.iter().cloned());
+    send(socket, &ServerMessage::Subscribed { channels: subscriptions }).await?;
+
+    // Reconnecting clients pass the last seq they saw; replay what they missed on the new channels.
+    if let Some(since) = since {
+        match state.events.replay_since(since) {
+            Some(missed) => {
+                for envelope in missed {
+                    // Events past `last_sent` are still queued for the live loop, so deliver them
+                    // here for every subscription and let the loop skip them as duplicates.
+                    let deliver = if envelope.seq > *last_sent {
+                        *last_sent = envelope.seq;
+                        is_subscribed(subscriptions, &envelope.channel)
+                    } else {
+                        is_subscribed(&added, &envelope.channel)
+                            && !is_subscribed(&previous, &envelope.channel)
+                    };
+                    if deliver {
+                        send(socket, &ServerMessage::Event(&envelope)).await?;
+                    }
+                }
+            }
+            None => {
+                let seq = state.events.last_seq();
+                send(socket, &ServerMessage::Resync { seq }).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn normalize_channel(channel: &str) -> String {
+    match channel.trim().split_once(':') {
+        Some((name @ ("prices" | "funding"), symbol)) => format!("{}:{}", name, symbol.to_uppercase()),
+        Some((name, rest)) => format!("{}:{}", name, rest),
+        None => channel.trim().to_string(),
+    }
+}
+
+fn wildcard_allowed(channel: &str) -> bool {
+    channel
+        .strip_suffix('*')
+        .is_none_or(|prefix| WILDCARD_PREFIXES.iter().any(|public| prefix.starts_with(public)))
+}
+
+fn channel_matches(subscription: &str, channel: &str) -> bool {
+    match subscription.strip_suffix('*') {
+        Some(prefix) => channel.starts_with(prefix),
+        None => subscription == channel,
+    }
+}
+
+fn is_subscribed(subscriptions: &BTreeSet<String>, channel: &str) -> bool {
+    subscriptions
+        .iter()
+        .any(|subscription| channel_matches(subscription, channel))
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage<'_>) -> Result<(), ()> {
+    let text = serde_json::to_string(message).map_err(|_| ())?;
+    match timeout(SEND_TIMEOUT, socket.send(Message::Text(text))).await {
+        Ok(Ok(())) => Ok(()),
+        _ => Err(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use crate::models::Account;
+    use crate::test_support::state;
+    use futures_util::{SinkExt, StreamExt};
+    use rust_decimal::Decimal;
+    use serde_json::{json, Value};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+    use uuid::Uuid;
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Serves `state` on a free local port and connects to its `/ws`, past the welcome.
+    async fn connect(state: &Arc<AppState>) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = crate::routes::router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        assert_eq!(next(&mut client).await["type"], "welcome");
+        client
+    }
+
+    async fn next(client: &mut Client) -> Value {
+        loop {
+            let message = timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap();
+            if let tungstenite::Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    async fn request(client: &mut Client, message: Value) -> Value {
+        client.send(tungstenite::Message::Text(message.to_string())).await.unwrap();
+        next(client).await
+    }
+
+    #[tokio::test]
+    async fn account_channels_cannot_be_subscribed_by_wildcard() {
+        let state = state();
+        let mut client = connect(&state).await;
+        for channel in ["account:*", "positions:*", "*", "acc*"] {
+            let answer = request(&mut client, json!({ "op": "subscribe", "channels": [channel] })).await;
+            assert_eq!(answer["type"], "error", "{}", channel);
+        }
+        let answer = request(&mut client, json!({ "op": "subscribe", "channels": ["prices:*", "funding:b*"] })).await;
+        assert_eq!(answer["type"], "subscribed");
+        assert_eq!(answer["channels"], json!(["funding:B*", "prices:*"]));
+    }
+
+    #[tokio::test]
+    async fn resubscribing_since_replays_a_missed_account_event() {
+        let state = state();
+        let account_id = Uuid::new_v4();
+        // Missed while disconnected: one account update among many more price ticks than
+        // the history holds.
+        state.events.publish(Event::Account {
+            account: Account {
+                id: account_id,
+                owner: "owner".to_string(),
+                account_state: None,
+                collateral: Decimal::from(100),
+                positions: Default::default(),
+            },
+        });
+        for _ in 0..5_000 {
+            state.events.publish(Event::Price {
+                symbol: "BTC".to_string(),
+                price: Decimal::from(60_000),
+            });
+        }
+
+        let mut client = connect(&state).await;
+        let channel = format!("account:{}", account_id);
+        let answer = request(&mut client, json!({ "op": "subscribe", "channels": [channel], "since": 0 })).await;
+        assert_eq!(answer["type"], "subscribed");
+        let replayed = next(&mut client).await;
+        assert_eq!(replayed["type"], "event");
+        assert_eq!(replayed["channel"], channel);
+        assert_eq!(replayed["seq"], 1);
+    }
+
+    #[test]
+    fn market_channels_are_case_insensitive() {
+        assert_eq!(normalize_channel(" prices:btc "), "prices:BTC");
+        assert_eq!(normalize_channel("funding:eth"), "funding:ETH");
+        assert_eq!(normalize_channel("account:AbC"), "account:AbC");
+    }
+
+    #[test]
+    fn wildcards_match_by_prefix() {
+        let subscriptions = BTreeSet::from(["prices:*".to_string(), "funding:BTC".to_string()]);
+        assert!(is_subscribed(&subscriptions, "prices:ETH"));
+        assert!(is_subscribed(&subscriptions, "funding:BTC"));
+        assert!(!is_subscribed(&subscriptions, "funding:ETH"));
+        assert!(!is_subscribed(&subscriptions, "liquidations"));
+        assert!(wildcard_allowed("prices:*") && wildcard_allowed("account:abc"));
+        assert!(!wildcard_allowed("account:*") && !wildcard_allowed("*"));
+    }
+}
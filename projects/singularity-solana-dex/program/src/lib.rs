@@ -1,20 +1,39 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint,
     entrypoint::ProgramResult,
     msg,
+    program::invoke_signed,
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_instruction,
+    sysvar::{rent::Rent, Sysvar},
 };
 use thiserror::Error;
 
 solana_program::declare_id!("525dTdNrVUY4S9hoZZaLnim5FNaTopxjcRbxYHXq66BK");
 
 const BPS_DIVISOR: u64 = 10_000;
+/// Seed of the program config PDA.
+pub const CONFIG_SEED: &[u8] = b"config";
+/// Seed of position PDAs, followed by the account state key and the little-endian market id.
+pub const POSITION_SEED: &[u8] = b"position";
+/// Seed of market PDAs, followed by the little-endian market id.
+pub const MARKET_SEED: &[u8] = b"market";
+const POSITION_STATE_LEN: usize = 1 + 32 + 2 + 1 + 8 + 8 + 4;
+const PROGRAM_CONFIG_LEN: usize = 1 + 32 + 32 + 32 + 1;
+/// Borsh size of [`MarketState`].
+pub const MARKET_STATE_LEN: usize = 1 + 2 + 32 + 4 + 4 + 4 + 8 + 8 + 32 + 1 + 8;
+/// Size of market accounts created before markets had an admin, a status and a settlement
+/// price; see [`LegacyMarketState`].
+pub const LEGACY_MARKET_STATE_LEN: usize = 1 + 2 + 32 + 4 + 4 + 4 + 8 + 8;
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub enum PerpsInstruction {
+    /// Only the program admin may send this, and re-initializing a market also needs its
+    /// `admin`. The market account is the PDA of [`market_address`], created here on first use.
     InitializeMarket {
         market_id: u16,
         oracle_authority: [u8; 32],
@@ -25,6 +44,7 @@ pub enum PerpsInstruction {
     InitializeAccount,
     Deposit { amount: u64 },
     Withdraw { amount: u64 },
+    /// The position account is the PDA of [`position_address`], created here on first use.
     OpenPosition {
         market_id: u16,
         base_qty: i64,
@@ -35,6 +55,38 @@ pub enum PerpsInstruction {
     ClosePosition { market_id: u16, exit_price: u64 },
     Liquidate { market_id: u16, exit_price: u64 },
     UpdatePrice { market_id: u16, price: u64 },
+    /// Only the market's `admin` may send this.
+    UpdateMarketParams {
+        market_id: u16,
+        max_leverage_bps: u32,
+        initial_margin_bps: u32,
+        maintenance_margin_bps: u32,
+        status: MarketStatus,
+    },
+    /// Delists the market at `settlement_price`; only the market's `admin` may send this.
+    SettleMarket { market_id: u16, settlement_price: u64 },
+    /// Closes a position in a settled market at its settlement price. Anyone may send this.
+    SettlePosition { market_id: u16 },
+    /// Creates the program config PDA with the signer as admin. The signer must be the
+    /// program's upgrade authority, so nobody can claim the config between deploy and init.
+    InitializeConfig { guardian: [u8; 32] },
+    /// The admin or guardian may pause; only the admin may unpause. While paused, opens and
+    /// withdrawals fail; closes, deposits and liquidations go through.
+    SetPause { paused: bool },
+    /// Admin only.
+    SetGuardian { guardian: [u8; 32] },
+    /// First step of an admin handover; the proposed key takes over with `AcceptAdmin`.
+    ProposeAdmin { new_admin: [u8; 32] },
+    AcceptAdmin,
+    /// Moves a market created at a keypair address to its PDA, closing the old account. A
+    /// legacy layout is upgraded to [`MarketState`] on the way, active and with the signer as
+    /// its admin. A legacy market's oracle authority, the only key that layout records, a
+    /// current market's admin, or the program admin may send it; the signer pays the PDA's
+    /// rent and gets the old account's back.
+    MigrateMarket { market_id: u16 },
+    /// Hands `UpdatePrice` for the market to another key; only the market's `admin` may send
+    /// this.
+    SetOracleAuthority { market_id: u16, oracle_authority: [u8; 32] },
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,6 +95,19 @@ pub enum Side {
     Short,
 }
 
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketStatus {
+    Active,
+    /// Opens only at prices that don't cross `last_price`.
+    PostOnly,
+    /// No new positions; closes and liquidations go through.
+    ReduceOnly,
+    /// Nothing trades, liquidations included.
+    Paused,
+    /// Final: positions only leave through `SettlePosition`.
+    Settled,
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub struct MarketState {
     pub is_initialized: bool,
@@ -53,6 +118,36 @@ pub struct MarketState {
     pub maintenance_margin_bps: u32,
     pub open_interest: u64,
     pub last_price: u64,
+    /// Signer of `InitializeMarket`; the only key allowed to change the market afterwards.
+    pub admin: Pubkey,
+    pub status: MarketStatus,
+    /// Price positions settle at once the market is `Settled`; zero before.
+    pub settlement_price: u64,
+}
+
+/// Market accounts of [`LEGACY_MARKET_STATE_LEN`] bytes, which only `MigrateMarket` reads.
+#[derive(BorshDeserialize, Debug, Clone)]
+pub struct LegacyMarketState {
+    pub is_initialized: bool,
+    pub market_id: u16,
+    pub oracle_authority: Pubkey,
+    pub max_leverage_bps: u32,
+    pub initial_margin_bps: u32,
+    pub maintenance_margin_bps: u32,
+    pub open_interest: u64,
+    pub last_price: u64,
+}
+
+/// Program-wide settings, kept at the PDA of [`CONFIG_SEED`].
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct ProgramConfig {
+    pub is_initialized: bool,
+    pub admin: Pubkey,
+    /// Proposed by the admin, waiting for `AcceptAdmin`; the default key when none is.
+    pub pending_admin: Pubkey,
+    /// May pause the program, but not unpause it or change anything else.
+    pub guardian: Pubkey,
+    pub paused: bool,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
@@ -84,6 +179,32 @@ pub enum PerpsError {
     InsufficientCollateral,
     #[error("invalid leverage")]
     InvalidLeverage,
+    #[error("invalid market parameters")]
+    InvalidMarketParams,
+    #[error("market is paused")]
+    MarketPaused,
+    #[error("market is reduce-only")]
+    MarketReduceOnly,
+    #[error("market is settled")]
+    MarketSettled,
+    #[error("market is not settled")]
+    MarketNotSettled,
+    #[error("post-only market: entry price would cross the last price")]
+    PostOnlyWouldCross,
+    #[error("program is paused")]
+    ProgramPaused,
+    #[error("invalid program config account")]
+    InvalidConfig,
+    #[error("account is not owned by the program")]
+    InvalidAccountOwner,
+    #[error("market account has the legacy layout; send MigrateMarket first")]
+    MarketNeedsMigration,
+    #[error("position account is not the position PDA of this account and market")]
+    InvalidPositionAccount,
+    #[error("a position is already open in this market")]
+    PositionExists,
+    #[error("market account is not the market PDA of its id")]
+    InvalidMarketAccount,
 }
 
 impl From<PerpsError> for ProgramError {
@@ -147,12 +268,47 @@ pub fn process_instruction(
         PerpsInstruction::UpdatePrice { market_id, price } => {
             update_price(accounts, program_id, market_id, price)
         }
+        PerpsInstruction::UpdateMarketParams {
+            market_id,
+            max_leverage_bps,
+            initial_margin_bps,
+            maintenance_margin_bps,
+            status,
+        } => update_market_params(
+            accounts,
+            program_id,
+            market_id,
+            max_leverage_bps,
+            initial_margin_bps,
+            maintenance_margin_bps,
+            status,
+        ),
+        PerpsInstruction::SettleMarket { market_id, settlement_price } => {
+            settle_market(accounts, program_id, market_id, settlement_price)
+        }
+        PerpsInstruction::SettlePosition { market_id } => settle_position(accounts, program_id, market_id),
+        PerpsInstruction::InitializeConfig { guardian } => {
+            initialize_config(accounts, program_id, Pubkey::new_from_array(guardian))
+        }
+        PerpsInstruction::SetPause { paused } => set_pause(accounts, program_id, paused),
+        PerpsInstruction::SetGuardian { guardian } => {
+            set_guardian(accounts, program_id, Pubkey::new_from_array(guardian))
+        }
+        PerpsInstruction::ProposeAdmin { new_admin } => {
+            propose_admin(accounts, program_id, Pubkey::new_from_array(new_admin))
+        }
+        PerpsInstruction::AcceptAdmin => accept_admin(accounts, program_id),
+        PerpsInstruction::MigrateMarket { market_id } => migrate_market(accounts, program_id, market_id),
+        PerpsInstruction::SetOracleAuthority {
+            market_id,
+            oracle_authority,
+        } => set_oracle_authority(accounts, program_id, market_id, Pubkey::new_from_array(oracle_authority)),
     }
 }
 
 fn initialize_market(
     accounts: &[AccountInfo],
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     market_id: u16,
     oracle_authority: Pubkey,
     max_leverage_bps: u32,
@@ -162,24 +318,71 @@ fn initialize_market(
     let mut iter = accounts.iter();
     let admin = next_account_info(&mut iter)?;
     let market_account = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+    let system_program = next_account_info(&mut iter)?;
 
-    if !admin.is_signer {
-        return Err(PerpsError::NotAuthorized.into());
+    // Markets are listed by the program admin alone; otherwise anyone could stand up a market
+    // with their own oracle authority under a real market's id.
+    admin_config(admin, config_account, program_id)?;
+    check_market_params(max_leverage_bps, initial_margin_bps, maintenance_margin_bps)?;
+    let (expected, bump) = market_address(market_id, program_id);
+    if *market_account.key != expected {
+        return Err(PerpsError::InvalidMarketAccount.into());
+    }
+    if market_account.data_is_empty() {
+        let rent = Rent::get()?.minimum_balance(MARKET_STATE_LEN);
+        invoke_signed(
+            &system_instruction::create_account(
+                admin.key,
+                market_account.key,
+                rent,
+                MARKET_STATE_LEN as u64,
+                program_id,
+            ),
+            &[admin.clone(), market_account.clone(), system_program.clone()],
+            &[&[MARKET_SEED, &market_id.to_le_bytes(), &[bump]]],
+        )?;
+    }
+    if market_account.owner != program_id {
+        return Err(PerpsError::InvalidAccountOwner.into());
+    }
+    if market_account.data_len() == LEGACY_MARKET_STATE_LEN {
+        return Err(PerpsError::MarketNeedsMigration.into());
     }
 
-    let mut market_state = MarketState::try_from_slice(&market_account.data.borrow())
-        .unwrap_or(MarketState {
-            is_initialized: false,
-            market_id,
-            oracle_authority,
-            max_leverage_bps,
-            initial_margin_bps,
-            maintenance_margin_bps,
-            open_interest: 0,
-            last_price: 0,
-        });
+    // Only a zeroed account is new; anything else must decode, so a live market is never
+    // mistaken for an empty one.
+    let existing = {
+        let data = market_account.data.borrow();
+        if data.iter().all(|byte| *byte == 0) {
+            None
+        } else {
+            Some(MarketState::try_from_slice(&data)?)
+        }
+    };
+    let mut market_state = existing.unwrap_or(MarketState {
+        is_initialized: false,
+        market_id,
+        oracle_authority,
+        max_leverage_bps,
+        initial_margin_bps,
+        maintenance_margin_bps,
+        open_interest: 0,
+        last_price: 0,
+        admin: *admin.key,
+        status: MarketStatus::Active,
+        settlement_price: 0,
+    });
+    // Re-initializing is how the admin resets a market; nobody else may take it over.
+    if market_state.is_initialized && market_state.admin != *admin.key {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    if market_state.is_initialized && market_state.status == MarketStatus::Settled {
+        return Err(PerpsError::MarketSettled.into());
+    }
 
     market_state.is_initialized = true;
+    market_state.admin = *admin.key;
     market_state.market_id = market_id;
     market_state.oracle_authority = oracle_authority;
     market_state.max_leverage_bps = max_leverage_bps;
@@ -233,14 +436,16 @@ fn deposit(accounts: &[AccountInfo], _program_id: &Pubkey, amount: u64) -> Progr
     Ok(())
 }
 
-fn withdraw(accounts: &[AccountInfo], _program_id: &Pubkey, amount: u64) -> ProgramResult {
+fn withdraw(accounts: &[AccountInfo], program_id: &Pubkey, amount: u64) -> ProgramResult {
     let mut iter = accounts.iter();
     let owner = next_account_info(&mut iter)?;
     let account_state_account = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
 
     if !owner.is_signer {
         return Err(PerpsError::NotAuthorized.into());
     }
+    ensure_not_paused(config_account, program_id)?;
 
     let mut state = AccountState::try_from_slice(&account_state_account.data.borrow())?;
     if state.owner != *owner.key {
@@ -259,7 +464,7 @@ fn withdraw(accounts: &[AccountInfo], _program_id: &Pubkey, amount: u64) -> Prog
 
 fn open_position(
     accounts: &[AccountInfo],
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     market_id: u16,
     base_qty: i64,
     entry_price: u64,
@@ -271,25 +476,67 @@ fn open_position(
     let account_state_account = next_account_info(&mut iter)?;
     let market_account = next_account_info(&mut iter)?;
     let position_account = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+    let system_program = next_account_info(&mut iter)?;
 
     if !owner.is_signer {
         return Err(PerpsError::NotAuthorized.into());
     }
+    ensure_not_paused(config_account, program_id)?;
 
-    let market = MarketState::try_from_slice(&market_account.data.borrow())?;
+    let market = load_market(market_account, program_id)?;
     if market.market_id != market_id {
         return Err(PerpsError::InvalidInstruction.into());
     }
+    match market.status {
+        MarketStatus::Active => {}
+        MarketStatus::PostOnly => {
+            let crosses = match side {
+                Side::Long => entry_price > market.last_price,
+                Side::Short => entry_price < market.last_price,
+            };
+            if crosses {
+                return Err(PerpsError::PostOnlyWouldCross.into());
+            }
+        }
+        MarketStatus::ReduceOnly => return Err(PerpsError::MarketReduceOnly.into()),
+        MarketStatus::Paused => return Err(PerpsError::MarketPaused.into()),
+        MarketStatus::Settled => return Err(PerpsError::MarketSettled.into()),
+    }
 
     if leverage_bps == 0 || leverage_bps > market.max_leverage_bps {
         return Err(PerpsError::InvalidLeverage.into());
     }
 
+    if account_state_account.owner != program_id {
+        return Err(PerpsError::InvalidAccountOwner.into());
+    }
     let mut account = AccountState::try_from_slice(&account_state_account.data.borrow())?;
     if account.owner != *owner.key {
         return Err(PerpsError::NotAuthorized.into());
     }
 
+    let (expected, bump) = position_address(account_state_account.key, market_id, program_id);
+    if *position_account.key != expected {
+        return Err(PerpsError::InvalidPositionAccount.into());
+    }
+    if position_account.data_is_empty() {
+        let rent = Rent::get()?.minimum_balance(POSITION_STATE_LEN);
+        invoke_signed(
+            &system_instruction::create_account(
+                owner.key,
+                position_account.key,
+                rent,
+                POSITION_STATE_LEN as u64,
+                program_id,
+            ),
+            &[owner.clone(), position_account.clone(), system_program.clone()],
+            &[&[POSITION_SEED, account_state_account.key.as_ref(), &market_id.to_le_bytes(), &[bump]]],
+        )?;
+    } else if PositionState::try_from_slice(&position_account.data.borrow())?.is_initialized {
+        return Err(PerpsError::PositionExists.into());
+    }
+
     let notional = (base_qty.unsigned_abs() as u128)
         .saturating_mul(entry_price as u128);
     let required_margin = notional
@@ -321,18 +568,20 @@ fn open_position(
 
 fn close_position(
     accounts: &[AccountInfo],
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     market_id: u16,
     _exit_price: u64,
 ) -> ProgramResult {
     let mut iter = accounts.iter();
     let owner = next_account_info(&mut iter)?;
     let account_state_account = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
     let position_account = next_account_info(&mut iter)?;
 
     if !owner.is_signer {
         return Err(PerpsError::NotAuthorized.into());
     }
+    ensure_trading(&load_market(market_account, program_id)?, market_id)?;
 
     let mut account = AccountState::try_from_slice(&account_state_account.data.borrow())?;
     if account.owner != *owner.key {
@@ -370,18 +619,20 @@ fn close_position(
 
 fn liquidate_position(
     accounts: &[AccountInfo],
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     market_id: u16,
     _exit_price: u64,
 ) -> ProgramResult {
     let mut iter = accounts.iter();
     let liquidator = next_account_info(&mut iter)?;
     let account_state_account = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
     let position_account = next_account_info(&mut iter)?;
 
     if !liquidator.is_signer {
         return Err(PerpsError::NotAuthorized.into());
     }
+    ensure_trading(&load_market(market_account, program_id)?, market_id)?;
 
     let mut account = AccountState::try_from_slice(&account_state_account.data.borrow())?;
     let position = PositionState::try_from_slice(&position_account.data.borrow())?;
@@ -417,7 +668,7 @@ fn liquidate_position(
 
 fn update_price(
     accounts: &[AccountInfo],
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     market_id: u16,
     price: u64,
 ) -> ProgramResult {
@@ -429,15 +680,970 @@ fn update_price(
         return Err(PerpsError::NotAuthorized.into());
     }
 
-    let mut market_state = MarketState::try_from_slice(&market_account.data.borrow())?;
+    let mut market_state = load_market(market_account, program_id)?;
     if market_state.market_id != market_id {
         return Err(PerpsError::InvalidInstruction.into());
     }
     if market_state.oracle_authority != *oracle_authority.key {
         return Err(PerpsError::NotAuthorized.into());
     }
+    if market_state.status == MarketStatus::Settled {
+        return Err(PerpsError::MarketSettled.into());
+    }
 
     market_state.last_price = price;
     market_state.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
     Ok(())
 }
+
+fn update_market_params(
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    market_id: u16,
+    max_leverage_bps: u32,
+    initial_margin_bps: u32,
+    maintenance_margin_bps: u32,
+    status: MarketStatus,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+
+    check_market_params(max_leverage_bps, initial_margin_bps, maintenance_margin_bps)?;
+    // Settling goes through `SettleMarket`, which records the price.
+    if status == MarketStatus::Settled {
+        return Err(PerpsError::InvalidMarketParams.into());
+    }
+
+    let mut market_state = admin_market(admin, market_account, program_id, market_id)?;
+
+    market_state.max_leverage_bps = max_leverage_bps;
+    market_state.initial_margin_bps = initial_margin_bps;
+    market_state.maintenance_margin_bps = maintenance_margin_bps;
+    market_state.status = status;
+    market_state.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+    msg!("market params updated");
+    Ok(())
+}
+
+fn set_oracle_authority(
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    market_id: u16,
+    oracle_authority: Pubkey,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+
+    let mut market_state = admin_market(admin, market_account, program_id, market_id)?;
+    market_state.oracle_authority = oracle_authority;
+    market_state.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+    msg!("oracle authority set to {}", oracle_authority);
+    Ok(())
+}
+
+fn settle_market(
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    market_id: u16,
+    settlement_price: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+
+    if settlement_price == 0 {
+        return Err(PerpsError::InvalidMarketParams.into());
+    }
+    let mut market_state = admin_market(admin, market_account, program_id, market_id)?;
+
+    market_state.status = MarketStatus::Settled;
+    market_state.settlement_price = settlement_price;
+    market_state.last_price = settlement_price;
+    market_state.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+    msg!("market settled");
+    Ok(())
+}
+
+fn settle_position(accounts: &[AccountInfo], program_id: &Pubkey, market_id: u16) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let caller = next_account_info(&mut iter)?;
+    let account_state_account = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+    let position_account = next_account_info(&mut iter)?;
+
+    if !caller.is_signer {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+
+    let market = load_market(market_account, program_id)?;
+    if market.market_id != market_id {
+        return Err(PerpsError::InvalidInstruction.into());
+    }
+    if market.status != MarketStatus::Settled {
+        return Err(PerpsError::MarketNotSettled.into());
+    }
+
+    // Anyone may send this, so every account must be the program's own and the position the
+    // one this account state holds in this market.
+    if account_state_account.owner != program_id || position_account.owner != program_id {
+        return Err(PerpsError::InvalidAccountOwner.into());
+    }
+    if *position_account.key != position_address(account_state_account.key, market_id, program_id).0 {
+        return Err(PerpsError::InvalidPositionAccount.into());
+    }
+    let mut account = AccountState::try_from_slice(&account_state_account.data.borrow())?;
+    let position = PositionState::try_from_slice(&position_account.data.borrow())?;
+    if !account.is_initialized || !position.is_initialized || position.market_id != market_id {
+        return Err(PerpsError::InvalidInstruction.into());
+    }
+    if position.owner != account.owner {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+
+    let qty = position.base_qty.unsigned_abs() as i128;
+    let move_per_unit = market.settlement_price as i128 - position.entry_price as i128;
+    let pnl = match position.side {
+        Side::Long => qty.saturating_mul(move_per_unit),
+        Side::Short => qty.saturating_mul(-move_per_unit),
+    };
+    // Losses beyond the collateral are written off.
+    let collateral = (account.collateral as i128).saturating_add(pnl).clamp(0, u64::MAX as i128);
+    account.collateral = collateral as u64;
+
+    let notional = (position.base_qty.unsigned_abs() as u128)
+        .saturating_mul(position.entry_price as u128);
+    let required_margin = notional
+        .saturating_mul(BPS_DIVISOR as u128)
+        .checked_div(position.leverage_bps as u128)
+        .unwrap_or(0) as u64;
+    account.locked_margin = account.locked_margin.saturating_sub(required_margin);
+    account.serialize(&mut &mut account_state_account.data.borrow_mut()[..])?;
+
+    let cleared = PositionState {
+        is_initialized: false,
+        owner: position.owner,
+        market_id,
+        side: position.side,
+        base_qty: 0,
+        entry_price: 0,
+        leverage_bps: position.leverage_bps,
+    };
+    cleared.serialize(&mut &mut position_account.data.borrow_mut()[..])?;
+
+    msg!("position settled");
+    Ok(())
+}
+
+/// The position PDA of an account state in a market, and its bump seed.
+pub fn position_address(account_state: &Pubkey, market_id: u16, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POSITION_SEED, account_state.as_ref(), &market_id.to_le_bytes()], program_id)
+}
+
+/// The market PDA of a market id, and its bump seed.
+pub fn market_address(market_id: u16, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MARKET_SEED, &market_id.to_le_bytes()], program_id)
+}
+
+/// The market state in `market_account`, refusing accounts the program doesn't own, which
+/// anyone could fill with a market of their choosing, accounts still in the legacy layout, and
+/// any account but the market's PDA, so there is one market per id.
+fn load_market(market_account: &AccountInfo, program_id: &Pubkey) -> Result<MarketState, ProgramError> {
+    if market_account.owner != program_id {
+        return Err(PerpsError::InvalidAccountOwner.into());
+    }
+    if market_account.data_len() == LEGACY_MARKET_STATE_LEN {
+        return Err(PerpsError::MarketNeedsMigration.into());
+    }
+    let market = MarketState::try_from_slice(&market_account.data.borrow())?;
+    if *market_account.key != market_address(market.market_id, program_id).0 {
+        return Err(PerpsError::InvalidMarketAccount.into());
+    }
+    Ok(market)
+}
+
+/// The market `admin` signed for, refusing anyone else and settled markets.
+fn admin_market(
+    admin: &AccountInfo,
+    market_account: &AccountInfo,
+    program_id: &Pubkey,
+    market_id: u16,
+) -> Result<MarketState, ProgramError> {
+    if !admin.is_signer {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    let market_state = load_market(market_account, program_id)?;
+    if !market_state.is_initialized || market_state.market_id != market_id {
+        return Err(PerpsError::InvalidInstruction.into());
+    }
+    if market_state.admin != *admin.key {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    if market_state.status == MarketStatus::Settled {
+        return Err(PerpsError::MarketSettled.into());
+    }
+    Ok(market_state)
+}
+
+fn migrate_market(accounts: &[AccountInfo], program_id: &Pubkey, market_id: u16) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let authority = next_account_info(&mut iter)?;
+    let old_account = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+    let system_program = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+
+    if !authority.is_signer {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    if old_account.owner != program_id {
+        return Err(PerpsError::InvalidAccountOwner.into());
+    }
+    let (expected, bump) = market_address(market_id, program_id);
+    if *market_account.key != expected {
+        return Err(PerpsError::InvalidMarketAccount.into());
+    }
+    // Already at its PDA.
+    if *old_account.key == expected || !market_account.data_is_empty() {
+        return Err(PerpsError::InvalidInstruction.into());
+    }
+
+    let (market_state, market_authority) = if old_account.data_len() == LEGACY_MARKET_STATE_LEN {
+        let legacy = LegacyMarketState::try_from_slice(&old_account.data.borrow())?;
+        if !legacy.is_initialized || legacy.market_id != market_id {
+            return Err(PerpsError::InvalidInstruction.into());
+        }
+        let market_state = MarketState {
+            is_initialized: true,
+            market_id: legacy.market_id,
+            oracle_authority: legacy.oracle_authority,
+            max_leverage_bps: legacy.max_leverage_bps,
+            initial_margin_bps: legacy.initial_margin_bps,
+            maintenance_margin_bps: legacy.maintenance_margin_bps,
+            open_interest: legacy.open_interest,
+            last_price: legacy.last_price,
+            admin: *authority.key,
+            status: MarketStatus::Active,
+            settlement_price: 0,
+        };
+        (market_state, legacy.oracle_authority)
+    } else {
+        let market_state = MarketState::try_from_slice(&old_account.data.borrow())?;
+        if !market_state.is_initialized || market_state.market_id != market_id {
+            return Err(PerpsError::InvalidInstruction.into());
+        }
+        let admin = market_state.admin;
+        (market_state, admin)
+    };
+    // The config may not exist yet; then only the market's own authority can migrate.
+    let program_admin = load_config(config_account, program_id).ok().map(|config| config.admin);
+    if *authority.key != market_authority && Some(*authority.key) != program_admin {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+
+    let rent = Rent::get()?.minimum_balance(MARKET_STATE_LEN);
+    invoke_signed(
+        &system_instruction::create_account(
+            authority.key,
+            market_account.key,
+            rent,
+            MARKET_STATE_LEN as u64,
+            program_id,
+        ),
+        &[authority.clone(), market_account.clone(), system_program.clone()],
+        &[&[MARKET_SEED, &market_id.to_le_bytes(), &[bump]]],
+    )?;
+    market_state.serialize(&mut &mut market_account.data.borrow_mut()[..])?;
+
+    let refund = old_account.lamports();
+    **authority.lamports.borrow_mut() = authority.lamports().saturating_add(refund);
+    **old_account.lamports.borrow_mut() = 0;
+    old_account.data.borrow_mut().fill(0);
+    msg!("market migrated to {}", market_account.key);
+    Ok(())
+}
+
+/// Closes and liquidations need a market that is neither paused nor settled.
+fn ensure_trading(market: &MarketState, market_id: u16) -> ProgramResult {
+    if market.market_id != market_id {
+        return Err(PerpsError::InvalidInstruction.into());
+    }
+    match market.status {
+        MarketStatus::Paused => Err(PerpsError::MarketPaused.into()),
+        MarketStatus::Settled => Err(PerpsError::MarketSettled.into()),
+        _ => Ok(()),
+    }
+}
+
+fn check_market_params(
+    max_leverage_bps: u32,
+    initial_margin_bps: u32,
+    maintenance_margin_bps: u32,
+) -> ProgramResult {
+    if max_leverage_bps == 0
+        || maintenance_margin_bps == 0
+        || maintenance_margin_bps >= initial_margin_bps
+        || initial_margin_bps as u64 > BPS_DIVISOR
+    {
+        return Err(PerpsError::InvalidMarketParams.into());
+    }
+    Ok(())
+}
+
+fn initialize_config(accounts: &[AccountInfo], program_id: &Pubkey, guardian: Pubkey) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+    let system_program = next_account_info(&mut iter)?;
+    let program_data = next_account_info(&mut iter)?;
+
+    if !admin.is_signer || upgrade_authority(program_data, program_id)? != Some(*admin.key) {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    let (expected, bump) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    if *config_account.key != expected {
+        return Err(PerpsError::InvalidConfig.into());
+    }
+    if !config_account.data_is_empty() {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+
+    let rent = Rent::get()?.minimum_balance(PROGRAM_CONFIG_LEN);
+    invoke_signed(
+        &system_instruction::create_account(
+            admin.key,
+            config_account.key,
+            rent,
+            PROGRAM_CONFIG_LEN as u64,
+            program_id,
+        ),
+        &[admin.clone(), config_account.clone(), system_program.clone()],
+        &[&[CONFIG_SEED, &[bump]]],
+    )?;
+
+    let config = ProgramConfig {
+        is_initialized: true,
+        admin: *admin.key,
+        pending_admin: Pubkey::default(),
+        guardian,
+        paused: false,
+    };
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("program config initialized");
+    Ok(())
+}
+
+fn set_pause(accounts: &[AccountInfo], program_id: &Pubkey, paused: bool) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let authority = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+
+    if !authority.is_signer {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    let mut config = load_config(config_account, program_id)?;
+    let allowed = *authority.key == config.admin || (paused && *authority.key == config.guardian);
+    if !allowed {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+
+    config.paused = paused;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!(if paused { "program paused" } else { "program unpaused" });
+    Ok(())
+}
+
+fn set_guardian(accounts: &[AccountInfo], program_id: &Pubkey, guardian: Pubkey) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+
+    let mut config = admin_config(admin, config_account, program_id)?;
+    config.guardian = guardian;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("guardian updated");
+    Ok(())
+}
+
+fn propose_admin(accounts: &[AccountInfo], program_id: &Pubkey, new_admin: Pubkey) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+
+    let mut config = admin_config(admin, config_account, program_id)?;
+    config.pending_admin = new_admin;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("admin proposed");
+    Ok(())
+}
+
+fn accept_admin(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let new_admin = next_account_info(&mut iter)?;
+    let config_account = next_account_info(&mut iter)?;
+
+    if !new_admin.is_signer {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    let mut config = load_config(config_account, program_id)?;
+    if config.pending_admin == Pubkey::default() || config.pending_admin != *new_admin.key {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+
+    config.admin = *new_admin.key;
+    config.pending_admin = Pubkey::default();
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    msg!("admin accepted");
+    Ok(())
+}
+
+/// The program config, refusing any account but the initialized config PDA.
+fn load_config(config_account: &AccountInfo, program_id: &Pubkey) -> Result<ProgramConfig, ProgramError> {
+    let (expected, _) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    if *config_account.key != expected || config_account.owner != program_id {
+        return Err(PerpsError::InvalidConfig.into());
+    }
+    let config = ProgramConfig::try_from_slice(&config_account.data.borrow())?;
+    if !config.is_initialized {
+        return Err(PerpsError::InvalidConfig.into());
+    }
+    Ok(config)
+}
+
+fn admin_config(admin: &AccountInfo, config_account: &AccountInfo, program_id: &Pubkey) -> Result<ProgramConfig, ProgramError> {
+    if !admin.is_signer {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    let config = load_config(config_account, program_id)?;
+    if config.admin != *admin.key {
+        return Err(PerpsError::NotAuthorized.into());
+    }
+    Ok(config)
+}
+
+/// The upgrade authority recorded in the program's ProgramData account; `None` once the
+/// program is immutable.
+fn upgrade_authority(program_data: &AccountInfo, program_id: &Pubkey) -> Result<Option<Pubkey>, ProgramError> {
+    let (expected, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if *program_data.key != expected || *program_data.owner != bpf_loader_upgradeable::id() {
+        return Err(PerpsError::InvalidConfig.into());
+    }
+    let data = program_data.data.borrow();
+    let metadata = data
+        .get(..UpgradeableLoaderState::size_of_programdata_metadata())
+        .ok_or(ProgramError::InvalidAccountData)?;
+    match bincode::deserialize(metadata) {
+        Ok(UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address,
+            ..
+        }) => Ok(upgrade_authority_address),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Before `InitializeConfig` the config PDA is still empty, which counts as unpaused so the
+/// program works from the moment it is deployed.
+fn ensure_not_paused(config_account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    let (expected, _) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    if *config_account.key != expected {
+        return Err(PerpsError::InvalidConfig.into());
+    }
+    if config_account.data_is_empty() {
+        return Ok(());
+    }
+    if load_config(config_account, program_id)?.paused {
+        return Err(PerpsError::ProgramPaused.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestAccount {
+        key: Pubkey,
+        is_signer: bool,
+        lamports: u64,
+        data: Vec<u8>,
+        owner: Pubkey,
+    }
+
+    impl TestAccount {
+        fn signer() -> Self {
+            Self {
+                key: Pubkey::new_unique(),
+                is_signer: true,
+                lamports: 1_000_000_000,
+                data: Vec::new(),
+                owner: solana_program::system_program::id(),
+            }
+        }
+
+        fn owned(owner: Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key: Pubkey::new_unique(),
+                is_signer: false,
+                lamports: 1_000_000_000,
+                data,
+                owner,
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    fn market(admin: Pubkey) -> MarketState {
+        MarketState {
+            is_initialized: true,
+            market_id: 1,
+            oracle_authority: admin,
+            max_leverage_bps: 200_000,
+            initial_margin_bps: 500,
+            maintenance_margin_bps: 250,
+            open_interest: 0,
+            last_price: 67_000,
+            admin,
+            status: MarketStatus::Active,
+            settlement_price: 0,
+        }
+    }
+
+    /// A program-owned account holding `data` at market 1's PDA.
+    fn market_pda(data: Vec<u8>) -> TestAccount {
+        let mut account = TestAccount::owned(id(), data);
+        account.key = market_address(1, &id()).0;
+        account
+    }
+
+    fn run(accounts: &[AccountInfo], instruction: PerpsInstruction) -> ProgramResult {
+        process_instruction(&id(), accounts, &instruction.try_to_vec().unwrap())
+    }
+
+    fn update_params() -> PerpsInstruction {
+        PerpsInstruction::UpdateMarketParams {
+            market_id: 1,
+            max_leverage_bps: 100_000,
+            initial_margin_bps: 1_000,
+            maintenance_margin_bps: 500,
+            status: MarketStatus::Active,
+        }
+    }
+
+    #[test]
+    fn market_layout_sizes() {
+        assert_eq!(market(Pubkey::new_unique()).try_to_vec().unwrap().len(), MARKET_STATE_LEN);
+        assert_eq!(LEGACY_MARKET_STATE_LEN, 63);
+    }
+
+    #[test]
+    fn forged_market_accounts_are_refused() {
+        let mut admin = TestAccount::signer();
+        let forger = Pubkey::new_unique();
+        let mut forged = TestAccount::owned(forger, market(admin.key).try_to_vec().unwrap());
+        assert_eq!(
+            run(&[admin.info(), forged.info()], update_params()),
+            Err(PerpsError::InvalidAccountOwner.into())
+        );
+        assert_eq!(
+            run(
+                &[admin.info(), forged.info()],
+                PerpsInstruction::SettleMarket { market_id: 1, settlement_price: 1 }
+            ),
+            Err(PerpsError::InvalidAccountOwner.into())
+        );
+    }
+
+    #[test]
+    fn only_the_market_admin_updates_params() {
+        let mut admin = TestAccount::signer();
+        let mut stranger = TestAccount::signer();
+        let mut account = market_pda(market(admin.key).try_to_vec().unwrap());
+        assert_eq!(
+            run(&[stranger.info(), account.info()], update_params()),
+            Err(PerpsError::NotAuthorized.into())
+        );
+        run(&[admin.info(), account.info()], update_params()).unwrap();
+        let updated = MarketState::try_from_slice(&account.data).unwrap();
+        assert_eq!(updated.max_leverage_bps, 100_000);
+    }
+
+    /// The initialized config PDA, with `admin` as the program admin.
+    fn admin_config_pda(admin: Pubkey) -> TestAccount {
+        let config = ProgramConfig {
+            is_initialized: true,
+            admin,
+            pending_admin: Pubkey::default(),
+            guardian: Pubkey::new_unique(),
+            paused: false,
+        };
+        config_pda(config.try_to_vec().unwrap())
+    }
+
+    fn initialize() -> PerpsInstruction {
+        PerpsInstruction::InitializeMarket {
+            market_id: 1,
+            oracle_authority: Pubkey::new_unique().to_bytes(),
+            max_leverage_bps: 200_000,
+            initial_margin_bps: 500,
+            maintenance_margin_bps: 250,
+        }
+    }
+
+    #[test]
+    fn only_the_program_admin_initializes_markets() {
+        let mut admin = TestAccount::signer();
+        let mut stranger = TestAccount::signer();
+        let mut account = market_pda(vec![0; MARKET_STATE_LEN]);
+        let mut config = admin_config_pda(admin.key);
+        let mut system = TestAccount::owned(Pubkey::default(), Vec::new());
+
+        assert_eq!(
+            run(&[stranger.info(), account.info(), config.info(), system.info()], initialize()),
+            Err(PerpsError::NotAuthorized.into())
+        );
+        let mut uninitialized = config_pda(Vec::new());
+        assert_eq!(
+            run(&[stranger.info(), account.info(), uninitialized.info(), system.info()], initialize()),
+            Err(PerpsError::InvalidConfig.into())
+        );
+        assert_eq!(account.data, vec![0; MARKET_STATE_LEN]);
+
+        run(&[admin.info(), account.info(), config.info(), system.info()], initialize()).unwrap();
+        let state = MarketState::try_from_slice(&account.data).unwrap();
+        assert!(state.is_initialized);
+        assert_eq!(state.admin, admin.key);
+    }
+
+    #[test]
+    fn legacy_market_accounts_must_be_migrated_first() {
+        let mut admin = TestAccount::signer();
+        let legacy = market(admin.key).try_to_vec().unwrap()[..LEGACY_MARKET_STATE_LEN].to_vec();
+        let mut account = market_pda(legacy);
+        assert_eq!(
+            run(&[admin.info(), account.info()], PerpsInstruction::UpdatePrice { market_id: 1, price: 1 }),
+            Err(PerpsError::MarketNeedsMigration.into())
+        );
+        let mut config = admin_config_pda(admin.key);
+        let mut system = TestAccount::owned(Pubkey::default(), Vec::new());
+        assert_eq!(
+            run(&[admin.info(), account.info(), config.info(), system.info()], initialize()),
+            Err(PerpsError::MarketNeedsMigration.into())
+        );
+    }
+
+    #[test]
+    fn undecodable_market_accounts_are_not_reinitialized() {
+        let mut admin = TestAccount::signer();
+        let mut account = market_pda(vec![0xff; MARKET_STATE_LEN]);
+        let mut config = admin_config_pda(admin.key);
+        let mut system = TestAccount::owned(Pubkey::default(), Vec::new());
+        assert!(run(&[admin.info(), account.info(), config.info(), system.info()], initialize()).is_err());
+        assert_eq!(account.data, vec![0xff; MARKET_STATE_LEN]);
+    }
+
+    #[test]
+    fn only_the_oracle_authority_or_program_admin_migrates() {
+        let oracle_authority = Pubkey::new_unique();
+        let legacy = market(oracle_authority).try_to_vec().unwrap()[..LEGACY_MARKET_STATE_LEN].to_vec();
+        let mut account = TestAccount::owned(id(), legacy);
+        let mut stranger = TestAccount::signer();
+        let mut config = TestAccount::owned(id(), Vec::new());
+        let mut system = TestAccount::owned(Pubkey::default(), Vec::new());
+        let mut pda = market_pda(Vec::new());
+        pda.owner = solana_program::system_program::id();
+        assert_eq!(
+            run(
+                &[stranger.info(), account.info(), config.info(), system.info(), pda.info()],
+                PerpsInstruction::MigrateMarket { market_id: 1 }
+            ),
+            Err(PerpsError::NotAuthorized.into())
+        );
+        assert_eq!(account.data.len(), LEGACY_MARKET_STATE_LEN);
+    }
+
+    #[test]
+    fn markets_must_live_at_their_pda() {
+        let mut admin = TestAccount::signer();
+        let mut config = admin_config_pda(admin.key);
+        let mut system = TestAccount::owned(Pubkey::default(), Vec::new());
+
+        // A second market of the same id at a keypair address is refused everywhere.
+        let mut elsewhere = TestAccount::owned(id(), market(admin.key).try_to_vec().unwrap());
+        assert_eq!(
+            run(&[admin.info(), elsewhere.info()], update_params()),
+            Err(PerpsError::InvalidMarketAccount.into())
+        );
+        let mut fresh = TestAccount::owned(id(), vec![0; MARKET_STATE_LEN]);
+        assert_eq!(
+            run(&[admin.info(), fresh.info(), config.info(), system.info()], initialize()),
+            Err(PerpsError::InvalidMarketAccount.into())
+        );
+
+        // At its PDA, the market account is created, which needs the runtime.
+        let mut pda = market_pda(Vec::new());
+        pda.owner = solana_program::system_program::id();
+        assert_eq!(
+            run(&[admin.info(), pda.info(), config.info(), system.info()], initialize()),
+            Err(ProgramError::UnsupportedSysvar)
+        );
+
+        // The keypair market's admin may move it there, but not onto a market already there.
+        let migrate = || PerpsInstruction::MigrateMarket { market_id: 1 };
+        assert_eq!(
+            run(&[admin.info(), elsewhere.info(), config.info(), system.info(), pda.info()], migrate()),
+            Err(ProgramError::UnsupportedSysvar)
+        );
+        let mut taken = market_pda(market(admin.key).try_to_vec().unwrap());
+        assert_eq!(
+            run(&[admin.info(), elsewhere.info(), config.info(), system.info(), taken.info()], migrate()),
+            Err(PerpsError::InvalidInstruction.into())
+        );
+        let mut stray = TestAccount::owned(Pubkey::default(), Vec::new());
+        assert_eq!(
+            run(&[admin.info(), elsewhere.info(), config.info(), system.info(), stray.info()], migrate()),
+            Err(PerpsError::InvalidMarketAccount.into())
+        );
+    }
+
+    struct Settlement {
+        caller: TestAccount,
+        account: TestAccount,
+        market: TestAccount,
+        position: TestAccount,
+    }
+
+    impl Settlement {
+        /// A long of 2 at 100 in a market settled at 150, held at its position PDA.
+        fn new() -> Self {
+            let caller = TestAccount::signer();
+            let trader = Pubkey::new_unique();
+            let account = TestAccount::owned(
+                id(),
+                AccountState {
+                    is_initialized: true,
+                    owner: trader,
+                    collateral: 1_000,
+                    locked_margin: 40,
+                }
+                .try_to_vec()
+                .unwrap(),
+            );
+            let mut settled = market(Pubkey::new_unique());
+            settled.status = MarketStatus::Settled;
+            settled.settlement_price = 150;
+            let market = market_pda(settled.try_to_vec().unwrap());
+            let mut position = TestAccount::owned(
+                id(),
+                PositionState {
+                    is_initialized: true,
+                    owner: trader,
+                    market_id: 1,
+                    side: Side::Long,
+                    base_qty: 2,
+                    entry_price: 100,
+                    leverage_bps: 50_000,
+                }
+                .try_to_vec()
+                .unwrap(),
+            );
+            position.key = position_address(&account.key, 1, &id()).0;
+            Self {
+                caller,
+                account,
+                market,
+                position,
+            }
+        }
+
+        fn settle(&mut self) -> ProgramResult {
+            run(
+                &[self.caller.info(), self.account.info(), self.market.info(), self.position.info()],
+                PerpsInstruction::SettlePosition { market_id: 1 },
+            )
+        }
+    }
+
+    #[test]
+    fn settling_credits_the_pnl_and_clears_the_position() {
+        let mut settlement = Settlement::new();
+        settlement.settle().unwrap();
+        let account = AccountState::try_from_slice(&settlement.account.data).unwrap();
+        assert_eq!(account.collateral, 1_100);
+        assert_eq!(account.locked_margin, 0);
+        assert!(!PositionState::try_from_slice(&settlement.position.data).unwrap().is_initialized);
+        assert_eq!(settlement.settle(), Err(PerpsError::InvalidInstruction.into()));
+    }
+
+    #[test]
+    fn settling_refuses_accounts_the_program_does_not_own() {
+        let forger = Pubkey::new_unique();
+
+        let mut settlement = Settlement::new();
+        settlement.market.owner = forger;
+        assert_eq!(settlement.settle(), Err(PerpsError::InvalidAccountOwner.into()));
+
+        let mut settlement = Settlement::new();
+        settlement.account.owner = forger;
+        assert_eq!(settlement.settle(), Err(PerpsError::InvalidAccountOwner.into()));
+
+        let mut settlement = Settlement::new();
+        settlement.position.owner = forger;
+        assert_eq!(settlement.settle(), Err(PerpsError::InvalidAccountOwner.into()));
+    }
+
+    #[test]
+    fn settling_needs_the_position_of_that_account() {
+        let mut settlement = Settlement::new();
+        settlement.position.key = Pubkey::new_unique();
+        assert_eq!(settlement.settle(), Err(PerpsError::InvalidPositionAccount.into()));
+
+        // Another account state's position, even of the same trader, doesn't settle here.
+        let mut settlement = Settlement::new();
+        settlement.account.key = Pubkey::new_unique();
+        assert_eq!(settlement.settle(), Err(PerpsError::InvalidPositionAccount.into()));
+        assert_eq!(AccountState::try_from_slice(&settlement.account.data).unwrap().collateral, 1_000);
+    }
+
+    /// The program's ProgramData account, upgradeable by `authority`.
+    fn program_data(authority: Option<Pubkey>) -> TestAccount {
+        let mut data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: authority,
+        })
+        .unwrap();
+        data.resize(UpgradeableLoaderState::size_of_programdata_metadata() + 64, 0);
+        let mut account = TestAccount::owned(bpf_loader_upgradeable::id(), data);
+        account.key = Pubkey::find_program_address(&[id().as_ref()], &bpf_loader_upgradeable::id()).0;
+        account
+    }
+
+    fn config_pda(data: Vec<u8>) -> TestAccount {
+        let owner = if data.is_empty() { solana_program::system_program::id() } else { id() };
+        let mut account = TestAccount::owned(owner, data);
+        account.key = Pubkey::find_program_address(&[CONFIG_SEED], &id()).0;
+        account
+    }
+
+    #[test]
+    fn only_the_upgrade_authority_initializes_the_config() {
+        let init = || PerpsInstruction::InitializeConfig {
+            guardian: Pubkey::new_unique().to_bytes(),
+        };
+        let mut deployer = TestAccount::signer();
+        let mut stranger = TestAccount::signer();
+        let mut config = config_pda(Vec::new());
+        let mut system = TestAccount::owned(Pubkey::default(), Vec::new());
+        let mut deployed = program_data(Some(deployer.key));
+
+        assert_eq!(
+            run(&[stranger.info(), config.info(), system.info(), deployed.info()], init()),
+            Err(PerpsError::NotAuthorized.into())
+        );
+
+        // A lookalike ProgramData account naming the stranger doesn't pass.
+        let mut forged = program_data(Some(stranger.key));
+        forged.owner = stranger.key;
+        assert_eq!(
+            run(&[stranger.info(), config.info(), system.info(), forged.info()], init()),
+            Err(PerpsError::InvalidConfig.into())
+        );
+
+        let mut immutable = program_data(None);
+        assert_eq!(
+            run(&[deployer.info(), config.info(), system.info(), immutable.info()], init()),
+            Err(PerpsError::NotAuthorized.into())
+        );
+
+        // The upgrade authority gets as far as creating the account, which needs the runtime.
+        assert_eq!(
+            run(&[deployer.info(), config.info(), system.info(), deployed.info()], init()),
+            Err(ProgramError::UnsupportedSysvar)
+        );
+    }
+
+    #[test]
+    fn the_program_is_unpaused_until_the_config_is_initialized() {
+        let mut owner = TestAccount::signer();
+        let mut account = TestAccount::owned(
+            id(),
+            AccountState {
+                is_initialized: true,
+                owner: owner.key,
+                collateral: 100,
+                locked_margin: 0,
+            }
+            .try_to_vec()
+            .unwrap(),
+        );
+        let withdraw = || PerpsInstruction::Withdraw { amount: 10 };
+
+        let mut config = config_pda(Vec::new());
+        run(&[owner.info(), account.info(), config.info()], withdraw()).unwrap();
+        assert_eq!(AccountState::try_from_slice(&account.data).unwrap().collateral, 90);
+
+        let mut elsewhere = TestAccount::owned(solana_program::system_program::id(), Vec::new());
+        assert_eq!(
+            run(&[owner.info(), account.info(), elsewhere.info()], withdraw()),
+            Err(PerpsError::InvalidConfig.into())
+        );
+
+        let paused = ProgramConfig {
+            is_initialized: true,
+            admin: Pubkey::new_unique(),
+            pending_admin: Pubkey::default(),
+            guardian: Pubkey::new_unique(),
+            paused: true,
+        };
+        let mut config = config_pda(paused.try_to_vec().unwrap());
+        assert_eq!(
+            run(&[owner.info(), account.info(), config.info()], withdraw()),
+            Err(PerpsError::ProgramPaused.into())
+        );
+    }
+
+    #[test]
+    fn the_market_admin_hands_over_the_oracle_authority() {
+        let mut admin = TestAccount::signer();
+        let mut publisher = TestAccount::signer();
+        let mut market_account = market_pda(market(admin.key).try_to_vec().unwrap());
+        let set = |authority: Pubkey| PerpsInstruction::SetOracleAuthority {
+            market_id: 1,
+            oracle_authority: authority.to_bytes(),
+        };
+        let push = || PerpsInstruction::UpdatePrice { market_id: 1, price: 68_000 };
+        let (old, new) = (admin.key, publisher.key);
+
+        assert_eq!(
+            run(&[publisher.info(), market_account.info()], set(new)),
+            Err(PerpsError::NotAuthorized.into())
+        );
+        assert_eq!(
+            run(&[publisher.info(), market_account.info()], push()),
+            Err(PerpsError::NotAuthorized.into())
+        );
+
+        run(&[admin.info(), market_account.info()], set(new)).unwrap();
+        run(&[publisher.info(), market_account.info()], push()).unwrap();
+        let state = MarketState::try_from_slice(&market_account.data).unwrap();
+        assert_eq!((state.oracle_authority, state.last_price), (new, 68_000));
+        // The old authority, here the admin, no longer pushes prices.
+        assert_eq!(state.admin, old);
+        assert_eq!(
+            run(&[admin.info(), market_account.info()], push()),
+            Err(PerpsError::NotAuthorized.into())
+        );
+    }
+}